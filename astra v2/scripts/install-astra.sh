@@ -98,7 +98,7 @@ fi
 
 # Configuration
 echo -e "${BLUE}🔧 Configuration du système...${RESET}"
-mkdir -p /etc/astra /var/log/astra
+mkdir -p /etc/astra /var/log/astra /var/lib/astra
 
 cat > /etc/astra/config.json << 'CONFIG_END'
 {
@@ -0,0 +1,633 @@
+use std::ffi::CString;
+use std::io;
+use std::mem;
+use std::os::raw::{c_int, c_uint, c_void};
+use std::ptr;
+use std::sync::atomic::{fence, AtomicU16, Ordering};
+use std::time::Duration;
+
+use crate::core::config::AfPacketConfig;
+
+// From linux/if_packet.h; not every libc 0.2 release exports these
+const PACKET_ADD_MEMBERSHIP: c_int = 1;
+const PACKET_RX_RING: c_int = 5;
+const PACKET_STATISTICS: c_int = 6;
+const PACKET_VERSION: c_int = 10;
+const PACKET_FANOUT: c_int = 18;
+const PACKET_MR_PROMISC: u16 = 1;
+const PACKET_FANOUT_HASH: c_uint = 0;
+const PACKET_FANOUT_FLAG_DEFRAG: c_uint = 0x8000;
+const TPACKET_V3: c_int = 2;
+const TP_STATUS_KERNEL: u32 = 0;
+const TP_STATUS_USER: u32 = 1;
+const SO_ATTACH_FILTER: c_int = 26;
+const ETH_P_ALL: u16 = 0x0003;
+
+#[repr(C)]
+struct TpacketReq3 {
+    tp_block_size: c_uint,
+    tp_block_nr: c_uint,
+    tp_frame_size: c_uint,
+    tp_frame_nr: c_uint,
+    tp_retire_blk_tov: c_uint,
+    tp_sizeof_priv: c_uint,
+    tp_feature_req_word: c_uint,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct TpacketStatsV3 {
+    tp_packets: c_uint,
+    tp_drops: c_uint,
+    tp_freeze_q_cnt: c_uint,
+}
+
+#[repr(C)]
+#[allow(dead_code)] // Kernel ABI layout, not every field is read
+struct TpacketBdTs {
+    ts_sec: c_uint,
+    ts_nsec: c_uint,
+}
+
+#[repr(C)]
+#[allow(dead_code)] // Kernel ABI layout, not every field is read
+struct TpacketHdrV1 {
+    block_status: u32,
+    num_pkts: u32,
+    offset_to_first_pkt: u32,
+    blk_len: u32,
+    seq_num: u64,
+    ts_first_pkt: TpacketBdTs,
+    ts_last_pkt: TpacketBdTs,
+}
+
+#[repr(C)]
+#[allow(dead_code)] // Kernel ABI layout, not every field is read
+struct TpacketBlockDesc {
+    version: u32,
+    offset_to_priv: u32,
+    hdr: TpacketHdrV1,
+}
+
+#[repr(C)]
+#[allow(dead_code)] // Kernel ABI layout, not every field is read
+struct Tpacket3Hdr {
+    tp_next_offset: u32,
+    tp_sec: u32,
+    tp_nsec: u32,
+    tp_snaplen: u32,
+    tp_len: u32,
+    tp_status: u32,
+    tp_mac: u16,
+    tp_net: u16,
+}
+
+#[repr(C)]
+struct PacketMreq {
+    mr_ifindex: c_int,
+    mr_type: u16,
+    mr_alen: u16,
+    mr_address: [u8; 8],
+}
+
+// Classic BPF opcodes (linux/bpf_common.h)
+const BPF_LD: u16 = 0x00;
+const BPF_LDX: u16 = 0x01;
+const BPF_ST: u16 = 0x02;
+const BPF_JMP: u16 = 0x05;
+const BPF_JA: u16 = 0x00;
+const BPF_RET: u16 = 0x06;
+const BPF_W: u16 = 0x00;
+const BPF_H: u16 = 0x08;
+const BPF_B: u16 = 0x10;
+const BPF_ABS: u16 = 0x20;
+const BPF_IND: u16 = 0x40;
+const BPF_MEM: u16 = 0x60;
+const BPF_MSH: u16 = 0xa0;
+const BPF_JEQ: u16 = 0x10;
+const BPF_JGT: u16 = 0x20;
+const BPF_JGE: u16 = 0x30;
+const BPF_JSET: u16 = 0x40;
+const BPF_K: u16 = 0x00;
+const BPF_IMM: u16 = 0x00;
+
+const ETH_HEADER_LEN: u32 = 14;
+const ETHERTYPE_IPV4: u32 = 0x0800;
+const ETHERTYPE_IPV6: u32 = 0x86dd;
+const IPV6_HEADER_LEN: u32 = 40;
+const IPPROTO_ICMP: u32 = 1;
+const IPPROTO_TCP: u32 = 6;
+const IPPROTO_UDP: u32 = 17;
+const IPPROTO_ICMPV6: u32 = 58;
+const SNAPLEN: u32 = 0xffff;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Jump {
+    Next,
+    Skip(u8),
+    Accept,
+    Reject,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Instruction {
+    code: u16,
+    jt: Jump,
+    jf: Jump,
+    k: u32,
+}
+
+/// A classic BPF program attached to a capture socket so the kernel drops
+/// uninteresting packets before they are copied into the ring. Programs
+/// match untagged Ethernet/IPv4 frames, and Ethernet/IPv6 when asked to,
+/// and always end in a reject/accept pair, in that order.
+#[derive(Debug, Clone)]
+pub struct BpfProgram {
+    instructions: Vec<libc::sock_filter>,
+}
+
+struct BpfBuilder {
+    instructions: Vec<Instruction>,
+}
+
+impl BpfBuilder {
+    fn new() -> Self {
+        BpfBuilder { instructions: Vec::new() }
+    }
+
+    fn stmt(&mut self, code: u16, k: u32) -> &mut Self {
+        self.instructions.push(Instruction { code, jt: Jump::Next, jf: Jump::Next, k });
+        self
+    }
+
+    fn jump(&mut self, code: u16, k: u32, jt: Jump, jf: Jump) -> &mut Self {
+        self.instructions.push(Instruction { code: BPF_JMP | code | BPF_K, jt, jf, k });
+        self
+    }
+
+    /// Leaves the IP protocol in A and the transport header offset in X.
+    /// IPv4 must be a first fragment. IPv6 packets that start with an
+    /// extension header cannot be walked here and are all accepted, leaving
+    /// userspace to parse and filter them; X is only meaningful for TCP and
+    /// UDP.
+    fn transport_prologue(&mut self, ipv6: bool) -> &mut Self {
+        self.stmt(BPF_LD | BPF_H | BPF_ABS, 12);
+        if ipv6 {
+            self.jump(BPF_JEQ, ETHERTYPE_IPV6, Jump::Next, Jump::Skip(6))
+                .stmt(BPF_LD | BPF_B | BPF_ABS, ETH_HEADER_LEN + 6)
+                .jump(BPF_JEQ, IPPROTO_TCP, Jump::Skip(2), Jump::Next)
+                .jump(BPF_JEQ, IPPROTO_UDP, Jump::Skip(1), Jump::Next)
+                .jump(BPF_JEQ, IPPROTO_ICMPV6, Jump::Next, Jump::Accept)
+                .stmt(BPF_LDX | BPF_W | BPF_IMM, IPV6_HEADER_LEN)
+                // Over the IPv4 checks below
+                .stmt(BPF_JMP | BPF_JA, 5);
+        }
+        self.jump(BPF_JEQ, ETHERTYPE_IPV4, Jump::Next, Jump::Reject)
+            .stmt(BPF_LD | BPF_H | BPF_ABS, ETH_HEADER_LEN + 6)
+            .jump(BPF_JSET, 0x1fff, Jump::Reject, Jump::Next)
+            .stmt(BPF_LD | BPF_B | BPF_ABS, ETH_HEADER_LEN + 9)
+            .stmt(BPF_LDX | BPF_B | BPF_MSH, ETH_HEADER_LEN)
+    }
+
+    fn finish(&self) -> Result<BpfProgram, Box<dyn std::error::Error>> {
+        let reject = self.instructions.len();
+        let accept = reject + 1;
+        let mut instructions = Vec::with_capacity(self.instructions.len() + 2);
+
+        for (index, instruction) in self.instructions.iter().enumerate() {
+            let offset = |jump: Jump| -> Result<u8, Box<dyn std::error::Error>> {
+                let target = match jump {
+                    Jump::Next => return Ok(0),
+                    Jump::Skip(count) => return Ok(count),
+                    Jump::Accept => accept,
+                    Jump::Reject => reject,
+                };
+                u8::try_from(target - index - 1).map_err(|_| "BPF filter too large: jump out of range".into())
+            };
+            instructions.push(libc::sock_filter {
+                code: instruction.code,
+                jt: offset(instruction.jt)?,
+                jf: offset(instruction.jf)?,
+                k: instruction.k,
+            });
+        }
+
+        instructions.push(libc::sock_filter { code: BPF_RET | BPF_K, jt: 0, jf: 0, k: 0 });
+        instructions.push(libc::sock_filter { code: BPF_RET | BPF_K, jt: 0, jf: 0, k: SNAPLEN });
+        Ok(BpfProgram { instructions })
+    }
+}
+
+impl BpfProgram {
+    /// TCP segments carrying SYN, FIN or RST, plus flagless NULL-scan probes.
+    pub fn tcp_control_segments(ipv6: bool) -> Self {
+        let mut builder = BpfBuilder::new();
+        builder.transport_prologue(ipv6)
+            .jump(BPF_JEQ, IPPROTO_TCP, Jump::Next, Jump::Reject)
+            .stmt(BPF_LD | BPF_B | BPF_IND, ETH_HEADER_LEN + 13)
+            // FIN | SYN | RST
+            .jump(BPF_JSET, 0x07, Jump::Accept, Jump::Next)
+            .jump(BPF_JSET, 0x3f, Jump::Reject, Jump::Accept);
+        builder.finish().expect("fixed BPF program fits")
+    }
+
+    /// Every UDP datagram plus ICMP and ICMPv6 messages.
+    pub fn udp_and_icmp(ipv6: bool) -> Self {
+        let mut builder = BpfBuilder::new();
+        builder.transport_prologue(ipv6)
+            .jump(BPF_JEQ, IPPROTO_UDP, Jump::Accept, Jump::Next)
+            .jump(BPF_JEQ, IPPROTO_ICMP, Jump::Accept, Jump::Next)
+            .jump(BPF_JEQ, IPPROTO_ICMPV6, Jump::Accept, Jump::Reject);
+        builder.finish().expect("fixed BPF program fits")
+    }
+
+    /// TCP or UDP to or from any of `ports`, plus UDP addressed into
+    /// `udp_dest_range` when given.
+    pub fn transport_ports(ports: &[u16], udp_dest_range: Option<(u16, u16)>, ipv6: bool) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut builder = BpfBuilder::new();
+        builder.transport_prologue(ipv6)
+            .jump(BPF_JEQ, IPPROTO_TCP, Jump::Skip(1), Jump::Next)
+            .jump(BPF_JEQ, IPPROTO_UDP, Jump::Next, Jump::Reject)
+            // Remember the protocol for the UDP-only range check
+            .stmt(BPF_ST, 0);
+
+        for header_offset in [0, 2] {
+            builder.stmt(BPF_LD | BPF_H | BPF_IND, ETH_HEADER_LEN + header_offset);
+            for &port in ports {
+                builder.jump(BPF_JEQ, port as u32, Jump::Accept, Jump::Next);
+            }
+        }
+
+        if let Some((min, max)) = udp_dest_range {
+            builder.stmt(BPF_LD | BPF_W | BPF_MEM, 0)
+                .jump(BPF_JEQ, IPPROTO_UDP, Jump::Next, Jump::Reject)
+                .stmt(BPF_LD | BPF_H | BPF_IND, ETH_HEADER_LEN + 2)
+                .jump(BPF_JGE, min as u32, Jump::Next, Jump::Reject)
+                .jump(BPF_JGT, max as u32, Jump::Reject, Jump::Accept);
+        }
+
+        builder.finish()
+    }
+}
+
+/// Kernel counters for one ring; the kernel resets them on every read so
+/// they are accumulated here.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RingStatistics {
+    pub packets: u64,
+    pub drops: u64,
+    pub freeze_count: u64,
+}
+
+static NEXT_FANOUT_GROUP: AtomicU16 = AtomicU16::new(0);
+
+/// Allocates a fanout group id, offset by the pid so two instances on one
+/// host do not end up sharing a group.
+pub fn allocate_fanout_group() -> u16 {
+    (std::process::id() as u16).wrapping_mul(97).wrapping_add(NEXT_FANOUT_GROUP.fetch_add(1, Ordering::Relaxed))
+}
+
+/// An AF_PACKET socket with a TPACKET_V3 receive ring mapped into memory.
+pub struct AfPacketRing {
+    fd: c_int,
+    map: *mut u8,
+    map_len: usize,
+    block_size: usize,
+    block_count: usize,
+    current_block: usize,
+    statistics: RingStatistics,
+}
+
+// The mapping is owned exclusively by this ring
+unsafe impl Send for AfPacketRing {}
+
+impl AfPacketRing {
+    /// Opens a ring of `ring_bytes` on `interface`. The filter is attached
+    /// before the socket is bound so no unfiltered packet is ever queued.
+    pub fn open(
+        interface: &str,
+        config: &AfPacketConfig,
+        ring_bytes: usize,
+        promiscuous: bool,
+        filter: &BpfProgram,
+        fanout_group: Option<u16>,
+    ) -> io::Result<Self> {
+        let ifindex = interface_index(interface)?;
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) }.max(4096) as usize;
+        let block_size = config.block_size.max(page_size).next_power_of_two();
+        let frame_size = config.frame_size.clamp(128, block_size) / 16 * 16;
+        let block_count = (ring_bytes / block_size).max(2);
+
+        // Protocol 0 receives nothing until bind
+        let fd = unsafe { libc::socket(libc::AF_PACKET, libc::SOCK_RAW | libc::SOCK_CLOEXEC, 0) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut ring = AfPacketRing {
+            fd,
+            map: ptr::null_mut(),
+            map_len: block_size * block_count,
+            block_size,
+            block_count,
+            current_block: 0,
+            statistics: RingStatistics::default(),
+        };
+
+        ring.set_option(libc::SOL_PACKET, PACKET_VERSION, &TPACKET_V3)?;
+
+        let program = libc::sock_fprog {
+            len: filter.instructions.len() as u16,
+            filter: filter.instructions.as_ptr() as *mut libc::sock_filter,
+        };
+        ring.set_option(libc::SOL_SOCKET, SO_ATTACH_FILTER, &program)?;
+
+        let request = TpacketReq3 {
+            tp_block_size: block_size as c_uint,
+            tp_block_nr: block_count as c_uint,
+            tp_frame_size: frame_size as c_uint,
+            tp_frame_nr: ((block_size / frame_size) * block_count) as c_uint,
+            tp_retire_blk_tov: config.block_timeout,
+            tp_sizeof_priv: 0,
+            tp_feature_req_word: 0,
+        };
+        ring.set_option(libc::SOL_PACKET, PACKET_RX_RING, &request)?;
+
+        let map = unsafe {
+            libc::mmap(ptr::null_mut(), ring.map_len, libc::PROT_READ | libc::PROT_WRITE, libc::MAP_SHARED, fd, 0)
+        };
+        if map == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        ring.map = map as *mut u8;
+
+        let mut address: libc::sockaddr_ll = unsafe { mem::zeroed() };
+        address.sll_family = libc::AF_PACKET as u16;
+        address.sll_protocol = ETH_P_ALL.to_be();
+        address.sll_ifindex = ifindex;
+        let bound = unsafe {
+            libc::bind(
+                fd,
+                &address as *const libc::sockaddr_ll as *const libc::sockaddr,
+                mem::size_of::<libc::sockaddr_ll>() as libc::socklen_t,
+            )
+        };
+        if bound < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        if promiscuous {
+            let membership = PacketMreq {
+                mr_ifindex: ifindex,
+                mr_type: PACKET_MR_PROMISC,
+                mr_alen: 0,
+                mr_address: [0; 8],
+            };
+            ring.set_option(libc::SOL_PACKET, PACKET_ADD_MEMBERSHIP, &membership)?;
+        }
+
+        // Hashing keeps each flow on one worker, so per-flow order is preserved
+        if let Some(group) = fanout_group {
+            let fanout = group as c_uint | ((PACKET_FANOUT_HASH | PACKET_FANOUT_FLAG_DEFRAG) << 16);
+            ring.set_option(libc::SOL_PACKET, PACKET_FANOUT, &fanout)?;
+        }
+
+        Ok(ring)
+    }
+
+    fn set_option<T>(&self, level: c_int, name: c_int, value: &T) -> io::Result<()> {
+        let result = unsafe {
+            libc::setsockopt(self.fd, level, name, value as *const T as *const c_void, mem::size_of::<T>() as libc::socklen_t)
+        };
+        if result < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Waits up to `timeout` for the next retired block and hands each frame
+    /// in it to `handle`, along with the time the kernel received it as an
+    /// offset from the Unix epoch. Returns the number of frames seen.
+    pub fn next_block<F: FnMut(&[u8], Duration)>(&mut self, timeout: Duration, mut handle: F) -> io::Result<usize> {
+        let block = unsafe { self.map.add(self.current_block * self.block_size) } as *mut TpacketBlockDesc;
+
+        if !self.block_ready(block) {
+            let mut poll_fd = libc::pollfd { fd: self.fd, events: libc::POLLIN | libc::POLLERR, revents: 0 };
+            let result = unsafe { libc::poll(&mut poll_fd, 1, timeout.as_millis().min(c_int::MAX as u128) as c_int) };
+            if result < 0 {
+                let error = io::Error::last_os_error();
+                return if error.kind() == io::ErrorKind::Interrupted { Ok(0) } else { Err(error) };
+            }
+            if poll_fd.revents & (libc::POLLERR | libc::POLLNVAL) != 0 {
+                return Err(io::Error::new(io::ErrorKind::BrokenPipe, "capture socket error (interface down?)"));
+            }
+            if !self.block_ready(block) {
+                return Ok(0);
+            }
+        }
+        fence(Ordering::Acquire);
+
+        let block_start = block as *const u8;
+        let (packet_count, mut offset) = unsafe {
+            ((*block).hdr.num_pkts as usize, (*block).hdr.offset_to_first_pkt as usize)
+        };
+
+        for _ in 0..packet_count {
+            if offset + mem::size_of::<Tpacket3Hdr>() > self.block_size {
+                break;
+            }
+            let header = unsafe { &*(block_start.add(offset) as *const Tpacket3Hdr) };
+            let frame_start = offset + header.tp_mac as usize;
+            let frame_len = header.tp_snaplen as usize;
+            if frame_start + frame_len <= self.block_size {
+                let received = Duration::new(header.tp_sec as u64, header.tp_nsec);
+                handle(unsafe { std::slice::from_raw_parts(block_start.add(frame_start), frame_len) }, received);
+            }
+            if header.tp_next_offset == 0 {
+                break;
+            }
+            offset += header.tp_next_offset as usize;
+        }
+
+        // Hand the block back to the kernel
+        fence(Ordering::Release);
+        unsafe { ptr::write_volatile(&mut (*block).hdr.block_status, TP_STATUS_KERNEL) };
+        self.current_block = (self.current_block + 1) % self.block_count;
+
+        Ok(packet_count)
+    }
+
+    fn block_ready(&self, block: *mut TpacketBlockDesc) -> bool {
+        unsafe { ptr::read_volatile(&(*block).hdr.block_status) & TP_STATUS_USER != 0 }
+    }
+
+    pub fn statistics(&mut self) -> io::Result<RingStatistics> {
+        let mut stats = TpacketStatsV3::default();
+        let mut len = mem::size_of::<TpacketStatsV3>() as libc::socklen_t;
+        let result = unsafe {
+            libc::getsockopt(self.fd, libc::SOL_PACKET, PACKET_STATISTICS, &mut stats as *mut TpacketStatsV3 as *mut c_void, &mut len)
+        };
+        if result < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        self.statistics.packets += stats.tp_packets as u64;
+        self.statistics.drops += stats.tp_drops as u64;
+        self.statistics.freeze_count += stats.tp_freeze_q_cnt as u64;
+        Ok(self.statistics)
+    }
+}
+
+impl Drop for AfPacketRing {
+    fn drop(&mut self) {
+        unsafe {
+            if !self.map.is_null() {
+                libc::munmap(self.map as *mut c_void, self.map_len);
+            }
+            libc::close(self.fd);
+        }
+    }
+}
+
+fn interface_index(interface: &str) -> io::Result<c_int> {
+    let name = CString::new(interface).map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "invalid interface name"))?;
+    match unsafe { libc::if_nametoindex(name.as_ptr()) } {
+        0 => Err(io::Error::last_os_error()),
+        index => Ok(index as c_int),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::{Command, Stdio};
+
+    fn jump_targets_in_range(program: &BpfProgram) -> bool {
+        let len = program.instructions.len();
+        program.instructions.iter().enumerate().all(|(index, instruction)| {
+            instruction.code & 0x07 != BPF_JMP
+                || (index + 1 + instruction.jt as usize) < len && (index + 1 + instruction.jf as usize) < len
+        })
+    }
+
+    #[test]
+    fn programs_end_in_reject_then_accept() {
+        for program in [
+            BpfProgram::tcp_control_segments(false),
+            BpfProgram::tcp_control_segments(true),
+            BpfProgram::udp_and_icmp(true),
+            BpfProgram::transport_ports(&[5060, 5061], Some((10000, 20000)), false).unwrap(),
+            BpfProgram::transport_ports(&[5060, 5061], Some((10000, 20000)), true).unwrap(),
+            BpfProgram::transport_ports(&[5060], None, false).unwrap(),
+        ] {
+            let last = &program.instructions[program.instructions.len() - 2..];
+            assert_eq!((last[0].code, last[0].k), (BPF_RET | BPF_K, 0));
+            assert_eq!((last[1].code, last[1].k), (BPF_RET | BPF_K, SNAPLEN));
+            assert!(jump_targets_in_range(&program));
+        }
+    }
+
+    #[test]
+    fn oversized_port_list_is_rejected() {
+        let ports: Vec<u16> = (1..=200).collect();
+        assert!(BpfProgram::transport_ports(&ports, None, false).is_err());
+    }
+
+    fn ip(args: &[&str]) -> bool {
+        Command::new("ip").args(args).stderr(Stdio::null()).status().is_ok_and(|status| status.success())
+    }
+
+    fn ethernet_ipv4_tcp(flags: u8, dest_port: u16) -> Vec<u8> {
+        let mut frame = vec![0u8; 54];
+        frame[0..6].copy_from_slice(&[0xff; 6]);
+        frame[6..12].copy_from_slice(&[0x02, 0, 0, 0, 0, 1]);
+        frame[12..14].copy_from_slice(&0x0800u16.to_be_bytes());
+        frame[14] = 0x45;
+        frame[16..18].copy_from_slice(&40u16.to_be_bytes());
+        frame[22] = 64;
+        frame[23] = IPPROTO_TCP as u8;
+        frame[26..30].copy_from_slice(&[192, 0, 2, 1]);
+        frame[30..34].copy_from_slice(&[192, 0, 2, 2]);
+        frame[34..36].copy_from_slice(&40000u16.to_be_bytes());
+        frame[36..38].copy_from_slice(&dest_port.to_be_bytes());
+        frame[46] = 0x50;
+        frame[47] = flags;
+        frame
+    }
+
+    fn ethernet_ipv6_tcp(flags: u8, dest_port: u16) -> Vec<u8> {
+        let mut frame = vec![0u8; 74];
+        frame[0..6].copy_from_slice(&[0xff; 6]);
+        frame[6..12].copy_from_slice(&[0x02, 0, 0, 0, 0, 1]);
+        frame[12..14].copy_from_slice(&0x86ddu16.to_be_bytes());
+        frame[14] = 0x60;
+        frame[18..20].copy_from_slice(&20u16.to_be_bytes());
+        frame[20] = IPPROTO_TCP as u8;
+        frame[21] = 64;
+        frame[22..26].copy_from_slice(&[0x20, 0x01, 0x0d, 0xb8]);
+        frame[37] = 1;
+        frame[38..42].copy_from_slice(&[0x20, 0x01, 0x0d, 0xb8]);
+        frame[53] = 2;
+        frame[54..56].copy_from_slice(&40000u16.to_be_bytes());
+        frame[56..58].copy_from_slice(&dest_port.to_be_bytes());
+        frame[66] = 0x50;
+        frame[67] = flags;
+        frame
+    }
+
+    /// Needs root: `cargo test -- --ignored afpacket`
+    #[test]
+    #[ignore]
+    fn ring_receives_filtered_frames_over_veth() {
+        let (near, far) = ("astra-t0", "astra-t1");
+        let _ = ip(&["link", "del", near]);
+        assert!(ip(&["link", "add", near, "type", "veth", "peer", "name", far]));
+        assert!(ip(&["link", "set", near, "up"]) && ip(&["link", "set", far, "up"]));
+
+        let config = AfPacketConfig::default();
+        let mut ring = AfPacketRing::open(far, &config, 4 << 20, false, &BpfProgram::tcp_control_segments(true), Some(allocate_fanout_group())).unwrap();
+
+        let sender = unsafe { libc::socket(libc::AF_PACKET, libc::SOCK_RAW, 0) };
+        assert!(sender >= 0);
+        let mut address: libc::sockaddr_ll = unsafe { mem::zeroed() };
+        address.sll_family = libc::AF_PACKET as u16;
+        address.sll_ifindex = interface_index(near).unwrap();
+        address.sll_halen = 6;
+        let frames = [0x02, 0x10, 0x10, 0x04].into_iter().map(|flags| ethernet_ipv4_tcp(flags, 22))
+            .chain([0x10, 0x02].into_iter().map(|flags| ethernet_ipv6_tcp(flags, 22)));
+        for frame in frames {
+            let sent = unsafe {
+                libc::sendto(
+                    sender,
+                    frame.as_ptr() as *const c_void,
+                    frame.len(),
+                    0,
+                    &address as *const libc::sockaddr_ll as *const libc::sockaddr,
+                    mem::size_of::<libc::sockaddr_ll>() as libc::socklen_t,
+                )
+            };
+            assert_eq!(sent, frame.len() as isize);
+        }
+
+        let mut flags_seen = Vec::new();
+        for _ in 0..20 {
+            ring.next_block(Duration::from_millis(100), |frame, _| {
+                // Skip the link's own IPv6 multicast listener reports
+                let (source_port, flags) = if frame[12..14] == [0x86, 0xdd] { (&frame[54..56], frame[67]) } else { (&frame[34..36], frame[47]) };
+                if source_port == 40000u16.to_be_bytes() {
+                    flags_seen.push(flags);
+                }
+            }).unwrap();
+            if flags_seen.len() >= 3 {
+                break;
+            }
+        }
+
+        unsafe { libc::close(sender) };
+        let _ = ip(&["link", "del", near]);
+
+        // Bare ACKs are filtered in the kernel
+        assert_eq!(flags_seen, vec![0x02, 0x04, 0x02]);
+        assert_eq!(ring.statistics().unwrap().drops, 0);
+    }
+}
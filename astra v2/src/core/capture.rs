@@ -0,0 +1,637 @@
+use std::collections::HashMap;
+use std::io;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{sync_channel, SyncSender};
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+use chrono::{DateTime, TimeZone, Utc};
+use pnet::datalink::{self, Channel::Ethernet};
+use pnet::packet::ethernet::{EtherTypes, EthernetPacket};
+use pnet::packet::icmp::IcmpPacket;
+use pnet::packet::icmpv6::Icmpv6Packet;
+use pnet::packet::ip::{IpNextHeaderProtocol, IpNextHeaderProtocols};
+use pnet::packet::ipv4::Ipv4Packet;
+use pnet::packet::ipv6::Ipv6Packet;
+use pnet::packet::tcp::TcpPacket;
+use pnet::packet::udp::UdpPacket;
+use pnet::packet::Packet;
+use tokio::sync::mpsc;
+
+use crate::core::afpacket::{allocate_fanout_group, AfPacketRing, BpfProgram};
+use crate::core::{config::Config, evidence::EvidenceRecorder, logger::Logger};
+
+const IPV6_HEADER_LEN: usize = 40;
+// Bound on the extension header chain walked before giving up on a packet
+const MAX_EXTENSION_HEADERS: usize = 8;
+
+/// Transport header fields the analyzers care about. ICMPv6 is reported
+/// as `Icmp` with its own type numbers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransportHeader {
+    Tcp { source_port: u16, dest_port: u16, sequence: u32, flags: u16 },
+    Udp { source_port: u16, dest_port: u16 },
+    Icmp { icmp_type: u8, code: u8 },
+    Other(u8),
+}
+
+/// One captured IP packet, parsed once on the capture thread and shared by
+/// every analyzer that subscribed to it. The whole IP packet is kept so it
+/// can be written out as evidence.
+#[derive(Debug, Clone)]
+pub struct PacketSummary {
+    pub interface: Arc<str>,
+    pub captured_at: Instant,
+    pub timestamp: DateTime<Utc>,
+    pub source_ip: IpAddr,
+    pub dest_ip: IpAddr,
+    pub transport: TransportHeader,
+    pub packet: Vec<u8>,
+    payload_offset: usize,
+}
+
+impl PacketSummary {
+    pub fn from_ethernet(interface: &Arc<str>, frame: &[u8], captured_at: Instant, timestamp: DateTime<Utc>) -> Option<Self> {
+        let ethernet = EthernetPacket::new(frame)?;
+        match ethernet.get_ethertype() {
+            EtherTypes::Ipv4 => Self::from_ipv4(interface, ethernet.payload(), captured_at, timestamp),
+            EtherTypes::Ipv6 => Self::from_ipv6(interface, ethernet.payload(), captured_at, timestamp),
+            _ => None,
+        }
+    }
+
+    /// A bare IP packet of either version.
+    pub fn from_ip(interface: &Arc<str>, packet: &[u8], captured_at: Instant, timestamp: DateTime<Utc>) -> Option<Self> {
+        match packet.first()? >> 4 {
+            4 => Self::from_ipv4(interface, packet, captured_at, timestamp),
+            6 => Self::from_ipv6(interface, packet, captured_at, timestamp),
+            _ => None,
+        }
+    }
+
+    pub fn from_ipv4(interface: &Arc<str>, packet: &[u8], captured_at: Instant, timestamp: DateTime<Utc>) -> Option<Self> {
+        let ipv4 = Ipv4Packet::new(packet)?;
+        let header_len = ipv4.get_header_length() as usize * 4;
+        let (transport, transport_header_len) = parse_transport(ipv4.get_next_level_protocol(), ipv4.payload())?;
+        // Link-layer padding past the IP total length is not part of the packet
+        let packet = packet[..(header_len + ipv4.payload().len()).min(packet.len())].to_vec();
+
+        Some(PacketSummary {
+            interface: interface.clone(),
+            captured_at,
+            timestamp,
+            source_ip: IpAddr::V4(ipv4.get_source()),
+            dest_ip: IpAddr::V4(ipv4.get_destination()),
+            transport,
+            payload_offset: (header_len + transport_header_len).min(packet.len()),
+            packet,
+        })
+    }
+
+    /// Walks the extension header chain to the transport header. Fragments
+    /// after the first carry no transport header and are reported as
+    /// `Other` with the fragment header's protocol number.
+    pub fn from_ipv6(interface: &Arc<str>, packet: &[u8], captured_at: Instant, timestamp: DateTime<Utc>) -> Option<Self> {
+        let ipv6 = Ipv6Packet::new(packet)?;
+        // A zero payload length means a jumbogram; trust the capture length
+        let packet = match ipv6.get_payload_length() as usize {
+            0 => packet,
+            payload_len => &packet[..(IPV6_HEADER_LEN + payload_len).min(packet.len())],
+        };
+        let (protocol, transport_offset) = skip_extension_headers(ipv6.get_next_header(), packet)?;
+        let (transport, transport_header_len) = parse_transport(protocol, packet.get(transport_offset..)?)?;
+
+        Some(PacketSummary {
+            interface: interface.clone(),
+            captured_at,
+            timestamp,
+            source_ip: IpAddr::V6(ipv6.get_source()),
+            dest_ip: IpAddr::V6(ipv6.get_destination()),
+            transport,
+            payload_offset: transport_offset + transport_header_len,
+            packet: packet.to_vec(),
+        })
+    }
+
+    /// Transport payload (everything after the TCP/UDP/ICMP header).
+    pub fn payload(&self) -> &[u8] {
+        &self.packet[self.payload_offset..]
+    }
+}
+
+/// Returns the protocol of the first non-extension header and its offset
+/// from the start of the IPv6 packet.
+fn skip_extension_headers(mut next_header: IpNextHeaderProtocol, packet: &[u8]) -> Option<(IpNextHeaderProtocol, usize)> {
+    let mut offset = IPV6_HEADER_LEN;
+    for _ in 0..MAX_EXTENSION_HEADERS {
+        match next_header {
+            IpNextHeaderProtocols::Hopopt | IpNextHeaderProtocols::Ipv6Route | IpNextHeaderProtocols::Ipv6Opts => {
+                let header = packet.get(offset..offset + 2)?;
+                next_header = IpNextHeaderProtocol(header[0]);
+                offset += (header[1] as usize + 1) * 8;
+            }
+            IpNextHeaderProtocols::Ipv6Frag => {
+                let header = packet.get(offset..offset + 8)?;
+                let fragment_offset = u16::from_be_bytes([header[2], header[3]]) >> 3;
+                offset += 8;
+                if fragment_offset != 0 {
+                    return Some((IpNextHeaderProtocols::Ipv6Frag, offset));
+                }
+                next_header = IpNextHeaderProtocol(header[0]);
+            }
+            IpNextHeaderProtocols::Ah => {
+                let header = packet.get(offset..offset + 2)?;
+                next_header = IpNextHeaderProtocol(header[0]);
+                offset += (header[1] as usize + 2) * 4;
+            }
+            protocol => return Some((protocol, offset)),
+        }
+    }
+    None
+}
+
+/// Returns the parsed header and its length. Truncated transport headers
+/// are dropped rather than guessed at.
+fn parse_transport(protocol: IpNextHeaderProtocol, data: &[u8]) -> Option<(TransportHeader, usize)> {
+    match protocol {
+        IpNextHeaderProtocols::Tcp => {
+            let tcp = TcpPacket::new(data)?;
+            let header = TransportHeader::Tcp {
+                source_port: tcp.get_source(),
+                dest_port: tcp.get_destination(),
+                sequence: tcp.get_sequence(),
+                flags: tcp.get_flags(),
+            };
+            Some((header, data.len() - tcp.payload().len()))
+        }
+        IpNextHeaderProtocols::Udp => {
+            let udp = UdpPacket::new(data)?;
+            let header = TransportHeader::Udp {
+                source_port: udp.get_source(),
+                dest_port: udp.get_destination(),
+            };
+            Some((header, data.len() - udp.payload().len()))
+        }
+        IpNextHeaderProtocols::Icmp => {
+            let icmp = IcmpPacket::new(data)?;
+            let header = TransportHeader::Icmp {
+                icmp_type: icmp.get_icmp_type().0,
+                code: icmp.get_icmp_code().0,
+            };
+            Some((header, data.len() - icmp.payload().len()))
+        }
+        IpNextHeaderProtocols::Icmpv6 => {
+            let icmp = Icmpv6Packet::new(data)?;
+            let header = TransportHeader::Icmp {
+                icmp_type: icmp.get_icmpv6_type().0,
+                code: icmp.get_icmpv6_code().0,
+            };
+            Some((header, data.len() - icmp.payload().len()))
+        }
+        other => Some((TransportHeader::Other(other.0), 0)),
+    }
+}
+
+pub type PacketFilter = Box<dyn Fn(&PacketSummary) -> bool + Send + Sync>;
+
+// An AF_PACKET ring with the analyzer it feeds, under its worker label
+type AnalyzerRing = (String, Arc<Subscriber>, AfPacketRing);
+
+/// An analyzer's bounded queue. When it is full the packet is dropped for
+/// that analyzer only, so one slow consumer cannot stall capture.
+struct Subscriber {
+    name: String,
+    filter: PacketFilter,
+    kernel_filter: BpfProgram,
+    sender: mpsc::Sender<Arc<PacketSummary>>,
+    delivered: AtomicU64,
+    dropped: AtomicU64,
+}
+
+impl Subscriber {
+    /// Returns false when the packet had to be dropped.
+    fn deliver(&self, summary: &Arc<PacketSummary>) -> bool {
+        if !(self.filter)(summary) {
+            return true;
+        }
+        match self.sender.try_send(summary.clone()) {
+            Ok(()) => {
+                self.delivered.fetch_add(1, Ordering::Relaxed);
+                true
+            }
+            Err(_) => {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+                false
+            }
+        }
+    }
+}
+
+/// Feeds the evidence recorder once per captured frame, before any analyzer
+/// sees it, so the packet that gets a source blocked is in its incident.
+/// With AF_PACKET every analyzer has rings of its own and the same frame
+/// arrives on each, so a ring only records frames that no analyzer
+/// subscribed before its own would have taken.
+struct EvidenceTap {
+    queue: SyncSender<Arc<PacketSummary>>,
+    dropped: Arc<AtomicU64>,
+    earlier: Vec<Arc<Subscriber>>,
+}
+
+impl EvidenceTap {
+    // Never waits: a frame the writer has no room for is counted and lost
+    fn record(&self, summary: &Arc<PacketSummary>) {
+        if self.earlier.iter().any(|subscriber| (subscriber.filter)(summary)) {
+            return;
+        }
+        if self.queue.try_send(summary.clone()).is_err() {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// The capture side of the evidence writer thread. Only that thread takes
+/// the recorder lock for captured frames, so the capture threads never wait
+/// on it or on the pcap writes of an open incident.
+struct EvidenceQueue {
+    sender: SyncSender<Arc<PacketSummary>>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl EvidenceQueue {
+    fn spawn(config: &Config, logger: Arc<Logger>, recorder: Arc<Mutex<EvidenceRecorder>>) -> Result<Self, Box<dyn std::error::Error>> {
+        let (sender, receiver) = sync_channel::<Arc<PacketSummary>>(config.network.capture_queue_size.max(1));
+        // Runs until the last capture thread holding a sender is gone
+        thread::Builder::new()
+            .name("astra-evidence".to_string())
+            .spawn(move || {
+                for summary in receiver {
+                    if let Err(e) = recorder.lock().unwrap().record(&summary) {
+                        let _ = logger.log_error(&format!("Evidence capture error: {}", e));
+                    }
+                }
+            })?;
+        Ok(EvidenceQueue { sender, dropped: Arc::new(AtomicU64::new(0)) })
+    }
+}
+
+#[derive(Debug, Default)]
+struct WorkerStats {
+    received: AtomicU64,
+    dropped: AtomicU64,
+    kernel_drops: AtomicU64,
+    read_errors: AtomicU64,
+}
+
+struct CaptureWorker {
+    label: String,
+    stop: Arc<AtomicBool>,
+    stats: Arc<WorkerStats>,
+    handle: thread::JoinHandle<()>,
+}
+
+/// Long-lived capture threads for every monitored interface: a fanout group
+/// of AF_PACKET rings per analyzer, or one pnet channel per interface when
+/// rings are disabled or unavailable. Interfaces are reconciled against
+/// `Config::get_interface_list` on every `refresh`, so interfaces that
+/// appear are picked up and ones that vanish are released.
+pub struct CaptureManager {
+    config: Arc<Config>,
+    logger: Arc<Logger>,
+    subscribers: Arc<RwLock<Vec<Arc<Subscriber>>>>,
+    evidence: Option<EvidenceQueue>,
+    workers: HashMap<String, Vec<CaptureWorker>>,
+    // Counters of workers that have exited are kept so totals never go backwards
+    retired_stats: HashMap<String, (u64, u64, u64)>,
+}
+
+impl CaptureManager {
+    const STATS_INTERVAL: Duration = Duration::from_secs(1);
+
+    pub fn new(config: &Arc<Config>, logger: Arc<Logger>, evidence: &Arc<Mutex<EvidenceRecorder>>) -> Result<Self, Box<dyn std::error::Error>> {
+        let evidence = if config.evidence.enabled {
+            Some(EvidenceQueue::spawn(config, logger.clone(), evidence.clone())?)
+        } else {
+            None
+        };
+        Ok(CaptureManager {
+            config: config.clone(),
+            logger,
+            subscribers: Arc::new(RwLock::new(Vec::new())),
+            evidence,
+            workers: HashMap::new(),
+            retired_stats: HashMap::new(),
+        })
+    }
+
+    /// Registers an analyzer. `kernel_filter` runs in the kernel on the
+    /// analyzer's AF_PACKET rings; `filter` is applied in userspace on every
+    /// backend, so the two must agree.
+    pub fn subscribe(&self, name: &str, filter: PacketFilter, kernel_filter: BpfProgram) -> mpsc::Receiver<Arc<PacketSummary>> {
+        let (sender, receiver) = mpsc::channel(self.config.network.capture_queue_size.max(1));
+        self.subscribers.write().unwrap().push(Arc::new(Subscriber {
+            name: name.to_string(),
+            filter,
+            kernel_filter,
+            sender,
+            delivered: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+        }));
+        receiver
+    }
+
+    pub fn refresh(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        let available: HashMap<String, datalink::NetworkInterface> = datalink::interfaces()
+            .into_iter()
+            .filter(|iface| iface.is_up())
+            .map(|iface| (iface.name.clone(), iface))
+            .collect();
+        let wanted: Vec<String> = self.config.get_interface_list()
+            .into_iter()
+            .filter(|name| available.contains_key(name))
+            .collect();
+
+        let released: Vec<String> = self.workers.iter()
+            .filter(|(name, workers)| !wanted.contains(name) || workers.iter().any(|worker| worker.handle.is_finished()))
+            .map(|(name, _)| name.clone())
+            .collect();
+        for name in released {
+            if let Some(workers) = self.workers.remove(&name) {
+                self.retire(workers);
+                self.logger.log_info(&format!("Packet capture released interface {}", name))?;
+            }
+        }
+
+        for name in wanted {
+            if self.workers.contains_key(&name) {
+                continue;
+            }
+            let workers = self.spawn_interface_workers(&available[&name])?;
+            self.workers.insert(name, workers);
+        }
+
+        Ok(())
+    }
+
+    fn retire(&mut self, workers: Vec<CaptureWorker>) {
+        for worker in workers {
+            // The thread notices within one read timeout; no need to join
+            worker.stop.store(true, Ordering::Relaxed);
+            let retired = self.retired_stats.entry(worker.label).or_default();
+            retired.0 += worker.stats.received.load(Ordering::Relaxed);
+            retired.1 += worker.stats.dropped.load(Ordering::Relaxed);
+            retired.2 += worker.stats.kernel_drops.load(Ordering::Relaxed);
+        }
+    }
+
+    fn spawn_interface_workers(&self, interface: &datalink::NetworkInterface) -> Result<Vec<CaptureWorker>, Box<dyn std::error::Error>> {
+        if self.config.network.af_packet.enabled {
+            match self.open_rings(&interface.name) {
+                Ok(rings) => {
+                    self.logger.log_info(&format!(
+                        "Packet capture attached to interface {} ({} AF_PACKET rings)", interface.name, rings.len()
+                    ))?;
+                    return rings.into_iter()
+                        .map(|(label, subscriber, ring)| self.spawn_ring_worker(label, subscriber, ring, &interface.name))
+                        .collect();
+                }
+                Err(e) => {
+                    self.logger.log_warning(&format!(
+                        "AF_PACKET capture unavailable on {} ({}), falling back to pnet", interface.name, e
+                    ))?;
+                }
+            }
+        }
+
+        let worker = self.spawn_pnet_worker(interface.clone())?;
+        self.logger.log_info(&format!("Packet capture attached to interface {}", interface.name))?;
+        Ok(vec![worker])
+    }
+
+    /// One fanout group per analyzer, each socket carrying that analyzer's
+    /// kernel filter. Either every ring opens or none are kept.
+    fn open_rings(&self, interface: &str) -> Result<Vec<AnalyzerRing>, Box<dyn std::error::Error>> {
+        let network = &self.config.network;
+        let ring_bytes = network.capture_buffer_bytes();
+        let worker_count = network.af_packet.fanout_workers.max(1);
+        let mut rings = Vec::new();
+
+        for subscriber in self.subscribers.read().unwrap().iter() {
+            let group = allocate_fanout_group();
+            for index in 0..worker_count {
+                let ring = AfPacketRing::open(
+                    interface,
+                    &network.af_packet,
+                    ring_bytes,
+                    network.promiscuous_mode,
+                    &subscriber.kernel_filter,
+                    Some(group),
+                )?;
+                rings.push((format!("{}_{}_ring{}", interface, subscriber.name, index), subscriber.clone(), ring));
+            }
+        }
+
+        Ok(rings)
+    }
+
+    fn spawn_ring_worker(&self, label: String, subscriber: Arc<Subscriber>, mut ring: AfPacketRing, interface: &str) -> Result<CaptureWorker, Box<dyn std::error::Error>> {
+        let evidence = self.evidence_tap(|subscribers| {
+            subscribers.iter().take_while(|earlier| !Arc::ptr_eq(earlier, &subscriber)).cloned().collect()
+        });
+        let stop = Arc::new(AtomicBool::new(false));
+        let stats = Arc::new(WorkerStats::default());
+        let logger = self.logger.clone();
+        let poll_timeout = Duration::from_millis(self.config.network.packet_timeout.max(1));
+        let name: Arc<str> = Arc::from(interface);
+        let worker_stop = stop.clone();
+        let worker_stats = stats.clone();
+        let worker_label = label.clone();
+
+        let handle = thread::Builder::new()
+            .name(format!("astra-{}", label))
+            .spawn(move || {
+                if let Err(e) = ring_loop(&mut ring, &name, poll_timeout, &worker_stop, &worker_stats, &subscriber, evidence.as_ref()) {
+                    worker_stats.read_errors.fetch_add(1, Ordering::Relaxed);
+                    let _ = logger.log_error(&format!("Packet capture {} stopped: {}", worker_label, e));
+                }
+            })?;
+
+        Ok(CaptureWorker { label, stop, stats, handle })
+    }
+
+    fn spawn_pnet_worker(&self, interface: datalink::NetworkInterface) -> Result<CaptureWorker, Box<dyn std::error::Error>> {
+        let channel_config = datalink::Config {
+            read_buffer_size: self.config.network.capture_buffer_bytes(),
+            read_timeout: Some(Duration::from_millis(self.config.network.packet_timeout.max(1))),
+            promiscuous: self.config.network.promiscuous_mode,
+            ..Default::default()
+        };
+
+        let label = interface.name.clone();
+        let stop = Arc::new(AtomicBool::new(false));
+        let stats = Arc::new(WorkerStats::default());
+        let subscribers = self.subscribers.clone();
+        // One pnet channel feeds every analyzer, so each frame is seen once here
+        let evidence = self.evidence_tap(|_| Vec::new());
+        let logger = self.logger.clone();
+        let ipv6 = self.config.network.ipv6_support;
+        let worker_stop = stop.clone();
+        let worker_stats = stats.clone();
+
+        let handle = thread::Builder::new()
+            .name(format!("astra-capture-{}", interface.name))
+            .spawn(move || {
+                if let Err(e) = pnet_loop(&interface, channel_config, ipv6, &worker_stop, &worker_stats, &subscribers, evidence.as_ref()) {
+                    worker_stats.read_errors.fetch_add(1, Ordering::Relaxed);
+                    let _ = logger.log_error(&format!("Packet capture on {} stopped: {}", interface.name, e));
+                }
+            })?;
+
+        Ok(CaptureWorker { label, stop, stats, handle })
+    }
+
+    fn evidence_tap(&self, earlier: impl FnOnce(&[Arc<Subscriber>]) -> Vec<Arc<Subscriber>>) -> Option<EvidenceTap> {
+        let queue = self.evidence.as_ref()?;
+        Some(EvidenceTap {
+            queue: queue.sender.clone(),
+            dropped: queue.dropped.clone(),
+            earlier: earlier(&self.subscribers.read().unwrap()),
+        })
+    }
+
+    /// Counters keyed by worker label: `<iface>` for pnet workers and
+    /// `<iface>_<analyzer>_ring<n>` for AF_PACKET rings.
+    pub fn get_capture_statistics(&self) -> HashMap<String, u64> {
+        let mut stats = HashMap::new();
+
+        for (label, (received, dropped, kernel_drops)) in &self.retired_stats {
+            stats.insert(format!("{}_received", label), *received);
+            stats.insert(format!("{}_dropped", label), *dropped);
+            stats.insert(format!("{}_kernel_drops", label), *kernel_drops);
+        }
+        let (mut total_dropped, mut total_kernel_drops) = (0, 0);
+        for worker in self.workers.values().flatten() {
+            let dropped = worker.stats.dropped.load(Ordering::Relaxed);
+            let kernel_drops = worker.stats.kernel_drops.load(Ordering::Relaxed);
+            total_dropped += dropped;
+            total_kernel_drops += kernel_drops;
+            *stats.entry(format!("{}_received", worker.label)).or_insert(0) += worker.stats.received.load(Ordering::Relaxed);
+            *stats.entry(format!("{}_dropped", worker.label)).or_insert(0) += dropped;
+            *stats.entry(format!("{}_kernel_drops", worker.label)).or_insert(0) += kernel_drops;
+            stats.insert(format!("{}_read_errors", worker.label), worker.stats.read_errors.load(Ordering::Relaxed));
+        }
+        for subscriber in self.subscribers.read().unwrap().iter() {
+            stats.insert(format!("{}_delivered", subscriber.name), subscriber.delivered.load(Ordering::Relaxed));
+            stats.insert(format!("{}_dropped", subscriber.name), subscriber.dropped.load(Ordering::Relaxed));
+        }
+        stats.insert("capture_interfaces".to_string(), self.workers.len() as u64);
+        stats.insert("capture_workers".to_string(), self.workers.values().map(Vec::len).sum::<usize>() as u64);
+        stats.insert("capture_active_dropped".to_string(), total_dropped);
+        stats.insert("capture_active_kernel_drops".to_string(), total_kernel_drops);
+        if let Some(evidence) = &self.evidence {
+            stats.insert("evidence_dropped".to_string(), evidence.dropped.load(Ordering::Relaxed));
+        }
+
+        stats
+    }
+
+    pub fn shutdown(&mut self) {
+        let workers: Vec<CaptureWorker> = self.workers.drain().flat_map(|(_, workers)| workers).collect();
+        self.retire(workers);
+    }
+}
+
+fn ring_loop(
+    ring: &mut AfPacketRing,
+    interface: &Arc<str>,
+    poll_timeout: Duration,
+    stop: &AtomicBool,
+    stats: &WorkerStats,
+    subscriber: &Subscriber,
+    evidence: Option<&EvidenceTap>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut last_stats = Instant::now();
+
+    while !stop.load(Ordering::Relaxed) {
+        // A block can sit for up to its retire timeout, so every frame keeps
+        // the kernel's receive time. Instants are placed relative to the
+        // moment the block was handed over.
+        let mut handed_over = None;
+        let frames = ring.next_block(poll_timeout, |frame, received| {
+            let (now, wall_clock) = *handed_over.get_or_insert_with(|| (Instant::now(), Utc::now()));
+            let timestamp = Utc.timestamp_opt(received.as_secs() as i64, received.subsec_nanos())
+                .single()
+                .unwrap_or(wall_clock);
+            let age = (wall_clock - timestamp).to_std().unwrap_or_default();
+            let captured_at = now.checked_sub(age).unwrap_or(now);
+            if let Some(summary) = PacketSummary::from_ethernet(interface, frame, captured_at, timestamp) {
+                let summary = Arc::new(summary);
+                if let Some(evidence) = evidence.filter(|_| (subscriber.filter)(&summary)) {
+                    evidence.record(&summary);
+                }
+                if !subscriber.deliver(&summary) {
+                    stats.dropped.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        })?;
+        stats.received.fetch_add(frames as u64, Ordering::Relaxed);
+
+        if last_stats.elapsed() >= CaptureManager::STATS_INTERVAL {
+            stats.kernel_drops.store(ring.statistics()?.drops, Ordering::Relaxed);
+            last_stats = Instant::now();
+        }
+    }
+
+    Ok(())
+}
+
+fn pnet_loop(
+    interface: &datalink::NetworkInterface,
+    channel_config: datalink::Config,
+    ipv6: bool,
+    stop: &AtomicBool,
+    stats: &WorkerStats,
+    subscribers: &RwLock<Vec<Arc<Subscriber>>>,
+    evidence: Option<&EvidenceTap>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut rx = match datalink::channel(interface, channel_config)? {
+        Ethernet(_, rx) => rx,
+        _ => return Err(format!("unsupported channel type on {}", interface.name).into()),
+    };
+    let name: Arc<str> = Arc::from(interface.name.as_str());
+
+    while !stop.load(Ordering::Relaxed) {
+        let frame = match rx.next() {
+            Ok(frame) => frame,
+            // Read timeout, only there so the stop flag gets checked
+            Err(e) if e.kind() == io::ErrorKind::TimedOut || e.kind() == io::ErrorKind::WouldBlock => continue,
+            // The interface went away; refresh picks it up again if it returns
+            Err(e) => return Err(e.into()),
+        };
+        stats.received.fetch_add(1, Ordering::Relaxed);
+
+        // AF_PACKET rings leave IPv6 out in the kernel filter instead
+        let summary = match PacketSummary::from_ethernet(&name, frame, Instant::now(), Utc::now()) {
+            Some(summary) if ipv6 || summary.source_ip.is_ipv4() => Arc::new(summary),
+            _ => continue,
+        };
+
+        let subscribers = subscribers.read().unwrap();
+        // Only what some analyzer takes is worth keeping as evidence
+        if let Some(evidence) = evidence {
+            if subscribers.iter().any(|subscriber| (subscriber.filter)(&summary)) {
+                evidence.record(&summary);
+            }
+        }
+        let mut dropped = false;
+        for subscriber in subscribers.iter() {
+            dropped |= !subscriber.deliver(&summary);
+        }
+        if dropped {
+            stats.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    Ok(())
+}
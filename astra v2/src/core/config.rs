@@ -11,6 +11,8 @@ pub struct Config {
     pub logging: LoggingConfig,
     pub modules: ModulesConfig,
     pub firewall: FirewallConfig,
+    #[serde(default)]
+    pub evidence: EvidenceConfig,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -20,6 +22,14 @@ pub struct SystemConfig {
     pub max_cpu_usage: f32,       // Percentage
     pub update_interval: u64,     // Seconds
     pub auto_restart: bool,
+    #[serde(default = "default_event_queue_size")]
+    pub event_queue_size: usize,  // Security events queued per bus subscriber before dropping
+    #[serde(default = "default_state_directory")]
+    pub state_directory: String,  // Threat profiles and blocks kept across restarts
+    #[serde(default = "default_state_flush_interval")]
+    pub state_flush_interval: u64, // Seconds
+    #[serde(default = "default_state_retention_days")]
+    pub state_retention_days: u64, // Idle, unblocked profiles are forgotten after this
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -27,9 +37,74 @@ pub struct NetworkConfig {
     pub interfaces: Vec<String>,
     pub monitor_all_interfaces: bool,
     pub promiscuous_mode: bool,
-    pub capture_buffer_size: usize,
+    pub capture_buffer_size: usize, // KiB, per AF_PACKET ring or pnet read buffer
     pub packet_timeout: u64,      // Milliseconds
     pub ipv6_support: bool,
+    #[serde(default = "default_netinfo_file")]
+    pub netinfo_file: String,     // cidr,asn,country CSV
+    #[serde(default = "default_capture_queue_size")]
+    pub capture_queue_size: usize, // Packets queued per analyzer before dropping
+    #[serde(default = "default_interface_refresh_interval")]
+    pub interface_refresh_interval: u64, // Seconds
+    #[serde(default)]
+    pub af_packet: AfPacketConfig,
+}
+
+impl NetworkConfig {
+    pub fn capture_buffer_bytes(&self) -> usize {
+        self.capture_buffer_size.saturating_mul(1024)
+    }
+}
+
+/// TPACKET_V3 capture rings. Each analyzer gets `fanout_workers` sockets per
+/// interface, each with a ring of `capture_buffer_size` KiB.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AfPacketConfig {
+    pub enabled: bool,
+    pub fanout_workers: usize,
+    pub block_size: usize,        // Bytes, rounded up to a power of two
+    pub frame_size: usize,        // Bytes
+    pub block_timeout: u32,       // Milliseconds before a partly filled block is retired
+}
+
+impl Default for AfPacketConfig {
+    fn default() -> Self {
+        AfPacketConfig {
+            enabled: true,
+            fanout_workers: 4,
+            block_size: 1 << 20,
+            frame_size: 2048,
+            block_timeout: 50,
+        }
+    }
+}
+
+fn default_netinfo_file() -> String {
+    "/etc/astra/netinfo.csv".to_string()
+}
+
+fn default_event_queue_size() -> usize {
+    1024
+}
+
+fn default_state_directory() -> String {
+    "/var/lib/astra".to_string()
+}
+
+fn default_state_flush_interval() -> u64 {
+    30
+}
+
+fn default_state_retention_days() -> u64 {
+    30
+}
+
+fn default_capture_queue_size() -> usize {
+    4096
+}
+
+fn default_interface_refresh_interval() -> u64 {
+    10
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -42,6 +117,12 @@ pub struct SecurityConfig {
     pub threat_intel_enabled: bool,
     pub whitelist_ips: Vec<String>,
     pub blacklist_ips: Vec<String>,
+    #[serde(default = "default_response_policy_file")]
+    pub response_policy_file: String, // Ordered response rules, see core::policy
+}
+
+fn default_response_policy_file() -> String {
+    "/etc/astra/response_policy.json".to_string()
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -59,6 +140,16 @@ pub struct LoggingConfig {
 pub struct ModulesConfig {
     pub tcp_guard: TcpGuardConfig,
     pub sip_shield: SipShieldConfig,
+    #[serde(default)]
+    pub sip_honeypot: SipHoneypotConfig,
+    #[serde(default)]
+    pub pbx_logs: PbxLogConfig,
+    #[serde(default)]
+    pub probe_guard: ProbeGuardConfig,
+    #[serde(default)]
+    pub scan_correlation: ScanCorrelationConfig,
+    #[serde(default)]
+    pub slow_scan: SlowScanConfig,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -81,6 +172,282 @@ pub struct SipShieldConfig {
     pub registration_monitoring: bool,
     pub invite_flood_threshold: u32,
     pub brute_force_threshold: u32,
+    #[serde(default = "default_brute_force_window")]
+    pub brute_force_window: u64,  // Seconds
+    #[serde(default)]
+    pub toll_fraud: TollFraudConfig,
+    #[serde(default = "default_fingerprint_file")]
+    pub fingerprint_file: String,
+    #[serde(default = "default_enumeration_threshold")]
+    pub enumeration_threshold: u32,
+    #[serde(default = "default_enumeration_window")]
+    pub enumeration_window: u64,  // Seconds
+    #[serde(default = "default_tls_ports")]
+    pub tls_ports: Vec<u16>,
+    #[serde(default = "default_tls_failure_threshold")]
+    pub tls_failure_threshold: u32,
+    #[serde(default)]
+    pub registration_hijack: RegistrationHijackConfig,
+    #[serde(default)]
+    pub media: MediaConfig,
+    #[serde(default)]
+    pub protocol: ProtocolAbuseConfig,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProtocolAbuseConfig {
+    pub enabled: bool,
+    pub anomaly_threshold: u32,
+    pub anomaly_window: u64,      // Seconds
+    pub max_header_length: usize,
+    pub max_headers: usize,
+    pub count_parse_failures: bool,
+    pub max_dialogs: usize,
+}
+
+impl Default for ProtocolAbuseConfig {
+    fn default() -> Self {
+        ProtocolAbuseConfig {
+            enabled: true,
+            anomaly_threshold: 5,
+            anomaly_window: 60,
+            max_header_length: 2048,
+            max_headers: 100,
+            count_parse_failures: true,
+            max_dialogs: 65536,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MediaConfig {
+    pub enabled: bool,
+    pub rtp_port_min: u16,
+    pub rtp_port_max: u16,
+    pub unsolicited_flood_threshold: u32,  // Packets per 10s into ports with no negotiated media
+    pub ended_dialog_grace: u64,  // Seconds
+}
+
+impl Default for MediaConfig {
+    fn default() -> Self {
+        MediaConfig {
+            enabled: true,
+            rtp_port_min: 10000,
+            rtp_port_max: 20000,
+            unsolicited_flood_threshold: 500,
+            ended_dialog_grace: 5,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RegistrationHijackConfig {
+    pub enabled: bool,
+    pub history_size: usize,      // Bindings remembered per AOR
+    pub max_concurrent_sources: u32,
+    pub concurrency_window: u64,  // Seconds
+}
+
+impl Default for RegistrationHijackConfig {
+    fn default() -> Self {
+        RegistrationHijackConfig {
+            enabled: true,
+            history_size: 16,
+            max_concurrent_sources: 3,
+            concurrency_window: 300,
+        }
+    }
+}
+
+fn default_enumeration_threshold() -> u32 {
+    10
+}
+
+fn default_enumeration_window() -> u64 {
+    120
+}
+
+fn default_tls_ports() -> Vec<u16> {
+    vec![5061]
+}
+
+fn default_tls_failure_threshold() -> u32 {
+    10
+}
+
+fn default_fingerprint_file() -> String {
+    "/etc/astra/sip_fingerprints.json".to_string()
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TollFraudConfig {
+    pub enabled: bool,
+    pub international_prefixes: Vec<String>,
+    pub premium_prefixes: Vec<String>,
+    pub burst_threshold: u32,
+    pub burst_window: u64,        // Seconds
+    pub max_parallel_calls: u32,
+    pub account_call_limits: HashMap<String, u32>,
+    pub baseline_min_calls: u32,
+    pub unusual_hour_ratio: f32,  // Share of an account's calls below which an hour is unusual
+}
+
+fn default_brute_force_window() -> u64 {
+    60
+}
+
+impl Default for TollFraudConfig {
+    fn default() -> Self {
+        TollFraudConfig {
+            enabled: true,
+            // Not "+": numbers in E.164 form include domestic ones
+            international_prefixes: vec!["00".to_string(), "011".to_string()],
+            premium_prefixes: vec![
+                "0900".to_string(), "1900".to_string(), "0906".to_string(), "0909".to_string(),
+                "00881".to_string(), "00882".to_string(), "00883".to_string(),
+                "+881".to_string(), "+882".to_string(), "+883".to_string(),
+            ],
+            burst_threshold: 5,
+            burst_window: 300,
+            max_parallel_calls: 4,
+            account_call_limits: HashMap::new(),
+            baseline_min_calls: 50,
+            unusual_hour_ratio: 0.02,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SipHoneypotConfig {
+    pub enabled: bool,
+    pub bind_address: String,     // A decoy IP must be assigned to a local interface
+    pub port: u16,
+    pub user_agent: String,
+    pub realm: String,
+    pub accept_registrations: bool,  // Answer authenticated REGISTERs with 200 OK to lure INVITEs
+    pub record_file: String,
+}
+
+impl Default for SipHoneypotConfig {
+    fn default() -> Self {
+        SipHoneypotConfig {
+            enabled: false,
+            bind_address: "0.0.0.0".to_string(),
+            port: 5070,
+            user_agent: "Asterisk PBX 16.28.0".to_string(),
+            realm: "asterisk".to_string(),
+            accept_registrations: true,
+            record_file: "/var/lib/astra/sip_honeypot.jsonl".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PbxLogConfig {
+    pub enabled: bool,
+    pub asterisk_security_logs: Vec<String>,
+    pub freeswitch_logs: Vec<String>,
+    pub poll_interval: u64,       // Milliseconds
+    pub failure_threshold: u32,
+    pub failure_window: u64,      // Seconds
+}
+
+impl Default for PbxLogConfig {
+    fn default() -> Self {
+        PbxLogConfig {
+            enabled: false,
+            asterisk_security_logs: vec!["/var/log/asterisk/security".to_string()],
+            freeswitch_logs: vec!["/var/log/freeswitch/freeswitch.log".to_string()],
+            poll_interval: 500,
+            failure_threshold: 5,
+            failure_window: 60,
+        }
+    }
+}
+
+/// UDP scan and ICMP sweep detection. UDP probes against the TCP Guardian
+/// stealth ports count towards service enumeration.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProbeGuardConfig {
+    pub enabled: bool,
+    pub udp_port_threshold: usize,    // Distinct UDP ports probed by one source
+    pub unreachable_threshold: usize, // Closed ports we answered with port unreachable
+    pub icmp_sweep_threshold: usize,  // Distinct hosts sent echo/timestamp/mask requests
+    pub time_window: u64,             // Seconds
+    // Addresses of this host besides the interface ones, e.g. the capture
+    // host's when replaying a pcap recorded elsewhere
+    #[serde(default)]
+    pub local_addresses: Vec<String>,
+}
+
+impl Default for ProbeGuardConfig {
+    fn default() -> Self {
+        ProbeGuardConfig {
+            enabled: true,
+            udp_port_threshold: 10,
+            unreachable_threshold: 5,
+            icmp_sweep_threshold: 8,
+            time_window: 60,
+            local_addresses: Vec::new(),
+        }
+    }
+}
+
+/// Distributed scans: many sources each probing a few of our ports. Ports
+/// probed by more than `min_sources` sources in the window are treated as
+/// busy services rather than sweep targets.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScanCorrelationConfig {
+    pub enabled: bool,
+    pub time_window: u64,             // Seconds
+    pub min_ports: usize,             // Distinct ports swept on one destination
+    pub min_sources: usize,           // Unrelated sources taking part
+    pub min_group_sources: usize,     // Sources from one /24 (/48) or ASN taking part
+    pub max_probes: usize,            // Probes remembered per destination
+}
+
+impl Default for ScanCorrelationConfig {
+    fn default() -> Self {
+        ScanCorrelationConfig {
+            enabled: true,
+            time_window: 300,
+            min_ports: 20,
+            min_sources: 10,
+            min_group_sources: 3,
+            max_probes: 20000,
+        }
+    }
+}
+
+/// Scans spread over hours or days, judged per source over each horizon.
+/// Longer horizons see more innocent port diversity, so they carry less
+/// confidence.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SlowScanConfig {
+    pub enabled: bool,
+    pub horizons: Vec<SlowScanHorizon>,
+    pub max_sources: usize,           // Sources tracked at once, least recently seen evicted
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SlowScanHorizon {
+    pub duration: u64,                // Seconds
+    pub port_threshold: usize,        // Distinct ports from one source within the horizon
+    pub confidence: f32,
+}
+
+impl Default for SlowScanConfig {
+    fn default() -> Self {
+        SlowScanConfig {
+            enabled: true,
+            horizons: vec![
+                SlowScanHorizon { duration: 3600, port_threshold: 15, confidence: 0.8 },
+                SlowScanHorizon { duration: 24 * 3600, port_threshold: 30, confidence: 0.65 },
+                SlowScanHorizon { duration: 7 * 24 * 3600, port_threshold: 60, confidence: 0.5 },
+            ],
+            max_sources: 20000,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -91,6 +458,44 @@ pub struct FirewallConfig {
     pub auto_rules: bool,
     pub backup_rules: bool,
     pub iptables_path: String,
+    #[serde(default = "default_ip6tables_path")]
+    pub ip6tables_path: String,     // Used when network.ipv6_support is on
+    #[serde(default = "default_ipv6_block_prefix")]
+    pub ipv6_block_prefix: u8,      // IPv6 sources are tracked and blocked per prefix
+}
+
+fn default_ip6tables_path() -> String {
+    "/sbin/ip6tables".to_string()
+}
+
+fn default_ipv6_block_prefix() -> u8 {
+    64
+}
+
+/// Per-incident pcap snippets written when a source gets blocked.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EvidenceConfig {
+    pub enabled: bool,
+    pub directory: String,
+    pub lead_packets: usize,       // Packets kept per source before an incident
+    pub lead_window: u64,          // Seconds of history kept per source
+    pub tail_packets: usize,       // Packets appended after the block decision
+    pub tail_seconds: u64,         // How long an incident keeps recording
+    pub max_buffer_size: usize,    // MB of packet history across all sources
+}
+
+impl Default for EvidenceConfig {
+    fn default() -> Self {
+        EvidenceConfig {
+            enabled: true,
+            directory: "/var/lib/astra/evidence".to_string(),
+            lead_packets: 200,
+            lead_window: 300,
+            tail_packets: 500,
+            tail_seconds: 30,
+            max_buffer_size: 64,
+        }
+    }
 }
 
 impl Default for Config {
@@ -102,6 +507,10 @@ impl Default for Config {
                 max_cpu_usage: 80.0,
                 update_interval: 1,
                 auto_restart: true,
+                event_queue_size: default_event_queue_size(),
+                state_directory: default_state_directory(),
+                state_flush_interval: default_state_flush_interval(),
+                state_retention_days: default_state_retention_days(),
             },
             network: NetworkConfig {
                 interfaces: vec!["eth0".to_string(), "wlan0".to_string()],
@@ -110,6 +519,10 @@ impl Default for Config {
                 capture_buffer_size: 8192,
                 packet_timeout: 100,
                 ipv6_support: false,
+                netinfo_file: default_netinfo_file(),
+                capture_queue_size: default_capture_queue_size(),
+                interface_refresh_interval: default_interface_refresh_interval(),
+                af_packet: AfPacketConfig::default(),
             },
             security: SecurityConfig {
                 default_sensitivity: 5,
@@ -126,6 +539,7 @@ impl Default for Config {
                     "172.16.0.0/12".to_string(),
                 ],
                 blacklist_ips: vec![],
+                response_policy_file: default_response_policy_file(),
             },
             logging: LoggingConfig {
                 log_level: "INFO".to_string(),
@@ -159,7 +573,22 @@ impl Default for Config {
                     registration_monitoring: true,
                     invite_flood_threshold: 20,
                     brute_force_threshold: 5,
+                    brute_force_window: default_brute_force_window(),
+                    toll_fraud: TollFraudConfig::default(),
+                    fingerprint_file: default_fingerprint_file(),
+                    enumeration_threshold: default_enumeration_threshold(),
+                    enumeration_window: default_enumeration_window(),
+                    tls_ports: default_tls_ports(),
+                    tls_failure_threshold: default_tls_failure_threshold(),
+                    registration_hijack: RegistrationHijackConfig::default(),
+                    media: MediaConfig::default(),
+                    protocol: ProtocolAbuseConfig::default(),
                 },
+                sip_honeypot: SipHoneypotConfig::default(),
+                pbx_logs: PbxLogConfig::default(),
+                probe_guard: ProbeGuardConfig::default(),
+                scan_correlation: ScanCorrelationConfig::default(),
+                slow_scan: SlowScanConfig::default(),
             },
             firewall: FirewallConfig {
                 enabled: true,
@@ -168,7 +597,10 @@ impl Default for Config {
                 auto_rules: true,
                 backup_rules: true,
                 iptables_path: "/sbin/iptables".to_string(),
+                ip6tables_path: default_ip6tables_path(),
+                ipv6_block_prefix: default_ipv6_block_prefix(),
             },
+            evidence: EvidenceConfig::default(),
         }
     }
 }
@@ -211,6 +643,7 @@ impl Config {
         Ok(default_config)
     }
 
+    #[allow(dead_code)]
     pub fn save(&self, path: &str) -> Result<(), Box<dyn std::error::Error>> {
         let config_json = serde_json::to_string_pretty(self)?;
         
@@ -234,6 +667,11 @@ impl Config {
             return Err("Minimum memory requirement is 64MB".into());
         }
 
+        // Validate network config
+        if self.network.af_packet.fanout_workers == 0 {
+            return Err("AF_PACKET fanout_workers must be at least 1".into());
+        }
+
         // Validate security config
         if self.security.default_sensitivity > 10 || self.security.default_sensitivity < 1 {
             return Err("Sensitivity level must be between 1-10".into());
@@ -252,11 +690,24 @@ impl Config {
             return Err("SIP Shield sensitivity must be between 1-10".into());
         }
 
+        let media = &self.modules.sip_shield.media;
+        if media.rtp_port_min > media.rtp_port_max {
+            return Err("RTP port range is empty (rtp_port_min is above rtp_port_max)".into());
+        }
+
+        if self.modules.slow_scan.horizons.is_empty() {
+            return Err("Slow scan detection needs at least one horizon".into());
+        }
+
         // Validate firewall config
         if !["ACCEPT", "DROP", "REJECT"].contains(&self.firewall.default_policy.as_str()) {
             return Err("Invalid firewall default policy (must be ACCEPT, DROP, or REJECT)".into());
         }
 
+        if self.firewall.ipv6_block_prefix > 128 {
+            return Err("IPv6 block prefix must be between 0-128".into());
+        }
+
         // Check if iptables exists
         if !Path::new(&self.firewall.iptables_path).exists() {
             return Err(format!("iptables not found at: {}", self.firewall.iptables_path).into());
@@ -280,28 +731,7 @@ impl Config {
         }
     }
 
-    pub fn is_whitelisted_ip(&self, ip: &str) -> bool {
-        self.security.whitelist_ips.iter().any(|whitelisted| {
-            if whitelisted.contains('/') {
-                // CIDR notation check (simplified)
-                ip.starts_with(&whitelisted.split('/').next().unwrap_or("")[..whitelisted.len().min(ip.len())])
-            } else {
-                ip == whitelisted
-            }
-        })
-    }
-
-    pub fn is_blacklisted_ip(&self, ip: &str) -> bool {
-        self.security.blacklist_ips.iter().any(|blacklisted| {
-            if blacklisted.contains('/') {
-                // CIDR notation check (simplified)
-                ip.starts_with(&blacklisted.split('/').next().unwrap_or("")[..blacklisted.len().min(ip.len())])
-            } else {
-                ip == blacklisted
-            }
-        })
-    }
-
+    #[allow(dead_code)]
     pub fn update_runtime_config(&mut self, updates: HashMap<String, serde_json::Value>) -> Result<(), Box<dyn std::error::Error>> {
         for (key, value) in updates {
             match key.as_str() {
@@ -312,21 +742,21 @@ impl Config {
                 }
                 "security.default_sensitivity" => {
                     if let Some(val) = value.as_u64() {
-                        if val >= 1 && val <= 10 {
+                        if (1..=10).contains(&val) {
                             self.security.default_sensitivity = val as u8;
                         }
                     }
                 }
                 "modules.tcp_guard.sensitivity" => {
                     if let Some(val) = value.as_u64() {
-                        if val >= 1 && val <= 10 {
+                        if (1..=10).contains(&val) {
                             self.modules.tcp_guard.sensitivity = val as u8;
                         }
                     }
                 }
                 "modules.sip_shield.sensitivity" => {
                     if let Some(val) = value.as_u64() {
-                        if val >= 1 && val <= 10 {
+                        if (1..=10).contains(&val) {
                             self.modules.sip_shield.sensitivity = val as u8;
                         }
                     }
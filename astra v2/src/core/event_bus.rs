@@ -0,0 +1,174 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+use tokio::sync::mpsc;
+
+use crate::core::config::Config;
+use crate::core::logger::Logger;
+use crate::SecurityEvent;
+
+/// A consumer's bounded queue. When it is full the event is dropped for that
+/// consumer only, so a slow logger or notifier cannot hold up blocking or
+/// the detectors publishing.
+struct Subscriber {
+    name: String,
+    sender: mpsc::Sender<Arc<SecurityEvent>>,
+    capacity: usize,
+    delivered: AtomicU64,
+    dropped: AtomicU64,
+    peak_backlog: AtomicUsize,
+}
+
+impl Subscriber {
+    fn backlog(&self) -> usize {
+        self.capacity - self.sender.capacity()
+    }
+}
+
+/// Carries every SecurityEvent from the modules to whoever acts on it: the
+/// engine's threat scoring, the security log and any later consumer. Events
+/// are shared, not copied, per subscriber. Publishing never waits.
+pub struct EventBus {
+    queue_size: usize,
+    subscribers: RwLock<Vec<Arc<Subscriber>>>,
+    published: AtomicU64,
+}
+
+impl EventBus {
+    pub fn new(config: &Config) -> Self {
+        EventBus {
+            queue_size: config.system.event_queue_size.max(1),
+            subscribers: RwLock::new(Vec::new()),
+            published: AtomicU64::new(0),
+        }
+    }
+
+    /// Registers a consumer. It only sees events published after this call.
+    pub fn subscribe(&self, name: &str) -> mpsc::Receiver<Arc<SecurityEvent>> {
+        let (sender, receiver) = mpsc::channel(self.queue_size);
+        self.subscribers.write().unwrap().push(Arc::new(Subscriber {
+            name: name.to_string(),
+            sender,
+            capacity: self.queue_size,
+            delivered: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+            peak_backlog: AtomicUsize::new(0),
+        }));
+        receiver
+    }
+
+    /// Hands the event to every subscriber. It comes back as the error when
+    /// no subscriber could take it, so the caller can still record it.
+    pub fn publish(&self, event: SecurityEvent) -> Result<(), Box<SecurityEvent>> {
+        self.published.fetch_add(1, Ordering::Relaxed);
+        let event = Arc::new(event);
+        let mut accepted = false;
+
+        for subscriber in self.subscribers.read().unwrap().iter() {
+            match subscriber.sender.try_send(event.clone()) {
+                Ok(()) => {
+                    subscriber.delivered.fetch_add(1, Ordering::Relaxed);
+                    subscriber.peak_backlog.fetch_max(subscriber.backlog(), Ordering::Relaxed);
+                    accepted = true;
+                }
+                Err(mpsc::error::TrySendError::Full(_)) => {
+                    subscriber.dropped.fetch_add(1, Ordering::Relaxed);
+                }
+                // The consumer has shut down
+                Err(mpsc::error::TrySendError::Closed(_)) => {}
+            }
+        }
+
+        if accepted {
+            Ok(())
+        } else {
+            Err(Box::new(Arc::try_unwrap(event).unwrap_or_else(|event| (*event).clone())))
+        }
+    }
+
+    /// Publishes the event, logging it instead when no subscriber could
+    /// take it so the intel is never lost.
+    pub fn publish_or_log(&self, logger: &Logger, event: SecurityEvent) -> Result<(), Box<dyn std::error::Error>> {
+        if let Err(event) = self.publish(event) {
+            logger.log_security_event(&event)?;
+        }
+        Ok(())
+    }
+
+    /// Per-subscriber delivery counts and lag: events still queued, the
+    /// deepest the queue has been, and events dropped on a full queue.
+    pub fn get_bus_statistics(&self) -> HashMap<String, u64> {
+        let mut stats = HashMap::new();
+        stats.insert("events_published".to_string(), self.published.load(Ordering::Relaxed));
+        for subscriber in self.subscribers.read().unwrap().iter() {
+            stats.insert(format!("{}_delivered", subscriber.name), subscriber.delivered.load(Ordering::Relaxed));
+            stats.insert(format!("{}_dropped", subscriber.name), subscriber.dropped.load(Ordering::Relaxed));
+            stats.insert(format!("{}_backlog", subscriber.name), subscriber.backlog() as u64);
+            stats.insert(format!("{}_peak_backlog", subscriber.name), subscriber.peak_backlog.load(Ordering::Relaxed) as u64);
+        }
+        stats
+    }
+
+    pub fn subscriber_names(&self) -> Vec<String> {
+        self.subscribers.read().unwrap().iter().map(|subscriber| subscriber.name.clone()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    use std::net::{IpAddr, Ipv4Addr};
+
+    fn bus(queue_size: usize) -> EventBus {
+        let mut config = Config::default();
+        config.system.event_queue_size = queue_size;
+        EventBus::new(&config)
+    }
+
+    fn event(details: &str) -> SecurityEvent {
+        SecurityEvent {
+            timestamp: Utc::now(),
+            source_ip: IpAddr::V4(Ipv4Addr::new(203, 0, 113, 7)),
+            event_type: "TEST".to_string(),
+            threat_level: crate::ThreatLevel { level: 5, confidence: 0.5, category: "TEST".to_string() },
+            details: details.to_string(),
+            action_taken: "NONE".to_string(),
+            evidence_id: None,
+            related_ips: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn an_event_nobody_takes_comes_back() {
+        let bus = bus(4);
+        let returned = bus.publish(event("unheard")).unwrap_err();
+        assert_eq!(returned.details, "unheard");
+
+        // Closed consumers do not count as taking it either
+        drop(bus.subscribe("gone"));
+        assert!(bus.publish(event("still unheard")).is_err());
+    }
+
+    #[test]
+    fn a_full_queue_drops_for_that_subscriber_only() {
+        let bus = bus(2);
+        let mut slow = bus.subscribe("slow");
+        let mut fast = bus.subscribe("fast");
+
+        for number in 0..3 {
+            bus.publish(event(&number.to_string())).unwrap();
+            assert_eq!(fast.try_recv().unwrap().details, number.to_string());
+        }
+
+        let stats = bus.get_bus_statistics();
+        assert_eq!(stats["events_published"], 3);
+        assert_eq!((stats["slow_delivered"], stats["slow_dropped"], stats["slow_backlog"]), (2, 1, 2));
+        assert_eq!((stats["fast_delivered"], stats["fast_dropped"], stats["fast_peak_backlog"]), (3, 0, 1));
+
+        // The oldest events are kept, the overflow is what was lost
+        assert_eq!(slow.try_recv().unwrap().details, "0");
+        assert_eq!(slow.try_recv().unwrap().details, "1");
+        assert!(slow.try_recv().is_err());
+    }
+}
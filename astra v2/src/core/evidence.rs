@@ -0,0 +1,208 @@
+use std::collections::{HashMap, VecDeque};
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+use crate::core::capture::PacketSummary;
+use crate::core::config::{Config, EvidenceConfig};
+use crate::core::netinfo;
+
+// Classic little-endian pcap with raw IP records
+const PCAP_MAGIC: u32 = 0xa1b2c3d4;
+const PCAP_VERSION: (u16, u16) = (2, 4);
+const LINKTYPE_RAW: u32 = 101;
+const SNAPLEN: u32 = 65535;
+
+// Bookkeeping charged against the buffer budget on top of the packet bytes
+const PACKET_OVERHEAD: usize = 128;
+
+struct SourceHistory {
+    packets: VecDeque<Arc<PacketSummary>>,
+    bytes: usize,
+    last_seen: Instant,
+}
+
+struct Incident {
+    source: IpAddr,
+    path: PathBuf,
+    writer: BufWriter<File>,
+    deadline: Instant,
+    tail_remaining: usize,
+}
+
+/// Keeps a short packet history per source and turns it into a pcap when
+/// that source gets blocked. Each incident file holds the packets that led
+/// to the decision plus a tail of whatever the source sends next. Sources
+/// are keyed the way the firewall blocks them, so an IPv6 history covers
+/// its whole prefix.
+pub struct EvidenceRecorder {
+    config: EvidenceConfig,
+    ipv6_prefix_len: u8,
+    history: HashMap<IpAddr, SourceHistory>,
+    buffered_bytes: usize,
+    incidents: HashMap<Uuid, Incident>,
+}
+
+impl EvidenceRecorder {
+    pub fn new(config: &Config) -> Self {
+        EvidenceRecorder {
+            config: config.evidence.clone(),
+            ipv6_prefix_len: config.firewall.ipv6_block_prefix,
+            history: HashMap::new(),
+            buffered_bytes: 0,
+            incidents: HashMap::new(),
+        }
+    }
+
+    /// Adds a captured packet to its source's history and to any incident
+    /// still recording that source. The capture layer's writer thread calls
+    /// this once per frame.
+    pub fn record(&mut self, packet: &Arc<PacketSummary>) -> Result<(), Box<dyn std::error::Error>> {
+        if !self.config.enabled {
+            return Ok(());
+        }
+
+        let source = netinfo::aggregate_address(packet.source_ip, self.ipv6_prefix_len);
+        let history = self.history.entry(source).or_insert_with(|| SourceHistory {
+            packets: VecDeque::new(),
+            bytes: 0,
+            last_seen: packet.captured_at,
+        });
+        let size = packet.packet.len() + PACKET_OVERHEAD;
+        history.packets.push_back(packet.clone());
+        history.bytes += size;
+        history.last_seen = packet.captured_at;
+        self.buffered_bytes += size;
+        while history.packets.len() > self.config.lead_packets.max(1) {
+            if let Some(old) = history.packets.pop_front() {
+                let old_size = old.packet.len() + PACKET_OVERHEAD;
+                history.bytes -= old_size;
+                self.buffered_bytes -= old_size;
+            }
+        }
+        self.enforce_budget(source);
+
+        for incident in self.incidents.values_mut() {
+            if incident.source == source && incident.tail_remaining > 0 && packet.captured_at <= incident.deadline {
+                write_record(&mut incident.writer, packet)?;
+                incident.tail_remaining -= 1;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Starts an incident for `source`, writing its buffered packets to a new
+    /// pcap right away. A source that is already being recorded keeps its
+    /// current incident. Returns `None` when evidence capture is disabled.
+    pub fn open_incident(&mut self, source: IpAddr, now: Instant) -> Result<Option<Uuid>, Box<dyn std::error::Error>> {
+        if !self.config.enabled {
+            return Ok(None);
+        }
+        let source = netinfo::aggregate_address(source, self.ipv6_prefix_len);
+        if let Some((id, _)) = self.incidents.iter().find(|(_, incident)| incident.source == source) {
+            return Ok(Some(*id));
+        }
+
+        fs::create_dir_all(&self.config.directory)?;
+        let id = Uuid::new_v4();
+        let path = PathBuf::from(&self.config.directory).join(format!("{}.pcap", id));
+        let mut writer = BufWriter::new(File::create(&path)?);
+        write_header(&mut writer)?;
+
+        if let Some(history) = self.history.get(&source) {
+            let lead_window = Duration::from_secs(self.config.lead_window);
+            for packet in &history.packets {
+                if now.saturating_duration_since(packet.captured_at) <= lead_window {
+                    write_record(&mut writer, packet)?;
+                }
+            }
+        }
+        writer.flush()?;
+
+        self.incidents.insert(id, Incident {
+            source,
+            path,
+            writer,
+            deadline: now + Duration::from_secs(self.config.tail_seconds),
+            tail_remaining: self.config.tail_packets,
+        });
+        Ok(Some(id))
+    }
+
+    /// Closes incidents whose tail is complete and forgets sources that have
+    /// been quiet for longer than the lead window. Returns the files closed.
+    pub fn expire(&mut self, now: Instant) -> Result<Vec<PathBuf>, Box<dyn std::error::Error>> {
+        let finished: Vec<Uuid> = self.incidents.iter()
+            .filter(|(_, incident)| incident.tail_remaining == 0 || now > incident.deadline)
+            .map(|(id, _)| *id)
+            .collect();
+        let mut closed = Vec::new();
+        for id in finished {
+            if let Some(mut incident) = self.incidents.remove(&id) {
+                incident.writer.flush()?;
+                closed.push(incident.path);
+            }
+        }
+
+        let lead_window = Duration::from_secs(self.config.lead_window);
+        let mut released = 0;
+        self.history.retain(|_, history| {
+            let keep = now.saturating_duration_since(history.last_seen) <= lead_window;
+            if !keep {
+                released += history.bytes;
+            }
+            keep
+        });
+        self.buffered_bytes -= released;
+
+        Ok(closed)
+    }
+
+    /// Flushes every open incident, used on shutdown.
+    pub fn close_all(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        for (_, mut incident) in self.incidents.drain() {
+            incident.writer.flush()?;
+        }
+        Ok(())
+    }
+
+    // Drops whole histories, least recently active first, until the buffer
+    // fits. The source that was just recorded is kept.
+    fn enforce_budget(&mut self, current: IpAddr) {
+        let budget = self.config.max_buffer_size * 1024 * 1024;
+        while self.buffered_bytes > budget {
+            let oldest = self.history.iter()
+                .filter(|(ip, _)| **ip != current)
+                .min_by_key(|(_, history)| history.last_seen)
+                .map(|(ip, _)| *ip);
+            match oldest.and_then(|ip| self.history.remove(&ip)) {
+                Some(history) => self.buffered_bytes -= history.bytes,
+                None => break,
+            }
+        }
+    }
+}
+
+fn write_header(writer: &mut impl Write) -> std::io::Result<()> {
+    writer.write_all(&PCAP_MAGIC.to_le_bytes())?;
+    writer.write_all(&PCAP_VERSION.0.to_le_bytes())?;
+    writer.write_all(&PCAP_VERSION.1.to_le_bytes())?;
+    writer.write_all(&0i32.to_le_bytes())?; // thiszone
+    writer.write_all(&0u32.to_le_bytes())?; // sigfigs
+    writer.write_all(&SNAPLEN.to_le_bytes())?;
+    writer.write_all(&LINKTYPE_RAW.to_le_bytes())
+}
+
+fn write_record(writer: &mut impl Write, packet: &PacketSummary) -> std::io::Result<()> {
+    let data = &packet.packet[..packet.packet.len().min(SNAPLEN as usize)];
+    writer.write_all(&(packet.timestamp.timestamp() as u32).to_le_bytes())?;
+    writer.write_all(&packet.timestamp.timestamp_subsec_micros().to_le_bytes())?;
+    writer.write_all(&(data.len() as u32).to_le_bytes())?;
+    writer.write_all(&(packet.packet.len() as u32).to_le_bytes())?;
+    writer.write_all(data)
+}
@@ -1,46 +1,87 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::net::IpAddr;
-use std::process::{Command, Output};
-use std::sync::{Arc, Mutex};
+use std::process::Command;
+use std::sync::Arc;
 use std::time::{Duration, Instant};
 use chrono::{DateTime, Utc};
 
 use crate::core::config::Config;
+use crate::core::ip_list::{self, IpList};
+use crate::core::netinfo;
+use crate::core::state_store::StoredBlock;
+
+const BLACKLIST_TAG: &str = "ASTRA-BLACKLIST";
+const WHITELIST_TAG: &str = "ASTRA-WHITELIST";
+
+/// Which of iptables/ip6tables a rule lives in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum IpFamily {
+    V4,
+    V6,
+}
 
 #[derive(Debug, Clone)]
 struct FirewallRule {
     id: String,
     chain: String,
+    #[allow(dead_code)]
     rule: String,
+    family: IpFamily,
+    args: Vec<String>,          // The rule as the kernel holds it, for `-D`
+    #[allow(dead_code)]
     timestamp: DateTime<Utc>,
+    #[allow(dead_code)]
     persistent: bool,
 }
 
+/// A rule tagged with an ASTRA comment in the live ruleset, as `iptables -S`
+/// prints it.
+#[derive(Debug, Clone, PartialEq)]
+struct LiveRule {
+    family: IpFamily,
+    chain: String,
+    id: String,
+    args: Vec<String>,          // Everything after `-A <chain>`
+}
+
 #[derive(Debug, Clone)]
-struct BlockedIp {
+pub struct BlockedIp {
+    #[allow(dead_code)]
     ip: IpAddr,
+    #[allow(dead_code)]
     blocked_at: Instant,
     expires_at: Option<Instant>,
+    expires_at_utc: Option<DateTime<Utc>>,   // The same expiry, as persisted
     reason: String,
     block_count: u32,
 }
 
 #[derive(Debug, Clone)]
 struct RateLimit {
+    #[allow(dead_code)]
     ip: IpAddr,
     limit: u32,
     window: Duration,
+    #[allow(dead_code)]
     current_count: u32,
+    #[allow(dead_code)]
     window_start: Instant,
 }
 
 pub struct Firewall {
     config: Arc<Config>,
     iptables_path: String,
+    ip6tables_path: String,
+    ipv6_enabled: bool,
     active_rules: Vec<FirewallRule>,
     blocked_ips: HashMap<IpAddr, BlockedIp>,
     rate_limits: HashMap<IpAddr, RateLimit>,
-    rule_counter: u32,
+    tarpitted: HashSet<IpAddr>,
+    whitelist: IpList,
+    blacklist: IpList,
+    whitelist_rules: HashSet<String>,   // `-s` arguments of the whitelist's ACCEPT rules
+    blacklist_rules: HashSet<String>,   // `-s` arguments of the blacklist's DROP rules
+    unclaimed_rules: Vec<LiveRule>,     // Left by an earlier run and not asked for again yet
     stealth_mode: bool,
     backup_created: bool,
 }
@@ -51,7 +92,7 @@ impl Firewall {
         
         // Verify iptables is available
         let output = Command::new(&iptables_path)
-            .args(&["--version"])
+            .args(["--version"])
             .output();
             
         match output {
@@ -59,13 +100,29 @@ impl Firewall {
             Err(e) => return Err(format!("Failed to initialize firewall: iptables not found at {}: {}", iptables_path, e).into()),
         }
 
+        let ip6tables_path = config.firewall.ip6tables_path.clone();
+        let ipv6_enabled = config.network.ipv6_support;
+        if ipv6_enabled {
+            match Command::new(&ip6tables_path).args(["--version"]).output() {
+                Ok(_) => println!("🔥 IPv6 enforcement ready - ip6tables found"),
+                Err(e) => return Err(format!("Failed to initialize firewall: ip6tables not found at {}: {}", ip6tables_path, e).into()),
+            }
+        }
+
         let mut firewall = Firewall {
             config: config.clone(),
             iptables_path,
+            ip6tables_path,
+            ipv6_enabled,
             active_rules: Vec::new(),
             blocked_ips: HashMap::new(),
             rate_limits: HashMap::new(),
-            rule_counter: 0,
+            tarpitted: HashSet::new(),
+            whitelist: IpList::default(),
+            blacklist: IpList::default(),
+            whitelist_rules: HashSet::new(),
+            blacklist_rules: HashSet::new(),
+            unclaimed_rules: Vec::new(),
             stealth_mode: config.system.stealth_mode,
             backup_created: false,
         };
@@ -75,9 +132,20 @@ impl Firewall {
             firewall.backup_current_rules()?;
         }
 
+        // Rules an earlier run left in place are adopted as they are asked for again
+        firewall.unclaimed_rules = firewall.list_live_rules()?;
+        if !firewall.unclaimed_rules.is_empty() {
+            println!("🔎 Found {} ASTRA rules from an earlier run", firewall.unclaimed_rules.len());
+        }
+
         // Initialize basic security rules
         firewall.initialize_base_rules()?;
 
+        // Blacklisted networks are dropped from the start
+        for warning in firewall.set_access_lists(&config.security.whitelist_ips, &config.security.blacklist_ips)? {
+            println!("⚠️  Warning: {}", warning);
+        }
+
         Ok(firewall)
     }
 
@@ -86,7 +154,7 @@ impl Firewall {
         let backup_file = format!("/etc/astra/firewall_backup_{}.rules", timestamp);
 
         let output = Command::new(&self.iptables_path)
-            .args(&["-S"])
+            .args(["-S"])
             .output();
 
         match output {
@@ -129,7 +197,7 @@ impl Firewall {
         }
 
         // Apply custom rules from config
-        for rule in &self.config.firewall.custom_rules {
+        for rule in &self.config.firewall.custom_rules.clone() {
             if let Err(e) = self.add_custom_rule(rule) {
                 println!("⚠️  Warning: Failed to apply custom rule '{}': {}", rule, e);
             }
@@ -144,6 +212,7 @@ impl Firewall {
 
         // Drop ICMP ping requests (become invisible to ping)
         self.add_stealth_rule("INPUT", "-p icmp --icmp-type echo-request -j DROP")?;
+        self.add_stealth_rule("INPUT", "-p ipv6-icmp --icmpv6-type echo-request -j DROP")?;
 
         // Drop all TCP RST responses (hide closed ports)
         self.add_stealth_rule("OUTPUT", "-p tcp --tcp-flags RST RST -j DROP")?;
@@ -154,8 +223,8 @@ impl Firewall {
         self.add_stealth_rule("INPUT", "-p tcp --syn -m state --state NEW -m recent --update --seconds 10 --hitcount 3 --name PORTSCAN -j DROP")?;
 
         // Drop packets to commonly scanned ports
-        let stealth_ports = &self.config.modules.tcp_guard.stealth_ports;
-        for &port in stealth_ports {
+        let stealth_ports = self.config.modules.tcp_guard.stealth_ports.clone();
+        for port in stealth_ports {
             self.add_stealth_rule("INPUT", &format!("-p tcp --dport {} -j LOG --log-prefix 'ASTRA-STEALTH-{}: '", port, port))?;
             self.add_stealth_rule("INPUT", &format!("-p tcp --dport {} -j DROP", port))?;
         }
@@ -165,75 +234,290 @@ impl Firewall {
     }
 
     pub fn add_rule(&mut self, chain: &str, rule: &str) -> Result<(), Box<dyn std::error::Error>> {
-        self.rule_counter += 1;
-        let rule_id = format!("ASTRA-{:06}", self.rule_counter);
+        let rule_id = rule_id("ASTRA", chain, rule);
 
-        let mut cmd = Command::new(&self.iptables_path);
-        cmd.args(&["-I", chain]);
-        
-        // Parse and add rule parameters
-        for param in rule.split_whitespace() {
-            cmd.arg(param);
-        }
+        self.insert_rule(chain, rule, &rule_id, false)
+            .map_err(|error| format!("Failed to add firewall rule: {}", error))?;
+        println!("🔧 Added firewall rule [{}]: {} {}", rule_id, chain, rule);
+
+        Ok(())
+    }
+
+    pub fn add_stealth_rule(&mut self, chain: &str, rule: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let rule_id = rule_id("ASTRA-STEALTH", chain, rule);
+
+        // Stealth rules are persistent
+        self.insert_rule(chain, rule, &rule_id, true)
+            .map_err(|error| format!("Failed to add stealth rule: {}", error))?;
+        println!("👻 Added stealth rule [{}]: {} {}", rule_id, chain, rule);
+
+        Ok(())
+    }
+
+    // Access list rules carry their own tags: the DROP of a blacklisted
+    // address is never the same rule as an engine block on it, and
+    // whitelist ACCEPTs are recognised to be kept on top
+    fn add_access_rule(&mut self, prefix: &str, rule: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let rule_id = rule_id(prefix, "INPUT", rule);
 
-        // Add comment for tracking
-        cmd.args(&["-m", "comment", "--comment", &rule_id]);
+        self.insert_rule("INPUT", rule, &rule_id, true)
+            .map_err(|error| format!("Failed to add access list rule: {}", error))?;
+        println!("🔧 Added access list rule [{}]: INPUT {}", rule_id, rule);
 
-        let output = cmd.output()?;
+        Ok(())
+    }
+
+    /// Inserts the rule into every family it applies to, tagged with
+    /// `rule_id` as its comment. A rule already in place, from this run or
+    /// left by an earlier one, is kept rather than inserted again.
+    ///
+    /// Whitelist ACCEPTs go in at the head of the chain and every other
+    /// rule right below them, so no DROP, however late it is added, sits
+    /// above a whitelisted source. A whitelist rule an earlier run left
+    /// further down is moved back to the top.
+    fn insert_rule(&mut self, chain: &str, rule: &str, rule_id: &str, persistent: bool) -> Result<(), String> {
+        let whitelist = rule_id.starts_with(WHITELIST_TAG);
+        for family in self.rule_families(rule) {
+            if self.active_rules.iter().any(|active| active.family == family && active.id == rule_id) {
+                continue;
+            }
+
+            let args = match self.claim_live_rule(family, chain, rule_id) {
+                Some(args) if !whitelist => args,
+                claimed => {
+                    if let Some(args) = claimed {
+                        let _ = self.command(family).args(["-D", chain]).args(&args).output();
+                    }
+                    let position = if whitelist {
+                        1
+                    } else {
+                        1 + self.active_rules.iter()
+                            .filter(|active| active.family == family && active.chain == chain && active.id.starts_with(WHITELIST_TAG))
+                            .count()
+                    };
+
+                    // Parse rule parameters and add the comment for tracking
+                    let mut args: Vec<String> = rule.split_whitespace().map(String::from).collect();
+                    args.extend(["-m", "comment", "--comment", rule_id].map(String::from));
+
+                    let output = self.command(family).args(["-I", chain, &position.to_string()]).args(&args).output().map_err(|e| e.to_string())?;
+                    if !output.status.success() {
+                        return Err(String::from_utf8_lossy(&output.stderr).to_string());
+                    }
+                    args
+                }
+            };
 
-        if output.status.success() {
-            let firewall_rule = FirewallRule {
-                id: rule_id.clone(),
+            self.active_rules.push(FirewallRule {
+                id: rule_id.to_string(),
                 chain: chain.to_string(),
                 rule: rule.to_string(),
+                family,
+                args,
                 timestamp: Utc::now(),
-                persistent: false,
-            };
-            
-            self.active_rules.push(firewall_rule);
-            println!("🔧 Added firewall rule [{}]: {} {}", rule_id, chain, rule);
-        } else {
-            let error = String::from_utf8_lossy(&output.stderr);
-            return Err(format!("Failed to add firewall rule: {}", error).into());
+                persistent,
+            });
         }
 
         Ok(())
     }
 
-    pub fn add_stealth_rule(&mut self, chain: &str, rule: &str) -> Result<(), Box<dyn std::error::Error>> {
-        self.rule_counter += 1;
-        let rule_id = format!("ASTRA-STEALTH-{:06}", self.rule_counter);
+    /// Every rule carrying an ASTRA comment in the filter table. ip6tables
+    /// is read even with IPv6 off, so rules from a run that had it on are
+    /// still found.
+    fn list_live_rules(&self) -> Result<Vec<LiveRule>, Box<dyn std::error::Error>> {
+        let mut rules = Vec::new();
+        for family in [IpFamily::V4, IpFamily::V6] {
+            let output = match self.command(family).arg("-S").output() {
+                Ok(output) if output.status.success() => output,
+                Ok(output) if family == IpFamily::V4 || self.ipv6_enabled => {
+                    return Err(format!("Failed to list firewall rules: {}", String::from_utf8_lossy(&output.stderr)).into());
+                }
+                Err(e) if family == IpFamily::V4 || self.ipv6_enabled => {
+                    return Err(format!("Failed to list firewall rules: {}", e).into());
+                }
+                _ => continue,
+            };
+            rules.extend(String::from_utf8_lossy(&output.stdout).lines().filter_map(|line| parse_live_rule(family, line)));
+        }
+        Ok(rules)
+    }
 
-        let mut cmd = Command::new(&self.iptables_path);
-        cmd.args(&["-I", chain]);
-        
-        for param in rule.split_whitespace() {
-            cmd.arg(param);
+    /// Takes over the earlier run's copy of a rule, if it left one.
+    fn claim_live_rule(&mut self, family: IpFamily, chain: &str, rule_id: &str) -> Option<Vec<String>> {
+        let index = self.unclaimed_rules.iter()
+            .position(|live| live.family == family && live.chain == chain && live.id == rule_id)?;
+        Some(self.unclaimed_rules.swap_remove(index).args)
+    }
+
+    /// Deletes the earlier run's rules that nothing has asked for since
+    /// startup: duplicates, blocks that lapsed while ASTRA was down, and
+    /// rules no longer in the configuration. Called once the engine has
+    /// put its own rules in place.
+    pub fn remove_unclaimed_rules(&mut self) -> Result<usize, Box<dyn std::error::Error>> {
+        let unclaimed = std::mem::take(&mut self.unclaimed_rules);
+        for live in &unclaimed {
+            let output = self.command(live.family).args(["-D", live.chain.as_str()]).args(&live.args).output()?;
+            if !output.status.success() {
+                println!("⚠️  Warning: Could not remove stale rule [{}]: {}", live.id, String::from_utf8_lossy(&output.stderr).trim());
+            }
+        }
+        if !unclaimed.is_empty() {
+            println!("🧹 Removed {} stale or duplicate ASTRA rules", unclaimed.len());
         }
+        Ok(unclaimed.len())
+    }
 
-        cmd.args(&["-m", "comment", "--comment", &rule_id]);
+    /// Rules naming an address or an ICMP flavour belong to that family;
+    /// anything else goes into both when IPv6 is enabled.
+    fn rule_families(&self, rule: &str) -> Vec<IpFamily> {
+        let params: Vec<&str> = rule.split_whitespace().collect();
+        let mut family = None;
+        for pair in params.windows(2) {
+            match pair[0] {
+                "-s" | "-d" | "--source" | "--destination" => {
+                    let address = pair[1].split('/').next().unwrap_or("");
+                    if let Ok(ip) = address.parse::<IpAddr>() {
+                        family = Some(if ip.is_ipv6() { IpFamily::V6 } else { IpFamily::V4 });
+                    }
+                }
+                "-p" if pair[1] == "icmp" => family = Some(IpFamily::V4),
+                "-p" if pair[1] == "ipv6-icmp" || pair[1] == "icmpv6" => family = Some(IpFamily::V6),
+                _ => {}
+            }
+        }
 
-        let output = cmd.output()?;
+        match family {
+            Some(IpFamily::V6) if !self.ipv6_enabled => Vec::new(),
+            Some(family) => vec![family],
+            None if self.ipv6_enabled => vec![IpFamily::V4, IpFamily::V6],
+            None => vec![IpFamily::V4],
+        }
+    }
 
-        if output.status.success() {
-            let firewall_rule = FirewallRule {
-                id: rule_id.clone(),
-                chain: chain.to_string(),
-                rule: rule.to_string(),
-                timestamp: Utc::now(),
-                persistent: true, // Stealth rules are persistent
-            };
-            
-            self.active_rules.push(firewall_rule);
-            println!("👻 Added stealth rule [{}]: {} {}", rule_id, chain, rule);
-        } else {
-            let error = String::from_utf8_lossy(&output.stderr);
-            return Err(format!("Failed to add stealth rule: {}", error).into());
+    fn command(&self, family: IpFamily) -> Command {
+        match family {
+            IpFamily::V4 => Command::new(&self.iptables_path),
+            IpFamily::V6 => Command::new(&self.ip6tables_path),
         }
+    }
+
+    /// Whether blocking `ip` would touch a whitelisted network. For IPv6
+    /// that is any whitelisted address inside the prefix a block would cover.
+    pub fn is_whitelisted(&self, ip: IpAddr) -> bool {
+        let (network, _) = self.block_target(ip);
+        let prefix_len = if network.is_ipv6() { self.config.firewall.ipv6_block_prefix.min(128) } else { 32 };
+        self.whitelist.overlaps(network, prefix_len)
+    }
 
+    pub fn is_blacklisted(&self, ip: IpAddr) -> bool {
+        self.blacklist.contains(ip)
+    }
+
+    /// Every blocking path goes through here, so a whitelisted source cannot
+    /// be blocked, rate limited or tarpitted whatever asked for it.
+    fn ensure_not_whitelisted(&self, ip: IpAddr) -> Result<(), Box<dyn std::error::Error>> {
+        if self.is_whitelisted(ip) {
+            return Err(format!("refusing to restrict whitelisted source {}", ip).into());
+        }
         Ok(())
     }
 
+    // The blacklist already drops everything a block on `ip` would
+    fn is_blacklist_blocked(&self, ip: IpAddr) -> bool {
+        let (network, _) = self.block_target(ip);
+        let prefix_len = if network.is_ipv6() { self.config.firewall.ipv6_block_prefix.min(128) } else { 32 };
+        self.blacklist.longest_match(network).is_some_and(|(_, listed_len)| listed_len <= prefix_len)
+    }
+
+    /// Replaces both access lists, as at startup or on a configuration
+    /// reload. Blocks covering newly whitelisted sources are lifted, new
+    /// blacklist entries are dropped and removed ones released, and every
+    /// whitelisted network is accepted ahead of every other rule. Returns warnings for
+    /// entries that were skipped.
+    pub fn set_access_lists(&mut self, whitelist: &[String], blacklist: &[String]) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+        let (whitelist, mut warnings) = IpList::from_entries(whitelist);
+        let (blacklist, blacklist_warnings) = IpList::from_entries(blacklist);
+        warnings.extend(blacklist_warnings);
+        self.whitelist = whitelist;
+
+        let released: Vec<IpAddr> = self.blocked_ips.keys()
+            .chain(self.rate_limits.keys())
+            .chain(self.tarpitted.iter())
+            .filter(|ip| self.is_whitelisted(**ip))
+            .cloned()
+            .collect();
+        for ip in released {
+            self.unblock_ip(ip)?;
+            println!("✅ WHITELISTED: {} - Existing restrictions lifted", ip);
+        }
+
+        let wanted: Vec<(IpAddr, u8, String)> = blacklist.networks().into_iter()
+            .map(|(network, prefix_len)| (network, prefix_len, ip_list::format_network(network, prefix_len)))
+            .collect();
+        let dropped: Vec<String> = self.blacklist_rules.iter()
+            .filter(|source| !wanted.iter().any(|(_, _, wanted)| wanted == *source))
+            .cloned()
+            .collect();
+        for source in dropped {
+            self.remove_rule(BLACKLIST_TAG, "INPUT", &format!("-s {} -j DROP", source))?;
+            self.blacklist_rules.remove(&source);
+            println!("❌ BLACKLIST REMOVED: {}", source);
+        }
+
+        for (network, prefix_len, source) in wanted {
+            if self.blacklist_rules.contains(&source) {
+                continue;
+            }
+            if self.whitelist.overlaps(network, prefix_len) {
+                warnings.push(format!("blacklist entry {} overlaps the whitelist and is not blocked", source));
+                continue;
+            }
+            if network.is_ipv6() && !self.ipv6_enabled {
+                warnings.push(format!("blacklist entry {} needs IPv6 support and is not blocked", source));
+                continue;
+            }
+            self.add_access_rule(BLACKLIST_TAG, &format!("-s {} -j DROP", source))?;
+            self.blacklist_rules.insert(source.clone());
+            println!("🚫 BLACKLISTED: {} - Dropped by policy", source);
+        }
+        self.blacklist = blacklist;
+
+        let wanted: Vec<String> = self.whitelist.networks().into_iter()
+            .map(|(network, prefix_len)| ip_list::format_network(network, prefix_len))
+            .collect();
+        let removed: Vec<String> = self.whitelist_rules.iter()
+            .filter(|source| !wanted.contains(source))
+            .cloned()
+            .collect();
+        for source in removed {
+            self.remove_rule(WHITELIST_TAG, "INPUT", &format!("-s {} -j ACCEPT", source))?;
+            self.whitelist_rules.remove(&source);
+            println!("❌ WHITELIST REMOVED: {}", source);
+        }
+        for source in wanted {
+            if self.whitelist_rules.contains(&source) {
+                continue;
+            }
+            self.add_access_rule(WHITELIST_TAG, &format!("-s {} -j ACCEPT", source))?;
+            self.whitelist_rules.insert(source.clone());
+            println!("✅ WHITELISTED: {} - Accepted by policy", source);
+        }
+        println!("🛡️  Access lists: {} whitelisted, {} blacklisted networks", self.whitelist.len(), self.blacklist.len());
+
+        Ok(warnings)
+    }
+
+    /// The tracking key and `-s` argument for blocking `ip`: IPv6 sources
+    /// are blocked as their whole prefix.
+    fn block_target(&self, ip: IpAddr) -> (IpAddr, String) {
+        let prefix_len = self.config.firewall.ipv6_block_prefix.min(128);
+        match netinfo::aggregate_address(ip, prefix_len) {
+            network @ IpAddr::V6(_) => (network, format!("{}/{}", network, prefix_len)),
+            ipv4 => (ipv4, ipv4.to_string()),
+        }
+    }
+
     pub fn add_custom_rule(&mut self, rule: &str) -> Result<(), Box<dyn std::error::Error>> {
         // Parse custom rule format: "CHAIN:RULE"
         let parts: Vec<&str> = rule.splitn(2, ':').collect();
@@ -249,18 +533,24 @@ impl Firewall {
     }
 
     pub fn block_ip_permanent(&mut self, ip: IpAddr) -> Result<(), Box<dyn std::error::Error>> {
+        self.ensure_not_whitelisted(ip)?;
+        if self.is_blacklist_blocked(ip) {
+            return Ok(());
+        }
+        let (ip, source) = self.block_target(ip);
         if self.blocked_ips.contains_key(&ip) {
             // Update existing block
             if let Some(blocked) = self.blocked_ips.get_mut(&ip) {
                 blocked.block_count += 1;
                 blocked.expires_at = None; // Make it permanent
+                blocked.expires_at_utc = None;
                 blocked.reason = "PERMANENT_THREAT".to_string();
             }
             return Ok(());
         }
 
         // Add permanent block rule
-        let rule = format!("-s {} -j DROP", ip);
+        let rule = format!("-s {} -j DROP", source);
         self.add_rule("INPUT", &rule)?;
 
         // Track blocked IP
@@ -268,30 +558,38 @@ impl Firewall {
             ip,
             blocked_at: Instant::now(),
             expires_at: None, // Permanent
+            expires_at_utc: None,
             reason: "HIGH_THREAT_PERMANENT".to_string(),
             block_count: 1,
         };
 
         self.blocked_ips.insert(ip, blocked_ip);
-        println!("🚫 PERMANENT BLOCK: {} - Added to firewall blackhole", ip);
+        println!("🚫 PERMANENT BLOCK: {} - Added to firewall blackhole", source);
 
         Ok(())
     }
 
     pub fn block_ip_temporary(&mut self, ip: IpAddr, duration: Duration) -> Result<(), Box<dyn std::error::Error>> {
+        self.ensure_not_whitelisted(ip)?;
+        if self.is_blacklist_blocked(ip) {
+            return Ok(());
+        }
         let expires_at = Instant::now() + duration;
+        let expires_at_utc = Utc::now() + chrono::Duration::from_std(duration).unwrap_or_else(|_| chrono::Duration::days(365));
+        let (ip, source) = self.block_target(ip);
 
         if self.blocked_ips.contains_key(&ip) {
             // Update existing block
             if let Some(blocked) = self.blocked_ips.get_mut(&ip) {
                 blocked.block_count += 1;
                 blocked.expires_at = Some(expires_at);
+                blocked.expires_at_utc = Some(expires_at_utc);
             }
             return Ok(());
         }
 
         // Add temporary block rule
-        let rule = format!("-s {} -j DROP", ip);
+        let rule = format!("-s {} -j DROP", source);
         self.add_rule("INPUT", &rule)?;
 
         // Track blocked IP
@@ -299,25 +597,84 @@ impl Firewall {
             ip,
             blocked_at: Instant::now(),
             expires_at: Some(expires_at),
+            expires_at_utc: Some(expires_at_utc),
             reason: "TEMPORARY_THREAT".to_string(),
             block_count: 1,
         };
 
         self.blocked_ips.insert(ip, blocked_ip);
-        println!("⏱️  TEMPORARY BLOCK: {} - Duration: {:?}", ip, duration);
+        println!("⏱️  TEMPORARY BLOCK: {} - Duration: {:?}", source, duration);
 
         Ok(())
     }
 
+    /// Re-applies a block saved by an earlier run for its remaining time.
+    /// Returns false when it is no longer needed: it has expired or the
+    /// blacklist now covers it.
+    pub fn restore_block(&mut self, ip: IpAddr, block: &StoredBlock) -> Result<bool, Box<dyn std::error::Error>> {
+        self.ensure_not_whitelisted(ip)?;
+        let remaining = match block.expires_at {
+            Some(expires_at) => match (expires_at - Utc::now()).to_std() {
+                Ok(remaining) if !remaining.is_zero() => Some(remaining),
+                _ => return Ok(false),
+            },
+            None => None,
+        };
+        if self.is_blacklist_blocked(ip) {
+            return Ok(false);
+        }
+        let (ip, source) = self.block_target(ip);
+        if self.blocked_ips.contains_key(&ip) {
+            return Ok(true);
+        }
+
+        self.add_rule("INPUT", &format!("-s {} -j DROP", source))?;
+        let now = Instant::now();
+        self.blocked_ips.insert(ip, BlockedIp {
+            ip,
+            blocked_at: now,
+            expires_at: remaining.map(|remaining| now + remaining),
+            expires_at_utc: block.expires_at,
+            reason: block.reason.clone(),
+            block_count: block.block_count,
+        });
+        println!("♻️  RESTORED BLOCK: {} - {}", source, block.reason);
+
+        Ok(true)
+    }
+
+    /// The current blocks in the form they are persisted.
+    pub fn export_blocks(&self) -> HashMap<IpAddr, StoredBlock> {
+        self.blocked_ips.iter()
+            .map(|(ip, blocked)| (*ip, StoredBlock {
+                expires_at: blocked.expires_at_utc,
+                reason: blocked.reason.clone(),
+                block_count: blocked.block_count,
+            }))
+            .collect()
+    }
+
     pub fn rate_limit_ip(&mut self, ip: IpAddr, limit: u32, window: Duration) -> Result<(), Box<dyn std::error::Error>> {
-        // Add rate limiting rule
-        let window_secs = window.as_secs();
-        let rule1 = format!("-s {} -m state --state NEW -m recent --set --name RATELIMIT_{}", ip, ip.to_string().replace(".", "_").replace(":", "_"));
-        let rule2 = format!("-s {} -m state --state NEW -m recent --update --seconds {} --hitcount {} --name RATELIMIT_{} -j DROP", 
-                           ip, window_secs, limit + 1, ip.to_string().replace(".", "_").replace(":", "_"));
+        self.ensure_not_whitelisted(ip)?;
+        if self.is_blacklist_blocked(ip) {
+            return Ok(());
+        }
+        let (ip, source) = self.block_target(ip);
 
-        self.add_rule("INPUT", &rule1)?;
-        self.add_rule("INPUT", &rule2)?;
+        // A changed limit replaces the rules of the previous one
+        if let Some(previous) = self.rate_limits.get(&ip) {
+            if previous.limit == limit && previous.window == window {
+                return Ok(());
+            }
+            for rule in rate_limit_rules(&source, previous.limit, previous.window) {
+                self.remove_rule("ASTRA", "INPUT", &rule)?;
+            }
+        }
+
+        // Add rate limiting rule
+        for rule in rate_limit_rules(&source, limit, window) {
+            self.add_rule("INPUT", &rule)?;
+        }
 
         // Track rate limit
         let rate_limit = RateLimit {
@@ -329,58 +686,76 @@ impl Firewall {
         };
 
         self.rate_limits.insert(ip, rate_limit);
-        println!("🚦 RATE LIMIT: {} - Max {} connections per {:?}", ip, limit, window);
+        println!("🚦 RATE LIMIT: {} - Max {} connections per {:?}", source, limit, window);
+
+        Ok(())
+    }
+
+    /// Holds the source's TCP connections open at zero window instead of
+    /// refusing them. Needs the TARPIT target from xtables-addons.
+    pub fn tarpit_ip(&mut self, ip: IpAddr) -> Result<(), Box<dyn std::error::Error>> {
+        self.ensure_not_whitelisted(ip)?;
+        if self.is_blacklist_blocked(ip) {
+            return Ok(());
+        }
+        let (ip, source) = self.block_target(ip);
+        if self.tarpitted.contains(&ip) {
+            return Ok(());
+        }
+
+        self.add_rule("INPUT", &format!("-s {} -p tcp -j TARPIT", source))?;
+        self.tarpitted.insert(ip);
+        println!("🕸️  TARPIT: {} - TCP connections held open", source);
 
         Ok(())
     }
 
     pub fn unblock_ip(&mut self, ip: IpAddr) -> Result<(), Box<dyn std::error::Error>> {
-        if let Some(_) = self.blocked_ips.remove(&ip) {
+        let (ip, source) = self.block_target(ip);
+        if self.blocked_ips.remove(&ip).is_some() {
             // Remove block rule
-            let rule = format!("-s {} -j DROP", ip);
-            self.remove_rule_by_content(&rule)?;
+            let rule = format!("-s {} -j DROP", source);
+            self.remove_rule("ASTRA", "INPUT", &rule)?;
             
-            println!("✅ UNBLOCKED: {} - Removed from firewall", ip);
+            println!("✅ UNBLOCKED: {} - Removed from firewall", source);
         }
 
         // Also remove rate limiting rules
-        if let Some(_) = self.rate_limits.remove(&ip) {
-            let ip_safe = ip.to_string().replace(".", "_").replace(":", "_");
-            let _ = self.remove_rule_by_content(&format!("-s {} -m state --state NEW -m recent --set --name RATELIMIT_{}", ip, ip_safe));
-            let _ = self.remove_rule_by_content(&format!("-s {} -m state --state NEW -m recent", ip));
+        if let Some(rate_limit) = self.rate_limits.remove(&ip) {
+            for rule in rate_limit_rules(&source, rate_limit.limit, rate_limit.window) {
+                self.remove_rule("ASTRA", "INPUT", &rule)?;
+            }
+        }
+
+        if self.tarpitted.remove(&ip) {
+            self.remove_rule("ASTRA", "INPUT", &format!("-s {} -p tcp -j TARPIT", source))?;
         }
 
         Ok(())
     }
 
-    fn remove_rule_by_content(&mut self, rule_content: &str) -> Result<(), Box<dyn std::error::Error>> {
-        // Find rule by content and remove it
-        let mut rules_to_remove = Vec::new();
-        
-        for (index, rule) in self.active_rules.iter().enumerate() {
-            if rule.rule.contains(rule_content) || rule_content.contains(&rule.rule) {
-                rules_to_remove.push((index, rule.clone()));
-            }
-        }
-
-        // Remove rules in reverse order to maintain indices
-        for (_, rule) in rules_to_remove.iter().rev() {
-            let mut cmd = Command::new(&self.iptables_path);
-            cmd.args(&["-D", &rule.chain]);
-            
-            for param in rule.rule.split_whitespace() {
-                cmd.arg(param);
-            }
+    /// Deletes the rule tagged `prefix` for `rule` in `chain` from every
+    /// family it went into. Only that exact rule goes, never one that
+    /// merely shares part of its text.
+    fn remove_rule(&mut self, prefix: &str, chain: &str, rule: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let rule_id = rule_id(prefix, chain, rule);
+        let (removed, kept): (Vec<FirewallRule>, Vec<FirewallRule>) = std::mem::take(&mut self.active_rules)
+            .into_iter()
+            .partition(|active| active.id == rule_id);
+        self.active_rules = kept;
+
+        for rule in removed {
+            let mut cmd = self.command(rule.family);
+            cmd.args(["-D", &rule.chain]);
+            cmd.args(&rule.args);
 
             let _ = cmd.output(); // Ignore errors during removal
         }
 
-        // Remove from our tracking
-        self.active_rules.retain(|rule| !rule.rule.contains(rule_content) && !rule_content.contains(&rule.rule));
-
         Ok(())
     }
 
+    #[allow(dead_code)]
     pub fn cleanup_expired_blocks(&mut self) -> Result<u32, Box<dyn std::error::Error>> {
         let now = Instant::now();
         let mut expired_ips = Vec::new();
@@ -407,10 +782,12 @@ impl Firewall {
         Ok(count)
     }
 
+    #[allow(dead_code)]
     pub fn get_blocked_ips(&self) -> Vec<(IpAddr, &BlockedIp)> {
         self.blocked_ips.iter().map(|(ip, blocked)| (*ip, blocked)).collect()
     }
 
+    #[allow(dead_code)]
     pub fn get_active_rules_count(&self) -> usize {
         self.active_rules.len()
     }
@@ -435,6 +812,7 @@ impl Firewall {
         stats
     }
 
+    #[allow(dead_code)]
     pub fn emergency_lockdown(&mut self) -> Result<(), Box<dyn std::error::Error>> {
         println!("🚨 EMERGENCY LOCKDOWN ACTIVATED");
 
@@ -448,17 +826,21 @@ impl Firewall {
         Ok(())
     }
 
+    #[allow(dead_code)]
     pub fn disable_lockdown(&mut self) -> Result<(), Box<dyn std::error::Error>> {
         println!("🔓 Disabling emergency lockdown...");
 
         // Remove lockdown rules
-        self.remove_rule_by_content("-m state --state NEW -j DROP")?;
-        self.remove_rule_by_content("-m state --state NEW -j LOG --log-prefix 'ASTRA-LOCKDOWN: '")?;
+        self.remove_rule("ASTRA", "INPUT", "-m state --state NEW -j DROP")?;
+        self.remove_rule("ASTRA", "INPUT", "-m state --state NEW -j LOG --log-prefix 'ASTRA-LOCKDOWN: '")?;
+        self.remove_rule("ASTRA", "INPUT", "-s ::1 -j ACCEPT")?;
+        self.remove_rule("ASTRA", "INPUT", "-s 127.0.0.1 -j ACCEPT")?;
 
         println!("✅ Emergency lockdown disabled");
         Ok(())
     }
 
+    #[allow(dead_code)]
     pub fn backup_and_restore(&mut self, restore: bool) -> Result<(), Box<dyn std::error::Error>> {
         if restore && self.backup_created {
             println!("🔄 Restoring firewall rules from backup...");
@@ -482,11 +864,11 @@ impl Firewall {
                     println!("📂 Restoring from: {:?}", backup_path);
                     
                     // Flush current rules
-                    let _ = Command::new(&self.iptables_path).args(&["-F"]).output();
+                    let _ = Command::new(&self.iptables_path).args(["-F"]).output();
                     
                     // Restore from backup
                     let _ = Command::new(&self.iptables_path)
-                        .args(&["-restore"])
+                        .args(["-restore"])
                         .arg(&backup_path)
                         .output();
                     
@@ -509,54 +891,87 @@ impl Firewall {
 
         // Remove only our rules (those with ASTRA comments)
         for rule in &self.active_rules {
-            let mut cmd = Command::new(&self.iptables_path);
-            cmd.args(&["-D", &rule.chain]);
-            
-            for param in rule.rule.split_whitespace() {
-                cmd.arg(param);
-            }
-            
+            let mut cmd = self.command(rule.family);
+            cmd.args(["-D", &rule.chain]);
+            cmd.args(&rule.args);
+
             let _ = cmd.output(); // Ignore errors
         }
 
         self.active_rules.clear();
         self.blocked_ips.clear();
         self.rate_limits.clear();
+        self.tarpitted.clear();
+        self.whitelist_rules.clear();
+        self.blacklist_rules.clear();
+
+        // Anything still tagged as ours was never tracked; it goes as well
+        self.unclaimed_rules = self.list_live_rules()?;
+        self.remove_unclaimed_rules()?;
 
         println!("✅ All ASTRA firewall rules flushed");
         Ok(())
     }
 
+    /// Leaves the ruleset in a known state on exit. With `auto_rules` every
+    /// rule this run asked for stays, so protection holds across a restart
+    /// that then adopts them; otherwise every ASTRA rule is removed.
+    /// Either way nothing stale from an earlier run is left behind.
+    pub fn shutdown(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        println!("🔥 Firewall module shutting down...");
+
+        // Option to clean up rules on shutdown (configurable)
+        if self.config.firewall.auto_rules {
+            self.remove_unclaimed_rules()?;
+            println!("🔒 Keeping {} ASTRA rules in place for the next start", self.active_rules.len());
+        } else {
+            self.flush_all_rules()?;
+        }
+
+        println!("✅ Firewall module shutdown complete");
+        Ok(())
+    }
+
     pub fn is_ip_blocked(&self, ip: IpAddr) -> bool {
-        self.blocked_ips.contains_key(&ip)
+        self.blocked_ips.contains_key(&self.block_target(ip).0)
     }
 
+    #[allow(dead_code)]
     pub fn is_ip_rate_limited(&self, ip: IpAddr) -> bool {
-        self.rate_limits.contains_key(&ip)
+        self.rate_limits.contains_key(&self.block_target(ip).0)
     }
 
+    #[allow(dead_code)]
     pub fn get_block_info(&self, ip: IpAddr) -> Option<&BlockedIp> {
-        self.blocked_ips.get(&ip)
+        self.blocked_ips.get(&self.block_target(ip).0)
     }
 
+    #[allow(dead_code)]
     pub fn add_whitelist_rule(&mut self, ip: IpAddr) -> Result<(), Box<dyn std::error::Error>> {
+        self.whitelist.insert(&ip.to_string())?;
+        self.unblock_ip(ip)?;
         let rule = format!("-s {} -j ACCEPT", ip);
-        self.add_rule("INPUT", &rule)?;
+        self.add_access_rule(WHITELIST_TAG, &rule)?;
+        self.whitelist_rules.insert(ip.to_string());
         println!("✅ WHITELISTED: {} - Permanent access granted", ip);
         Ok(())
     }
 
+    #[allow(dead_code)]
     pub fn remove_whitelist_rule(&mut self, ip: IpAddr) -> Result<(), Box<dyn std::error::Error>> {
+        self.whitelist.remove(&ip.to_string())?;
         let rule = format!("-s {} -j ACCEPT", ip);
-        self.remove_rule_by_content(&rule)?;
+        self.remove_rule(WHITELIST_TAG, "INPUT", &rule)?;
+        self.whitelist_rules.remove(&ip.to_string());
         println!("❌ WHITELIST REMOVED: {}", ip);
         Ok(())
     }
 
+    #[allow(dead_code)]
     pub fn get_connection_count(&self, ip: IpAddr) -> Result<u32, Box<dyn std::error::Error>> {
         // Use netstat to count active connections from IP
         let output = Command::new("netstat")
-            .args(&["-tn"])
+            .args(["-tn"])
             .output()?;
 
         let netstat_output = String::from_utf8_lossy(&output.stdout);
@@ -565,19 +980,238 @@ impl Firewall {
             .filter(|line| line.contains(&ip.to_string()))
             .count() as u32;
 
+        Ok(count)
+    }
+}
+
+/// Comment tagging an ASTRA rule. It is derived from the chain and rule
+/// text, so a rule carries the same tag in every run and is recognised in
+/// the live ruleset after a restart.
+fn rule_id(prefix: &str, chain: &str, rule: &str) -> String {
+    // FNV-1a, which unlike the std hasher is stable across builds
+    let canonical = format!("{} {}", chain, rule.split_whitespace().collect::<Vec<_>>().join(" "));
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in canonical.bytes() {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    format!("{}-{:012x}", prefix, hash >> 16)
+}
+
+/// The rule on an `iptables -S` line, if its comment marks it as ASTRA's.
+fn parse_live_rule(family: IpFamily, line: &str) -> Option<LiveRule> {
+    let mut words = split_rule_words(line).into_iter();
+    if words.next()? != "-A" {
+        return None;
+    }
+    let chain = words.next()?;
+    let args: Vec<String> = words.collect();
+    let id = args.windows(2)
+        .find(|pair| pair[0] == "--comment" && pair[1].starts_with("ASTRA-"))
+        .map(|pair| pair[1].clone())?;
+    Some(LiveRule { family, chain, id, args })
+}
+
+/// Splits a rule the way the shell would, since `iptables -S` quotes
+/// arguments holding spaces such as log prefixes.
+fn split_rule_words(line: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut in_word = false;
+    let mut quoted = false;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if quoted => {
+                if let Some(escaped) = chars.next() {
+                    word.push(escaped);
+                }
+            }
+            '"' => {
+                quoted = !quoted;
+                in_word = true;
+            }
+            c if c.is_whitespace() && !quoted => {
+                if in_word {
+                    words.push(std::mem::take(&mut word));
+                    in_word = false;
+                }
+            }
+            c => {
+                word.push(c);
+                in_word = true;
+            }
+        }
+    }
+    if in_word {
+        words.push(word);
+    }
+    words
+}
+
+/// `recent` list name for a rate-limited source or prefix.
+fn rate_limit_name(source: &str) -> String {
+    format!("RATELIMIT_{}", source.replace(['.', ':', '/'], "_"))
+}
+
+/// The pair of rules holding `source` to `limit` new connections per `window`.
+fn rate_limit_rules(source: &str, limit: u32, window: Duration) -> [String; 2] {
+    let list_name = rate_limit_name(source);
+    [
+        format!("-s {} -m state --state NEW -m recent --set --name {}", source, list_name),
+        format!("-s {} -m state --state NEW -m recent --update --seconds {} --hitcount {} --name {} -j DROP",
+                source, window.as_secs(), limit + 1, list_name),
+    ]
 }
 
 impl Drop for Firewall {
     fn drop(&mut self) {
-        println!("🔥 Firewall module shutting down...");
-        
-        // Option to clean up rules on shutdown (configurable)
-        if !self.config.firewall.auto_rules {
-            if let Err(e) = self.flush_all_rules() {
-                println!("⚠️  Warning: Could not clean up firewall rules: {}", e);
-            }
+        if let Err(e) = self.shutdown() {
+            println!("⚠️  Warning: Could not clean up firewall rules: {}", e);
         }
-        
-        println!("✅ Firewall module shutdown complete");
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // `true` stands in for iptables: every command succeeds and the live
+    // ruleset lists empty
+    fn firewall(configure: impl FnOnce(&mut Config)) -> Firewall {
+        let mut config = Config::default();
+        config.firewall.iptables_path = "true".to_string();
+        config.firewall.backup_rules = false;
+        config.firewall.custom_rules.clear();
+        config.system.stealth_mode = false;
+        config.network.ipv6_support = false;
+        config.security.whitelist_ips.clear();
+        config.security.blacklist_ips.clear();
+        configure(&mut config);
+        Firewall::new(&Arc::new(config)).unwrap()
+    }
+
+    fn has_rule(firewall: &Firewall, prefix: &str, rule: &str) -> bool {
+        let id = rule_id(prefix, "INPUT", rule);
+        firewall.active_rules.iter().any(|active| active.id == id)
+    }
+
+    fn ip(address: &str) -> IpAddr {
+        address.parse().unwrap()
+    }
+
+    #[test]
+    fn removal_takes_only_the_exact_rule() {
+        let mut firewall = firewall(|config| config.firewall.custom_rules = vec!["INPUT:-j DROP".to_string()]);
+        let source = ip("203.0.113.9");
+        firewall.block_ip_permanent(source).unwrap();
+        firewall.rate_limit_ip(source, 10, Duration::from_secs(60)).unwrap();
+
+        // A new limit replaces both rules of the old one
+        firewall.rate_limit_ip(source, 5, Duration::from_secs(60)).unwrap();
+        let [_, old_drop] = rate_limit_rules("203.0.113.9", 10, Duration::from_secs(60));
+        let [set, new_drop] = rate_limit_rules("203.0.113.9", 5, Duration::from_secs(60));
+        assert!(!has_rule(&firewall, "ASTRA", &old_drop));
+        assert!(has_rule(&firewall, "ASTRA", &set) && has_rule(&firewall, "ASTRA", &new_drop));
+
+        firewall.unblock_ip(source).unwrap();
+        assert!(has_rule(&firewall, "ASTRA", "-j DROP"));
+        assert!(!firewall.active_rules.iter().any(|active| active.args.iter().any(|arg| arg == "203.0.113.9")));
+    }
+
+    #[test]
+    fn blacklist_drops_outlive_engine_blocks_on_the_same_address() {
+        let mut firewall = firewall(|_| {});
+        let source = ip("198.51.100.7");
+        let drop = "-s 198.51.100.7 -j DROP";
+
+        // Blocked, then blacklisted, then the block expires
+        firewall.block_ip_temporary(source, Duration::from_secs(60)).unwrap();
+        firewall.set_access_lists(&[], &["198.51.100.7".to_string()]).unwrap();
+        assert!(has_rule(&firewall, "ASTRA", drop) && has_rule(&firewall, BLACKLIST_TAG, drop));
+        firewall.unblock_ip(source).unwrap();
+        assert!(!has_rule(&firewall, "ASTRA", drop));
+        assert!(has_rule(&firewall, BLACKLIST_TAG, drop));
+
+        // Taken off the blacklist while the engine still blocks it
+        firewall.set_access_lists(&[], &[]).unwrap();
+        firewall.block_ip_permanent(source).unwrap();
+        firewall.set_access_lists(&[], &["198.51.100.7".to_string()]).unwrap();
+        firewall.set_access_lists(&[], &[]).unwrap();
+        assert!(has_rule(&firewall, "ASTRA", drop));
+        assert!(!has_rule(&firewall, BLACKLIST_TAG, drop));
+        assert!(firewall.is_ip_blocked(source));
+    }
+
+    #[test]
+    fn whitelist_accepts_stay_above_later_drops() {
+        use std::os::unix::fs::PermissionsExt;
+
+        // Stand-in for iptables that records each command
+        let directory = std::env::temp_dir().join(format!("astra_firewall_{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let log = directory.join("commands");
+        let script = directory.join("iptables");
+        std::fs::write(&script, format!("#!/bin/sh\necho \"$*\" >> '{}'\n", log.display())).unwrap();
+        std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let inserted = |rule: &str| -> Vec<String> {
+            std::fs::read_to_string(&log).unwrap().lines()
+                .filter(|line| line.starts_with("-I ") && line.contains(rule))
+                .map(|line| line.split_whitespace().take(3).collect::<Vec<_>>().join(" "))
+                .collect()
+        };
+
+        let mut firewall = firewall(|config| {
+            config.firewall.iptables_path = script.to_string_lossy().to_string();
+            config.security.whitelist_ips = vec!["10.0.0.0/8".to_string()];
+        });
+        assert_eq!(inserted("-s 10.0.0.0/8 -j ACCEPT"), vec!["-I INPUT 1"]);
+
+        // Stealth and block rules added after startup go in below the whitelist
+        firewall.add_stealth_rule("INPUT", "-p tcp --syn -m state --state NEW -j DROP").unwrap();
+        firewall.block_ip_permanent(ip("203.0.113.9")).unwrap();
+        assert_eq!(inserted("-p tcp --syn -m state --state NEW -j DROP"), vec!["-I INPUT 2"]);
+        assert_eq!(inserted("-s 203.0.113.9 -j DROP"), vec!["-I INPUT 2"]);
+
+        // A whitelist rule left by an earlier run is moved back to the top
+        let id = rule_id(WHITELIST_TAG, "INPUT", "-s 192.0.2.0/24 -j ACCEPT");
+        let args = format!("-s 192.0.2.0/24 -m comment --comment {} -j ACCEPT", id);
+        firewall.unclaimed_rules.push(parse_live_rule(IpFamily::V4, &format!("-A INPUT {}", args)).unwrap());
+        firewall.set_access_lists(&["10.0.0.0/8".to_string(), "192.0.2.0/24".to_string()], &[]).unwrap();
+        let commands = std::fs::read_to_string(&log).unwrap();
+        assert!(commands.contains(&format!("-D INPUT {}", args)));
+        assert_eq!(inserted("-s 192.0.2.0/24 -j ACCEPT"), vec!["-I INPUT 1"]);
+
+        firewall.block_ip_temporary(ip("203.0.113.10"), Duration::from_secs(60)).unwrap();
+        assert_eq!(inserted("-s 203.0.113.10 -j DROP"), vec!["-I INPUT 3"]);
+    }
+
+    #[test]
+    fn rule_ids_are_stable_per_rule() {
+        let id = rule_id("ASTRA", "INPUT", "-s 203.0.113.9 -j DROP");
+        assert_eq!(id, rule_id("ASTRA", "INPUT", "-s 203.0.113.9   -j DROP"));
+        assert_ne!(id, rule_id("ASTRA", "OUTPUT", "-s 203.0.113.9 -j DROP"));
+        assert_ne!(id, rule_id("ASTRA", "INPUT", "-s 203.0.113.90 -j DROP"));
+        assert!(rule_id("ASTRA-STEALTH", "INPUT", "-p icmp -j DROP").starts_with("ASTRA-STEALTH-"));
+        assert_eq!(id.len(), "ASTRA-".len() + 12);
+    }
+
+    #[test]
+    fn live_rules_parse_from_iptables_listing() {
+        let listing = [
+            "-P INPUT ACCEPT",
+            "-N f2b-sshd",
+            "-A INPUT -s 203.0.113.9/32 -m comment --comment ASTRA-00a1b2c3d4e5 -j DROP",
+            "-A INPUT -p tcp -m tcp --syn -m state --state NEW -m comment --comment ASTRA-STEALTH-0123456789ab -j LOG --log-prefix \"ASTRA-SCAN-DETECT: \"",
+            "-A INPUT -p tcp -m tcp --dport 22 -m comment --comment \"managed by \\\"ops\\\"\" -j ACCEPT",
+            "-A f2b-sshd -j RETURN",
+        ];
+        let rules: Vec<LiveRule> = listing.iter().filter_map(|line| parse_live_rule(IpFamily::V4, line)).collect();
+        assert_eq!(rules.len(), 2);
+        assert_eq!(rules[0].chain, "INPUT");
+        assert_eq!(rules[0].id, "ASTRA-00a1b2c3d4e5");
+        assert_eq!(rules[0].args.join(" "), "-s 203.0.113.9/32 -m comment --comment ASTRA-00a1b2c3d4e5 -j DROP");
+        assert_eq!(rules[1].id, "ASTRA-STEALTH-0123456789ab");
+        assert_eq!(rules[1].args.last().map(String::as_str), Some("ASTRA-SCAN-DETECT: "));
+        assert_eq!(split_rule_words("--comment \"managed by \\\"ops\\\"\""), vec!["--comment", "managed by \"ops\""]);
+    }
+}
@@ -0,0 +1,260 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use crate::core::netinfo;
+
+#[derive(Debug, Default)]
+struct TrieNode {
+    children: [Option<Box<TrieNode>>; 2],
+    present: bool,
+}
+
+impl TrieNode {
+    fn is_empty(&self) -> bool {
+        !self.present && self.children.iter().all(Option::is_none)
+    }
+
+    // Unmarks the prefix `bits[depth..]` leads to, pruning branches left empty
+    fn remove(&mut self, bits: u128, width: u8, prefix_len: u8, depth: u8) -> bool {
+        if depth == prefix_len {
+            return std::mem::replace(&mut self.present, false);
+        }
+        let bit = bit_at(bits, width, depth);
+        let removed = match self.children[bit].as_mut() {
+            Some(child) => child.remove(bits, width, prefix_len, depth + 1),
+            None => false,
+        };
+        if self.children[bit].as_ref().is_some_and(|child| child.is_empty()) {
+            self.children[bit] = None;
+        }
+        removed
+    }
+
+    fn collect(&self, bits: u128, width: u8, depth: u8, out: &mut Vec<(u128, u8)>) {
+        if self.present {
+            out.push((bits, depth));
+        }
+        for (bit, child) in self.children.iter().enumerate() {
+            if let Some(child) = child {
+                child.collect(bits | (bit as u128) << (width - 1 - depth), width, depth + 1, out);
+            }
+        }
+    }
+}
+
+/// A set of IPv4 and IPv6 networks with longest-prefix lookups, one binary
+/// trie per family. Host bits past the prefix length are ignored, and
+/// IPv4-mapped IPv6 addresses match the IPv4 tree.
+#[derive(Debug, Default)]
+pub struct IpList {
+    v4: TrieNode,
+    v6: TrieNode,
+    entries: usize,
+}
+
+impl IpList {
+    /// Builds a list from `addr/len` or bare address strings. Entries that do
+    /// not parse are skipped and reported.
+    pub fn from_entries(entries: &[String]) -> (Self, Vec<String>) {
+        let mut list = IpList::default();
+        let mut warnings = Vec::new();
+        for entry in entries {
+            if list.insert(entry).is_err() {
+                warnings.push(format!("invalid network '{}'", entry));
+            }
+        }
+        (list, warnings)
+    }
+
+    /// Adds a network; returns false if it was already listed.
+    pub fn insert(&mut self, cidr: &str) -> Result<bool, String> {
+        let (address, prefix_len) = netinfo::parse_network(cidr).ok_or_else(|| format!("invalid network '{}'", cidr))?;
+        let (root, bits, width) = self.tree_mut(address);
+        let mut node = root;
+        for depth in 0..prefix_len {
+            node = node.children[bit_at(bits, width, depth)].get_or_insert_with(Default::default);
+        }
+        let added = !std::mem::replace(&mut node.present, true);
+        if added {
+            self.entries += 1;
+        }
+        Ok(added)
+    }
+
+    /// Removes a network exactly as listed; returns false if it was not.
+    pub fn remove(&mut self, cidr: &str) -> Result<bool, String> {
+        let (address, prefix_len) = netinfo::parse_network(cidr).ok_or_else(|| format!("invalid network '{}'", cidr))?;
+        let (root, bits, width) = self.tree_mut(address);
+        let removed = root.remove(bits, width, prefix_len, 0);
+        if removed {
+            self.entries -= 1;
+        }
+        Ok(removed)
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        self.longest_match(ip).is_some()
+    }
+
+    /// The most specific listed network holding `ip`.
+    pub fn longest_match(&self, ip: IpAddr) -> Option<(IpAddr, u8)> {
+        let (mut node, bits, width) = self.tree(ip);
+        let mut best = node.present.then_some(0);
+        for depth in 0..width {
+            match node.children[bit_at(bits, width, depth)].as_deref() {
+                Some(child) => node = child,
+                None => break,
+            }
+            if node.present {
+                best = Some(depth + 1);
+            }
+        }
+        best.map(|prefix_len| (to_address(width == 128, bits & prefix_mask(width, prefix_len)), prefix_len))
+    }
+
+    /// Whether any listed network overlaps `network/prefix_len`, either by
+    /// covering it or by lying inside it.
+    pub fn overlaps(&self, network: IpAddr, prefix_len: u8) -> bool {
+        let (mut node, bits, width) = self.tree(network);
+        for depth in 0..prefix_len.min(width) {
+            if node.present {
+                return true;
+            }
+            match node.children[bit_at(bits, width, depth)].as_deref() {
+                Some(child) => node = child,
+                None => return false,
+            }
+        }
+        !node.is_empty()
+    }
+
+    /// Every listed network, IPv4 first.
+    pub fn networks(&self) -> Vec<(IpAddr, u8)> {
+        let mut v4 = Vec::new();
+        self.v4.collect(0, 32, 0, &mut v4);
+        let mut v6 = Vec::new();
+        self.v6.collect(0, 128, 0, &mut v6);
+        v4.into_iter().map(|(bits, len)| (to_address(false, bits), len))
+            .chain(v6.into_iter().map(|(bits, len)| (to_address(true, bits), len)))
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries
+    }
+
+    fn tree(&self, ip: IpAddr) -> (&TrieNode, u128, u8) {
+        match canonical_bits(ip) {
+            (bits, 32) => (&self.v4, bits, 32),
+            (bits, width) => (&self.v6, bits, width),
+        }
+    }
+
+    fn tree_mut(&mut self, ip: IpAddr) -> (&mut TrieNode, u128, u8) {
+        match canonical_bits(ip) {
+            (bits, 32) => (&mut self.v4, bits, 32),
+            (bits, width) => (&mut self.v6, bits, width),
+        }
+    }
+}
+
+/// `addr/len`, or the bare address for a single host, as iptables takes it.
+pub fn format_network(network: IpAddr, prefix_len: u8) -> String {
+    let width = if network.is_ipv6() { 128 } else { 32 };
+    if prefix_len >= width {
+        network.to_string()
+    } else {
+        format!("{}/{}", network, prefix_len)
+    }
+}
+
+fn canonical_bits(ip: IpAddr) -> (u128, u8) {
+    match ip {
+        IpAddr::V4(ipv4) => (u32::from(ipv4) as u128, 32),
+        IpAddr::V6(ipv6) => match ipv6.to_ipv4_mapped() {
+            Some(ipv4) => (u32::from(ipv4) as u128, 32),
+            None => (u128::from(ipv6), 128),
+        },
+    }
+}
+
+fn bit_at(bits: u128, width: u8, depth: u8) -> usize {
+    ((bits >> (width - 1 - depth)) & 1) as usize
+}
+
+fn prefix_mask(width: u8, prefix_len: u8) -> u128 {
+    let all = if width == 128 { u128::MAX } else { (1u128 << width) - 1 };
+    let host_bits = (width - prefix_len) as u32;
+    all.checked_shr(host_bits).unwrap_or(0).checked_shl(host_bits).unwrap_or(0) & all
+}
+
+fn to_address(is_ipv6: bool, bits: u128) -> IpAddr {
+    if is_ipv6 {
+        IpAddr::V6(Ipv6Addr::from(bits))
+    } else {
+        IpAddr::V4(Ipv4Addr::from(bits as u32))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn list(entries: &[&str]) -> IpList {
+        let entries: Vec<String> = entries.iter().map(|entry| entry.to_string()).collect();
+        let (list, warnings) = IpList::from_entries(&entries);
+        assert!(warnings.is_empty(), "{:?}", warnings);
+        list
+    }
+
+    fn ip(address: &str) -> IpAddr {
+        address.parse().unwrap()
+    }
+
+    #[test]
+    fn matches_networks_not_string_prefixes() {
+        let list = list(&["192.168.1.0/24", "10.0.0.0/8", "203.0.113.7"]);
+        assert!(list.contains(ip("192.168.1.200")));
+        assert!(!list.contains(ip("192.168.10.1")));
+        assert!(!list.contains(ip("192.168.2.1")));
+        assert!(list.contains(ip("10.255.0.1")));
+        assert!(!list.contains(ip("100.0.0.1")));
+        assert!(list.contains(ip("203.0.113.7")));
+        assert!(!list.contains(ip("203.0.113.70")));
+        assert!(list.contains(ip("::ffff:10.1.2.3")));
+    }
+
+    #[test]
+    fn longest_match_prefers_the_most_specific_network() {
+        let list = list(&["2001:db8::/32", "2001:db8:1::/48", "0.0.0.0/0"]);
+        assert_eq!(list.longest_match(ip("2001:db8:1::5")), Some((ip("2001:db8:1::"), 48)));
+        assert_eq!(list.longest_match(ip("2001:db8:2::5")), Some((ip("2001:db8::"), 32)));
+        assert_eq!(list.longest_match(ip("2001:db9::1")), None);
+        assert_eq!(list.longest_match(ip("198.51.100.1")), Some((ip("0.0.0.0"), 0)));
+    }
+
+    #[test]
+    fn overlap_covers_both_directions() {
+        let list = list(&["2001:db8::1", "198.51.100.0/24"]);
+        assert!(list.overlaps(ip("2001:db8::"), 64));
+        assert!(!list.overlaps(ip("2001:db8:0:1::"), 64));
+        assert!(list.overlaps(ip("198.51.100.9"), 32));
+        assert!(list.overlaps(ip("198.51.0.0"), 16));
+        assert!(!list.overlaps(ip("198.51.101.0"), 24));
+    }
+
+    #[test]
+    fn edits_keep_the_trie_consistent() {
+        let mut list = list(&["10.0.0.0/8"]);
+        assert_eq!(list.insert("10.1.2.3/8"), Ok(false));
+        assert_eq!(list.insert("10.1.0.0/16"), Ok(true));
+        assert_eq!(list.len(), 2);
+        assert_eq!(list.remove("10.0.0.0/8"), Ok(true));
+        assert!(!list.contains(ip("10.2.0.1")));
+        assert!(list.contains(ip("10.1.0.1")));
+        assert_eq!(list.remove("10.0.0.0/8"), Ok(false));
+        assert!(list.insert("10.0.0.0/33").is_err());
+        assert_eq!(list.networks(), vec![(ip("10.1.0.0"), 16)]);
+        assert_eq!(format_network(ip("10.1.0.0"), 16), "10.1.0.0/16");
+        assert_eq!(format_network(ip("2001:db8::1"), 128), "2001:db8::1");
+    }
+}
@@ -81,7 +81,6 @@ impl Logger {
         // Open main log file
         let log_file = OpenOptions::new()
             .create(true)
-            .write(true)
             .append(true)
             .open(&config.logging.log_file)?;
         
@@ -92,7 +91,6 @@ impl Logger {
             let audit_path = config.logging.log_file.replace(".log", "_audit.log");
             let audit_file = OpenOptions::new()
                 .create(true)
-                .write(true)
                 .append(true)
                 .open(&audit_path)?;
             Some(Arc::new(Mutex::new(BufWriter::new(audit_file))))
@@ -197,7 +195,7 @@ impl Logger {
         let message = format!("ASTRA[{}]: {}", entry.module, entry.message);
 
         let _ = Command::new("logger")
-            .args(&["-p", &format!("daemon.{}", priority), &message])
+            .args(["-p", &format!("daemon.{}", priority), &message])
             .output();
 
         Ok(())
@@ -257,10 +255,12 @@ impl Logger {
                 "category": event.threat_level.category
             },
             "details": event.details,
-            "action_taken": event.action_taken
+            "action_taken": event.action_taken,
+            "evidence_id": event.evidence_id.map(|id| id.to_string()),
+            "related_ips": event.related_ips.iter().map(|ip| ip.to_string()).collect::<Vec<_>>()
         });
 
-        let message = format!(
+        let mut message = format!(
             "SECURITY EVENT: {} from {} - Threat Level: {}/10 ({}%) - {}",
             event.event_type,
             event.source_ip,
@@ -268,6 +268,10 @@ impl Logger {
             (event.threat_level.confidence * 100.0) as u8,
             event.details
         );
+        // Plain text entries drop the metadata, so the capture reference goes in the message
+        if let Some(evidence_id) = event.evidence_id {
+            message.push_str(&format!(" [evidence {}]", evidence_id));
+        }
 
         // Log to main log
         self.log_with_metadata(
@@ -299,6 +303,7 @@ impl Logger {
         Ok(())
     }
 
+    #[allow(dead_code)]
     pub fn log_system_event(&self, event_type: &str, details: &str) -> Result<(), Box<dyn std::error::Error>> {
         let metadata = serde_json::json!({
             "event_type": event_type,
@@ -319,6 +324,7 @@ impl Logger {
         }
     }
 
+    #[allow(dead_code)]
     pub fn log_performance_metrics(&self, metrics: &std::collections::HashMap<String, f64>) -> Result<(), Box<dyn std::error::Error>> {
         let metadata = serde_json::json!(metrics);
         
@@ -332,6 +338,7 @@ impl Logger {
         self.log_with_metadata(LogLevel::Debug, "PERFORMANCE", &message, metadata)
     }
 
+    #[allow(dead_code)]
     pub fn rotate_logs(&self) -> Result<(), Box<dyn std::error::Error>> {
         let log_path = &self.config.logging.log_file;
         let max_size = self.config.logging.max_log_size * 1024 * 1024; // Convert MB to bytes
@@ -349,9 +356,8 @@ impl Logger {
                 std::fs::rename(log_path, &rotated_path)?;
                 
                 // Reopen log file
-                let new_file = OpenOptions::new()
+                let _new_file = OpenOptions::new()
                     .create(true)
-                    .write(true)
                     .append(true)
                     .open(log_path)?;
                 
@@ -363,29 +369,28 @@ impl Logger {
         Ok(())
     }
 
+    #[allow(dead_code)]
     pub fn cleanup_old_logs(&self) -> Result<(), Box<dyn std::error::Error>> {
         let log_dir = Path::new(&self.config.logging.log_file).parent().unwrap_or(Path::new("."));
         let retention_days = self.config.logging.log_retention_days;
         let cutoff = Utc::now() - chrono::Duration::days(retention_days as i64);
 
         if let Ok(entries) = std::fs::read_dir(log_dir) {
-            for entry in entries {
-                if let Ok(entry) = entry {
-                    let path = entry.path();
-                    if let Some(file_name) = path.file_name() {
-                        let file_name_str = file_name.to_string_lossy();
-                        
-                        // Check if it's an ASTRA log file
-                        if file_name_str.starts_with("astra") && file_name_str.contains(".log.") {
-                            if let Ok(metadata) = entry.metadata() {
-                                if let Ok(modified) = metadata.modified() {
-                                    let modified_dt: DateTime<Utc> = modified.into();
-                                    if modified_dt < cutoff {
-                                        if let Err(e) = std::fs::remove_file(&path) {
-                                            self.log_warning(&format!("Could not remove old log file {:?}: {}", path, e))?;
-                                        } else {
-                                            self.log_info(&format!("Removed old log file: {:?}", path))?;
-                                        }
+            for entry in entries.flatten() {
+                let path = entry.path();
+                if let Some(file_name) = path.file_name() {
+                    let file_name_str = file_name.to_string_lossy();
+
+                    // Check if it's an ASTRA log file
+                    if file_name_str.starts_with("astra") && file_name_str.contains(".log.") {
+                        if let Ok(metadata) = entry.metadata() {
+                            if let Ok(modified) = metadata.modified() {
+                                let modified_dt: DateTime<Utc> = modified.into();
+                                if modified_dt < cutoff {
+                                    if let Err(e) = std::fs::remove_file(&path) {
+                                        self.log_warning(&format!("Could not remove old log file {:?}: {}", path, e))?;
+                                    } else {
+                                        self.log_info(&format!("Removed old log file: {:?}", path))?;
                                     }
                                 }
                             }
@@ -398,6 +403,7 @@ impl Logger {
         Ok(())
     }
 
+    #[allow(dead_code)]
     pub fn get_log_stats(&self) -> Result<std::collections::HashMap<String, u64>, Box<dyn std::error::Error>> {
         let mut stats = std::collections::HashMap::new();
         
@@ -419,7 +425,6 @@ impl Logger {
                 Command::new("hostname")
                     .output()
                     .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
-                    .unwrap_or_else(|_| "unknown".to_string())
             })
             .unwrap_or_else(|_| "unknown".to_string())
     }
@@ -442,9 +447,11 @@ impl Logger {
         self.console_output = enabled;
     }
 
+    #[allow(dead_code)]
     pub fn set_log_level(&mut self, level: LogLevel) {
+        let name = level.as_str();
         self.log_level = level;
-        let _ = self.log_info(&format!("Log level changed to: {}", level.as_str()));
+        let _ = self.log_info(&format!("Log level changed to: {}", name));
     }
 }
 
@@ -1,3 +1,11 @@
+pub mod afpacket;
+pub mod capture;
 pub mod config;
+pub mod event_bus;
+pub mod evidence;
 pub mod firewall;
-pub mod logger;
\ No newline at end of file
+pub mod ip_list;
+pub mod logger;
+pub mod netinfo;
+pub mod policy;
+pub mod state_store;
\ No newline at end of file
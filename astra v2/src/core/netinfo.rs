@@ -0,0 +1,217 @@
+use std::collections::HashMap;
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::path::Path;
+
+/// Where an address lives: the most specific known network, and its ASN and
+/// country when the database has them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NetworkInfo {
+    pub network: String,
+    pub asn: Option<u32>,
+    pub country: Option<String>,
+}
+
+impl NetworkInfo {
+    pub fn describe(&self) -> String {
+        let asn = self.asn.map_or_else(|| "AS?".to_string(), |asn| format!("AS{}", asn));
+        format!("{} {} {}", self.network, asn, self.country.as_deref().unwrap_or("??"))
+    }
+}
+
+#[derive(Debug, Clone)]
+struct NetworkEntry {
+    asn: Option<u32>,
+    country: Option<String>,
+}
+
+/// Longest-prefix lookup over an operator supplied CSV of
+/// `cidr,asn,country` lines, e.g. an export of a GeoIP/ASN feed.
+#[derive(Debug, Default)]
+pub struct NetInfoDatabase {
+    // Keyed by (is_ipv6, prefix length) then by the masked network address
+    networks: HashMap<(bool, u8), HashMap<u128, NetworkEntry>>,
+    prefix_lengths: Vec<(bool, u8)>,
+    entries: usize,
+}
+
+impl NetInfoDatabase {
+    const TEMPLATE: &'static str = "# cidr,asn,country\n# 203.0.113.0/24,64500,FR\n# 2001:db8::/32,64501,DE\n";
+
+    /// Loads the database, leaving a commented template behind if it does not
+    /// exist. Unparseable lines are skipped and reported.
+    pub fn load(path: &str) -> Result<(Self, Vec<String>), Box<dyn std::error::Error>> {
+        if !Path::new(path).exists() {
+            if let Some(parent) = Path::new(path).parent() {
+                if fs::create_dir_all(parent).is_ok() {
+                    let _ = fs::write(path, Self::TEMPLATE);
+                }
+            }
+            return Ok((Self::default(), Vec::new()));
+        }
+
+        let data = fs::read_to_string(path)?;
+        Ok(Self::parse(&data))
+    }
+
+    pub fn parse(data: &str) -> (Self, Vec<String>) {
+        let mut database = Self::default();
+        let mut warnings = Vec::new();
+
+        for (number, line) in data.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut fields = line.split(',').map(str::trim);
+            let cidr = fields.next().unwrap_or("");
+            let asn = fields.next()
+                .map(|asn| asn.trim_start_matches("AS").trim_start_matches("as"))
+                .and_then(|asn| asn.parse().ok());
+            let country = fields.next()
+                .filter(|country| !country.is_empty())
+                .map(|country| country.to_ascii_uppercase());
+
+            match parse_cidr(cidr) {
+                Some((address, prefix_len)) => database.insert(address, prefix_len, NetworkEntry { asn, country }),
+                None => warnings.push(format!("line {}: invalid network '{}'", number + 1, cidr)),
+            }
+        }
+
+        (database, warnings)
+    }
+
+    fn insert(&mut self, address: IpAddr, prefix_len: u8, entry: NetworkEntry) {
+        let key = (address.is_ipv6(), prefix_len);
+        let network = mask(address, prefix_len);
+        if !self.networks.contains_key(&key) {
+            self.prefix_lengths.push(key);
+            // Longest prefixes first
+            self.prefix_lengths.sort_by_key(|&(_, prefix_len)| std::cmp::Reverse(prefix_len));
+        }
+        if self.networks.entry(key).or_default().insert(network, entry).is_none() {
+            self.entries += 1;
+        }
+    }
+
+    pub fn entry_count(&self) -> usize {
+        self.entries
+    }
+
+    /// Always answers: addresses outside every known network fall back to
+    /// their /24 (IPv4) or /48 (IPv6) with unknown ASN and country.
+    pub fn lookup(&self, ip: IpAddr) -> NetworkInfo {
+        let ip = canonical(ip);
+        for &(is_ipv6, prefix_len) in &self.prefix_lengths {
+            if is_ipv6 != ip.is_ipv6() {
+                continue;
+            }
+            let network = mask(ip, prefix_len);
+            if let Some(entry) = self.networks.get(&(is_ipv6, prefix_len)).and_then(|networks| networks.get(&network)) {
+                return NetworkInfo {
+                    network: format_network(is_ipv6, network, prefix_len),
+                    asn: entry.asn,
+                    country: entry.country.clone(),
+                };
+            }
+        }
+
+        NetworkInfo {
+            network: site_network(ip),
+            asn: None,
+            country: None,
+        }
+    }
+}
+
+/// The /24 (IPv4) or /48 (IPv6) an address belongs to, the usual unit one
+/// operator controls.
+pub fn site_network(ip: IpAddr) -> String {
+    let ip = canonical(ip);
+    let prefix_len = if ip.is_ipv6() { 48 } else { 24 };
+    format_network(ip.is_ipv6(), mask(ip, prefix_len), prefix_len)
+}
+
+/// Loopback, private, link-local and unspecified addresses on either
+/// family: RFC 1918 and 169.254/16 for IPv4, fc00::/7 (ULA) and fe80::/10
+/// for IPv6.
+pub fn is_internal_address(ip: IpAddr) -> bool {
+    match canonical(ip) {
+        IpAddr::V4(ipv4) => ipv4.is_private() || ipv4.is_loopback() || ipv4.is_link_local() || ipv4.is_unspecified(),
+        IpAddr::V6(ipv6) => {
+            let first = ipv6.segments()[0];
+            ipv6.is_loopback() || ipv6.is_unspecified() || first & 0xfe00 == 0xfc00 || first & 0xffc0 == 0xfe80
+        }
+    }
+}
+
+/// The address a source is tracked and blocked as. IPv6 sources collapse to
+/// their `ipv6_prefix_len` network, since one host can rotate through a
+/// whole /64.
+pub fn aggregate_address(ip: IpAddr, ipv6_prefix_len: u8) -> IpAddr {
+    match canonical(ip) {
+        ip @ IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::from(mask(ip, ipv6_prefix_len))),
+        ipv4 => ipv4,
+    }
+}
+
+/// Parses `addr/len` or a bare address into a network and prefix length.
+pub fn parse_network(cidr: &str) -> Option<(IpAddr, u8)> {
+    parse_cidr(cidr)
+}
+
+pub fn network_contains(network: (IpAddr, u8), ip: IpAddr) -> bool {
+    let ip = canonical(ip);
+    ip.is_ipv6() == network.0.is_ipv6() && mask(ip, network.1) == mask(network.0, network.1)
+}
+
+/// IPv4-mapped IPv6 addresses are looked up as IPv4.
+fn canonical(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(ipv6) => match ipv6.to_ipv4_mapped() {
+            Some(ipv4) => IpAddr::V4(ipv4),
+            None => ip,
+        },
+        IpAddr::V4(_) => ip,
+    }
+}
+
+fn parse_cidr(cidr: &str) -> Option<(IpAddr, u8)> {
+    let (address, prefix_len) = match cidr.split_once('/') {
+        Some((address, prefix_len)) => (address.parse::<IpAddr>().ok()?, prefix_len.parse::<u8>().ok()?),
+        None => {
+            let address = cidr.parse::<IpAddr>().ok()?;
+            (address, if address.is_ipv6() { 128 } else { 32 })
+        }
+    };
+
+    let max_len = if address.is_ipv6() { 128 } else { 32 };
+    if prefix_len > max_len {
+        return None;
+    }
+    Some((canonical(address), prefix_len))
+}
+
+fn mask(ip: IpAddr, prefix_len: u8) -> u128 {
+    match ip {
+        IpAddr::V4(ipv4) => {
+            let bits = u32::from(ipv4) as u128;
+            let host_bits = 32 - prefix_len.min(32) as u32;
+            (bits >> host_bits) << host_bits
+        }
+        IpAddr::V6(ipv6) => {
+            let bits = u128::from(ipv6);
+            let host_bits = 128 - prefix_len.min(128) as u32;
+            bits.checked_shr(host_bits).unwrap_or(0).checked_shl(host_bits).unwrap_or(0)
+        }
+    }
+}
+
+fn format_network(is_ipv6: bool, network: u128, prefix_len: u8) -> String {
+    if is_ipv6 {
+        format!("{}/{}", Ipv6Addr::from(network), prefix_len)
+    } else {
+        format!("{}/{}", Ipv4Addr::from(network as u32), prefix_len)
+    }
+}
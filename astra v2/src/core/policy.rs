@@ -0,0 +1,324 @@
+use std::fs;
+use std::net::IpAddr;
+use std::path::Path;
+use serde::{Deserialize, Serialize};
+
+use crate::core::netinfo::{self, NetworkInfo};
+use crate::SecurityEvent;
+
+/// One step of a response. Durations are in seconds.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum ResponseAction {
+    Log,
+    RateLimit { limit: u32, window: u64, duration: u64 },
+    TempBlock { duration: u64 },
+    PermanentBlock,
+    Tarpit { duration: u64 },
+    /// Runs `command` with the event in ASTRA_* environment variables
+    Notify { command: String },
+}
+
+impl ResponseAction {
+    /// Whether the action puts the source under a firewall rule.
+    pub fn is_enforcement(&self) -> bool {
+        !matches!(self, ResponseAction::Log | ResponseAction::Notify { .. })
+    }
+}
+
+/// Conditions a rule needs; anything left out matches every event. Lists
+/// match when any entry does, and event types or categories ending in `*`
+/// match by prefix.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PolicyMatch {
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub event_types: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub categories: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_level: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_score: Option<f32>,         // Inclusive
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_score: Option<f32>,         // Exclusive
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_offenses: Option<u32>,      // Earlier responses to the same source
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_offenses: Option<u32>,      // Inclusive
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub countries: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub asns: Vec<u32>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub networks: Vec<String>,          // CIDR
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ip_version: Option<u8>,
+}
+
+/// Replaces a rule's actions once the source has been responded to at least
+/// `offenses` times before.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EscalationStep {
+    pub offenses: u32,
+    pub actions: Vec<ResponseAction>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PolicyRule {
+    pub name: String,
+    #[serde(default)]
+    pub when: PolicyMatch,
+    pub actions: Vec<ResponseAction>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub escalation: Vec<EscalationStep>,
+}
+
+/// What a rule is judged against: the event plus what the engine knows
+/// about its source at that moment.
+#[derive(Debug, Clone)]
+pub struct PolicySubject<'a> {
+    pub event: &'a SecurityEvent,
+    pub source: IpAddr,
+    pub threat_score: f32,
+    pub offenses: u32,
+    pub network: &'a NetworkInfo,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PolicyDecision {
+    pub rule: String,
+    pub actions: Vec<ResponseAction>,
+}
+
+/// Ordered response rules read from the policy file. The first rule that
+/// matches an event decides the response; an event no rule matches is only
+/// scored.
+#[derive(Debug, Clone)]
+pub struct ResponsePolicy {
+    rules: Vec<PolicyRule>,
+    networks: Vec<Vec<(IpAddr, u8)>>,   // Parsed `when.networks`, per rule
+}
+
+impl ResponsePolicy {
+    /// Loads the policy, writing the default one to `path` if it does not
+    /// exist yet.
+    pub fn load(path: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        if !Path::new(path).exists() {
+            let policy = Self::default_rules();
+            if let Some(parent) = Path::new(path).parent() {
+                if fs::create_dir_all(parent).is_ok() {
+                    let _ = fs::write(path, serde_json::to_string_pretty(&policy)?);
+                }
+            }
+            return Self::new(policy);
+        }
+
+        let data = fs::read_to_string(path)?;
+        Self::parse(&data).map_err(|e| format!("response policy {}: {}", path, e).into())
+    }
+
+    pub fn parse(data: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        Self::new(serde_json::from_str(data)?)
+    }
+
+    pub fn new(rules: Vec<PolicyRule>) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut networks = Vec::new();
+        for rule in &rules {
+            Self::validate(rule)?;
+            let parsed = rule.when.networks.iter()
+                .map(|cidr| netinfo::parse_network(cidr).ok_or_else(|| format!("rule '{}': invalid network '{}'", rule.name, cidr)))
+                .collect::<Result<Vec<_>, _>>()?;
+            networks.push(parsed);
+        }
+        Ok(ResponsePolicy { rules, networks })
+    }
+
+    /// The tiers ASTRA used before policies, plus a ladder that lengthens
+    /// bans for sources that come back after their block expired.
+    pub fn default_rules() -> Vec<PolicyRule> {
+        vec![
+            PolicyRule {
+                name: "critical-threat".to_string(),
+                when: PolicyMatch { min_score: Some(0.9), ..PolicyMatch::default() },
+                actions: vec![ResponseAction::Log, ResponseAction::PermanentBlock],
+                escalation: Vec::new(),
+            },
+            PolicyRule {
+                name: "high-threat".to_string(),
+                when: PolicyMatch { min_score: Some(0.8), ..PolicyMatch::default() },
+                actions: vec![ResponseAction::Log, ResponseAction::TempBlock { duration: 6 * 3600 }],
+                escalation: vec![
+                    EscalationStep { offenses: 1, actions: vec![ResponseAction::Log, ResponseAction::TempBlock { duration: 24 * 3600 }] },
+                    EscalationStep { offenses: 2, actions: vec![ResponseAction::Log, ResponseAction::TempBlock { duration: 7 * 24 * 3600 }] },
+                    EscalationStep { offenses: 4, actions: vec![ResponseAction::Log, ResponseAction::PermanentBlock] },
+                ],
+            },
+        ]
+    }
+
+    pub fn rule_count(&self) -> usize {
+        self.rules.len()
+    }
+
+    pub fn evaluate(&self, subject: &PolicySubject) -> Option<PolicyDecision> {
+        self.rules.iter().zip(&self.networks)
+            .find(|(rule, networks)| Self::matches(&rule.when, networks, subject))
+            .map(|(rule, _)| {
+                // Highest step the source has reached, else the rule's own actions
+                let actions = rule.escalation.iter()
+                    .filter(|step| subject.offenses >= step.offenses)
+                    .max_by_key(|step| step.offenses)
+                    .map_or(&rule.actions, |step| &step.actions);
+                PolicyDecision { rule: rule.name.clone(), actions: actions.clone() }
+            })
+    }
+
+    fn matches(when: &PolicyMatch, networks: &[(IpAddr, u8)], subject: &PolicySubject) -> bool {
+        let event = subject.event;
+        let version = if subject.source.is_ipv4() { 4 } else { 6 };
+
+        matches_any(&when.event_types, &event.event_type)
+            && matches_any(&when.categories, &event.threat_level.category)
+            && when.min_level.is_none_or(|level| event.threat_level.level >= level)
+            && when.min_score.is_none_or(|score| subject.threat_score >= score)
+            && when.max_score.is_none_or(|score| subject.threat_score < score)
+            && when.min_offenses.is_none_or(|offenses| subject.offenses >= offenses)
+            && when.max_offenses.is_none_or(|offenses| subject.offenses <= offenses)
+            && (when.countries.is_empty() || subject.network.country.as_ref().is_some_and(|country| {
+                when.countries.iter().any(|wanted| wanted.eq_ignore_ascii_case(country))
+            }))
+            && (when.asns.is_empty() || subject.network.asn.is_some_and(|asn| when.asns.contains(&asn)))
+            && (networks.is_empty() || networks.iter().any(|network| netinfo::network_contains(*network, subject.source)))
+            && when.ip_version.is_none_or(|wanted| wanted == version)
+    }
+
+    fn validate(rule: &PolicyRule) -> Result<(), String> {
+        if rule.name.is_empty() {
+            return Err("rule without a name".to_string());
+        }
+        if let (Some(min), Some(max)) = (rule.when.min_score, rule.when.max_score) {
+            if min >= max {
+                return Err(format!("rule '{}': min_score must be below max_score", rule.name));
+            }
+        }
+        if rule.when.ip_version.is_some_and(|version| version != 4 && version != 6) {
+            return Err(format!("rule '{}': ip_version must be 4 or 6", rule.name));
+        }
+        if rule.actions.is_empty() {
+            return Err(format!("rule '{}': no actions", rule.name));
+        }
+        for action in rule.actions.iter().chain(rule.escalation.iter().flat_map(|step| &step.actions)) {
+            let valid = match action {
+                ResponseAction::RateLimit { limit, window, duration } => *limit > 0 && *window > 0 && *duration > 0,
+                ResponseAction::TempBlock { duration } | ResponseAction::Tarpit { duration } => *duration > 0,
+                ResponseAction::Notify { command } => !command.is_empty(),
+                ResponseAction::Log | ResponseAction::PermanentBlock => true,
+            };
+            if !valid {
+                return Err(format!("rule '{}': invalid action {:?}", rule.name, action));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn matches_any(patterns: &[String], value: &str) -> bool {
+    patterns.is_empty() || patterns.iter().any(|pattern| match pattern.strip_suffix('*') {
+        Some(prefix) => value.starts_with(prefix),
+        None => pattern == value,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    use crate::ThreatLevel;
+
+    fn event(event_type: &str, category: &str, level: u8) -> SecurityEvent {
+        SecurityEvent {
+            timestamp: Utc::now(),
+            source_ip: "203.0.113.7".parse().unwrap(),
+            event_type: event_type.to_string(),
+            threat_level: ThreatLevel { level, confidence: 0.9, category: category.to_string() },
+            details: String::new(),
+            action_taken: "MONITORED".to_string(),
+            evidence_id: None,
+            related_ips: Vec::new(),
+        }
+    }
+
+    fn network(asn: Option<u32>, country: Option<&str>) -> NetworkInfo {
+        NetworkInfo { network: "203.0.113.0/24".to_string(), asn, country: country.map(str::to_string) }
+    }
+
+    fn decide(policy: &ResponsePolicy, event: &SecurityEvent, score: f32, offenses: u32, network: &NetworkInfo) -> Option<PolicyDecision> {
+        policy.evaluate(&PolicySubject { event, source: event.source_ip, threat_score: score, offenses, network })
+    }
+
+    #[test]
+    fn default_rules_keep_the_old_tiers() {
+        let policy = ResponsePolicy::new(ResponsePolicy::default_rules()).unwrap();
+        let event = event("PORT_SCAN_DETECTED", "RECONNAISSANCE", 7);
+        let unknown = network(None, None);
+
+        assert_eq!(decide(&policy, &event, 0.5, 0, &unknown), None);
+        let high = decide(&policy, &event, 0.85, 0, &unknown).unwrap();
+        assert_eq!(high.rule, "high-threat");
+        assert_eq!(high.actions, vec![ResponseAction::Log, ResponseAction::TempBlock { duration: 6 * 3600 }]);
+        let critical = decide(&policy, &event, 0.95, 0, &unknown).unwrap();
+        assert_eq!(critical.actions, vec![ResponseAction::Log, ResponseAction::PermanentBlock]);
+    }
+
+    #[test]
+    fn repeat_offenders_climb_the_ladder() {
+        let policy = ResponsePolicy::new(ResponsePolicy::default_rules()).unwrap();
+        let event = event("SIP_BRUTE_FORCE", "CREDENTIAL_ATTACK", 8);
+        let unknown = network(None, None);
+        let ban = |offenses| decide(&policy, &event, 0.85, offenses, &unknown).unwrap().actions[1].clone();
+
+        assert_eq!(ban(1), ResponseAction::TempBlock { duration: 24 * 3600 });
+        assert_eq!(ban(3), ResponseAction::TempBlock { duration: 7 * 24 * 3600 });
+        assert_eq!(ban(4), ResponseAction::PermanentBlock);
+        assert_eq!(ban(9), ResponseAction::PermanentBlock);
+    }
+
+    #[test]
+    fn first_matching_rule_wins_and_attributes_narrow_it() {
+        let policy = ResponsePolicy::parse(r#"[
+            {"name": "toll-fraud", "when": {"event_types": ["SIP_TOLL_FRAUD*"]},
+             "actions": [{"action": "permanent_block"}, {"action": "notify", "command": "/usr/local/bin/page-oncall"}]},
+            {"name": "hostile-as", "when": {"asns": [64500], "categories": ["RECONNAISSANCE"]},
+             "actions": [{"action": "tarpit", "duration": 3600}]},
+            {"name": "v6-lab", "when": {"networks": ["2001:db8::/32"], "ip_version": 6},
+             "actions": [{"action": "log"}]},
+            {"name": "mid-score", "when": {"min_score": 0.4, "max_score": 0.8, "max_offenses": 0},
+             "actions": [{"action": "rate_limit", "limit": 10, "window": 300, "duration": 3600}]}
+        ]"#).unwrap();
+        let hostile = network(Some(64500), Some("FR"));
+        let unknown = network(None, None);
+
+        let fraud = event("SIP_TOLL_FRAUD_PREMIUM", "TOLL_FRAUD", 9);
+        assert_eq!(decide(&policy, &fraud, 0.1, 0, &hostile).unwrap().rule, "toll-fraud");
+
+        let scan = event("UDP_SCAN_DETECTED", "RECONNAISSANCE", 6);
+        assert_eq!(decide(&policy, &scan, 0.1, 0, &hostile).unwrap().rule, "hostile-as");
+        assert_eq!(decide(&policy, &scan, 0.1, 0, &unknown), None);
+        assert_eq!(decide(&policy, &scan, 0.5, 0, &unknown).unwrap().rule, "mid-score");
+        assert_eq!(decide(&policy, &scan, 0.5, 1, &unknown), None);
+
+        let mut lab = event("ICMP_SWEEP_DETECTED", "RECONNAISSANCE", 6);
+        lab.source_ip = "2001:db8::1".parse().unwrap();
+        assert_eq!(decide(&policy, &lab, 0.1, 0, &unknown).unwrap().rule, "v6-lab");
+    }
+
+    #[test]
+    fn invalid_policies_are_rejected() {
+        assert!(ResponsePolicy::parse(r#"[{"name": "empty", "actions": []}]"#).is_err());
+        assert!(ResponsePolicy::parse(r#"[{"name": "net", "when": {"networks": ["10.0.0.0/40"]}, "actions": [{"action": "log"}]}]"#).is_err());
+        assert!(ResponsePolicy::parse(r#"[{"name": "range", "when": {"min_score": 0.8, "max_score": 0.5}, "actions": [{"action": "log"}]}]"#).is_err());
+        assert!(ResponsePolicy::parse(r#"[{"name": "ban", "actions": [{"action": "temp_block", "duration": 0}]}]"#).is_err());
+        assert!(ResponsePolicy::parse(r#"[{"name": "what", "actions": [{"action": "reboot"}]}]"#).is_err());
+    }
+}
@@ -0,0 +1,356 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::ThreatProfile;
+
+const SNAPSHOT_FILE: &str = "state.json";
+const JOURNAL_FILE: &str = "state.journal";
+const STATE_VERSION: u32 = 1;
+// The journal is folded into the snapshot once it holds this many records,
+// or twice the live entries when that is more
+const MIN_COMPACTION_RECORDS: usize = 1024;
+
+/// A firewall block as it survives a restart. Expiry is wall-clock time,
+/// since an `Instant` means nothing to the next process.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StoredBlock {
+    pub expires_at: Option<DateTime<Utc>>,   // None for a permanent block
+    pub reason: String,
+    pub block_count: u32,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PersistedState {
+    #[serde(default)]
+    pub profiles: HashMap<IpAddr, ThreatProfile>,
+    #[serde(default)]
+    pub blocks: HashMap<IpAddr, StoredBlock>,
+    #[serde(default)]
+    pub event_counts: HashMap<String, u64>,
+}
+
+impl PersistedState {
+    fn entries(&self) -> usize {
+        self.profiles.len() + self.blocks.len() + self.event_counts.len()
+    }
+
+    fn apply(&mut self, record: JournalRecord) {
+        match record {
+            JournalRecord::Profile { ip, profile } => {
+                self.profiles.insert(ip, profile);
+            }
+            JournalRecord::Forget { ip } => {
+                self.profiles.remove(&ip);
+            }
+            JournalRecord::Block { ip, block } => {
+                self.blocks.insert(ip, block);
+            }
+            JournalRecord::Unblock { ip } => {
+                self.blocks.remove(&ip);
+            }
+            JournalRecord::EventCount { event_type, count } => {
+                self.event_counts.insert(event_type, count);
+            }
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Snapshot {
+    version: u32,
+    saved_at: DateTime<Utc>,
+    #[serde(flatten)]
+    state: PersistedState,
+}
+
+/// One change to the state. Records carry whole values, never deltas, so
+/// replaying a journal over a snapshot it was already folded into is harmless.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum JournalRecord {
+    Profile { ip: IpAddr, profile: ThreatProfile },
+    Forget { ip: IpAddr },
+    Block { ip: IpAddr, block: StoredBlock },
+    Unblock { ip: IpAddr },
+    EventCount { event_type: String, count: u64 },
+}
+
+/// Threat profiles, firewall blocks and event counts kept on disk across
+/// restarts. Changes go to an append-only journal of JSON lines, synced on
+/// every flush; compaction rewrites the snapshot through a synced temporary
+/// file and a rename, then empties the journal. A crash at any point leaves
+/// either the old or the new snapshot plus a journal that replays cleanly
+/// over both, at worst missing a torn final line.
+pub struct StateStore {
+    directory: PathBuf,
+    state: PersistedState,
+    journal: File,
+    journal_records: usize,
+    pending: Vec<JournalRecord>,
+    dirty_counts: HashSet<String>,
+}
+
+impl StateStore {
+    /// Loads the snapshot and replays the journal over it, then compacts so
+    /// the journal starts out empty. Returns warnings for anything that had
+    /// to be discarded.
+    pub fn open(directory: &Path) -> Result<(Self, Vec<String>), Box<dyn std::error::Error>> {
+        fs::create_dir_all(directory)?;
+        let mut warnings = Vec::new();
+
+        let snapshot_path = directory.join(SNAPSHOT_FILE);
+        let mut state = match fs::read_to_string(&snapshot_path) {
+            Ok(contents) => match serde_json::from_str::<Snapshot>(&contents) {
+                Ok(snapshot) => snapshot.state,
+                Err(e) => {
+                    // Keep the unreadable file for inspection instead of overwriting it
+                    let aside = directory.join(format!("{}.corrupt-{}", SNAPSHOT_FILE, Utc::now().format("%Y%m%d_%H%M%S")));
+                    fs::rename(&snapshot_path, &aside)?;
+                    warnings.push(format!("unreadable snapshot moved to {}: {}", aside.display(), e));
+                    PersistedState::default()
+                }
+            },
+            Err(e) if e.kind() == io::ErrorKind::NotFound => PersistedState::default(),
+            Err(e) => return Err(e.into()),
+        };
+
+        let journal_path = directory.join(JOURNAL_FILE);
+        match fs::read_to_string(&journal_path) {
+            Ok(contents) => {
+                let lines: Vec<&str> = contents.lines().filter(|line| !line.trim().is_empty()).collect();
+                for (index, line) in lines.iter().enumerate() {
+                    match serde_json::from_str::<JournalRecord>(line) {
+                        Ok(record) => state.apply(record),
+                        Err(_) if index + 1 == lines.len() && !contents.ends_with('\n') => {
+                            warnings.push("discarded a journal record torn by an unclean shutdown".to_string());
+                        }
+                        Err(e) => warnings.push(format!("skipped corrupt journal record {}: {}", index + 1, e)),
+                    }
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+
+        let journal = OpenOptions::new().create(true).append(true).open(&journal_path)?;
+        let mut store = StateStore {
+            directory: directory.to_path_buf(),
+            state,
+            journal,
+            journal_records: 0,
+            pending: Vec::new(),
+            dirty_counts: HashSet::new(),
+        };
+        store.compact()?;
+
+        Ok((store, warnings))
+    }
+
+    pub fn state(&self) -> &PersistedState {
+        &self.state
+    }
+
+    /// Queues whatever differs between `profiles` and the stored ones,
+    /// including profiles that are gone.
+    pub fn sync_profiles(&mut self, profiles: &HashMap<IpAddr, ThreatProfile>) {
+        for (ip, profile) in profiles {
+            if self.state.profiles.get(ip) != Some(profile) {
+                self.update_profile(*ip, profile.clone());
+            }
+        }
+        let forgotten: Vec<IpAddr> = self.state.profiles.keys()
+            .filter(|ip| !profiles.contains_key(ip))
+            .cloned()
+            .collect();
+        for ip in forgotten {
+            self.state.profiles.remove(&ip);
+            self.pending.push(JournalRecord::Forget { ip });
+        }
+    }
+
+    pub fn update_profile(&mut self, ip: IpAddr, profile: ThreatProfile) {
+        self.state.profiles.insert(ip, profile.clone());
+        self.pending.push(JournalRecord::Profile { ip, profile });
+    }
+
+    /// Queues whatever differs between the firewall's current blocks and
+    /// the stored ones.
+    pub fn sync_blocks(&mut self, blocks: HashMap<IpAddr, StoredBlock>) {
+        let lifted: Vec<IpAddr> = self.state.blocks.keys()
+            .filter(|ip| !blocks.contains_key(ip))
+            .cloned()
+            .collect();
+        for ip in lifted {
+            self.state.blocks.remove(&ip);
+            self.pending.push(JournalRecord::Unblock { ip });
+        }
+        for (ip, block) in blocks {
+            if self.state.blocks.get(&ip) != Some(&block) {
+                self.state.blocks.insert(ip, block.clone());
+                self.pending.push(JournalRecord::Block { ip, block });
+            }
+        }
+    }
+
+    pub fn count_event(&mut self, event_type: &str) {
+        *self.state.event_counts.entry(event_type.to_string()).or_insert(0) += 1;
+        if !self.dirty_counts.contains(event_type) {
+            self.dirty_counts.insert(event_type.to_string());
+        }
+    }
+
+    /// Appends the queued changes to the journal and syncs it, compacting
+    /// once the journal has grown well past the live state. Returns the
+    /// number of records written.
+    pub fn flush(&mut self) -> Result<usize, Box<dyn std::error::Error>> {
+        for event_type in self.dirty_counts.drain() {
+            let count = self.state.event_counts.get(&event_type).copied().unwrap_or(0);
+            self.pending.push(JournalRecord::EventCount { event_type, count });
+        }
+        if self.pending.is_empty() {
+            return Ok(0);
+        }
+
+        let mut buffer = Vec::new();
+        for record in &self.pending {
+            serde_json::to_writer(&mut buffer, record)?;
+            buffer.push(b'\n');
+        }
+        self.journal.write_all(&buffer)?;
+        self.journal.sync_data()?;
+
+        let written = self.pending.len();
+        self.pending.clear();
+        self.journal_records += written;
+
+        if self.journal_records >= MIN_COMPACTION_RECORDS.max(2 * self.state.entries()) {
+            self.compact()?;
+        }
+        Ok(written)
+    }
+
+    /// Writes the whole state as a new snapshot and empties the journal.
+    /// Queued changes are part of the snapshot, so they are dropped.
+    pub fn compact(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        let snapshot = Snapshot {
+            version: STATE_VERSION,
+            saved_at: Utc::now(),
+            state: self.state.clone(),
+        };
+        write_atomically(&self.directory.join(SNAPSHOT_FILE), &serde_json::to_vec(&snapshot)?)?;
+
+        // Until this truncation the old records replay harmlessly over the new snapshot
+        self.journal.set_len(0)?;
+        self.journal.sync_all()?;
+        self.journal_records = 0;
+        self.pending.clear();
+        self.dirty_counts.clear();
+        Ok(())
+    }
+}
+
+/// Replaces `path` so that a crash leaves either the old or the new
+/// contents, never a mix.
+fn write_atomically(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let temporary = path.with_extension("tmp");
+    {
+        let mut file = File::create(&temporary)?;
+        file.write_all(contents)?;
+        file.sync_all()?;
+    }
+    fs::rename(&temporary, path)?;
+
+    // The rename is only durable once the directory entry is synced
+    if let Some(directory) = path.parent() {
+        File::open(directory)?.sync_all()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scratch_directory(name: &str) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!("astra_state_{}_{}", name, uuid::Uuid::new_v4()));
+        fs::create_dir_all(&directory).unwrap();
+        directory
+    }
+
+    fn profile(score: f32, offenses: u32) -> ThreatProfile {
+        let now = Utc::now();
+        ThreatProfile {
+            first_seen: now,
+            last_activity: now,
+            threat_score: score,
+            events_count: 3,
+            blocked: offenses > 0,
+            auto_unblock_time: None,
+            offenses,
+        }
+    }
+
+    #[test]
+    fn journal_replays_over_the_snapshot() {
+        let directory = scratch_directory("replay");
+        let attacker: IpAddr = "203.0.113.9".parse().unwrap();
+        let scanner: IpAddr = "2001:db8:1::".parse().unwrap();
+        {
+            let (mut store, warnings) = StateStore::open(&directory).unwrap();
+            assert!(warnings.is_empty());
+            let mut profiles = HashMap::new();
+            profiles.insert(attacker, profile(0.9, 2));
+            profiles.insert(scanner, profile(0.4, 0));
+            store.sync_profiles(&profiles);
+            store.sync_blocks(HashMap::from([(attacker, StoredBlock {
+                expires_at: Some(Utc::now() + chrono::Duration::hours(6)),
+                reason: "TEMPORARY_THREAT".to_string(),
+                block_count: 2,
+            })]));
+            store.count_event("SIP_SCANNER_DETECTED");
+            store.count_event("SIP_SCANNER_DETECTED");
+            assert_eq!(store.flush().unwrap(), 4);
+
+            // Unchanged state writes nothing
+            store.sync_profiles(&profiles);
+            assert_eq!(store.flush().unwrap(), 0);
+
+            profiles.remove(&scanner);
+            store.sync_profiles(&profiles);
+            store.flush().unwrap();
+        }
+
+        let (store, warnings) = StateStore::open(&directory).unwrap();
+        assert!(warnings.is_empty());
+        assert_eq!(store.state().profiles.len(), 1);
+        let restored = &store.state().profiles[&attacker];
+        assert_eq!((restored.threat_score, restored.offenses, restored.blocked), (0.9, 2, true));
+        assert_eq!(store.state().blocks[&attacker].block_count, 2);
+        assert_eq!(store.state().event_counts["SIP_SCANNER_DETECTED"], 2);
+        assert_eq!(fs::metadata(directory.join(JOURNAL_FILE)).unwrap().len(), 0);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn torn_journal_tail_is_discarded() {
+        let directory = scratch_directory("torn");
+        let (mut store, _) = StateStore::open(&directory).unwrap();
+        store.count_event("TCP_PORT_SCAN");
+        store.flush().unwrap();
+        drop(store);
+
+        let mut journal = OpenOptions::new().append(true).open(directory.join(JOURNAL_FILE)).unwrap();
+        journal.write_all(br#"{"op":"event_count","event_type":"TCP_PO"#).unwrap();
+        drop(journal);
+
+        let (store, warnings) = StateStore::open(&directory).unwrap();
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(store.state().event_counts["TCP_PORT_SCAN"], 1);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
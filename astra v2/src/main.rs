@@ -1,17 +1,43 @@
 use std::sync::{Arc, Mutex};
-use std::thread;
 use std::time::{Duration, Instant};
 use std::collections::HashMap;
-use std::net::{IpAddr, Ipv4Addr};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
 use tokio::time::sleep;
-use serde_json;
 use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use clap::{Parser, Subcommand};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::{mpsc, Mutex as AsyncMutex};
 
 mod modules;
 mod core;
+mod replay;
 
-use modules::{tcp_guard::TcpGuard, sip_shield::SipShield};
-use core::{firewall::Firewall, logger::Logger, config::Config};
+use modules::{tcp_guard::TcpGuard, probe_guard::ProbeGuard, sip_shield::SipShield, sip_honeypot::SipHoneypot, pbx_log_source::PbxLogSource};
+use core::{capture::{CaptureManager, PacketSummary}, event_bus::EventBus, evidence::EvidenceRecorder, firewall::Firewall, logger::Logger, config::Config, netinfo::{self, NetInfoDatabase}, policy::{PolicyDecision, PolicySubject, ResponseAction, ResponsePolicy}, state_store::StateStore};
+
+#[derive(Parser)]
+#[command(name = "astra", version, about = "Advanced Stealth Threat Response Architecture")]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Run a pcap through the detectors and print the events they raise
+    Replay {
+        /// Capture file (Ethernet, Linux cooked or raw IP link types)
+        file: PathBuf,
+        /// One JSON object per event
+        #[arg(long)]
+        json: bool,
+        /// Write events here instead of stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+}
 
 #[derive(Debug, Clone)]
 pub struct ThreatLevel {
@@ -28,19 +54,27 @@ pub struct SecurityEvent {
     pub threat_level: ThreatLevel,
     pub details: String,
     pub action_taken: String,
+    pub evidence_id: Option<uuid::Uuid>,  // Incident pcap under the evidence directory
+    pub related_ips: Vec<IpAddr>,          // Other sources acting together with source_ip
 }
 
 pub struct AstraEngine {
     config: Arc<Config>,
     firewall: Arc<Mutex<Firewall>>,
     logger: Arc<Logger>,
-    tcp_guard: Arc<Mutex<TcpGuard>>,
-    sip_shield: Arc<Mutex<SipShield>>,
+    tcp_guard: Arc<AsyncMutex<TcpGuard>>,
+    sip_shield: Arc<AsyncMutex<SipShield>>,
+    capture: Mutex<CaptureManager>,
+    evidence: Arc<Mutex<EvidenceRecorder>>,
+    policy: ResponsePolicy,
+    netinfo: NetInfoDatabase,
     threat_intelligence: Arc<Mutex<HashMap<IpAddr, ThreatProfile>>>,
+    state: Mutex<StateStore>,
+    events: Arc<EventBus>,
     running: Arc<Mutex<bool>>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 struct ThreatProfile {
     first_seen: DateTime<Utc>,
     last_activity: DateTime<Utc>,
@@ -48,29 +82,123 @@ struct ThreatProfile {
     events_count: u32,
     blocked: bool,
     auto_unblock_time: Option<DateTime<Utc>>,
+    offenses: u32,                           // Policy responses so far, for escalation
 }
 
 impl AstraEngine {
+    // Analyzers wait at most this long for packets before running maintenance
+    const PACKET_POLL_INTERVAL: Duration = Duration::from_millis(250);
+    const MAX_PACKET_BATCH: usize = 256;
+    const MAINTENANCE_INTERVAL: Duration = Duration::from_secs(1);
+    const STATUS_INTERVAL: Duration = Duration::from_secs(300);
+    const ADAPTIVE_BLOCK_EVENT: &'static str = "ADAPTIVE_BLOCK";
+
     pub fn new() -> Result<Self, Box<dyn std::error::Error>> {
-        let config = Arc::new(Config::load()?);
+        let config = Config::load()?;
+        config.validate()?;
+        let config = Arc::new(config);
         let logger = Arc::new(Logger::new(&config)?);
         let firewall = Arc::new(Mutex::new(Firewall::new(&config)?));
-        let tcp_guard = Arc::new(Mutex::new(TcpGuard::new(&config, logger.clone())?));
-        let sip_shield = Arc::new(Mutex::new(SipShield::new(&config, logger.clone())?));
-        let threat_intelligence = Arc::new(Mutex::new(HashMap::new()));
+        let events = Arc::new(EventBus::new(&config));
+        let tcp_guard = Arc::new(AsyncMutex::new(TcpGuard::new(&config, logger.clone(), events.clone())?));
+        let sip_shield = Arc::new(AsyncMutex::new(SipShield::new(&config, logger.clone(), events.clone())?));
+        let evidence = Arc::new(Mutex::new(EvidenceRecorder::new(&config)));
+        let capture = Mutex::new(CaptureManager::new(&config, logger.clone(), &evidence)?);
+        let policy = ResponsePolicy::load(&config.security.response_policy_file)?;
+        // SIP Shield reports the file's parse warnings
+        let (netinfo, _) = NetInfoDatabase::load(&config.network.netinfo_file)?;
+        let (state, state_warnings) = StateStore::open(Path::new(&config.system.state_directory))?;
+        for warning in state_warnings {
+            logger.log_warning(&format!("Threat state: {}", warning))?;
+        }
+        let threat_intelligence = Arc::new(Mutex::new(Self::restore_threat_intelligence(&state, &firewall, &logger)?));
         let running = Arc::new(Mutex::new(false));
 
+        logger.log_info(&format!(
+            "Response policy: {} rules from {}", policy.rule_count(), config.security.response_policy_file
+        ))?;
         logger.log_info("ASTRA Defense Engine initialized - OPERATIONAL STATUS: GREEN")?;
         
-        Ok(AstraEngine {
+        let engine = AstraEngine {
             config,
             firewall,
             logger,
             tcp_guard,
             sip_shield,
+            capture,
+            evidence,
+            policy,
+            netinfo,
             threat_intelligence,
+            state: Mutex::new(state),
+            events,
             running,
-        })
+        };
+        // Drop whatever the restore found expired or whitelisted from disk as well
+        engine.persist_state()?;
+        Ok(engine)
+    }
+
+    /// Rebuilds the threat profiles saved by the previous run and re-applies
+    /// its blocks that are still due. Rate limits and tarpits are not kept,
+    /// so a profile whose restriction did not come back is unblocked and
+    /// evaluated afresh on its next event, its offenses still counting
+    /// towards escalation.
+    fn restore_threat_intelligence(state: &StateStore, firewall: &Mutex<Firewall>, logger: &Logger) -> Result<HashMap<IpAddr, ThreatProfile>, Box<dyn std::error::Error>> {
+        let stored = state.state();
+        let mut firewall = firewall.lock().unwrap();
+
+        let (mut restored, mut lapsed) = (0, 0);
+        for (ip, block) in &stored.blocks {
+            if firewall.is_whitelisted(*ip) {
+                lapsed += 1;
+                continue;
+            }
+            match firewall.restore_block(*ip, block) {
+                Ok(true) => restored += 1,
+                Ok(false) => lapsed += 1,
+                Err(e) => logger.log_error(&format!("Restoring the block on {} failed: {}", ip, e))?,
+            }
+        }
+
+        let mut threat_intelligence = HashMap::new();
+        for (ip, profile) in &stored.profiles {
+            if firewall.is_whitelisted(*ip) {
+                continue;
+            }
+            let mut profile = profile.clone();
+            if profile.blocked && !firewall.is_ip_blocked(*ip) {
+                profile.blocked = false;
+                profile.auto_unblock_time = None;
+            }
+            threat_intelligence.insert(*ip, profile);
+        }
+
+        logger.log_info(&format!(
+            "Threat state restored: {} profiles, {} blocks re-applied, {} lapsed while offline, {} events on record",
+            threat_intelligence.len(), restored, lapsed, stored.event_counts.values().sum::<u64>()
+        ))?;
+        Ok(threat_intelligence)
+    }
+
+    /// Writes the threat profiles, blocks and event counts that changed to
+    /// the state store. Idle profiles past the retention period, and not
+    /// blocked, are forgotten here.
+    fn persist_state(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let retention = chrono::Duration::days(self.config.system.state_retention_days as i64);
+        let now = Utc::now();
+        let profiles = {
+            let mut ti = self.threat_intelligence.lock().unwrap();
+            ti.retain(|_, profile| profile.blocked || now.signed_duration_since(profile.last_activity) < retention);
+            ti.clone()
+        };
+        let blocks = self.firewall.lock().unwrap().export_blocks();
+
+        let mut state = self.state.lock().unwrap();
+        state.sync_profiles(&profiles);
+        state.sync_blocks(blocks);
+        state.flush()?;
+        Ok(())
     }
 
     pub async fn start(&self) -> Result<(), Box<dyn std::error::Error>> {
@@ -88,11 +216,21 @@ impl AstraEngine {
         // Initialize stealth mode
         self.initialize_stealth_mode().await?;
 
+        // Every rule the engine wants is in place; what the last run left beyond that goes
+        let stale_rules = self.firewall.lock().unwrap().remove_unclaimed_rules()?;
+        if stale_rules > 0 {
+            self.logger.log_info(&format!("Firewall reconciled: {} stale or duplicate rules removed", stale_rules))?;
+        }
+
+        // Consumers subscribe before any module can publish
+        let engine_events = self.events.subscribe("engine");
+        self.start_event_logger();
+
         // Start core modules
         self.start_modules().await?;
 
         // Start main defense loop
-        self.start_defense_loop().await?;
+        self.start_defense_loop(engine_events).await?;
 
         Ok(())
     }
@@ -104,6 +242,7 @@ impl AstraEngine {
         
         // Drop all ICMP ping responses
         firewall.add_stealth_rule("INPUT", "-p icmp --icmp-type echo-request -j DROP")?;
+        firewall.add_stealth_rule("INPUT", "-p ipv6-icmp --icmpv6-type echo-request -j DROP")?;
         
         // Drop TCP RST packets that could reveal open ports
         firewall.add_stealth_rule("INPUT", "-p tcp --tcp-flags RST RST -j DROP")?;
@@ -119,65 +258,229 @@ impl AstraEngine {
         Ok(())
     }
 
+    /// Writes every published event to the security log and audit trail.
+    fn start_event_logger(&self) {
+        let mut events = self.events.subscribe("logger");
+        let logger = self.logger.clone();
+        tokio::spawn(async move {
+            while let Some(event) = events.recv().await {
+                if let Err(e) = logger.log_security_event(&event) {
+                    eprintln!("Security event logging failed: {}", e);
+                }
+            }
+        });
+    }
+
     async fn start_modules(&self) -> Result<(), Box<dyn std::error::Error>> {
         let tcp_guard = self.tcp_guard.clone();
         let sip_shield = self.sip_shield.clone();
         let running = self.running.clone();
         let logger = self.logger.clone();
 
-        // TCP Guardian Thread
+        // Analyzers subscribe before capture starts so no early packets are missed
+        let (mut tcp_packets, mut sip_packets, probe_packets) = {
+            let mut capture = self.capture.lock().unwrap();
+            let tcp_packets = capture.subscribe("tcp_guard", TcpGuard::packet_filter(), TcpGuard::kernel_filter(&self.config));
+            let sip_packets = capture.subscribe("sip_shield", SipShield::packet_filter(&self.config), SipShield::kernel_filter(&self.config)?);
+            let probe_packets = self.config.modules.probe_guard.enabled
+                .then(|| capture.subscribe("probe_guard", ProbeGuard::packet_filter(), ProbeGuard::kernel_filter(&self.config)));
+            capture.refresh()?;
+            (tcp_packets, sip_packets, probe_packets)
+        };
+
+        // TCP Guardian
         let tcp_running = running.clone();
         let tcp_logger = logger.clone();
         tokio::spawn(async move {
             tcp_logger.log_info("TCP Guardian module - ACTIVE").unwrap();
+            let mut last_maintenance = Instant::now();
             while *tcp_running.lock().unwrap() {
-                if let Ok(mut guard) = tcp_guard.lock() {
-                    if let Err(e) = guard.scan_network().await {
+                let batch = Self::next_packet_batch(&mut tcp_packets).await;
+                let mut guard = tcp_guard.lock().await;
+                for packet in &batch {
+                    if let Err(e) = guard.process_packet(packet).await {
+                        tcp_logger.log_error(&format!("TCP Guardian error: {}", e)).unwrap();
+                    }
+                }
+                if last_maintenance.elapsed() > Self::MAINTENANCE_INTERVAL {
+                    if let Err(e) = guard.perform_maintenance(Instant::now()) {
                         tcp_logger.log_error(&format!("TCP Guardian error: {}", e)).unwrap();
                     }
+                    last_maintenance = Instant::now();
                 }
-                sleep(Duration::from_millis(100)).await;
             }
         });
 
-        // SIP Shield Thread
+        // SIP Shield
         let sip_running = running.clone();
         let sip_logger = logger.clone();
         tokio::spawn(async move {
             sip_logger.log_info("SIP Shield module - ACTIVE").unwrap();
+            let mut last_maintenance = Instant::now();
             while *sip_running.lock().unwrap() {
-                if let Ok(mut shield) = sip_shield.lock() {
-                    if let Err(e) = shield.monitor_sip_traffic().await {
+                let batch = Self::next_packet_batch(&mut sip_packets).await;
+                let mut shield = sip_shield.lock().await;
+                for packet in &batch {
+                    if let Err(e) = shield.process_packet(packet).await {
                         sip_logger.log_error(&format!("SIP Shield error: {}", e)).unwrap();
                     }
                 }
-                sleep(Duration::from_millis(50)).await;
+                if last_maintenance.elapsed() > Self::MAINTENANCE_INTERVAL {
+                    if let Err(e) = shield.perform_maintenance(Instant::now()) {
+                        sip_logger.log_error(&format!("SIP Shield error: {}", e)).unwrap();
+                    }
+                    last_maintenance = Instant::now();
+                }
             }
         });
 
+        // Probe Guard
+        if let Some(mut probe_packets) = probe_packets {
+            let mut probe_guard = ProbeGuard::new(&self.config, logger.clone(), self.events.clone())?;
+            let probe_running = running.clone();
+            let probe_logger = logger.clone();
+            tokio::spawn(async move {
+                probe_logger.log_info("Probe Guard module - ACTIVE").unwrap();
+                let mut last_maintenance = Instant::now();
+                while *probe_running.lock().unwrap() {
+                    let batch = Self::next_packet_batch(&mut probe_packets).await;
+                    for packet in &batch {
+                        if let Err(e) = probe_guard.process_packet(packet).await {
+                            probe_logger.log_error(&format!("Probe Guard error: {}", e)).unwrap();
+                        }
+                    }
+                    if last_maintenance.elapsed() > Self::MAINTENANCE_INTERVAL {
+                        if let Err(e) = probe_guard.perform_maintenance(Instant::now()) {
+                            probe_logger.log_error(&format!("Probe Guard error: {}", e)).unwrap();
+                        }
+                        last_maintenance = Instant::now();
+                    }
+                }
+            });
+        }
+
+        // SIP Honeypot
+        if self.config.modules.sip_honeypot.enabled {
+            let mut honeypot = SipHoneypot::new(&self.config, logger.clone(), self.events.clone())?;
+            let honeypot_running = running.clone();
+            let honeypot_logger = logger.clone();
+            tokio::spawn(async move {
+                honeypot_logger.log_info("SIP Honeypot module - ACTIVE").unwrap();
+                if let Err(e) = honeypot.run(honeypot_running).await {
+                    honeypot_logger.log_error(&format!("SIP Honeypot error: {}", e)).unwrap();
+                }
+            });
+        }
+
+        // PBX Log Source
+        if self.config.modules.pbx_logs.enabled {
+            let mut pbx_logs = PbxLogSource::new(&self.config, logger.clone(), self.events.clone())?;
+            let pbx_running = running.clone();
+            let pbx_logger = logger.clone();
+            tokio::spawn(async move {
+                pbx_logger.log_info("PBX Log Source module - ACTIVE").unwrap();
+                if let Err(e) = pbx_logs.run(pbx_running).await {
+                    pbx_logger.log_error(&format!("PBX Log Source error: {}", e)).unwrap();
+                }
+            });
+        }
+
         self.logger.log_info("All defense modules deployed and operational")?;
         Ok(())
     }
 
-    async fn start_defense_loop(&self) -> Result<(), Box<dyn std::error::Error>> {
+    /// Waits up to the poll interval for the next captured packet, then takes
+    /// whatever else is already queued.
+    async fn next_packet_batch(packets: &mut mpsc::Receiver<Arc<PacketSummary>>) -> Vec<Arc<PacketSummary>> {
+        let mut batch = Vec::new();
+        match tokio::time::timeout(Self::PACKET_POLL_INTERVAL, packets.recv()).await {
+            Ok(Some(packet)) => batch.push(packet),
+            // Capture has shut down
+            Ok(None) => sleep(Self::PACKET_POLL_INTERVAL).await,
+            Err(_) => {}
+        }
+        while !batch.is_empty() && batch.len() < Self::MAX_PACKET_BATCH {
+            match packets.try_recv() {
+                Ok(packet) => batch.push(packet),
+                Err(_) => break,
+            }
+        }
+        batch
+    }
+
+    fn refresh_capture(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let mut capture = self.capture.lock().unwrap();
+        capture.refresh()?;
+
+        let stats = capture.get_capture_statistics();
+        let dropped = stats.get("capture_active_dropped").copied().unwrap_or(0);
+        let kernel_drops = stats.get("capture_active_kernel_drops").copied().unwrap_or(0);
+        if dropped > 0 || kernel_drops > 0 {
+            self.logger.log_debug(&format!(
+                "Packet capture: {} packets dropped on full analyzer queues, {} dropped by full rings", dropped, kernel_drops
+            ))?;
+        }
+        Ok(())
+    }
+
+    async fn start_defense_loop(&self, mut events: mpsc::Receiver<Arc<SecurityEvent>>) -> Result<(), Box<dyn std::error::Error>> {
         self.logger.log_info("Main defense loop - ENGAGED")?;
         
         let mut cleanup_timer = Instant::now();
+        let mut status_timer = Instant::now();
+        let mut state_timer = Instant::now();
+        let state_flush = Duration::from_secs(self.config.system.state_flush_interval.max(1));
+        let mut interface_timer = Instant::now();
+        let interface_refresh = Duration::from_secs(self.config.network.interface_refresh_interval.max(1));
+        let mut tick = tokio::time::interval(Duration::from_secs(1));
+        let mut bus_dropped = HashMap::new();
         
         while *self.running.lock().unwrap() {
+            tokio::select! {
+                // Module events are scored, and acted on, as they arrive
+                Some(event) = events.recv() => {
+                    self.register_security_event(&event).await?;
+                    continue;
+                }
+                _ = tick.tick() => {}
+            }
+
             // Threat intelligence analysis every 5 seconds
             self.analyze_threat_intelligence().await?;
             
             // Cleanup expired blocks every 60 seconds
             if cleanup_timer.elapsed() > Duration::from_secs(60) {
                 self.cleanup_expired_blocks().await?;
+                self.report_event_bus_lag(&mut bus_dropped)?;
                 cleanup_timer = Instant::now();
             }
+
+            if status_timer.elapsed() > Self::STATUS_INTERVAL {
+                self.report_module_status().await?;
+                status_timer = Instant::now();
+            }
             
+            // A full disk or a read-only state directory must not stop the defense
+            if state_timer.elapsed() > state_flush {
+                if let Err(e) = self.persist_state() {
+                    self.logger.log_error(&format!("Threat state could not be saved: {}", e))?;
+                }
+                state_timer = Instant::now();
+            }
+
+            // Pick up interfaces that came up and release ones that went away
+            if interface_timer.elapsed() > interface_refresh {
+                self.refresh_capture()?;
+                interface_timer = Instant::now();
+            }
+
+            // Finish incident captures whose tail is complete
+            for path in self.evidence.lock().unwrap().expire(Instant::now())? {
+                self.logger.log_debug(&format!("Evidence capture closed: {}", path.display()))?;
+            }
+
             // Adaptive response calibration
             self.calibrate_defense_systems().await?;
-            
-            sleep(Duration::from_secs(1)).await;
         }
         
         Ok(())
@@ -186,47 +489,180 @@ impl AstraEngine {
     async fn analyze_threat_intelligence(&self) -> Result<(), Box<dyn std::error::Error>> {
         let mut ti = self.threat_intelligence.lock().unwrap();
         let now = Utc::now();
-        
+
         // Update threat scores based on activity patterns
-        for (ip, profile) in ti.iter_mut() {
+        for profile in ti.values_mut() {
             let time_since_last = now.signed_duration_since(profile.last_activity);
             let hours_elapsed = time_since_last.num_hours() as f32;
-            
+
             // Decay threat score over time (rehabilitative approach)
             if hours_elapsed > 1.0 {
                 profile.threat_score *= 0.95_f32.powf(hours_elapsed / 24.0);
             }
-            
-            // Adaptive blocking based on threat evolution
-            if profile.threat_score > 0.8 && !profile.blocked {
-                self.execute_adaptive_block(*ip, profile.threat_score).await?;
-                profile.blocked = true;
-                profile.auto_unblock_time = Some(now + chrono::Duration::hours(
-                    (profile.threat_score * 12.0) as i64
-                ));
-            }
         }
-        
+
         Ok(())
     }
 
-    async fn execute_adaptive_block(&self, ip: IpAddr, threat_score: f32) -> Result<(), Box<dyn std::error::Error>> {
+    /// Runs the response policy for `ip` against the event that just raised
+    /// its score. A source already under a firewall response is left alone
+    /// until that response expires.
+    async fn respond_to_threat(&self, ip: IpAddr, event: &SecurityEvent) -> Result<(), Box<dyn std::error::Error>> {
+        let now = Utc::now();
+        let (threat_score, events_count, offenses) = {
+            let ti = self.threat_intelligence.lock().unwrap();
+            match ti.get(&ip) {
+                Some(profile) if !profile.blocked => (profile.threat_score, profile.events_count, profile.offenses),
+                _ => return Ok(()),
+            }
+        };
+
+        let network = self.netinfo.lookup(ip);
+        let subject = PolicySubject { event, source: ip, threat_score, offenses, network: &network };
+        let decision = match self.policy.evaluate(&subject) {
+            Some(decision) => decision,
+            None => return Ok(()),
+        };
+        if !decision.actions.iter().any(ResponseAction::is_enforcement) {
+            self.execute_policy_actions(ip, event, &decision, threat_score)?;
+            return Ok(());
+        }
+
+        let evidence_id = match self.evidence.lock().unwrap().open_incident(ip, Instant::now()) {
+            Ok(id) => id,
+            Err(e) => {
+                self.logger.log_error(&format!("Evidence capture for {} failed: {}", ip, e))?;
+                None
+            }
+        };
+        let (actions, release_after) = self.execute_policy_actions(ip, event, &decision, threat_score)?;
+        if actions.is_empty() {
+            // Every firewall action failed; the next event tries again
+            return Ok(());
+        }
+
+        let profile = self.threat_intelligence.lock().unwrap().get_mut(&ip).map(|profile| {
+            profile.blocked = true;
+            profile.offenses += 1;
+            profile.auto_unblock_time = release_after.map(|duration| {
+                now + chrono::Duration::from_std(duration).unwrap_or_else(|_| chrono::Duration::days(365))
+            });
+            profile.clone()
+        });
+
+        // A response goes to disk straight away so a crash cannot orphan its rules
+        let blocks = self.firewall.lock().unwrap().export_blocks();
+        {
+            let mut state = self.state.lock().unwrap();
+            if let Some(profile) = profile {
+                state.update_profile(ip, profile);
+            }
+            state.sync_blocks(blocks);
+            // The response stands either way; the next flush retries the write
+            if let Err(e) = state.flush() {
+                self.logger.log_error(&format!("Threat state could not be saved: {}", e))?;
+            }
+        }
+
+        let event = SecurityEvent {
+            timestamp: now,
+            source_ip: ip,
+            event_type: Self::ADAPTIVE_BLOCK_EVENT.to_string(),
+            threat_level: ThreatLevel {
+                level: (threat_score * 10.0).ceil() as u8,
+                confidence: threat_score,
+                category: "Adaptive Response".to_string(),
+            },
+            details: format!(
+                "Policy '{}' matched {} at threat score {:.2} after {} events ({} earlier responses)",
+                decision.rule, event.event_type, threat_score, events_count, offenses
+            ),
+            action_taken: actions.join(", "),
+            evidence_id,
+            related_ips: Vec::new(),
+        };
+        self.events.publish_or_log(&self.logger, event)
+    }
+
+    /// Applies a policy decision in order. A failed action is logged and the
+    /// rest still run. Returns the firewall actions taken and how long until
+    /// they should be lifted, `None` when one of them is permanent.
+    fn execute_policy_actions(&self, ip: IpAddr, event: &SecurityEvent, decision: &PolicyDecision, threat_score: f32) -> Result<(Vec<String>, Option<Duration>), Box<dyn std::error::Error>> {
+        let mut taken = Vec::new();
+        let mut release_after = Some(Duration::ZERO);
+
+        for action in &decision.actions {
+            let result = match action {
+                ResponseAction::Log => self.logger.log_warning(&format!(
+                    "📋 POLICY {}: {} from {} - threat score {:.2}", decision.rule, event.event_type, ip, threat_score
+                )),
+                ResponseAction::Notify { command } => self.notify(command, ip, event, &decision.rule),
+                enforcement => match self.execute_adaptive_block(ip, enforcement) {
+                    Ok((description, duration)) => {
+                        taken.push(description);
+                        release_after = match (release_after, duration) {
+                            (Some(current), Some(duration)) => Some(current.max(duration)),
+                            _ => None,
+                        };
+                        Ok(())
+                    }
+                    Err(e) => Err(e),
+                },
+            };
+            if let Err(e) = result {
+                self.logger.log_error(&format!("Policy '{}' action {:?} for {} failed: {}", decision.rule, action, ip, e))?;
+            }
+        }
+
+        Ok((taken, release_after))
+    }
+
+    /// Puts one firewall action in place. Returns its description and
+    /// duration, `None` for a permanent one.
+    fn execute_adaptive_block(&self, ip: IpAddr, action: &ResponseAction) -> Result<(String, Option<Duration>), Box<dyn std::error::Error>> {
         let mut firewall = self.firewall.lock().unwrap();
-        
-        if threat_score > 0.9 {
-            // High threat: Complete blackhole
-            firewall.block_ip_permanent(ip)?;
-            self.logger.log_critical(&format!("🚨 HIGH THREAT NEUTRALIZED: {} - PERMANENT BLACKHOLE", ip))?;
-        } else if threat_score > 0.7 {
-            // Medium threat: Temporary aggressive block
-            firewall.block_ip_temporary(ip, Duration::from_hours(6))?;
-            self.logger.log_warning(&format!("⚠️  MEDIUM THREAT CONTAINED: {} - 6H QUARANTINE", ip))?;
-        } else {
-            // Low threat: Rate limiting
-            firewall.rate_limit_ip(ip, 10, Duration::from_minutes(5))?;
-            self.logger.log_info(&format!("📊 LOW THREAT MANAGED: {} - RATE LIMITED", ip))?;
+
+        match action {
+            ResponseAction::PermanentBlock => {
+                firewall.block_ip_permanent(ip)?;
+                self.logger.log_critical(&format!("🚨 HIGH THREAT NEUTRALIZED: {} - PERMANENT BLACKHOLE", ip))?;
+                Ok(("PERMANENT BLACKHOLE".to_string(), None))
+            }
+            ResponseAction::TempBlock { duration } => {
+                firewall.block_ip_temporary(ip, Duration::from_secs(*duration))?;
+                let description = format!("{} QUARANTINE", describe_duration(*duration));
+                self.logger.log_warning(&format!("⚠️  THREAT CONTAINED: {} - {}", ip, description))?;
+                Ok((description, Some(Duration::from_secs(*duration))))
+            }
+            ResponseAction::RateLimit { limit, window, duration } => {
+                firewall.rate_limit_ip(ip, *limit, Duration::from_secs(*window))?;
+                self.logger.log_info(&format!("📊 THREAT MANAGED: {} - RATE LIMITED to {}/{}s", ip, limit, window))?;
+                Ok(("RATE LIMITED".to_string(), Some(Duration::from_secs(*duration))))
+            }
+            ResponseAction::Tarpit { duration } => {
+                firewall.tarpit_ip(ip)?;
+                let description = format!("{} TARPIT", describe_duration(*duration));
+                self.logger.log_warning(&format!("🕸️  THREAT TARPITTED: {} - {}", ip, description))?;
+                Ok((description, Some(Duration::from_secs(*duration))))
+            }
+            ResponseAction::Log | ResponseAction::Notify { .. } => Err(format!("{:?} is not a firewall action", action).into()),
         }
-        
+    }
+
+    /// Hands the event to an operator command without waiting for it. The
+    /// first word of `command` is the program, the rest its arguments.
+    fn notify(&self, command: &str, ip: IpAddr, event: &SecurityEvent, rule: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let mut words = command.split_whitespace();
+        let program = words.next().ok_or("empty notify command")?;
+        tokio::process::Command::new(program)
+            .args(words)
+            .env("ASTRA_SOURCE_IP", ip.to_string())
+            .env("ASTRA_EVENT_TYPE", &event.event_type)
+            .env("ASTRA_CATEGORY", &event.threat_level.category)
+            .env("ASTRA_LEVEL", event.threat_level.level.to_string())
+            .env("ASTRA_DETAILS", &event.details)
+            .env("ASTRA_POLICY_RULE", rule)
+            .spawn()?;
         Ok(())
     }
 
@@ -258,47 +694,83 @@ impl AstraEngine {
 
     async fn calibrate_defense_systems(&self) -> Result<(), Box<dyn std::error::Error>> {
         // Dynamic calibration based on current threat landscape
-        let ti = self.threat_intelligence.lock().unwrap();
-        let active_threats = ti.values().filter(|p| p.blocked).count();
+        let active_threats = {
+            let ti = self.threat_intelligence.lock().unwrap();
+            ti.values().filter(|p| p.blocked).count()
+        };
         
+        // Levels move relative to what the operator configured, never below it
+        let modules = &self.config.modules;
         if active_threats > 100 {
             // High threat environment - increase sensitivity
-            if let Ok(mut tcp_guard) = self.tcp_guard.lock() {
-                tcp_guard.set_sensitivity_level(9)?;
-            }
-            if let Ok(mut sip_shield) = self.sip_shield.lock() {
-                sip_shield.set_sensitivity_level(9)?;
-            }
+            self.tcp_guard.lock().await.set_sensitivity_level(Self::high_alert_sensitivity(modules.tcp_guard.sensitivity))?;
+            self.sip_shield.lock().await.set_sensitivity_level(Self::high_alert_sensitivity(modules.sip_shield.sensitivity))?;
             self.logger.log_warning("Defense systems calibrated to HIGH ALERT due to threat density")?;
         } else if active_threats < 10 {
-            // Low threat environment - normal sensitivity
-            if let Ok(mut tcp_guard) = self.tcp_guard.lock() {
-                tcp_guard.set_sensitivity_level(5)?;
-            }
-            if let Ok(mut sip_shield) = self.sip_shield.lock() {
-                sip_shield.set_sensitivity_level(5)?;
-            }
+            // Low threat environment - back to the configured sensitivity
+            self.tcp_guard.lock().await.set_sensitivity_level(modules.tcp_guard.sensitivity.clamp(1, 10))?;
+            self.sip_shield.lock().await.set_sensitivity_level(modules.sip_shield.sensitivity.clamp(1, 10))?;
         }
         
         Ok(())
     }
 
-    pub fn register_security_event(&self, event: SecurityEvent) -> Result<(), Box<dyn std::error::Error>> {
-        // Update threat intelligence
+    /// Raises a configured level by the HIGH ALERT margin, capped at 10.
+    fn high_alert_sensitivity(configured: u8) -> u8 {
+        configured.clamp(1, 10).saturating_add(4).min(10)
+    }
+
+    /// Logs what the packet analyzers are tracking, SIP Shield's dialog,
+    /// media, registration, call and TCP flow tables included.
+    async fn report_module_status(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let tcp_guard = self.tcp_guard.lock().await.get_threat_statistics();
+        self.logger.log_module_event("TCP_GUARD", "Module status", Some(serde_json::json!(tcp_guard)))?;
+        let sip_shield = self.sip_shield.lock().await.get_threat_statistics();
+        self.logger.log_module_event("SIP_SHIELD", "Module status", Some(serde_json::json!(sip_shield)))?;
+        let firewall = self.firewall.lock().unwrap().get_firewall_stats();
+        self.logger.log_module_event("FIREWALL", "Module status", Some(serde_json::json!(firewall)))?;
+        Ok(())
+    }
+
+    /// Warns about bus subscribers that dropped events since the last report.
+    fn report_event_bus_lag(&self, last_dropped: &mut HashMap<String, u64>) -> Result<(), Box<dyn std::error::Error>> {
+        let stats = self.events.get_bus_statistics();
+        for name in self.events.subscriber_names() {
+            let dropped = stats.get(&format!("{}_dropped", name)).copied().unwrap_or(0);
+            let previous = last_dropped.insert(name.clone(), dropped).unwrap_or(0);
+            if dropped > previous {
+                self.logger.log_warning(&format!(
+                    "Event bus: {} dropped {} events on a full queue ({} queued, peak {})",
+                    name,
+                    dropped - previous,
+                    stats.get(&format!("{}_backlog", name)).copied().unwrap_or(0),
+                    stats.get(&format!("{}_peak_backlog", name)).copied().unwrap_or(0)
+                ))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Scores a module event against every source it names and blocks those
+    /// that cross the threshold. Logging is left to the logger's subscription.
+    pub async fn register_security_event(&self, event: &SecurityEvent) -> Result<(), Box<dyn std::error::Error>> {
+        // The engine's own responses come back over the bus too
+        if event.event_type == Self::ADAPTIVE_BLOCK_EVENT {
+            return Ok(());
+        }
+
+        // Update threat intelligence; IPv6 sources are tracked per prefix like their blocks.
+        // Every participant of a correlated campaign is scored alike so they get blocked together.
+        // Whitelisted sources are never scored, so no response can ever target them;
+        // blacklisted ones are already dropped for good.
+        let mut sources: Vec<IpAddr> = std::iter::once(&event.source_ip).chain(&event.related_ips)
+            .map(|ip| netinfo::aggregate_address(*ip, self.config.firewall.ipv6_block_prefix))
+            .collect();
+        {
+            let firewall = self.firewall.lock().unwrap();
+            sources.retain(|source| !firewall.is_whitelisted(*source) && !firewall.is_blacklisted(*source));
+        }
         {
-            let mut ti = self.threat_intelligence.lock().unwrap();
-            let profile = ti.entry(event.source_ip).or_insert(ThreatProfile {
-                first_seen: event.timestamp,
-                last_activity: event.timestamp,
-                threat_score: 0.0,
-                events_count: 0,
-                blocked: false,
-                auto_unblock_time: None,
-            });
-            
-            profile.last_activity = event.timestamp;
-            profile.events_count += 1;
-            
             // Calculate threat score increase based on event severity
             let score_increase = match event.threat_level.level {
                 1..=3 => 0.1,
@@ -307,13 +779,61 @@ impl AstraEngine {
                 9..=10 => 0.8,
                 _ => 0.05,
             } * event.threat_level.confidence;
-            
-            profile.threat_score = (profile.threat_score + score_increase).min(1.0);
+
+            let mut ti = self.threat_intelligence.lock().unwrap();
+            for &source in &sources {
+                let profile = ti.entry(source).or_insert(ThreatProfile {
+                    first_seen: event.timestamp,
+                    last_activity: event.timestamp,
+                    threat_score: 0.0,
+                    events_count: 0,
+                    blocked: false,
+                    auto_unblock_time: None,
+                    offenses: 0,
+                });
+
+                profile.last_activity = event.timestamp;
+                profile.events_count += 1;
+                profile.threat_score = (profile.threat_score + score_increase).min(1.0);
+            }
         }
-        
-        // Log the event
-        self.logger.log_security_event(&event)?;
-        
+
+        self.state.lock().unwrap().count_event(&event.event_type);
+
+        for source in sources {
+            self.respond_to_threat(source, event).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Re-reads the whitelist and blacklist from the configuration and
+    /// applies them to the firewall; sent by SIGHUP.
+    pub fn reload_access_lists(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let config = Config::load()?;
+        let warnings = self.firewall.lock().unwrap()
+            .set_access_lists(&config.security.whitelist_ips, &config.security.blacklist_ips)?;
+        for warning in warnings {
+            self.logger.log_warning(&format!("Access lists: {}", warning))?;
+        }
+
+        // Newly whitelisted sources drop their threat history along with their blocks
+        let tracked: Vec<IpAddr> = self.threat_intelligence.lock().unwrap().keys().cloned().collect();
+        let whitelisted: Vec<IpAddr> = {
+            let firewall = self.firewall.lock().unwrap();
+            tracked.into_iter().filter(|ip| firewall.is_whitelisted(*ip)).collect()
+        };
+        {
+            let mut ti = self.threat_intelligence.lock().unwrap();
+            for ip in &whitelisted {
+                ti.remove(ip);
+            }
+        }
+
+        self.logger.log_info(&format!(
+            "Access lists reloaded: {} whitelist and {} blacklist entries, {} tracked sources now whitelisted",
+            config.security.whitelist_ips.len(), config.security.blacklist_ips.len(), whitelisted.len()
+        ))?;
         Ok(())
     }
 
@@ -324,6 +844,16 @@ impl AstraEngine {
             let mut running = self.running.lock().unwrap();
             *running = false;
         }
+
+        self.capture.lock().unwrap().shutdown();
+        self.evidence.lock().unwrap().close_all()?;
+
+        // Leave a compacted snapshot for the next start, then the ruleset,
+        // which is cleaned up even when the state cannot be written
+        if let Err(e) = self.persist_state().and_then(|_| self.state.lock().unwrap().compact()) {
+            self.logger.log_error(&format!("Threat state could not be saved: {}", e))?;
+        }
+        self.firewall.lock().unwrap().shutdown()?;
         
         // Graceful cleanup
         sleep(Duration::from_secs(2)).await;
@@ -336,6 +866,11 @@ impl AstraEngine {
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let cli = Cli::parse();
+    if let Some(Command::Replay { file, json, output }) = cli.command {
+        return replay::run_replay(&file, json, output.as_deref()).await;
+    }
+
     // ASCII Banner
     println!(r#"
     ░█████╗░░██████╗████████╗██████╗░░█████╗░
@@ -364,8 +899,30 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let astra_clone = Arc::new(astra);
     let shutdown_astra = astra_clone.clone();
     
+    // Whitelist and blacklist edits in the configuration apply on SIGHUP
+    let reload_astra = astra_clone.clone();
     tokio::spawn(async move {
-        tokio::signal::ctrl_c().await.expect("Failed to listen for Ctrl+C");
+        let mut hangup = match signal(SignalKind::hangup()) {
+            Ok(hangup) => hangup,
+            Err(e) => {
+                eprintln!("Failed to listen for SIGHUP: {}", e);
+                return;
+            }
+        };
+        while hangup.recv().await.is_some() {
+            if let Err(e) = reload_astra.reload_access_lists() {
+                eprintln!("Access list reload failed: {}", e);
+            }
+        }
+    });
+
+    // systemd stops the service with SIGTERM
+    let mut terminate = signal(SignalKind::terminate())?;
+    tokio::spawn(async move {
+        tokio::select! {
+            result = tokio::signal::ctrl_c() => result.expect("Failed to listen for Ctrl+C"),
+            _ = terminate.recv() => {}
+        }
         println!("\n🛑 Shutdown signal received...");
         if let Err(e) = shutdown_astra.shutdown().await {
             eprintln!("Error during shutdown: {}", e);
@@ -379,6 +936,19 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
+/// "6H", "7D" or "30M", the way block durations are reported.
+fn describe_duration(secs: u64) -> String {
+    if secs.is_multiple_of(86400) && secs >= 2 * 86400 {
+        format!("{}D", secs / 86400)
+    } else if secs.is_multiple_of(3600) {
+        format!("{}H", secs / 3600)
+    } else if secs.is_multiple_of(60) {
+        format!("{}M", secs / 60)
+    } else {
+        format!("{}S", secs)
+    }
+}
+
 fn is_root() -> bool {
     unsafe { libc::getuid() == 0 }
 }
@@ -1,2 +1,14 @@
 pub mod tcp_guard;
-pub mod sip_shield;
\ No newline at end of file
+pub mod probe_guard;
+pub mod scan_correlator;
+pub mod slow_scan;
+pub mod sip_shield;
+pub mod sip_parser;
+pub mod sip_toll_fraud;
+pub mod sip_fingerprint;
+pub mod sip_stream;
+pub mod sip_honeypot;
+pub mod pbx_log_source;
+pub mod sip_registration;
+pub mod sip_media;
+pub mod sip_dialog;
\ No newline at end of file
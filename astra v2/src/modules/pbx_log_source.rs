@@ -0,0 +1,568 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::net::IpAddr;
+use std::os::unix::fs::MetadataExt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use chrono::Utc;
+use regex::Regex;
+use tokio::time::sleep;
+
+use crate::core::{event_bus::EventBus, config::Config, logger::Logger};
+use crate::modules::sip_shield::SlidingWindow;
+use crate::{SecurityEvent, ThreatLevel};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PbxKind {
+    Asterisk,
+    FreeSwitch,
+}
+
+impl PbxKind {
+    fn name(&self) -> &'static str {
+        match self {
+            PbxKind::Asterisk => "Asterisk",
+            PbxKind::FreeSwitch => "FreeSWITCH",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PbxFailureKind {
+    InvalidPassword,
+    ChallengeResponseFailed,
+    InvalidAccountId,
+    AuthFailure,
+    UnknownUser,
+}
+
+impl PbxFailureKind {
+    /// Failures that name an account which does not exist on the PBX.
+    fn is_unknown_account(&self) -> bool {
+        matches!(self, PbxFailureKind::InvalidAccountId | PbxFailureKind::UnknownUser)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PbxAuthFailure {
+    pub pbx: PbxKind,
+    pub kind: PbxFailureKind,
+    pub remote_ip: IpAddr,
+    pub remote_port: Option<u16>,
+    pub account: Option<String>,
+    pub service: Option<String>,
+}
+
+/// Line parser for the PBX log formats we understand.
+pub struct PbxLogParser {
+    asterisk_field: Regex,
+    freeswitch_auth_failure: Regex,
+    freeswitch_unknown_user: Regex,
+}
+
+impl PbxLogParser {
+    pub fn new() -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(PbxLogParser {
+            asterisk_field: Regex::new(r#"(\w+)="([^"]*)""#)?,
+            freeswitch_auth_failure: Regex::new(
+                r"SIP auth failure \((\w+)\) on sofia profile '[^']*' for \[([^\]]*)\] from ip ([0-9A-Fa-f:.]+)"
+            )?,
+            freeswitch_unknown_user: Regex::new(r"Can't find user \[([^\]]*)\] from ([0-9A-Fa-f:.]+)")?,
+        })
+    }
+
+    pub fn parse_line(&self, pbx: PbxKind, line: &str) -> Option<PbxAuthFailure> {
+        match pbx {
+            PbxKind::Asterisk => self.parse_asterisk(line),
+            PbxKind::FreeSwitch => self.parse_freeswitch(line),
+        }
+    }
+
+    /// res_security_log lines:
+    /// `[date] SECURITY[pid] res_security_log.c: SecurityEvent="InvalidPassword",...,RemoteAddress="IPV4/UDP/203.0.113.5/5060",...`
+    fn parse_asterisk(&self, line: &str) -> Option<PbxAuthFailure> {
+        if !line.contains("SecurityEvent=") {
+            return None;
+        }
+
+        let fields: HashMap<&str, &str> = self.asterisk_field.captures_iter(line)
+            .filter_map(|caps| Some((caps.get(1)?.as_str(), caps.get(2)?.as_str())))
+            .collect();
+
+        let kind = match *fields.get("SecurityEvent")? {
+            "InvalidPassword" => PbxFailureKind::InvalidPassword,
+            "ChallengeResponseFailed" => PbxFailureKind::ChallengeResponseFailed,
+            "InvalidAccountID" => PbxFailureKind::InvalidAccountId,
+            _ => return None,
+        };
+
+        // IPV4/UDP/203.0.113.5/5060 or IPV6/UDP/2001:db8::1/5060
+        let mut address = fields.get("RemoteAddress")?.split('/');
+        let remote_ip = address.nth(2)?.parse().ok()?;
+        let remote_port = address.next().and_then(|port| port.parse().ok());
+
+        Some(PbxAuthFailure {
+            pbx: PbxKind::Asterisk,
+            kind,
+            remote_ip,
+            remote_port,
+            account: fields.get("AccountID").map(|account| account.to_string()),
+            service: fields.get("Service").map(|service| service.to_string()),
+        })
+    }
+
+    /// sofia_reg.c lines:
+    /// `... [WARNING] sofia_reg.c:1752 SIP auth failure (REGISTER) on sofia profile 'internal' for [1001@pbx] from ip 203.0.113.5`
+    fn parse_freeswitch(&self, line: &str) -> Option<PbxAuthFailure> {
+        if let Some(caps) = self.freeswitch_auth_failure.captures(line) {
+            return Some(PbxAuthFailure {
+                pbx: PbxKind::FreeSwitch,
+                kind: PbxFailureKind::AuthFailure,
+                remote_ip: caps.get(3)?.as_str().parse().ok()?,
+                remote_port: None,
+                account: caps.get(2).map(|account| account.as_str().to_string()),
+                service: caps.get(1).map(|method| method.as_str().to_string()),
+            });
+        }
+
+        let caps = self.freeswitch_unknown_user.captures(line)?;
+        Some(PbxAuthFailure {
+            pbx: PbxKind::FreeSwitch,
+            kind: PbxFailureKind::UnknownUser,
+            remote_ip: caps.get(2)?.as_str().parse().ok()?,
+            remote_port: None,
+            account: caps.get(1).map(|account| account.as_str().to_string()),
+            service: None,
+        })
+    }
+}
+
+/// Follows a log file by inode, so both rename-based rotation and
+/// copytruncate are handled without losing the lines written in between.
+pub struct LogTailer {
+    path: String,
+    pbx: PbxKind,
+    file: Option<File>,
+    inode: u64,
+    offset: u64,
+    partial: Vec<u8>,
+    start_at_end: bool,
+}
+
+impl LogTailer {
+    const MAX_LINE: usize = 64 * 1024;
+
+    pub fn new(path: &str, pbx: PbxKind) -> Self {
+        LogTailer {
+            path: path.to_string(),
+            pbx,
+            file: None,
+            inode: 0,
+            offset: 0,
+            partial: Vec::new(),
+            // History written before startup is not replayed
+            start_at_end: true,
+        }
+    }
+
+    /// Complete lines appended since the last poll. A missing file is not an
+    /// error, it is simply picked up once it appears.
+    pub fn poll(&mut self) -> io::Result<Vec<String>> {
+        let mut lines = Vec::new();
+
+        if self.file.is_none() {
+            let start_at_end = self.start_at_end;
+            self.start_at_end = false;
+            match self.open(start_at_end) {
+                Ok(()) => {}
+                Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(lines),
+                Err(e) => return Err(e),
+            }
+        }
+
+        // Drain the handle first: after a rename it still points at the rotated file
+        self.read_available(&mut lines)?;
+
+        match fs::metadata(&self.path) {
+            Ok(metadata) if metadata.ino() != self.inode => {
+                self.open(false)?;
+                self.read_available(&mut lines)?;
+            }
+            Ok(metadata) if metadata.len() < self.offset => {
+                if let Some(file) = self.file.as_mut() {
+                    file.seek(SeekFrom::Start(0))?;
+                }
+                self.offset = 0;
+                self.partial.clear();
+                self.read_available(&mut lines)?;
+            }
+            // Rotated away and not recreated yet: keep the old handle
+            _ => {}
+        }
+
+        Ok(lines)
+    }
+
+    fn open(&mut self, at_end: bool) -> io::Result<()> {
+        let mut file = File::open(&self.path)?;
+        let metadata = file.metadata()?;
+        self.offset = if at_end { file.seek(SeekFrom::End(0))? } else { 0 };
+        self.inode = metadata.ino();
+        self.partial.clear();
+        self.file = Some(file);
+        Ok(())
+    }
+
+    fn read_available(&mut self, lines: &mut Vec<String>) -> io::Result<()> {
+        let file = match self.file.as_mut() {
+            Some(file) => file,
+            None => return Ok(()),
+        };
+
+        let mut data = Vec::new();
+        self.offset += file.read_to_end(&mut data)? as u64;
+        self.partial.extend_from_slice(&data);
+
+        while let Some(position) = self.partial.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.partial.drain(..=position).collect();
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim_end();
+            if !line.is_empty() {
+                lines.push(line.to_string());
+            }
+        }
+
+        if self.partial.len() > Self::MAX_LINE {
+            self.partial.clear();
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default)]
+struct OffenderState {
+    failures: SlidingWindow,
+    unknown_accounts: HashMap<String, Instant>,
+    kinds: HashMap<PbxFailureKind, u32>,
+    last_account: Option<String>,
+    last_enumeration_alert: Option<Instant>,
+    last_seen: Option<Instant>,
+}
+
+/// Turns PBX authentication failures into security events. Works without
+/// packet capture, which makes it the only signal for TLS-only SIP.
+pub struct PbxLogSource {
+    config: Arc<Config>,
+    logger: Arc<Logger>,
+    events: Arc<EventBus>,
+    parser: PbxLogParser,
+    tailers: Vec<LogTailer>,
+    offenders: HashMap<IpAddr, OffenderState>,
+    lines_read: u32,
+    failures_parsed: u32,
+}
+
+impl PbxLogSource {
+    const ALERT_COOLDOWN: Duration = Duration::from_secs(300);
+    const OFFENDER_RETENTION: Duration = Duration::from_secs(3600);
+    const STATUS_INTERVAL: Duration = Duration::from_secs(300);
+
+    pub fn new(config: &Arc<Config>, logger: Arc<Logger>, events: Arc<EventBus>) -> Result<Self, Box<dyn std::error::Error>> {
+        let pbx_logs = &config.modules.pbx_logs;
+        let mut tailers = Vec::new();
+        for path in &pbx_logs.asterisk_security_logs {
+            tailers.push(LogTailer::new(path, PbxKind::Asterisk));
+        }
+        for path in &pbx_logs.freeswitch_logs {
+            tailers.push(LogTailer::new(path, PbxKind::FreeSwitch));
+        }
+
+        for tailer in &tailers {
+            if !std::path::Path::new(&tailer.path).exists() {
+                logger.log_warning(&format!("{} log {} not found yet - will follow it once created", tailer.pbx.name(), tailer.path))?;
+            }
+        }
+
+        logger.log_info(&format!("PBX log source initialized - following {} log files", tailers.len()))?;
+
+        Ok(PbxLogSource {
+            config: config.clone(),
+            logger,
+            events,
+            parser: PbxLogParser::new()?,
+            tailers,
+            offenders: HashMap::new(),
+            lines_read: 0,
+            failures_parsed: 0,
+        })
+    }
+
+    pub async fn run(&mut self, running: Arc<Mutex<bool>>) -> Result<(), Box<dyn std::error::Error>> {
+        let poll_interval = Duration::from_millis(self.config.modules.pbx_logs.poll_interval.max(50));
+        let mut cleanup_timer = Instant::now();
+        let mut status_timer = Instant::now();
+
+        while is_running(&running) {
+            self.poll_logs(Instant::now())?;
+
+            if cleanup_timer.elapsed() > Duration::from_secs(60) {
+                self.cleanup_old_offenders(Instant::now());
+                cleanup_timer = Instant::now();
+            }
+
+            // Like the honeypot, this task reports its own status
+            if status_timer.elapsed() > Self::STATUS_INTERVAL {
+                self.logger.log_module_event("PBX_LOGS", "Module status", Some(serde_json::json!(self.get_threat_statistics())))?;
+                status_timer = Instant::now();
+            }
+
+            sleep(poll_interval).await;
+        }
+
+        Ok(())
+    }
+
+    pub fn poll_logs(&mut self, now: Instant) -> Result<(), Box<dyn std::error::Error>> {
+        for index in 0..self.tailers.len() {
+            let pbx = self.tailers[index].pbx;
+            let lines = match self.tailers[index].poll() {
+                Ok(lines) => lines,
+                Err(e) => {
+                    self.logger.log_debug(&format!("Could not read {}: {}", self.tailers[index].path, e))?;
+                    continue;
+                }
+            };
+
+            self.lines_read = self.lines_read.saturating_add(lines.len() as u32);
+            for line in lines {
+                if let Some(failure) = self.parser.parse_line(pbx, &line) {
+                    self.record_failure(failure, now)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn record_failure(&mut self, failure: PbxAuthFailure, now: Instant) -> Result<(), Box<dyn std::error::Error>> {
+        self.failures_parsed = self.failures_parsed.saturating_add(1);
+
+        let pbx_logs = &self.config.modules.pbx_logs;
+        let window = Duration::from_secs(pbx_logs.failure_window);
+        let threshold = pbx_logs.failure_threshold.max(1) as usize;
+
+        let offender = self.offenders.entry(failure.remote_ip).or_default();
+        offender.last_seen = Some(now);
+        *offender.kinds.entry(failure.kind).or_insert(0) += 1;
+        if let Some(account) = &failure.account {
+            offender.last_account = Some(account.clone());
+            if failure.kind.is_unknown_account() {
+                offender.unknown_accounts.insert(account.clone(), now);
+            }
+        }
+        offender.unknown_accounts.retain(|_, seen| now.duration_since(*seen) <= window);
+
+        let count = offender.failures.record(now, window);
+        let brute_force = count >= threshold && offender.failures.try_alert(now, Self::ALERT_COOLDOWN);
+
+        let unknown_accounts = offender.unknown_accounts.len();
+        let enumeration = unknown_accounts >= threshold
+            && offender.last_enumeration_alert.is_none_or(|last| now.duration_since(last) >= Self::ALERT_COOLDOWN);
+        if enumeration {
+            offender.last_enumeration_alert = Some(now);
+        }
+
+        let breakdown = offender.kinds.iter()
+            .map(|(kind, total)| format!("{:?}={}", kind, total))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let last_account = offender.last_account.clone().unwrap_or_else(|| "-".to_string());
+        let remote = match failure.remote_port {
+            Some(port) => format!("{}:{}", failure.remote_ip, port),
+            None => failure.remote_ip.to_string(),
+        };
+        let service = failure.service.as_deref().unwrap_or("SIP");
+
+        if brute_force {
+            self.raise_event(failure.remote_ip, "PBX_AUTH_BRUTE_FORCE", ThreatLevel {
+                level: if count >= threshold * 3 { 9 } else { 7 },
+                // The PBX itself rejected these credentials
+                confidence: 0.95,
+                category: "CREDENTIAL_ATTACK".to_string(),
+            }, format!(
+                "{} reported {} {} authentication failures from {} in {}s ({}), last account '{}'",
+                failure.pbx.name(), count, service, remote, window.as_secs(), breakdown, last_account
+            ))?;
+        }
+
+        if enumeration {
+            self.raise_event(failure.remote_ip, "PBX_ACCOUNT_ENUMERATION", ThreatLevel {
+                level: 6,
+                confidence: 0.9,
+                category: "RECONNAISSANCE".to_string(),
+            }, format!(
+                "{} rejected {} unknown accounts from {} in {}s, last '{}'",
+                failure.pbx.name(), unknown_accounts, remote, window.as_secs(), last_account
+            ))?;
+        }
+
+        Ok(())
+    }
+
+    fn raise_event(&self, source_ip: IpAddr, event_type: &str, threat_level: ThreatLevel, details: String) -> Result<(), Box<dyn std::error::Error>> {
+        let event = SecurityEvent {
+            timestamp: Utc::now(),
+            source_ip,
+            event_type: event_type.to_string(),
+            threat_level,
+            details,
+            action_taken: "MONITORING_ENHANCED".to_string(),
+            evidence_id: None,
+            related_ips: Vec::new(),
+        };
+
+        self.events.publish_or_log(&self.logger, event)
+    }
+
+    fn cleanup_old_offenders(&mut self, now: Instant) {
+        self.offenders.retain(|_, offender| {
+            offender.last_seen.is_some_and(|seen| now.duration_since(seen) < Self::OFFENDER_RETENTION)
+        });
+    }
+
+    pub fn get_threat_statistics(&self) -> HashMap<String, u32> {
+        let mut stats = HashMap::new();
+
+        stats.insert("pbx_log_files".to_string(), self.tailers.len() as u32);
+        stats.insert("pbx_log_lines".to_string(), self.lines_read);
+        stats.insert("pbx_auth_failures".to_string(), self.failures_parsed);
+        stats.insert("pbx_offending_ips".to_string(), self.offenders.len() as u32);
+
+        stats
+    }
+}
+
+fn is_running(running: &Mutex<bool>) -> bool {
+    *running.lock().unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::path::PathBuf;
+
+    const ASTERISK_INVALID_ACCOUNT: &str = r#"[Jan 18 07:40:47] SECURITY[1891] res_security_log.c: SecurityEvent="InvalidAccountID",EventTV="2023-01-18T07:40:47.321+0000",Severity="Error",Service="PJSIP",EventVersion="1",AccountID="100",SessionID="3c26700b4b3c-1e4a6c2e@203.0.113.5",LocalAddress="IPV4/UDP/192.0.2.10/5060",RemoteAddress="IPV4/UDP/203.0.113.5/5071""#;
+    const ASTERISK_CHALLENGE_FAILED: &str = r#"[Jan 18 07:41:02] SECURITY[1891] res_security_log.c: SecurityEvent="ChallengeResponseFailed",EventTV="2023-01-18T07:41:02.117+0000",Severity="Error",Service="PJSIP",EventVersion="1",AccountID="1001",SessionID="7f2a91c0-4d1b@2001:db8::5",LocalAddress="IPV6/UDP/2001:db8::10/5060",RemoteAddress="IPV6/UDP/2001:db8::5/5060",Challenge="1674027662/5e6b1f0c",ReceivedChallenge="1674027662/5e6b1f0c",ReceivedHash="6f1ed002ab5595859014ebf0951522d9""#;
+    const ASTERISK_SUCCESS: &str = r#"[Jan 18 07:42:10] SECURITY[1891] res_security_log.c: SecurityEvent="SuccessfulAuth",EventTV="2023-01-18T07:42:10.005+0000",Severity="Informational",Service="PJSIP",EventVersion="1",AccountID="1001",SessionID="a1b2c3",LocalAddress="IPV4/UDP/192.0.2.10/5060",RemoteAddress="IPV4/UDP/198.51.100.20/5062",UsingPassword="1""#;
+    const FREESWITCH_AUTH_FAILURE: &str = "2023-01-18 07:41:02.117890 [WARNING] sofia_reg.c:1800 SIP auth failure (REGISTER) on sofia profile 'internal' for [1001@192.0.2.10] from ip 203.0.113.5";
+    const FREESWITCH_UNKNOWN_USER: &str = "2023-01-18 07:40:47.321456 [WARNING] sofia_reg.c:3210 Can't find user [100@192.0.2.10] from 203.0.113.5";
+    const FREESWITCH_CHALLENGE: &str = "2023-01-18 07:41:01.998120 [DEBUG] sofia_reg.c:1790 SIP auth challenge (REGISTER) on sofia profile 'internal' for [1001@192.0.2.10] from ip 203.0.113.5";
+
+    #[test]
+    fn parses_asterisk_security_log_failures() {
+        let parser = PbxLogParser::new().unwrap();
+
+        let failure = parser.parse_line(PbxKind::Asterisk, ASTERISK_INVALID_ACCOUNT).unwrap();
+        assert_eq!(failure.kind, PbxFailureKind::InvalidAccountId);
+        assert_eq!(failure.remote_ip, "203.0.113.5".parse::<IpAddr>().unwrap());
+        assert_eq!(failure.remote_port, Some(5071));
+        assert_eq!((failure.account.as_deref(), failure.service.as_deref()), (Some("100"), Some("PJSIP")));
+
+        let failure = parser.parse_line(PbxKind::Asterisk, ASTERISK_CHALLENGE_FAILED).unwrap();
+        assert_eq!(failure.kind, PbxFailureKind::ChallengeResponseFailed);
+        assert_eq!(failure.remote_ip, "2001:db8::5".parse::<IpAddr>().unwrap());
+        assert_eq!(failure.account.as_deref(), Some("1001"));
+
+        assert!(parser.parse_line(PbxKind::Asterisk, ASTERISK_SUCCESS).is_none());
+        assert!(parser.parse_line(PbxKind::Asterisk, "[Jan 18 07:40:47] NOTICE[1900] chan_sip.c: Registration from '<sip:100@192.0.2.10>' failed").is_none());
+        // Each parser only reads its own format
+        assert!(parser.parse_line(PbxKind::FreeSwitch, ASTERISK_INVALID_ACCOUNT).is_none());
+    }
+
+    #[test]
+    fn parses_freeswitch_sofia_failures() {
+        let parser = PbxLogParser::new().unwrap();
+
+        let failure = parser.parse_line(PbxKind::FreeSwitch, FREESWITCH_AUTH_FAILURE).unwrap();
+        assert_eq!(failure.kind, PbxFailureKind::AuthFailure);
+        assert_eq!(failure.remote_ip, "203.0.113.5".parse::<IpAddr>().unwrap());
+        assert_eq!((failure.account.as_deref(), failure.service.as_deref()), (Some("1001@192.0.2.10"), Some("REGISTER")));
+
+        let failure = parser.parse_line(PbxKind::FreeSwitch, FREESWITCH_UNKNOWN_USER).unwrap();
+        assert_eq!(failure.kind, PbxFailureKind::UnknownUser);
+        assert_eq!(failure.account.as_deref(), Some("100@192.0.2.10"));
+
+        // The challenge every client gets first is not a failure
+        assert!(parser.parse_line(PbxKind::FreeSwitch, FREESWITCH_CHALLENGE).is_none());
+        assert!(parser.parse_line(PbxKind::Asterisk, FREESWITCH_AUTH_FAILURE).is_none());
+    }
+
+    fn log_path(name: &str) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!("astra_pbx_{}_{}", name, uuid::Uuid::new_v4()));
+        fs::create_dir_all(&directory).unwrap();
+        directory.join("security.log")
+    }
+
+    fn append(path: &PathBuf, data: &str) {
+        fs::OpenOptions::new().create(true).append(true).open(path).unwrap().write_all(data.as_bytes()).unwrap();
+    }
+
+    #[test]
+    fn follows_appended_lines_from_the_end() {
+        let path = log_path("append");
+        append(&path, "written before startup\n");
+        let mut tailer = LogTailer::new(path.to_str().unwrap(), PbxKind::Asterisk);
+
+        assert!(tailer.poll().unwrap().is_empty());
+        append(&path, &format!("{}\n{}", ASTERISK_INVALID_ACCOUNT, &ASTERISK_CHALLENGE_FAILED[..40]));
+        assert_eq!(tailer.poll().unwrap(), vec![ASTERISK_INVALID_ACCOUNT.to_string()]);
+
+        // A line is only handed over once it is complete
+        append(&path, &format!("{}\n", &ASTERISK_CHALLENGE_FAILED[40..]));
+        assert_eq!(tailer.poll().unwrap(), vec![ASTERISK_CHALLENGE_FAILED.to_string()]);
+    }
+
+    #[test]
+    fn a_log_created_after_startup_is_read_from_the_start() {
+        let path = log_path("created");
+        let mut tailer = LogTailer::new(path.to_str().unwrap(), PbxKind::FreeSwitch);
+        assert!(tailer.poll().unwrap().is_empty());
+
+        append(&path, &format!("{}\n", FREESWITCH_UNKNOWN_USER));
+        assert_eq!(tailer.poll().unwrap(), vec![FREESWITCH_UNKNOWN_USER.to_string()]);
+    }
+
+    #[test]
+    fn keeps_lines_across_rename_rotation() {
+        let path = log_path("rename");
+        append(&path, "");
+        let mut tailer = LogTailer::new(path.to_str().unwrap(), PbxKind::FreeSwitch);
+        assert!(tailer.poll().unwrap().is_empty());
+
+        // logrotate renames the file, the PBX finishes writing to it, then reopens
+        let rotated = path.with_extension("log.1");
+        fs::rename(&path, &rotated).unwrap();
+        append(&rotated, &format!("{}\n", FREESWITCH_UNKNOWN_USER));
+        assert_eq!(tailer.poll().unwrap(), vec![FREESWITCH_UNKNOWN_USER.to_string()]);
+
+        append(&rotated, &format!("{}\n", FREESWITCH_CHALLENGE));
+        append(&path, &format!("{}\n", FREESWITCH_AUTH_FAILURE));
+        assert_eq!(tailer.poll().unwrap(), vec![FREESWITCH_CHALLENGE.to_string(), FREESWITCH_AUTH_FAILURE.to_string()]);
+    }
+
+    #[test]
+    fn rereads_a_copytruncated_log_from_the_start() {
+        let path = log_path("truncate");
+        append(&path, "");
+        let mut tailer = LogTailer::new(path.to_str().unwrap(), PbxKind::Asterisk);
+        assert!(tailer.poll().unwrap().is_empty());
+        append(&path, &format!("{}\n{}\n", ASTERISK_SUCCESS, ASTERISK_INVALID_ACCOUNT));
+        assert_eq!(tailer.poll().unwrap().len(), 2);
+
+        fs::write(&path, format!("{}\n", ASTERISK_CHALLENGE_FAILED)).unwrap();
+        assert_eq!(tailer.poll().unwrap(), vec![ASTERISK_CHALLENGE_FAILED.to_string()]);
+    }
+}
@@ -0,0 +1,518 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use chrono::Utc;
+use pnet::datalink;
+
+use crate::core::{event_bus::EventBus, afpacket::BpfProgram, capture::{PacketFilter, PacketSummary, TransportHeader}, config::{Config, ProbeGuardConfig}, logger::Logger, netinfo};
+use crate::modules::{scan_correlator::ScanCorrelator, slow_scan::SlowScanTracker};
+use crate::{SecurityEvent, ThreatLevel};
+
+// ICMP messages used for host discovery, and the errors we send back
+const ICMP_DEST_UNREACHABLE: u8 = 3;
+const ICMP_PORT_UNREACHABLE: u8 = 3;
+const ICMP_ECHO_REQUEST: u8 = 8;
+const ICMP_TIMESTAMP_REQUEST: u8 = 13;
+const ICMP_INFO_REQUEST: u8 = 15;
+const ICMP_ADDRESS_MASK_REQUEST: u8 = 17;
+const ICMPV6_DEST_UNREACHABLE: u8 = 1;
+const ICMPV6_PORT_UNREACHABLE: u8 = 4;
+const ICMPV6_ECHO_REQUEST: u8 = 128;
+
+// Unused bytes between an ICMP error header and the datagram it quotes
+const ICMP_ERROR_PREAMBLE: usize = 4;
+// How long a UDP flow this host started still accepts replies, as conntrack's
+// stream timeout; the table is bounded in case a local service floods it
+const OUTBOUND_FLOW_TIMEOUT: Duration = Duration::from_secs(180);
+const MAX_OUTBOUND_FLOWS: usize = 65_536;
+
+// (local address, local port, remote address, remote port)
+type UdpFlow = (IpAddr, u16, IpAddr, u16);
+
+#[derive(Debug)]
+struct ProbeProfile {
+    udp_probes: VecDeque<(Instant, u16)>,
+    closed_ports: VecDeque<(Instant, u16)>,
+    sweep_targets: VecDeque<(Instant, IpAddr, u8)>,
+    last_udp_alert: Option<Instant>,
+    last_sweep_alert: Option<Instant>,
+    last_activity: Instant,
+    threat_score: f32,
+}
+
+impl ProbeProfile {
+    fn new(now: Instant) -> Self {
+        ProbeProfile {
+            udp_probes: VecDeque::new(),
+            closed_ports: VecDeque::new(),
+            sweep_targets: VecDeque::new(),
+            last_udp_alert: None,
+            last_sweep_alert: None,
+            last_activity: now,
+            threat_score: 0.0,
+        }
+    }
+
+    fn prune(&mut self, now: Instant, window: Duration) {
+        while self.udp_probes.front().is_some_and(|(seen, _)| now.duration_since(*seen) >= window) {
+            self.udp_probes.pop_front();
+        }
+        while self.closed_ports.front().is_some_and(|(seen, _)| now.duration_since(*seen) >= window) {
+            self.closed_ports.pop_front();
+        }
+        while self.sweep_targets.front().is_some_and(|(seen, _, _)| now.duration_since(*seen) >= window) {
+            self.sweep_targets.pop_front();
+        }
+    }
+}
+
+/// This host's own addresses and the UDP flows it started. Capture sees both
+/// directions, so our own datagrams and the replies to them have to be told
+/// apart from probes by where they come from, not by their ports.
+#[derive(Debug)]
+struct HostTraffic {
+    configured: Vec<IpAddr>,
+    addresses: HashSet<IpAddr>,
+    outbound_flows: HashMap<UdpFlow, Instant>,
+}
+
+impl HostTraffic {
+    fn new(configured: Vec<IpAddr>) -> Self {
+        let mut host = HostTraffic { configured, addresses: HashSet::new(), outbound_flows: HashMap::new() };
+        host.refresh_addresses();
+        host
+    }
+
+    /// Addresses can be added or move between interfaces at runtime
+    fn refresh_addresses(&mut self) {
+        self.addresses = datalink::interfaces()
+            .iter()
+            .flat_map(|iface| iface.ips.iter().map(|network| network.ip()))
+            .chain(self.configured.iter().cloned())
+            .collect();
+    }
+
+    fn is_local(&self, address: IpAddr) -> bool {
+        self.addresses.contains(&address)
+    }
+
+    fn record_outbound(&mut self, flow: UdpFlow, now: Instant) {
+        if self.outbound_flows.len() >= MAX_OUTBOUND_FLOWS && !self.outbound_flows.contains_key(&flow) {
+            self.expire(now);
+            if self.outbound_flows.len() >= MAX_OUTBOUND_FLOWS {
+                return;
+            }
+        }
+        self.outbound_flows.insert(flow, now);
+    }
+
+    /// Whether an inbound datagram answers a flow this host started
+    fn is_reply(&self, source: (IpAddr, u16), dest: (IpAddr, u16), now: Instant) -> bool {
+        self.outbound_flows.get(&(dest.0, dest.1, source.0, source.1))
+            .is_some_and(|sent| now.duration_since(*sent) < OUTBOUND_FLOW_TIMEOUT)
+    }
+
+    fn expire(&mut self, now: Instant) {
+        self.outbound_flows.retain(|_, sent| now.duration_since(*sent) < OUTBOUND_FLOW_TIMEOUT);
+    }
+}
+
+/// Reconnaissance that TCP Guardian cannot see: UDP port scans and ICMP
+/// host discovery sweeps. The port-unreachable errors this host sends back
+/// confirm which probes hit closed ports, so they count as evidence even
+/// when the probes themselves were missed.
+pub struct ProbeGuard {
+    config: ProbeGuardConfig,
+    logger: Arc<Logger>,
+    profiles: HashMap<IpAddr, ProbeProfile>,
+    stealth_ports: HashSet<u16>,
+    enumeration_threshold: usize,
+    media_ports: Option<(u16, u16)>,
+    time_window: Duration,
+    host: HostTraffic,
+    correlator: ScanCorrelator,
+    slow_scans: SlowScanTracker,
+    events: Arc<EventBus>,
+}
+
+impl ProbeGuard {
+    pub fn new(config: &Arc<Config>, logger: Arc<Logger>, events: Arc<EventBus>) -> Result<Self, Box<dyn std::error::Error>> {
+        let guard_config = config.modules.probe_guard.clone();
+        let media = &config.modules.sip_shield.media;
+        let time_window = Duration::from_secs(guard_config.time_window.max(1));
+        let mut local_addresses = Vec::new();
+        for address in &guard_config.local_addresses {
+            match address.parse::<IpAddr>() {
+                Ok(address) => local_addresses.push(address),
+                Err(_) => logger.log_warning(&format!("Probe Guard: ignoring invalid local address '{}'", address))?,
+            }
+        }
+
+        logger.log_info(&format!(
+            "Probe Guard thresholds: {} UDP ports, {} closed ports, {} ICMP targets / {}s",
+            guard_config.udp_port_threshold, guard_config.unreachable_threshold,
+            guard_config.icmp_sweep_threshold, time_window.as_secs()
+        ))?;
+
+        Ok(ProbeGuard {
+            config: guard_config,
+            logger,
+            profiles: HashMap::new(),
+            stealth_ports: config.modules.tcp_guard.stealth_ports.iter().cloned().collect(),
+            enumeration_threshold: config.modules.tcp_guard.scan_threshold.max(2),
+            // RTP from a busy trunk spreads over many ports without being a scan
+            media_ports: Some((media.rtp_port_min, media.rtp_port_max)).filter(|_| media.enabled),
+            time_window,
+            host: HostTraffic::new(local_addresses),
+            correlator: ScanCorrelator::new(&config.modules.scan_correlation, &config.network.netinfo_file)?,
+            slow_scans: SlowScanTracker::new(config),
+            events,
+        })
+    }
+
+    pub async fn process_packet(&mut self, packet: &PacketSummary) -> Result<(), Box<dyn std::error::Error>> {
+        let now = packet.captured_at;
+        match packet.transport {
+            TransportHeader::Udp { source_port, dest_port } => {
+                if self.host.is_local(packet.source_ip) {
+                    self.host.record_outbound((packet.source_ip, source_port, packet.dest_ip, dest_port), now);
+                    return Ok(());
+                }
+                if netinfo::is_internal_address(packet.source_ip) || self.is_expected_udp(packet, source_port, dest_port) {
+                    return Ok(());
+                }
+                self.correlator.record(packet.dest_ip, packet.source_ip, dest_port, now);
+                self.record_slow_scan(packet.source_ip, dest_port, now)?;
+                self.profile(packet.source_ip, now).udp_probes.push_back((now, dest_port));
+                self.check_udp_scan(packet.source_ip, now)?;
+            }
+            TransportHeader::Icmp { icmp_type, code } => {
+                // Only errors we sent ourselves; anyone can forge one towards us
+                if let Some((prober, port)) = Self::port_unreachable_target(packet, icmp_type, code) {
+                    if self.host.is_local(packet.source_ip) && !netinfo::is_internal_address(prober) {
+                        self.profile(prober, now).closed_ports.push_back((now, port));
+                        self.record_slow_scan(prober, port, now)?;
+                        self.check_udp_scan(prober, now)?;
+                    }
+                } else if Self::is_discovery_request(packet, icmp_type)
+                    && !self.host.is_local(packet.source_ip)
+                    && !netinfo::is_internal_address(packet.source_ip)
+                {
+                    self.profile(packet.source_ip, now).sweep_targets.push_back((now, packet.dest_ip, icmp_type));
+                    self.check_icmp_sweep(packet.source_ip, now)?;
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    pub fn packet_filter() -> PacketFilter {
+        Box::new(|packet| matches!(packet.transport, TransportHeader::Udp { .. } | TransportHeader::Icmp { .. }))
+    }
+
+    pub fn kernel_filter(config: &Config) -> BpfProgram {
+        BpfProgram::udp_and_icmp(config.network.ipv6_support)
+    }
+
+    pub fn perform_maintenance(&mut self, now: Instant) -> Result<(), Box<dyn std::error::Error>> {
+        let window = self.time_window;
+        self.profiles.retain(|_, profile| now.duration_since(profile.last_activity) < window);
+        self.slow_scans.expire(now);
+        self.host.expire(now);
+        self.host.refresh_addresses();
+
+        for campaign in self.correlator.evaluate(now) {
+            self.logger.log_critical(&format!(
+                "🚨 DISTRIBUTED UDP SCAN: {} sources ({}) sweeping {}", campaign.sources.len(), campaign.scope, campaign.target
+            ))?;
+            self.events.publish_or_log(&self.logger, campaign.to_event("UDP", self.correlator.window()))?;
+        }
+
+        Ok(())
+    }
+
+    fn record_slow_scan(&mut self, source_ip: IpAddr, port: u16, now: Instant) -> Result<(), Box<dyn std::error::Error>> {
+        for scan in self.slow_scans.record(source_ip, port, now) {
+            self.logger.log_warning(&format!(
+                "🐢 SLOW UDP SCAN: {} probed ~{} ports over {}s", scan.source, scan.ports, scan.horizon.as_secs()
+            ))?;
+            self.events.publish_or_log(&self.logger, scan.to_event("UDP"))?;
+        }
+        Ok(())
+    }
+
+    fn profile(&mut self, source_ip: IpAddr, now: Instant) -> &mut ProbeProfile {
+        let profile = self.profiles.entry(source_ip).or_insert_with(|| ProbeProfile::new(now));
+        profile.last_activity = now;
+        profile
+    }
+
+    /// Replies to flows this host started and media streams, which look like
+    /// port diversity but are not probes. A low source port alone proves
+    /// nothing: `nmap -sU -g 53` sends every probe from port 53.
+    fn is_expected_udp(&self, packet: &PacketSummary, source_port: u16, dest_port: u16) -> bool {
+        let reply = self.host.is_reply((packet.source_ip, source_port), (packet.dest_ip, dest_port), packet.captured_at);
+        let media = self.media_ports.is_some_and(|(min, max)| (min..=max).contains(&dest_port));
+        reply || media
+    }
+
+    /// For a port unreachable sent by this host, the prober and the port it
+    /// tried. The quoted datagram has to be the reverse of the error itself.
+    fn port_unreachable_target(packet: &PacketSummary, icmp_type: u8, code: u8) -> Option<(IpAddr, u16)> {
+        let expected = if packet.source_ip.is_ipv6() {
+            (ICMPV6_DEST_UNREACHABLE, ICMPV6_PORT_UNREACHABLE)
+        } else {
+            (ICMP_DEST_UNREACHABLE, ICMP_PORT_UNREACHABLE)
+        };
+        if (icmp_type, code) != expected {
+            return None;
+        }
+
+        let quoted = packet.payload().get(ICMP_ERROR_PREAMBLE..)?;
+        let quoted = PacketSummary::from_ip(&packet.interface, quoted, packet.captured_at, packet.timestamp)?;
+        match quoted.transport {
+            TransportHeader::Udp { dest_port, .. } if quoted.source_ip == packet.dest_ip && quoted.dest_ip == packet.source_ip => {
+                Some((packet.dest_ip, dest_port))
+            }
+            _ => None,
+        }
+    }
+
+    fn is_discovery_request(packet: &PacketSummary, icmp_type: u8) -> bool {
+        if packet.source_ip.is_ipv6() {
+            icmp_type == ICMPV6_ECHO_REQUEST
+        } else {
+            matches!(icmp_type, ICMP_ECHO_REQUEST | ICMP_TIMESTAMP_REQUEST | ICMP_INFO_REQUEST | ICMP_ADDRESS_MASK_REQUEST)
+        }
+    }
+
+    fn check_udp_scan(&mut self, source_ip: IpAddr, now: Instant) -> Result<(), Box<dyn std::error::Error>> {
+        let window = self.time_window;
+        let profile = match self.profiles.get_mut(&source_ip) {
+            Some(profile) => profile,
+            None => return Ok(()),
+        };
+        profile.prune(now, window);
+        // One alert per window; the profile keeps counting in between
+        if profile.last_udp_alert.is_some_and(|alerted| now.duration_since(alerted) < window) {
+            return Ok(());
+        }
+
+        let closed: HashSet<u16> = profile.closed_ports.iter().map(|(_, port)| *port).collect();
+        let ports: HashSet<u16> = profile.udp_probes.iter().map(|(_, port)| *port).chain(closed.iter().cloned()).collect();
+        let enumerated = ports.intersection(&self.stealth_ports).count();
+        let confirmed = closed.len() >= self.config.unreachable_threshold.max(1);
+
+        let (scan_type, threat_increase, confidence) = if ports.len() >= self.config.udp_port_threshold.max(2) {
+            ("UDP_PORT_SCAN", 0.6, if confirmed { 0.95 } else { 0.8 })
+        } else if confirmed {
+            ("UDP_CLOSED_PORT_PROBING", 0.5, 0.9)
+        } else if enumerated >= self.enumeration_threshold {
+            ("UDP_SERVICE_ENUMERATION", 0.5, 0.85)
+        } else {
+            return Ok(());
+        };
+
+        profile.threat_score += threat_increase;
+        profile.last_udp_alert = Some(now);
+        let threat_score = profile.threat_score;
+
+        self.trigger_probe_alert(
+            source_ip,
+            "UDP_SCAN_DETECTED",
+            format!(
+                "{} - {} ports probed, {} answered port unreachable, threat_score: {:.2}",
+                scan_type, ports.len(), closed.len(), threat_score
+            ),
+            threat_score,
+            confidence,
+        )
+    }
+
+    fn check_icmp_sweep(&mut self, source_ip: IpAddr, now: Instant) -> Result<(), Box<dyn std::error::Error>> {
+        let window = self.time_window;
+        let profile = match self.profiles.get_mut(&source_ip) {
+            Some(profile) => profile,
+            None => return Ok(()),
+        };
+        profile.prune(now, window);
+        if profile.last_sweep_alert.is_some_and(|alerted| now.duration_since(alerted) < window) {
+            return Ok(());
+        }
+
+        let targets: HashSet<IpAddr> = profile.sweep_targets.iter().map(|(_, target, _)| *target).collect();
+        if targets.len() < self.config.icmp_sweep_threshold.max(2) {
+            return Ok(());
+        }
+
+        // Timestamp, information and mask requests have no everyday use
+        let unusual = profile.sweep_targets.iter()
+            .any(|(_, _, icmp_type)| !matches!(*icmp_type, ICMP_ECHO_REQUEST | ICMPV6_ECHO_REQUEST));
+        profile.threat_score += 0.4;
+        profile.last_sweep_alert = Some(now);
+        let threat_score = profile.threat_score;
+
+        self.trigger_probe_alert(
+            source_ip,
+            "ICMP_SWEEP_DETECTED",
+            format!(
+                "{} - {} hosts probed, threat_score: {:.2}",
+                if unusual { "ICMP_QUERY_SWEEP" } else { "ICMP_ECHO_SWEEP" }, targets.len(), threat_score
+            ),
+            threat_score,
+            if unusual { 0.9 } else { 0.8 },
+        )
+    }
+
+    fn trigger_probe_alert(&self, source_ip: IpAddr, event_type: &str, details: String, threat_score: f32, confidence: f32) -> Result<(), Box<dyn std::error::Error>> {
+        let event = SecurityEvent {
+            timestamp: Utc::now(),
+            source_ip,
+            event_type: event_type.to_string(),
+            threat_level: ThreatLevel {
+                level: if threat_score > 0.8 { 9 } else if threat_score > 0.5 { 7 } else { 5 },
+                confidence,
+                category: "RECONNAISSANCE".to_string(),
+            },
+            details,
+            action_taken: "MONITORING_ENHANCED".to_string(),
+            evidence_id: None,
+            related_ips: Vec::new(),
+        };
+
+        self.events.publish_or_log(&self.logger, event)
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+    use tokio::sync::mpsc;
+
+    const LOCAL: Ipv4Addr = Ipv4Addr::new(192, 0, 2, 10);
+    const SCANNER: Ipv4Addr = Ipv4Addr::new(203, 0, 113, 5);
+    const RESOLVER: Ipv4Addr = Ipv4Addr::new(198, 51, 100, 7);
+
+    fn guard(name: &str) -> (ProbeGuard, mpsc::Receiver<Arc<SecurityEvent>>) {
+        let directory = std::env::temp_dir().join(format!("astra_probe_{}_{}", name, uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let mut config = Config::default();
+        config.logging.log_file = directory.join("astra.log").to_string_lossy().to_string();
+        config.logging.audit_trail = false;
+        config.network.netinfo_file = directory.join("netinfo.csv").to_string_lossy().to_string();
+        config.modules.probe_guard.local_addresses = vec![LOCAL.to_string()];
+        let config = Arc::new(config);
+
+        let events = Arc::new(EventBus::new(&config));
+        let receiver = events.subscribe("test");
+        let logger = Arc::new(Logger::new(&config).unwrap());
+        (ProbeGuard::new(&config, logger, events).unwrap(), receiver)
+    }
+
+    fn ipv4(source: Ipv4Addr, dest: Ipv4Addr, protocol: u8, transport: &[u8]) -> Vec<u8> {
+        let mut packet = vec![0x45, 0];
+        packet.extend_from_slice(&((20 + transport.len()) as u16).to_be_bytes());
+        packet.extend_from_slice(&[0, 0, 0, 0, 64, protocol, 0, 0]);
+        packet.extend_from_slice(&source.octets());
+        packet.extend_from_slice(&dest.octets());
+        packet.extend_from_slice(transport);
+        packet
+    }
+
+    fn udp(source: Ipv4Addr, source_port: u16, dest: Ipv4Addr, dest_port: u16) -> Vec<u8> {
+        let mut header = source_port.to_be_bytes().to_vec();
+        header.extend_from_slice(&dest_port.to_be_bytes());
+        header.extend_from_slice(&[0, 8, 0, 0]);
+        ipv4(source, dest, 17, &header)
+    }
+
+    /// A port unreachable from `source` quoting the probe it answers
+    fn port_unreachable(source: Ipv4Addr, prober: Ipv4Addr, port: u16) -> Vec<u8> {
+        let mut icmp = vec![ICMP_DEST_UNREACHABLE, ICMP_PORT_UNREACHABLE, 0, 0, 0, 0, 0, 0];
+        icmp.extend_from_slice(&udp(prober, 40000, source, port));
+        ipv4(source, prober, 1, &icmp)
+    }
+
+    fn summary(packet: &[u8], now: Instant) -> PacketSummary {
+        PacketSummary::from_ip(&Arc::from("test0"), packet, now, Utc::now()).unwrap()
+    }
+
+    fn scan_alerts(receiver: &mut mpsc::Receiver<Arc<SecurityEvent>>) -> Vec<String> {
+        let mut details = Vec::new();
+        while let Ok(event) = receiver.try_recv() {
+            if event.event_type == "UDP_SCAN_DETECTED" {
+                details.push(event.details.clone());
+            }
+        }
+        details
+    }
+
+    #[tokio::test]
+    async fn own_queries_and_their_replies_are_not_probes() {
+        let (mut guard, mut events) = guard("replies");
+        let now = Instant::now();
+        for port in 40000..40020 {
+            guard.process_packet(&summary(&udp(LOCAL, port, RESOLVER, 30053), now)).await.unwrap();
+            guard.process_packet(&summary(&udp(RESOLVER, 30053, LOCAL, port), now)).await.unwrap();
+        }
+        assert!(scan_alerts(&mut events).is_empty());
+        assert!(guard.profiles.is_empty());
+    }
+
+    #[tokio::test]
+    async fn probes_from_a_low_source_port_are_detected() {
+        // nmap -sU -g 53: no outbound flow matches, so the port proves nothing
+        let (mut guard, mut events) = guard("low_port");
+        let now = Instant::now();
+        for port in 30000..30000 + guard.config.udp_port_threshold as u16 {
+            guard.process_packet(&summary(&udp(SCANNER, 53, LOCAL, port), now)).await.unwrap();
+        }
+        let alerts = scan_alerts(&mut events);
+        assert_eq!(alerts.len(), 1);
+        assert!(alerts[0].starts_with("UDP_PORT_SCAN"));
+    }
+
+    #[tokio::test]
+    async fn only_port_unreachables_we_sent_count() {
+        let (mut guard, mut events) = guard("unreachable");
+        let now = Instant::now();
+        let threshold = guard.config.unreachable_threshold as u16;
+
+        // Forged errors claiming another host turned the scanner away
+        for port in 30000..30000 + threshold {
+            guard.process_packet(&summary(&port_unreachable(RESOLVER, SCANNER, port), now)).await.unwrap();
+        }
+        assert!(scan_alerts(&mut events).is_empty());
+
+        for port in 30000..30000 + threshold {
+            guard.process_packet(&summary(&port_unreachable(LOCAL, SCANNER, port), now)).await.unwrap();
+        }
+        let alerts = scan_alerts(&mut events);
+        assert_eq!(alerts.len(), 1);
+        assert!(alerts[0].starts_with("UDP_CLOSED_PORT_PROBING"));
+    }
+
+    #[tokio::test]
+    async fn one_alert_per_window() {
+        let (mut guard, mut events) = guard("window");
+        let start = Instant::now();
+        let threshold = guard.config.udp_port_threshold as u16;
+        for port in 30000..30000 + threshold * 2 {
+            guard.process_packet(&summary(&udp(SCANNER, 40000, LOCAL, port), start)).await.unwrap();
+        }
+        assert_eq!(scan_alerts(&mut events).len(), 1);
+
+        // Probes older than the window no longer count towards the next alert
+        let later = start + guard.time_window;
+        for port in 31000..31000 + threshold - 1 {
+            guard.process_packet(&summary(&udp(SCANNER, 40000, LOCAL, port), later)).await.unwrap();
+        }
+        assert!(scan_alerts(&mut events).is_empty());
+        guard.process_packet(&summary(&udp(SCANNER, 40000, LOCAL, 32000), later)).await.unwrap();
+        assert_eq!(scan_alerts(&mut events).len(), 1);
+    }
+}
@@ -0,0 +1,278 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+use chrono::Utc;
+
+use crate::core::config::ScanCorrelationConfig;
+use crate::core::netinfo::{self, NetInfoDatabase};
+use crate::{SecurityEvent, ThreatLevel};
+
+// Participants named in the event details; the event itself carries all of them
+const LISTED_SOURCES: usize = 20;
+
+#[derive(Debug, Clone, Copy)]
+struct Probe {
+    seen: Instant,
+    source: IpAddr,
+    port: u16,
+}
+
+/// One destination swept by several sources together.
+#[derive(Debug, Clone)]
+pub struct ScanCampaign {
+    pub target: IpAddr,
+    pub scope: String,                 // "distributed", "network <cidr>" or "AS<n>"
+    pub sources: Vec<(IpAddr, usize)>, // Participants and the swept ports each hit, busiest first
+    pub ports: usize,
+}
+
+impl ScanCampaign {
+    /// A single event for the whole campaign: the busiest participant is the
+    /// source and every other one is listed in `related_ips`.
+    pub fn to_event(&self, protocol: &str, window: Duration) -> SecurityEvent {
+        let mut listed: Vec<String> = self.sources.iter()
+            .take(LISTED_SOURCES)
+            .map(|(source, ports)| format!("{} ({})", source, ports))
+            .collect();
+        if self.sources.len() > LISTED_SOURCES {
+            listed.push(format!("{} more", self.sources.len() - LISTED_SOURCES));
+        }
+
+        SecurityEvent {
+            timestamp: Utc::now(),
+            source_ip: self.sources[0].0,
+            event_type: "DISTRIBUTED_SCAN_DETECTED".to_string(),
+            threat_level: ThreatLevel {
+                level: 8,
+                // Sources sharing a network or ASN are far less likely to be a coincidence
+                confidence: if self.scope == "distributed" { 0.75 } else { 0.85 },
+                category: "RECONNAISSANCE".to_string(),
+            },
+            details: format!(
+                "{} DISTRIBUTED_PORT_SCAN - {} sources ({}) swept {} ports on {} within {}s: {}",
+                protocol, self.sources.len(), self.scope, self.ports, self.target, window.as_secs(), listed.join(", ")
+            ),
+            action_taken: "CAMPAIGN_CORRELATED".to_string(),
+            evidence_id: None,
+            related_ips: self.sources[1..].iter().map(|(source, _)| *source).collect(),
+        }
+    }
+}
+
+/// Destination-centric view of probes. Per-source detectors never see a
+/// sweep split across hundreds of addresses that each touch a port or two;
+/// here the probes are pooled per target and checked for many sources, or
+/// a whole /24 (/48) or ASN, covering a wide port range together.
+pub struct ScanCorrelator {
+    config: ScanCorrelationConfig,
+    window: Duration,
+    netinfo: NetInfoDatabase,
+    probes: HashMap<IpAddr, VecDeque<Probe>>,
+    reported: HashMap<(IpAddr, String), Instant>,
+}
+
+impl ScanCorrelator {
+    pub fn new(config: &ScanCorrelationConfig, netinfo_file: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        // SIP Shield loads the same file and reports its parse warnings
+        let (netinfo, _) = NetInfoDatabase::load(netinfo_file)?;
+
+        Ok(ScanCorrelator {
+            config: config.clone(),
+            window: Duration::from_secs(config.time_window.max(1)),
+            netinfo,
+            probes: HashMap::new(),
+            reported: HashMap::new(),
+        })
+    }
+
+    pub fn window(&self) -> Duration {
+        self.window
+    }
+
+    pub fn record(&mut self, target: IpAddr, source: IpAddr, port: u16, now: Instant) {
+        if !self.config.enabled {
+            return;
+        }
+        let probes = self.probes.entry(target).or_default();
+        probes.push_back(Probe { seen: now, source, port });
+        while probes.len() > self.config.max_probes.max(1) {
+            probes.pop_front();
+        }
+    }
+
+    /// Expires old probes and returns campaigns not already reported within
+    /// the window.
+    pub fn evaluate(&mut self, now: Instant) -> Vec<ScanCampaign> {
+        let window = self.window;
+        let mut campaigns = Vec::new();
+        for (target, probes) in self.probes.iter_mut() {
+            while probes.front().is_some_and(|probe| now.duration_since(probe.seen) >= window) {
+                probes.pop_front();
+            }
+            campaigns.extend(Self::find_campaigns(&self.config, &self.netinfo, *target, probes));
+        }
+        self.probes.retain(|_, probes| !probes.is_empty());
+
+        self.reported.retain(|_, reported_at| now.duration_since(*reported_at) < window);
+        campaigns.retain(|campaign| {
+            let key = (campaign.target, campaign.scope.clone());
+            if self.reported.contains_key(&key) {
+                return false;
+            }
+            self.reported.insert(key, now);
+            true
+        });
+        campaigns
+    }
+
+    fn find_campaigns(config: &ScanCorrelationConfig, netinfo: &NetInfoDatabase, target: IpAddr, probes: &VecDeque<Probe>) -> Vec<ScanCampaign> {
+        let min_ports = config.min_ports.max(2);
+        let min_sources = config.min_sources.max(2);
+
+        let mut port_sources: HashMap<u16, HashSet<IpAddr>> = HashMap::new();
+        for probe in probes {
+            port_sources.entry(probe.port).or_default().insert(probe.source);
+        }
+        // Ports many sources connect to are services in use, not sweep targets
+        let swept: HashSet<u16> = port_sources.iter()
+            .filter(|(_, sources)| sources.len() <= min_sources)
+            .map(|(port, _)| *port)
+            .collect();
+        if swept.len() < min_ports {
+            return Vec::new();
+        }
+
+        let mut source_ports: HashMap<IpAddr, HashSet<u16>> = HashMap::new();
+        for probe in probes.iter().filter(|probe| swept.contains(&probe.port)) {
+            source_ports.entry(probe.source).or_default().insert(probe.port);
+        }
+
+        let mut campaigns = Vec::new();
+        let participants: Vec<IpAddr> = source_ports.keys().cloned().collect();
+        if participants.len() >= min_sources {
+            campaigns.push(Self::campaign(target, "distributed".to_string(), &participants, &source_ports));
+        }
+
+        let mut groups: HashMap<String, Vec<IpAddr>> = HashMap::new();
+        for &source in &participants {
+            groups.entry(format!("network {}", netinfo::site_network(source))).or_default().push(source);
+            if let Some(asn) = netinfo.lookup(source).asn {
+                groups.entry(format!("AS{}", asn)).or_default().push(source);
+            }
+        }
+        for (scope, members) in groups {
+            if members.len() < config.min_group_sources.max(2) {
+                continue;
+            }
+            let covered: HashSet<u16> = members.iter().flat_map(|member| source_ports[member].iter().cloned()).collect();
+            if covered.len() >= min_ports {
+                campaigns.push(Self::campaign(target, scope, &members, &source_ports));
+            }
+        }
+
+        campaigns
+    }
+
+    fn campaign(target: IpAddr, scope: String, members: &[IpAddr], source_ports: &HashMap<IpAddr, HashSet<u16>>) -> ScanCampaign {
+        let mut sources: Vec<(IpAddr, usize)> = members.iter().map(|member| (*member, source_ports[member].len())).collect();
+        sources.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        let ports = members.iter()
+            .flat_map(|member| source_ports[member].iter().cloned())
+            .collect::<HashSet<u16>>()
+            .len();
+        ScanCampaign { target, scope, sources, ports }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    const TARGET: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 10));
+
+    fn build(netinfo: &str) -> ScanCorrelator {
+        let config = ScanCorrelationConfig {
+            enabled: true,
+            time_window: 300,
+            min_ports: 20,
+            min_sources: 10,
+            min_group_sources: 3,
+            max_probes: 20000,
+        };
+        ScanCorrelator {
+            window: Duration::from_secs(config.time_window),
+            config,
+            netinfo: NetInfoDatabase::parse(netinfo).0,
+            probes: HashMap::new(),
+            reported: HashMap::new(),
+        }
+    }
+
+    // Sources in different /24s, so only the distributed scope can match
+    fn scattered(index: u8) -> IpAddr {
+        IpAddr::V4(Ipv4Addr::new(203, 0, index, 5))
+    }
+
+    #[test]
+    fn reports_a_distributed_sweep_once_per_window() {
+        let mut correlator = build("");
+        let now = Instant::now();
+        for index in 0..10u8 {
+            for port in [1000 + index as u16 * 2, 1001 + index as u16 * 2] {
+                correlator.record(TARGET, scattered(index), port, now);
+            }
+        }
+
+        let campaigns = correlator.evaluate(now);
+        assert_eq!(campaigns.len(), 1);
+        assert_eq!(campaigns[0].scope, "distributed");
+        assert_eq!((campaigns[0].sources.len(), campaigns[0].ports), (10, 20));
+        let event = campaigns[0].to_event("TCP", correlator.window());
+        assert_eq!(event.related_ips.len(), 9);
+
+        assert!(correlator.evaluate(now + Duration::from_secs(10)).is_empty());
+        // Past the window the probes themselves have expired
+        assert!(correlator.evaluate(now + correlator.window()).is_empty());
+    }
+
+    #[test]
+    fn fewer_sources_or_ports_are_not_a_campaign() {
+        let mut correlator = build("");
+        let now = Instant::now();
+        for index in 0..9u8 {
+            for port in 0..3u16 {
+                correlator.record(TARGET, scattered(index), 2000 + index as u16 * 3 + port, now);
+            }
+        }
+        assert!(correlator.evaluate(now).is_empty());
+
+        // A port every source uses is a service, not part of the sweep
+        let mut correlator = build("");
+        for index in 0..12u8 {
+            correlator.record(TARGET, scattered(index), 5060, now);
+            correlator.record(TARGET, scattered(index), 3000 + index as u16, now);
+        }
+        assert!(correlator.evaluate(now).is_empty());
+    }
+
+    #[test]
+    fn groups_sources_by_network_and_asn() {
+        let mut correlator = build("198.51.0.0/16,64500,FR\n");
+        let now = Instant::now();
+        let members = [
+            IpAddr::V4(Ipv4Addr::new(198, 51, 100, 1)),
+            IpAddr::V4(Ipv4Addr::new(198, 51, 100, 2)),
+            IpAddr::V4(Ipv4Addr::new(198, 51, 100, 3)),
+        ];
+        for (index, member) in members.iter().enumerate() {
+            for port in 0..7u16 {
+                correlator.record(TARGET, *member, 4000 + index as u16 * 7 + port, now);
+            }
+        }
+
+        let mut scopes: Vec<String> = correlator.evaluate(now).into_iter().map(|campaign| campaign.scope).collect();
+        scopes.sort();
+        assert_eq!(scopes, vec!["AS64500".to_string(), "network 198.51.100.0/24".to_string()]);
+    }
+}
@@ -0,0 +1,394 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+use crate::core::config::ProtocolAbuseConfig;
+use crate::modules::sip_parser::{parse_uri, SipMessage, SipMethod, SipParseError};
+
+/// Something about a message, or its place in a dialog, that a conforming
+/// stack would not produce.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ProtocolAnomaly {
+    ParseFailure { reason: String },
+    OversizedHeader { name: String, length: usize },
+    TooManyHeaders { count: usize },
+    MissingHeader { name: &'static str },
+    DuplicateHeader { name: &'static str },
+    ConflictingHeaders { name: &'static str },
+    CSeqMismatch { method: String, cseq_method: String },
+    ContentLengthMismatch { declared: usize, actual: usize },
+    InvalidMaxForwards { value: String },
+    ControlCharacters { name: String },
+    MalformedRequestUri { uri: String },
+    OutOfState { method: String, call_id: String, reason: &'static str },
+}
+
+impl ProtocolAnomaly {
+    pub fn event_type(&self) -> &'static str {
+        match self {
+            ProtocolAnomaly::OversizedHeader { .. } | ProtocolAnomaly::TooManyHeaders { .. } => "SIP_OVERSIZED_HEADER",
+            ProtocolAnomaly::DuplicateHeader { .. } | ProtocolAnomaly::ConflictingHeaders { .. } => "SIP_CONFLICTING_HEADERS",
+            ProtocolAnomaly::OutOfState { .. } => "SIP_OUT_OF_STATE_REQUEST",
+            _ => "SIP_MALFORMED_MESSAGE",
+        }
+    }
+
+    /// Level for an alert triggered by this anomaly. Oversized and unparseable
+    /// input is what crashes stacks, so it ranks above dialog confusion.
+    pub fn level(&self) -> u8 {
+        match self {
+            ProtocolAnomaly::OversizedHeader { .. }
+            | ProtocolAnomaly::TooManyHeaders { .. }
+            | ProtocolAnomaly::ParseFailure { .. }
+            | ProtocolAnomaly::ControlCharacters { .. } => 7,
+            _ => 6,
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            ProtocolAnomaly::ParseFailure { .. } => "parse_failure",
+            ProtocolAnomaly::OversizedHeader { .. } => "oversized_header",
+            ProtocolAnomaly::TooManyHeaders { .. } => "too_many_headers",
+            ProtocolAnomaly::MissingHeader { .. } => "missing_header",
+            ProtocolAnomaly::DuplicateHeader { .. } => "duplicate_header",
+            ProtocolAnomaly::ConflictingHeaders { .. } => "conflicting_headers",
+            ProtocolAnomaly::CSeqMismatch { .. } => "cseq_mismatch",
+            ProtocolAnomaly::ContentLengthMismatch { .. } => "content_length_mismatch",
+            ProtocolAnomaly::InvalidMaxForwards { .. } => "invalid_max_forwards",
+            ProtocolAnomaly::ControlCharacters { .. } => "control_characters",
+            ProtocolAnomaly::MalformedRequestUri { .. } => "malformed_request_uri",
+            ProtocolAnomaly::OutOfState { .. } => "out_of_state",
+        }
+    }
+
+    pub fn from_parse_error(error: &SipParseError) -> Self {
+        match error {
+            SipParseError::HeaderTooLarge(limit) => ProtocolAnomaly::OversizedHeader {
+                name: "<header section>".to_string(),
+                length: *limit,
+            },
+            _ => ProtocolAnomaly::ParseFailure { reason: error.to_string() },
+        }
+    }
+}
+
+impl fmt::Display for ProtocolAnomaly {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ProtocolAnomaly::ParseFailure { reason } => write!(f, "unparseable message ({})", reason),
+            ProtocolAnomaly::OversizedHeader { name, length } => write!(f, "{} header of {} bytes", name, length),
+            ProtocolAnomaly::TooManyHeaders { count } => write!(f, "{} header fields", count),
+            ProtocolAnomaly::MissingHeader { name } => write!(f, "missing mandatory {} header", name),
+            ProtocolAnomaly::DuplicateHeader { name } => write!(f, "repeated {} header", name),
+            ProtocolAnomaly::ConflictingHeaders { name } => write!(f, "conflicting {} headers", name),
+            ProtocolAnomaly::CSeqMismatch { method, cseq_method } => write!(f, "{} request with CSeq method {}", method, cseq_method),
+            ProtocolAnomaly::ContentLengthMismatch { declared, actual } => write!(f, "Content-Length {} with {} body bytes", declared, actual),
+            ProtocolAnomaly::InvalidMaxForwards { value } => write!(f, "invalid Max-Forwards '{}'", value),
+            ProtocolAnomaly::ControlCharacters { name } => write!(f, "control characters in {} header", name),
+            ProtocolAnomaly::MalformedRequestUri { uri } => write!(f, "malformed Request-URI '{}'", uri),
+            ProtocolAnomaly::OutOfState { method, call_id, reason } => write!(f, "{} for Call-ID '{}' {}", method, call_id, reason),
+        }
+    }
+}
+
+// Headers that RFC 3261 allows exactly once per message
+const SINGLE_INSTANCE_HEADERS: [&str; 7] = ["From", "To", "Call-ID", "CSeq", "Max-Forwards", "Content-Length", "Content-Type"];
+const MANDATORY_HEADERS: [&str; 5] = ["Via", "From", "To", "Call-ID", "CSeq"];
+
+/// Structural checks on a parsed message, in the spirit of the RFC 4475
+/// torture tests.
+pub fn inspect_message(message: &SipMessage, config: &ProtocolAbuseConfig) -> Vec<ProtocolAnomaly> {
+    let mut anomalies = Vec::new();
+
+    if message.headers.len() > config.max_headers {
+        anomalies.push(ProtocolAnomaly::TooManyHeaders { count: message.headers.len() });
+    }
+
+    for (name, value) in &message.headers {
+        if value.len() > config.max_header_length {
+            anomalies.push(ProtocolAnomaly::OversizedHeader { name: name.clone(), length: value.len() });
+        }
+        if value.chars().any(|c| c.is_control() && c != '\t') {
+            anomalies.push(ProtocolAnomaly::ControlCharacters { name: name.clone() });
+        }
+    }
+
+    for name in MANDATORY_HEADERS {
+        if message.header(name).is_none() {
+            anomalies.push(ProtocolAnomaly::MissingHeader { name });
+        }
+    }
+
+    for name in SINGLE_INSTANCE_HEADERS {
+        let mut values = message.headers.iter()
+            .filter(|(header_name, _)| header_name.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str());
+        let first = match values.next() {
+            Some(first) => first,
+            None => continue,
+        };
+        let rest: Vec<&str> = values.collect();
+        if rest.iter().any(|value| *value != first) {
+            anomalies.push(ProtocolAnomaly::ConflictingHeaders { name });
+        } else if !rest.is_empty() {
+            anomalies.push(ProtocolAnomaly::DuplicateHeader { name });
+        }
+    }
+
+    if let Some(declared) = message.content_length() {
+        // The parser trims longer bodies, so a shortfall is all that is left to see
+        if declared > message.body.len() {
+            anomalies.push(ProtocolAnomaly::ContentLengthMismatch { declared, actual: message.body.len() });
+        }
+    }
+
+    if let Some(value) = message.header("Max-Forwards") {
+        if value.trim().parse::<u8>().is_err() {
+            anomalies.push(ProtocolAnomaly::InvalidMaxForwards { value: truncate(value) });
+        }
+    }
+
+    if let (Some(method), Some(uri)) = (message.method(), message.request_uri()) {
+        if let Some(cseq) = message.cseq() {
+            if &cseq.method != method {
+                anomalies.push(ProtocolAnomaly::CSeqMismatch {
+                    method: method.to_string(),
+                    cseq_method: cseq.method.to_string(),
+                });
+            }
+        }
+        if parse_uri(uri).is_none() {
+            anomalies.push(ProtocolAnomaly::MalformedRequestUri { uri: truncate(uri) });
+        }
+    }
+
+    anomalies
+}
+
+fn truncate(value: &str) -> String {
+    value.chars().take(64).collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DialogState {
+    Calling,
+    Early,
+    Confirmed,
+    Failed,
+    Terminated,
+}
+
+#[derive(Debug)]
+struct DialogEntry {
+    state: DialogState,
+    last_activity: Instant,
+}
+
+/// Bounded table of INVITE dialogs keyed by Call-ID, used to tell whether
+/// ACK, CANCEL, BYE and other in-dialog requests refer to anything real.
+/// A confirmed dialog set up before we started watching can live for
+/// `CONFIRMED_TIMEOUT`, so until then a source's unknown in-dialog requests
+/// only count once it has been seen setting up a dialog itself.
+pub struct DialogTable {
+    dialogs: HashMap<String, DialogEntry>,
+    max_dialogs: usize,
+    started: Instant,
+    // Sources that sent an INVITE since we started, bounded like the dialogs
+    setup_sources: HashSet<IpAddr>,
+    // Set when the table was full and a dialog went untracked
+    saturated_at: Option<Instant>,
+}
+
+impl DialogTable {
+    // RFC 3261 Timer B/F: transactions are over after 64*T1
+    const TRANSACTION_TIMEOUT: Duration = Duration::from_secs(32);
+    const SETUP_TIMEOUT: Duration = Duration::from_secs(180);
+    const CONFIRMED_TIMEOUT: Duration = Duration::from_secs(4 * 3600);
+
+    pub fn new(max_dialogs: usize, now: Instant) -> Self {
+        DialogTable {
+            dialogs: HashMap::new(),
+            max_dialogs: max_dialogs.max(1),
+            started: now,
+            setup_sources: HashSet::new(),
+            saturated_at: None,
+        }
+    }
+
+    /// Advances dialog state for a request and reports it if no dialog it
+    /// could belong to exists.
+    pub fn on_request(&mut self, source: IpAddr, message: &SipMessage, now: Instant) -> Option<ProtocolAnomaly> {
+        let method = message.method()?.clone();
+        let call_id = message.call_id()?.to_string();
+        let in_dialog = message.to().is_some_and(|to| to.tag.is_some());
+
+        if method == SipMethod::Invite && !in_dialog {
+            if !self.is_fully_observed(now) && self.setup_sources.len() < self.max_dialogs {
+                self.setup_sources.insert(source);
+            }
+            self.insert(call_id, now);
+            return None;
+        }
+
+        let state = match self.dialogs.get_mut(&call_id) {
+            Some(entry) => {
+                entry.last_activity = now;
+                entry.state
+            }
+            None => {
+                // Out-of-dialog requests like OPTIONS or REGISTER need no dialog
+                let needs_dialog = matches!(method, SipMethod::Ack | SipMethod::Cancel | SipMethod::Bye) || in_dialog;
+                if !needs_dialog || !self.is_confident(source, now) {
+                    return None;
+                }
+                return Some(ProtocolAnomaly::OutOfState {
+                    method: method.to_string(),
+                    call_id,
+                    reason: "matches no known dialog",
+                });
+            }
+        };
+
+        let next_state = match (&method, state) {
+            (SipMethod::Bye, DialogState::Calling) => {
+                return Some(ProtocolAnomaly::OutOfState {
+                    method: method.to_string(),
+                    call_id,
+                    reason: "arrived before the INVITE was answered",
+                });
+            }
+            (SipMethod::Bye, _) => DialogState::Terminated,
+            (SipMethod::Ack, DialogState::Failed) => DialogState::Terminated,
+            (SipMethod::Ack, DialogState::Calling) | (SipMethod::Ack, DialogState::Early) => {
+                return Some(ProtocolAnomaly::OutOfState {
+                    method: method.to_string(),
+                    call_id,
+                    reason: "acknowledges an INVITE with no final response",
+                });
+            }
+            _ => state,
+        };
+
+        if let Some(entry) = self.dialogs.get_mut(&call_id) {
+            entry.state = next_state;
+        }
+        None
+    }
+
+    pub fn on_response(&mut self, message: &SipMessage, now: Instant) {
+        let (status_code, cseq, call_id) = match (message.status_code(), message.cseq(), message.call_id()) {
+            (Some(status_code), Some(cseq), Some(call_id)) => (status_code, cseq, call_id),
+            _ => return,
+        };
+
+        let entry = match self.dialogs.get_mut(call_id) {
+            Some(entry) => entry,
+            None => return,
+        };
+        entry.last_activity = now;
+
+        entry.state = match (cseq.method, entry.state, status_code) {
+            (SipMethod::Invite, DialogState::Calling, 101..=199) => DialogState::Early,
+            (SipMethod::Invite, DialogState::Calling, 200..=299)
+            | (SipMethod::Invite, DialogState::Early, 200..=299) => DialogState::Confirmed,
+            (SipMethod::Invite, DialogState::Calling, 300..=699)
+            | (SipMethod::Invite, DialogState::Early, 300..=699) => DialogState::Failed,
+            (_, state, _) => state,
+        };
+    }
+
+    fn insert(&mut self, call_id: String, now: Instant) {
+        if !self.dialogs.contains_key(&call_id) && self.dialogs.len() >= self.max_dialogs {
+            self.cleanup(now);
+            if self.dialogs.len() >= self.max_dialogs {
+                self.saturated_at = Some(now);
+                return;
+            }
+        }
+
+        // A retransmitted INVITE must not reset a dialog that already progressed
+        self.dialogs.entry(call_id).or_insert(DialogEntry {
+            state: DialogState::Calling,
+            last_activity: now,
+        });
+    }
+
+    /// Unknown-dialog findings are only trusted once every dialog the source
+    /// could be in has been watched from its start, and not while dialogs may
+    /// have been dropped for lack of room.
+    fn is_confident(&self, source: IpAddr, now: Instant) -> bool {
+        (self.is_fully_observed(now) || self.setup_sources.contains(&source))
+            && self.saturated_at.is_none_or(|at| now.duration_since(at) >= Self::CONFIRMED_TIMEOUT)
+    }
+
+    // Any dialog older than our start has timed out by now
+    fn is_fully_observed(&self, now: Instant) -> bool {
+        now.duration_since(self.started) >= Self::CONFIRMED_TIMEOUT
+    }
+
+    pub fn dialog_count(&self) -> usize {
+        self.dialogs.len()
+    }
+
+    pub fn cleanup(&mut self, now: Instant) {
+        if self.is_fully_observed(now) && !self.setup_sources.is_empty() {
+            self.setup_sources = HashSet::new();
+        }
+        self.dialogs.retain(|_, entry| {
+            let idle = now.duration_since(entry.last_activity);
+            match entry.state {
+                DialogState::Calling | DialogState::Early => idle < Self::SETUP_TIMEOUT,
+                DialogState::Confirmed => idle < Self::CONFIRMED_TIMEOUT,
+                // Kept briefly so retransmitted BYE/ACK still match
+                DialogState::Failed | DialogState::Terminated => idle < Self::TRANSACTION_TIMEOUT,
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::sip_parser::parse_sip_message;
+
+    fn request(method: &str, call_id: &str, to_tag: Option<&str>) -> SipMessage {
+        let to_tag = to_tag.map(|tag| format!(";tag={}", tag)).unwrap_or_default();
+        let data = format!(
+            "{method} sip:100@pbx SIP/2.0\r\n\
+            Via: SIP/2.0/UDP 203.0.113.5;branch=z9hG4bK1\r\n\
+            From: <sip:200@203.0.113.5>;tag=a1\r\n\
+            To: <sip:100@pbx>{to_tag}\r\n\
+            Call-ID: {call_id}\r\n\
+            CSeq: 2 {method}\r\n\r\n"
+        );
+        parse_sip_message(data.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn unknown_in_dialog_requests_wait_for_the_source_or_the_dialog_timeout() {
+        let start = Instant::now();
+        let mut table = DialogTable::new(16, start);
+        let caller: IpAddr = "203.0.113.5".parse().unwrap();
+        let other: IpAddr = "198.51.100.7".parse().unwrap();
+        let hour = start + Duration::from_secs(3600);
+
+        // Could be a call that was already up when we started
+        assert_eq!(table.on_request(caller, &request("BYE", "old-call", Some("b1")), hour), None);
+
+        // Once the source is seen setting up dialogs, all of its new ones are tracked
+        assert_eq!(table.on_request(caller, &request("INVITE", "new-call", None), hour), None);
+        assert!(matches!(
+            table.on_request(caller, &request("BYE", "old-call", Some("b1")), hour),
+            Some(ProtocolAnomaly::OutOfState { reason: "matches no known dialog", .. })
+        ));
+        assert_eq!(table.on_request(other, &request("BYE", "other-call", Some("b2")), hour), None);
+
+        // No dialog outlives the confirmed timeout
+        let later = start + DialogTable::CONFIRMED_TIMEOUT;
+        assert!(table.on_request(other, &request("BYE", "other-call", Some("b2")), later).is_some());
+        table.cleanup(later);
+        assert!(table.setup_sources.is_empty());
+    }
+}
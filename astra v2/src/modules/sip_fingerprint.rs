@@ -0,0 +1,426 @@
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+use crate::modules::sip_parser::SipMessage;
+
+/// Part of a SIP message a fingerprint trait is matched against.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FingerprintField {
+    UserAgent,
+    ViaBranch,
+    FromTag,
+    ToTag,
+    FromUser,
+    ToUser,
+    FromDisplayName,
+    CallId,
+    Method,
+    RequestUri,
+    Contact,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FingerprintTraitSpec {
+    pub field: FingerprintField,
+    pub pattern: String,
+    /// Evidence this trait contributes on its own, 0.0-1.0
+    pub weight: f32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScannerFingerprintSpec {
+    pub name: String,
+    pub description: String,
+    pub threat_level: u8,
+    pub traits: Vec<FingerprintTraitSpec>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FingerprintFile {
+    pub min_confidence: f32,
+    pub fingerprints: Vec<ScannerFingerprintSpec>,
+}
+
+#[derive(Debug)]
+struct CompiledTrait {
+    field: FingerprintField,
+    pattern: Regex,
+    weight: f32,
+}
+
+#[derive(Debug)]
+struct CompiledFingerprint {
+    name: String,
+    threat_level: u8,
+    traits: Vec<CompiledTrait>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScannerMatch {
+    pub tool: String,
+    pub confidence: f32,
+    pub threat_level: u8,
+    pub matched_traits: Vec<FingerprintField>,
+}
+
+pub struct FingerprintDatabase {
+    min_confidence: f32,
+    fingerprints: Vec<CompiledFingerprint>,
+}
+
+impl FingerprintDatabase {
+    /// Loads the fingerprint file, seeding it with the built-in database if it
+    /// does not exist yet. Entries in the file replace built-ins of the same name.
+    pub fn load(path: &str) -> Result<(Self, Vec<String>), Box<dyn std::error::Error>> {
+        let mut file = FingerprintFile::builtin();
+
+        if Path::new(path).exists() {
+            let data = fs::read_to_string(path)?;
+            let user_file: FingerprintFile = serde_json::from_str(&data)
+                .map_err(|e| format!("Invalid fingerprint file {}: {}", path, e))?;
+            file.merge(user_file);
+        } else if let Some(parent) = Path::new(path).parent() {
+            // Best effort, like Config::load: leave a template for operators to extend
+            if fs::create_dir_all(parent).is_ok() {
+                if let Ok(json) = serde_json::to_string_pretty(&file) {
+                    let _ = fs::write(path, json);
+                }
+            }
+        }
+
+        Ok(Self::compile(file))
+    }
+
+    /// Compiles the specs, returning the database and any rejected patterns.
+    pub fn compile(file: FingerprintFile) -> (Self, Vec<String>) {
+        let mut warnings = Vec::new();
+        let mut fingerprints = Vec::new();
+
+        for spec in file.fingerprints {
+            let mut traits = Vec::new();
+            for trait_spec in spec.traits {
+                match Regex::new(&trait_spec.pattern) {
+                    Ok(pattern) => traits.push(CompiledTrait {
+                        field: trait_spec.field,
+                        pattern,
+                        weight: trait_spec.weight.clamp(0.0, 1.0),
+                    }),
+                    Err(e) => warnings.push(format!("fingerprint '{}': invalid pattern '{}': {}", spec.name, trait_spec.pattern, e)),
+                }
+            }
+
+            if !traits.is_empty() {
+                fingerprints.push(CompiledFingerprint {
+                    name: spec.name,
+                    threat_level: spec.threat_level.clamp(1, 10),
+                    traits,
+                });
+            }
+        }
+
+        let database = FingerprintDatabase {
+            min_confidence: file.min_confidence.clamp(0.0, 1.0),
+            fingerprints,
+        };
+        (database, warnings)
+    }
+
+    pub fn fingerprint_count(&self) -> usize {
+        self.fingerprints.len()
+    }
+
+    /// Best match for a message. Independent traits combine as 1 - Π(1 - weight),
+    /// so a spoofable User-Agent alone scores lower than a User-Agent plus the
+    /// tool's characteristic branch and tag formats.
+    pub fn identify(&self, message: &SipMessage) -> Option<ScannerMatch> {
+        let fields = MessageFields::extract(message);
+        let mut best: Option<ScannerMatch> = None;
+
+        for fingerprint in &self.fingerprints {
+            let mut miss_probability = 1.0_f32;
+            let mut matched_traits = Vec::new();
+
+            for fingerprint_trait in &fingerprint.traits {
+                let matched = fields.get(fingerprint_trait.field)
+                    .is_some_and(|value| fingerprint_trait.pattern.is_match(value));
+                if matched {
+                    miss_probability *= 1.0 - fingerprint_trait.weight;
+                    matched_traits.push(fingerprint_trait.field);
+                }
+            }
+
+            let confidence = 1.0 - miss_probability;
+            if confidence < self.min_confidence {
+                continue;
+            }
+
+            if best.as_ref().is_none_or(|current| confidence > current.confidence) {
+                best = Some(ScannerMatch {
+                    tool: fingerprint.name.clone(),
+                    confidence,
+                    threat_level: fingerprint.threat_level,
+                    matched_traits,
+                });
+            }
+        }
+
+        best
+    }
+}
+
+impl FingerprintFile {
+    fn merge(&mut self, other: FingerprintFile) {
+        self.min_confidence = other.min_confidence;
+        for spec in other.fingerprints {
+            match self.fingerprints.iter_mut().find(|existing| existing.name == spec.name) {
+                Some(existing) => *existing = spec,
+                None => self.fingerprints.push(spec),
+            }
+        }
+    }
+
+    pub fn builtin() -> Self {
+        fn spec(name: &str, description: &str, threat_level: u8, traits: &[(FingerprintField, &str, f32)]) -> ScannerFingerprintSpec {
+            ScannerFingerprintSpec {
+                name: name.to_string(),
+                description: description.to_string(),
+                threat_level,
+                traits: traits.iter()
+                    .map(|(field, pattern, weight)| FingerprintTraitSpec {
+                        field: *field,
+                        pattern: pattern.to_string(),
+                        weight: *weight,
+                    })
+                    .collect(),
+            }
+        }
+
+        use FingerprintField::*;
+
+        FingerprintFile {
+            min_confidence: 0.6,
+            fingerprints: vec![
+                spec("sipvicious", "SIPVicious svmap/svwar/svcrack", 8, &[
+                    (UserAgent, r"(?i)^(friendly-scanner|sipvicious|sipcli)", 0.9),
+                    // svmap encodes its own IP and port as hex in the From tag
+                    (FromTag, r"^[0-9a-f]{12}01[0-9a-f]*$", 0.5),
+                    (ViaBranch, r"^z9hG4bK-\d{5,}-\d+-\d+(-\w+)?$", 0.4),
+                    (FromDisplayName, r"(?i)^sipvicious$", 0.8),
+                    (Method, r"^OPTIONS$", 0.05),
+                ]),
+                spec("sipsak", "sipsak SIP swiss army knife", 6, &[
+                    (UserAgent, r"(?i)^sipsak", 0.9),
+                    (ViaBranch, r"^z9hG4bK\.[0-9a-f]{8}$", 0.5),
+                    (FromUser, r"^sipsak$", 0.6),
+                ]),
+                spec("sundayddr", "sundayddr worm scanner", 9, &[
+                    (UserAgent, r"(?i)sundayddr", 0.95),
+                ]),
+                spec("sip-scan", "Generic sip-scan / SIPScan sweeper", 7, &[
+                    (UserAgent, r"(?i)^sip-?scan", 0.9),
+                ]),
+                spec("smap", "smap SIP mapper", 7, &[
+                    (UserAgent, r"(?i)^smap", 0.9),
+                    (Method, r"^OPTIONS$", 0.05),
+                ]),
+                spec("iwar", "iWar war dialer", 8, &[
+                    (UserAgent, r"(?i)^iwar", 0.9),
+                ]),
+                spec("sivus", "SiVuS VoIP vulnerability scanner", 8, &[
+                    (UserAgent, r"(?i)sivus", 0.9),
+                ]),
+                spec("vaxsip", "VaxSIPUserAgent based brute forcer", 7, &[
+                    (UserAgent, r"(?i)^VaxSIPUserAgent", 0.85),
+                ]),
+                spec("pplsip", "pplsip brute forcer", 7, &[
+                    (UserAgent, r"(?i)^pplsip", 0.9),
+                ]),
+                spec("voip-brute", "Tools probing with the 'sip:100' bait extension", 6, &[
+                    (FromUser, r"^(100|1000|101)$", 0.2),
+                    (ToUser, r"^(100|1000|101)$", 0.2),
+                    (CallId, r"^\d{8,12}$", 0.35),
+                    (Method, r"^OPTIONS$", 0.1),
+                ]),
+            ],
+        }
+    }
+}
+
+struct MessageFields {
+    user_agent: Option<String>,
+    via_branch: Option<String>,
+    from_tag: Option<String>,
+    to_tag: Option<String>,
+    from_user: Option<String>,
+    to_user: Option<String>,
+    from_display_name: Option<String>,
+    call_id: Option<String>,
+    method: Option<String>,
+    request_uri: Option<String>,
+    contact: Option<String>,
+}
+
+impl MessageFields {
+    fn extract(message: &SipMessage) -> Self {
+        let from = message.from();
+        let to = message.to();
+
+        MessageFields {
+            user_agent: message.user_agent().map(str::to_string),
+            via_branch: message.vias().into_iter().next().and_then(|via| via.branch),
+            from_tag: from.as_ref().and_then(|addr| addr.tag.clone()),
+            to_tag: to.as_ref().and_then(|addr| addr.tag.clone()),
+            from_user: from.as_ref().and_then(|addr| addr.uri.user.clone()),
+            to_user: to.as_ref().and_then(|addr| addr.uri.user.clone()),
+            from_display_name: from.and_then(|addr| addr.display_name),
+            call_id: message.call_id().map(str::to_string),
+            method: message.method().map(|method| method.to_string()),
+            request_uri: message.request_uri().map(str::to_string),
+            contact: message.header("Contact").map(str::to_string),
+        }
+    }
+
+    fn get(&self, field: FingerprintField) -> Option<&str> {
+        let value = match field {
+            FingerprintField::UserAgent => &self.user_agent,
+            FingerprintField::ViaBranch => &self.via_branch,
+            FingerprintField::FromTag => &self.from_tag,
+            FingerprintField::ToTag => &self.to_tag,
+            FingerprintField::FromUser => &self.from_user,
+            FingerprintField::ToUser => &self.to_user,
+            FingerprintField::FromDisplayName => &self.from_display_name,
+            FingerprintField::CallId => &self.call_id,
+            FingerprintField::Method => &self.method,
+            FingerprintField::RequestUri => &self.request_uri,
+            FingerprintField::Contact => &self.contact,
+        };
+        value.as_deref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::sip_parser::parse_sip_message;
+
+    fn builtin() -> FingerprintDatabase {
+        let (database, warnings) = FingerprintDatabase::compile(FingerprintFile::builtin());
+        assert!(warnings.is_empty());
+        database
+    }
+
+    fn identify(database: &FingerprintDatabase, message: &str) -> Option<ScannerMatch> {
+        database.identify(&parse_sip_message(message.replace('\n', "\r\n").as_bytes()).unwrap())
+    }
+
+    // As sent by svmap 0.3
+    const SVMAP_OPTIONS: &str = "OPTIONS sip:100@192.0.2.10 SIP/2.0
+Via: SIP/2.0/UDP 203.0.113.5:5060;branch=z9hG4bK-2415716063-1-1;rport
+Content-Length: 0
+From: \"sipvicious\"<sip:100@1.1.1.1>;tag=6134363935633030313300303135323938363436
+Accept: application/sdp
+User-Agent: friendly-scanner
+To: \"sipvicious\"<sip:100@1.1.1.1>
+Contact: sip:100@203.0.113.5:5060
+CSeq: 1 OPTIONS
+Call-ID: 844185834545470566225616
+Max-Forwards: 70
+
+";
+
+    const PHONE_REGISTER: &str = "REGISTER sip:pbx.example.com SIP/2.0
+Via: SIP/2.0/UDP 198.51.100.20:5062;branch=z9hG4bK1873447543;rport
+From: \"Reception\" <sip:1001@pbx.example.com>;tag=2734565312
+To: \"Reception\" <sip:1001@pbx.example.com>
+Call-ID: 0_1683384462@198.51.100.20
+CSeq: 1 REGISTER
+Contact: <sip:1001@198.51.100.20:5062>
+Max-Forwards: 70
+User-Agent: Yealink SIP-T46S 66.86.0.15
+Expires: 3600
+Content-Length: 0
+
+";
+
+    #[test]
+    fn user_agent_and_header_traits_identify_svmap() {
+        let found = identify(&builtin(), SVMAP_OPTIONS).unwrap();
+        assert_eq!(found.tool, "sipvicious");
+        assert_eq!(found.threat_level, 8);
+        assert!(found.matched_traits.contains(&FingerprintField::UserAgent));
+        assert!(found.matched_traits.contains(&FingerprintField::ViaBranch));
+        assert!(found.matched_traits.contains(&FingerprintField::FromDisplayName));
+        assert!(found.confidence > 0.98);
+    }
+
+    #[test]
+    fn user_agent_matching_ignores_case_and_stays_anchored() {
+        let database = builtin();
+        let renamed = PHONE_REGISTER.replace("Yealink SIP-T46S 66.86.0.15", "SIPVicious 0.3.4");
+        let found = identify(&database, &renamed).unwrap();
+        assert_eq!((found.tool.as_str(), found.matched_traits.as_slice()), ("sipvicious", &[FingerprintField::UserAgent][..]));
+        assert!((found.confidence - 0.9).abs() < 1e-6);
+
+        // The tool name later in the string is not the tool's own User-Agent
+        let mentioned = PHONE_REGISTER.replace("Yealink SIP-T46S 66.86.0.15", "Linphone (blocks sipvicious)");
+        assert_eq!(identify(&database, &mentioned), None);
+        assert_eq!(identify(&database, PHONE_REGISTER), None);
+    }
+
+    #[test]
+    fn header_traits_without_a_user_agent_add_up_past_the_minimum() {
+        let database = builtin();
+        let sipsak = "OPTIONS sip:192.0.2.10 SIP/2.0
+Via: SIP/2.0/UDP 203.0.113.5:34567;branch=z9hG4bK.1a2b3c4d;rport
+From: sip:sipsak@203.0.113.5:34567;tag=6b8b4567
+To: sip:192.0.2.10
+Call-ID: 1804289383@203.0.113.5
+CSeq: 1 OPTIONS
+Content-Length: 0
+
+";
+        let found = identify(&database, sipsak).unwrap();
+        assert_eq!(found.tool, "sipsak");
+        assert!((found.confidence - 0.8).abs() < 1e-6);
+
+        // The branch format alone is too weak to name the tool
+        assert_eq!(identify(&database, &sipsak.replace("sip:sipsak@", "sip:alice@")), None);
+    }
+
+    #[test]
+    fn operator_entries_replace_builtins_and_bad_patterns_are_reported() {
+        let mut file = FingerprintFile::builtin();
+        let count = file.fingerprints.len();
+        file.merge(FingerprintFile {
+            min_confidence: 0.5,
+            fingerprints: vec![
+                ScannerFingerprintSpec {
+                    name: "sipvicious".to_string(),
+                    description: "Only the display name".to_string(),
+                    threat_level: 12,
+                    traits: vec![FingerprintTraitSpec { field: FingerprintField::FromDisplayName, pattern: "(?i)^sipvicious$".to_string(), weight: 0.7 }],
+                },
+                ScannerFingerprintSpec {
+                    name: "broken".to_string(),
+                    description: String::new(),
+                    threat_level: 5,
+                    traits: vec![FingerprintTraitSpec { field: FingerprintField::UserAgent, pattern: "(".to_string(), weight: 0.9 }],
+                },
+            ],
+        });
+        assert_eq!(file.fingerprints.len(), count + 1);
+
+        let (database, warnings) = FingerprintDatabase::compile(file);
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("broken"));
+        // A fingerprint with no usable traits is left out
+        assert_eq!(database.fingerprint_count(), count);
+
+        let found = identify(&database, SVMAP_OPTIONS).unwrap();
+        assert_eq!((found.tool.as_str(), found.threat_level), ("sipvicious", 10));
+        assert_eq!(found.matched_traits, vec![FingerprintField::FromDisplayName]);
+    }
+}
@@ -0,0 +1,419 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::hash::{Hash, Hasher};
+use std::io::Write;
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tokio::net::UdpSocket;
+use tokio::time::timeout;
+use uuid::Uuid;
+
+use crate::core::{event_bus::EventBus, config::Config, logger::Logger};
+use crate::modules::sip_parser::{parse_sip_message, parse_uri, SipMessage, SipMethod};
+use crate::{SecurityEvent, ThreatLevel};
+
+/// One credential or dialed number captured by the honeypot, written as a JSON line.
+#[derive(Debug, Clone, Serialize)]
+pub struct HoneypotRecord {
+    pub timestamp: DateTime<Utc>,
+    pub source_ip: IpAddr,
+    pub source_port: u16,
+    pub kind: String,
+    pub method: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub realm: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nonce: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub digest_uri: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub digest_response: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dialed_number: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_agent: Option<String>,
+}
+
+#[derive(Debug)]
+struct HoneypotSource {
+    last_seen: Instant,
+    requests: u32,
+    credentials: u32,
+    dialed_numbers: u32,
+    last_alerts: HashMap<&'static str, Instant>,
+}
+
+impl HoneypotSource {
+    fn new(now: Instant) -> Self {
+        HoneypotSource {
+            last_seen: now,
+            requests: 0,
+            credentials: 0,
+            dialed_numbers: 0,
+            last_alerts: HashMap::new(),
+        }
+    }
+}
+
+/// Decoy SIP endpoint. Nothing legitimate talks to it, so every request it
+/// answers is attributed to the sender with high confidence.
+pub struct SipHoneypot {
+    config: Arc<Config>,
+    logger: Arc<Logger>,
+    events: Arc<EventBus>,
+    sources: HashMap<IpAddr, HoneypotSource>,
+    requests_answered: u32,
+    credentials_captured: u32,
+    numbers_captured: u32,
+}
+
+impl SipHoneypot {
+    const ALERT_COOLDOWN: Duration = Duration::from_secs(60);
+    const SOURCE_RETENTION: Duration = Duration::from_secs(3600);
+    const MAX_SOURCES: usize = 65536;
+    const RECEIVE_TIMEOUT: Duration = Duration::from_millis(500);
+    const STATUS_INTERVAL: Duration = Duration::from_secs(300);
+
+    pub fn new(config: &Arc<Config>, logger: Arc<Logger>, events: Arc<EventBus>) -> Result<Self, Box<dyn std::error::Error>> {
+        let honeypot = &config.modules.sip_honeypot;
+
+        if let Some(parent) = Path::new(&honeypot.record_file).parent() {
+            if let Err(e) = fs::create_dir_all(parent) {
+                logger.log_warning(&format!("Could not create {}: {}", parent.display(), e))?;
+            }
+        }
+
+        logger.log_info(&format!(
+            "SIP honeypot initialized as '{}' on {}:{}",
+            honeypot.user_agent, honeypot.bind_address, honeypot.port
+        ))?;
+
+        Ok(SipHoneypot {
+            config: config.clone(),
+            logger,
+            events,
+            sources: HashMap::new(),
+            requests_answered: 0,
+            credentials_captured: 0,
+            numbers_captured: 0,
+        })
+    }
+
+    pub async fn run(&mut self, running: Arc<Mutex<bool>>) -> Result<(), Box<dyn std::error::Error>> {
+        let honeypot = &self.config.modules.sip_honeypot;
+        let bind_address = format!("{}:{}", honeypot.bind_address, honeypot.port);
+        let socket = UdpSocket::bind(&bind_address).await
+            .map_err(|e| format!("SIP honeypot could not bind udp/{}: {}", bind_address, e))?;
+
+        self.logger.log_info(&format!("SIP honeypot listening on udp/{}", bind_address))?;
+
+        let mut buffer = vec![0u8; 65535];
+        let mut cleanup_timer = Instant::now();
+        let mut status_timer = Instant::now();
+
+        while is_running(&running) {
+            if let Ok(received) = timeout(Self::RECEIVE_TIMEOUT, socket.recv_from(&mut buffer)).await {
+                match received {
+                    Ok((length, peer)) => {
+                        let responses = self.handle_datagram(peer, &buffer[..length])?;
+                        for response in responses {
+                            if let Err(e) = socket.send_to(&response, peer).await {
+                                self.logger.log_debug(&format!("SIP honeypot reply to {} failed: {}", peer, e))?;
+                            }
+                        }
+                    }
+                    Err(e) => self.logger.log_debug(&format!("SIP honeypot receive error: {}", e))?,
+                }
+            }
+
+            if cleanup_timer.elapsed() > Duration::from_secs(60) {
+                self.cleanup_old_sources(Instant::now());
+                cleanup_timer = Instant::now();
+            }
+
+            // The engine cannot reach this task, so it reports its own status
+            if status_timer.elapsed() > Self::STATUS_INTERVAL {
+                self.logger.log_module_event("SIP_HONEYPOT", "Module status", Some(serde_json::json!(self.get_threat_statistics())))?;
+                status_timer = Instant::now();
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Builds the responses for one datagram. Anything that is not a parseable
+    /// SIP request is ignored, as a real PBX would.
+    pub fn handle_datagram(&mut self, peer: SocketAddr, data: &[u8]) -> Result<Vec<Vec<u8>>, Box<dyn std::error::Error>> {
+        let message = match parse_sip_message(data) {
+            Ok(message) if message.is_request() => message,
+            _ => return Ok(Vec::new()),
+        };
+        let method = match message.method() {
+            Some(method) => method.clone(),
+            None => return Ok(Vec::new()),
+        };
+
+        let now = Instant::now();
+        if !self.sources.contains_key(&peer.ip()) && self.sources.len() >= Self::MAX_SOURCES {
+            self.cleanup_old_sources(now);
+        }
+        let source = self.sources.entry(peer.ip()).or_insert_with(|| HoneypotSource::new(now));
+        source.last_seen = now;
+        source.requests += 1;
+        let requests = source.requests;
+
+        self.raise_event(peer, "SIP_HONEYPOT_PROBE", ThreatLevel {
+            level: 6,
+            confidence: 0.95,
+            category: "RECONNAISSANCE".to_string(),
+        }, format!(
+            "{} to SIP honeypot port {} ({} requests, User-Agent: {})",
+            method, self.config.modules.sip_honeypot.port, requests,
+            message.user_agent().unwrap_or("-")
+        ), now)?;
+
+        let responses = match method {
+            SipMethod::Options => vec![self.build_response(&message, peer, 200, "OK", &self.capability_headers())],
+            SipMethod::Register => self.answer_register(peer, &message, now)?,
+            SipMethod::Invite => self.answer_invite(peer, &message, now)?,
+            SipMethod::Ack => Vec::new(),
+            SipMethod::Bye | SipMethod::Cancel => vec![self.build_response(&message, peer, 200, "OK", &[])],
+            _ => vec![self.build_response(&message, peer, 405, "Method Not Allowed", &self.capability_headers())],
+        };
+
+        self.requests_answered = self.requests_answered.saturating_add(responses.len() as u32);
+        Ok(responses)
+    }
+
+    fn answer_register(&mut self, peer: SocketAddr, message: &SipMessage, now: Instant) -> Result<Vec<Vec<u8>>, Box<dyn std::error::Error>> {
+        if message.auth_params().is_empty() {
+            let challenge = self.digest_challenge("WWW-Authenticate");
+            return Ok(vec![self.build_response(message, peer, 401, "Unauthorized", &[challenge])]);
+        }
+
+        let record = self.capture_credential(peer, message)?;
+        let target = message.to().and_then(|to| to.uri.user).unwrap_or_else(|| "-".to_string());
+        self.raise_event(peer, "SIP_HONEYPOT_CREDENTIAL_ATTEMPT", ThreatLevel {
+            level: 8,
+            confidence: 0.99,
+            category: "CREDENTIAL_ATTACK".to_string(),
+        }, format!(
+            "REGISTER for extension '{}' with username '{}' captured by SIP honeypot",
+            target, record.username.as_deref().unwrap_or("-")
+        ), now)?;
+
+        if !self.config.modules.sip_honeypot.accept_registrations {
+            return Ok(vec![self.build_response(message, peer, 403, "Forbidden", &[])]);
+        }
+
+        // Pretend the guess worked so the attacker moves on to placing calls
+        let mut headers = vec![("Expires".to_string(), "3600".to_string())];
+        if let Some(contact) = message.header("Contact") {
+            headers.push(("Contact".to_string(), format!("{};expires=3600", contact)));
+        }
+        Ok(vec![self.build_response(message, peer, 200, "OK", &headers)])
+    }
+
+    fn answer_invite(&mut self, peer: SocketAddr, message: &SipMessage, now: Instant) -> Result<Vec<Vec<u8>>, Box<dyn std::error::Error>> {
+        let dialed_number = message.request_uri()
+            .and_then(parse_uri)
+            .and_then(|uri| uri.user)
+            .unwrap_or_default();
+
+        let mut record = self.base_record(peer, message, "dialed_number");
+        record.username = message.auth_username();
+        record.dialed_number = Some(dialed_number.clone());
+        self.write_record(&record)?;
+        self.numbers_captured = self.numbers_captured.saturating_add(1);
+        if let Some(source) = self.sources.get_mut(&peer.ip()) {
+            source.dialed_numbers += 1;
+        }
+
+        self.raise_event(peer, "SIP_HONEYPOT_CALL_ATTEMPT", ThreatLevel {
+            level: 9,
+            confidence: 0.99,
+            category: "TOLL_FRAUD".to_string(),
+        }, format!(
+            "INVITE to '{}' captured by SIP honeypot (From: {})",
+            dialed_number,
+            message.header("From").unwrap_or("-")
+        ), now)?;
+
+        let trying = self.build_response(message, peer, 100, "Trying", &[]);
+        if message.auth_params().is_empty() {
+            let challenge = self.digest_challenge("Proxy-Authenticate");
+            return Ok(vec![trying, self.build_response(message, peer, 407, "Proxy Authentication Required", &[challenge])]);
+        }
+
+        self.capture_credential(peer, message)?;
+        Ok(vec![trying, self.build_response(message, peer, 486, "Busy Here", &[])])
+    }
+
+    fn capture_credential(&mut self, peer: SocketAddr, message: &SipMessage) -> Result<HoneypotRecord, Box<dyn std::error::Error>> {
+        let params: HashMap<String, String> = message.auth_params().into_iter().collect();
+        let mut record = self.base_record(peer, message, "credential");
+        record.username = params.get("username").cloned();
+        record.realm = params.get("realm").cloned();
+        record.nonce = params.get("nonce").cloned();
+        record.digest_uri = params.get("uri").cloned();
+        record.digest_response = params.get("response").cloned();
+
+        self.write_record(&record)?;
+        self.credentials_captured = self.credentials_captured.saturating_add(1);
+        if let Some(source) = self.sources.get_mut(&peer.ip()) {
+            source.credentials += 1;
+        }
+
+        Ok(record)
+    }
+
+    fn base_record(&self, peer: SocketAddr, message: &SipMessage, kind: &str) -> HoneypotRecord {
+        HoneypotRecord {
+            timestamp: Utc::now(),
+            source_ip: peer.ip(),
+            source_port: peer.port(),
+            kind: kind.to_string(),
+            method: message.method().map(|method| method.to_string()).unwrap_or_default(),
+            username: None,
+            realm: None,
+            nonce: None,
+            digest_uri: None,
+            digest_response: None,
+            dialed_number: None,
+            user_agent: message.user_agent().map(str::to_string),
+        }
+    }
+
+    fn write_record(&self, record: &HoneypotRecord) -> Result<(), Box<dyn std::error::Error>> {
+        let line = serde_json::to_string(record)?;
+        self.logger.log_info(&format!("SIP honeypot captured: {}", line))?;
+
+        let path = &self.config.modules.sip_honeypot.record_file;
+        let written = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .and_then(|mut file| writeln!(file, "{}", line));
+        if let Err(e) = written {
+            self.logger.log_warning(&format!("Could not write honeypot record to {}: {}", path, e))?;
+        }
+
+        Ok(())
+    }
+
+    /// Reports the event unless this source raised the same kind within
+    /// the alert cooldown.
+    fn raise_event(&mut self, peer: SocketAddr, event_type: &'static str, threat_level: ThreatLevel, details: String, now: Instant) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(source) = self.sources.get_mut(&peer.ip()) {
+            let cooled_down = source.last_alerts.get(event_type)
+                .is_none_or(|last| now.duration_since(*last) >= Self::ALERT_COOLDOWN);
+            if !cooled_down {
+                return Ok(());
+            }
+            source.last_alerts.insert(event_type, now);
+        }
+
+        let event = SecurityEvent {
+            timestamp: Utc::now(),
+            source_ip: peer.ip(),
+            event_type: event_type.to_string(),
+            threat_level,
+            details,
+            action_taken: "HONEYPOT_ENGAGED".to_string(),
+            evidence_id: None,
+            related_ips: Vec::new(),
+        };
+
+        self.events.publish_or_log(&self.logger, event)
+    }
+
+    fn digest_challenge(&self, header: &str) -> (String, String) {
+        let nonce = Uuid::new_v4().simple().to_string();
+        (
+            header.to_string(),
+            format!(
+                "Digest algorithm=MD5, realm=\"{}\", nonce=\"{}\"",
+                self.config.modules.sip_honeypot.realm, &nonce[..16]
+            ),
+        )
+    }
+
+    fn capability_headers(&self) -> Vec<(String, String)> {
+        vec![
+            ("Allow".to_string(), "INVITE, ACK, CANCEL, OPTIONS, BYE, REFER, SUBSCRIBE, NOTIFY, INFO, PUBLISH, MESSAGE".to_string()),
+            ("Supported".to_string(), "replaces, timer".to_string()),
+            ("Accept".to_string(), "application/sdp".to_string()),
+        ]
+    }
+
+    fn build_response(&self, request: &SipMessage, peer: SocketAddr, status_code: u16, reason: &str, extra_headers: &[(String, String)]) -> Vec<u8> {
+        let mut response = format!("SIP/2.0 {} {}\r\n", status_code, reason);
+
+        for (index, (_, via)) in request.headers.iter().filter(|(name, _)| name == "Via").enumerate() {
+            if index == 0 && !via.contains("received=") {
+                response.push_str(&format!("Via: {};received={}\r\n", via, peer.ip()));
+            } else {
+                response.push_str(&format!("Via: {}\r\n", via));
+            }
+        }
+
+        if let Some(from) = request.header("From") {
+            response.push_str(&format!("From: {}\r\n", from));
+        }
+        if let Some(to) = request.header("To") {
+            let tagged = request.to().is_some_and(|to| to.tag.is_some());
+            if status_code > 100 && !tagged {
+                response.push_str(&format!("To: {};tag={}\r\n", to, dialog_tag(request.call_id().unwrap_or(""))));
+            } else {
+                response.push_str(&format!("To: {}\r\n", to));
+            }
+        }
+        if let Some(call_id) = request.call_id() {
+            response.push_str(&format!("Call-ID: {}\r\n", call_id));
+        }
+        if let Some(cseq) = request.header("CSeq") {
+            response.push_str(&format!("CSeq: {}\r\n", cseq));
+        }
+
+        response.push_str(&format!("Server: {}\r\n", self.config.modules.sip_honeypot.user_agent));
+        for (name, value) in extra_headers {
+            response.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        response.push_str("Content-Length: 0\r\n\r\n");
+
+        response.into_bytes()
+    }
+
+    fn cleanup_old_sources(&mut self, now: Instant) {
+        self.sources.retain(|_, source| now.duration_since(source.last_seen) < Self::SOURCE_RETENTION);
+    }
+
+    pub fn get_threat_statistics(&self) -> HashMap<String, u32> {
+        let mut stats = HashMap::new();
+
+        stats.insert("honeypot_sources".to_string(), self.sources.len() as u32);
+        stats.insert("honeypot_responses".to_string(), self.requests_answered);
+        stats.insert("credentials_captured".to_string(), self.credentials_captured);
+        stats.insert("dialed_numbers_captured".to_string(), self.numbers_captured);
+
+        stats
+    }
+}
+
+fn is_running(running: &Mutex<bool>) -> bool {
+    *running.lock().unwrap()
+}
+
+/// Stable To tag per Call-ID so retransmitted requests get identical answers.
+fn dialog_tag(call_id: &str) -> String {
+    let mut hasher = DefaultHasher::new();
+    call_id.hash(&mut hasher);
+    format!("as{:08x}", hasher.finish() as u32)
+}
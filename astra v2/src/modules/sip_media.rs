@@ -0,0 +1,399 @@
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+use crate::core::config::MediaConfig;
+use crate::modules::sip_shield::SlidingWindow;
+
+/// Media streams offered or answered in one SDP body.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionDescription {
+    pub connection: Option<IpAddr>,
+    pub media: Vec<MediaStream>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MediaStream {
+    pub kind: String,
+    pub connection: Option<IpAddr>,
+    pub port: u16,
+}
+
+/// Minimal RFC 4566 parser: only the `c=` and `m=` lines matter for
+/// knowing where media is expected to flow.
+pub fn parse_sdp(body: &[u8]) -> Option<SessionDescription> {
+    let body = std::str::from_utf8(body).ok()?;
+    if !body.trim_start().starts_with("v=") {
+        return None;
+    }
+
+    let mut description = SessionDescription {
+        connection: None,
+        media: Vec::new(),
+    };
+
+    for line in body.lines() {
+        let line = line.trim_end();
+        if let Some(connection) = line.strip_prefix("c=") {
+            // c=IN IP4 203.0.113.5 (multicast TTL suffixes are dropped)
+            let address = connection.split_whitespace()
+                .nth(2)
+                .and_then(|address| address.split('/').next())
+                .and_then(|address| address.parse().ok());
+            match description.media.last_mut() {
+                Some(stream) => stream.connection = address,
+                None => description.connection = address,
+            }
+        } else if let Some(media) = line.strip_prefix("m=") {
+            // m=audio 49170 RTP/AVP 0 8 (a port count suffix "49170/2" is ignored)
+            let mut fields = media.split_whitespace();
+            let kind = fields.next().unwrap_or("").to_string();
+            let port = fields.next()
+                .and_then(|port| port.split('/').next())
+                .and_then(|port| port.parse().ok());
+            if let Some(port) = port {
+                description.media.push(MediaStream { kind, connection: None, port });
+            }
+        }
+    }
+
+    Some(description)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MediaFinding {
+    ForeignSource { call_id: String, source_ip: IpAddr, port: u16 },
+    UnsolicitedFlood { source_ip: IpAddr, packets: usize, ports: usize, window: Duration },
+    MediaAfterDialogEnd { call_id: String, source_ip: IpAddr, port: u16, ended_for: Duration },
+}
+
+impl MediaFinding {
+    pub fn event_type(&self) -> &'static str {
+        match self {
+            MediaFinding::ForeignSource { .. } => "RTP_INJECTION",
+            MediaFinding::UnsolicitedFlood { .. } => "RTP_FLOOD",
+            MediaFinding::MediaAfterDialogEnd { .. } => "RTP_AFTER_DIALOG_END",
+        }
+    }
+
+    pub fn severity(&self) -> (u8, f32) {
+        match self {
+            MediaFinding::ForeignSource { .. } => (7, 0.8),
+            MediaFinding::UnsolicitedFlood { packets, .. } => (if *packets > 5000 { 9 } else { 7 }, 0.85),
+            // Late packets from a slow endpoint look the same for a moment
+            MediaFinding::MediaAfterDialogEnd { .. } => (5, 0.6),
+        }
+    }
+
+    pub fn category(&self) -> &'static str {
+        match self {
+            MediaFinding::UnsolicitedFlood { .. } => "DOS_ATTACK",
+            _ => "MEDIA_ATTACK",
+        }
+    }
+
+    pub fn source_ip(&self) -> IpAddr {
+        match self {
+            MediaFinding::ForeignSource { source_ip, .. }
+            | MediaFinding::UnsolicitedFlood { source_ip, .. }
+            | MediaFinding::MediaAfterDialogEnd { source_ip, .. } => *source_ip,
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        match self {
+            MediaFinding::ForeignSource { call_id, source_ip, port } => {
+                format!("RTP into port {} of dialog '{}' from {}, which is not a party to the call", port, call_id, source_ip)
+            }
+            MediaFinding::UnsolicitedFlood { source_ip, packets, ports, window } => {
+                format!(
+                    "{} sent {} UDP packets to {} RTP ports with no negotiated media in {}s",
+                    source_ip, packets, ports, window.as_secs()
+                )
+            }
+            MediaFinding::MediaAfterDialogEnd { call_id, source_ip, port, ended_for } => {
+                format!(
+                    "RTP from {} into port {} of dialog '{}', {}s after the dialog ended",
+                    source_ip, port, call_id, ended_for.as_secs()
+                )
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+struct DialogMedia {
+    ports: HashSet<u16>,
+    parties: HashSet<IpAddr>,
+    ended_at: Option<Instant>,
+    last_activity: Instant,
+    alerted_sources: HashSet<IpAddr>,
+    late_media_alerted: bool,
+}
+
+#[derive(Debug, Default)]
+struct UnsolicitedSource {
+    packets: SlidingWindow,
+    ports: HashMap<u16, Instant>,
+    last_seen: Option<Instant>,
+}
+
+/// Per-dialog media expectations learned from SDP, checked against the UDP
+/// traffic arriving in the RTP port range. Media ports are assumed to be
+/// allocated uniquely by the media servers being protected.
+pub struct MediaTracker {
+    config: MediaConfig,
+    dialogs: HashMap<String, DialogMedia>,
+    ports: HashMap<u16, String>,
+    unsolicited: HashMap<IpAddr, UnsolicitedSource>,
+    flood_threshold: u32,
+}
+
+impl MediaTracker {
+    const FLOOD_WINDOW: Duration = Duration::from_secs(10);
+    const ALERT_COOLDOWN: Duration = Duration::from_secs(60);
+    // Ended dialogs are remembered this long to catch media sent into them
+    const ENDED_RETENTION: Duration = Duration::from_secs(300);
+    // Dialogs whose BYE we never saw
+    const IDLE_DIALOG_TIMEOUT: Duration = Duration::from_secs(4 * 3600);
+    const MAX_DIALOGS: usize = 65536;
+    const MAX_UNSOLICITED_SOURCES: usize = 65536;
+
+    pub fn new(config: MediaConfig, flood_threshold: u32) -> Self {
+        MediaTracker {
+            config,
+            dialogs: HashMap::new(),
+            ports: HashMap::new(),
+            unsolicited: HashMap::new(),
+            flood_threshold,
+        }
+    }
+
+    pub fn set_flood_threshold(&mut self, threshold: u32) {
+        self.flood_threshold = threshold;
+    }
+
+    pub fn is_media_port(&self, port: u16) -> bool {
+        self.config.enabled && (self.config.rtp_port_min..=self.config.rtp_port_max).contains(&port)
+    }
+
+    /// Records an offer or answer. The signalling peer is trusted as a media
+    /// source too, since NATed endpoints send RTP from their public address.
+    pub fn on_session_description(&mut self, call_id: &str, description: &SessionDescription, signalling_ip: IpAddr, now: Instant) {
+        if !self.config.enabled {
+            return;
+        }
+        if !self.dialogs.contains_key(call_id) && self.dialogs.len() >= Self::MAX_DIALOGS {
+            self.cleanup(now);
+            if self.dialogs.len() >= Self::MAX_DIALOGS {
+                return;
+            }
+        }
+
+        let dialog = self.dialogs.entry(call_id.to_string()).or_insert_with(|| DialogMedia {
+            ports: HashSet::new(),
+            parties: HashSet::new(),
+            ended_at: None,
+            last_activity: now,
+            alerted_sources: HashSet::new(),
+            late_media_alerted: false,
+        });
+        dialog.last_activity = now;
+        dialog.parties.insert(signalling_ip);
+        if let Some(connection) = description.connection {
+            dialog.parties.insert(connection);
+        }
+
+        for stream in &description.media {
+            // Port 0 rejects the stream
+            if stream.port == 0 {
+                continue;
+            }
+            if let Some(connection) = stream.connection {
+                dialog.parties.insert(connection);
+            }
+            // RTCP rides on the next port up
+            for port in [stream.port, stream.port.saturating_add(1)] {
+                dialog.ports.insert(port);
+                self.ports.insert(port, call_id.to_string());
+            }
+        }
+    }
+
+    pub fn on_dialog_end(&mut self, call_id: &str, now: Instant) {
+        if let Some(dialog) = self.dialogs.get_mut(call_id) {
+            if dialog.ended_at.is_none() {
+                dialog.ended_at = Some(now);
+            }
+        }
+    }
+
+    /// Checks one UDP datagram addressed to the RTP range.
+    pub fn observe_packet(&mut self, source_ip: IpAddr, dest_port: u16, payload: &[u8], now: Instant) -> Option<MediaFinding> {
+        if !self.is_media_port(dest_port) {
+            return None;
+        }
+
+        let dialog = self.ports.get(&dest_port)
+            .and_then(|call_id| self.dialogs.get_mut(call_id).map(|dialog| (call_id, dialog)));
+
+        let (call_id, dialog) = match dialog {
+            Some(found) => found,
+            None => return self.record_unsolicited(source_ip, dest_port, now),
+        };
+
+        if let Some(ended_at) = dialog.ended_at {
+            let ended_for = now.duration_since(ended_at);
+            let grace = Duration::from_secs(self.config.ended_dialog_grace);
+            if ended_for > grace && is_rtp(payload) && !dialog.late_media_alerted {
+                dialog.late_media_alerted = true;
+                return Some(MediaFinding::MediaAfterDialogEnd {
+                    call_id: call_id.clone(),
+                    source_ip,
+                    port: dest_port,
+                    ended_for,
+                });
+            }
+            return None;
+        }
+
+        dialog.last_activity = now;
+        if is_rtp(payload) && !dialog.parties.contains(&source_ip) && dialog.alerted_sources.insert(source_ip) {
+            return Some(MediaFinding::ForeignSource {
+                call_id: call_id.clone(),
+                source_ip,
+                port: dest_port,
+            });
+        }
+
+        None
+    }
+
+    fn record_unsolicited(&mut self, source_ip: IpAddr, dest_port: u16, now: Instant) -> Option<MediaFinding> {
+        if !self.unsolicited.contains_key(&source_ip) && self.unsolicited.len() >= Self::MAX_UNSOLICITED_SOURCES {
+            return None;
+        }
+
+        let source = self.unsolicited.entry(source_ip).or_default();
+        source.last_seen = Some(now);
+        source.ports.insert(dest_port, now);
+        source.ports.retain(|_, seen| now.duration_since(*seen) <= Self::FLOOD_WINDOW);
+
+        let packets = source.packets.record(now, Self::FLOOD_WINDOW);
+        if packets > self.flood_threshold as usize && source.packets.try_alert(now, Self::ALERT_COOLDOWN) {
+            return Some(MediaFinding::UnsolicitedFlood {
+                source_ip,
+                packets,
+                ports: source.ports.len(),
+                window: Self::FLOOD_WINDOW,
+            });
+        }
+
+        None
+    }
+
+    pub fn active_dialogs(&self) -> usize {
+        self.dialogs.values().filter(|dialog| dialog.ended_at.is_none()).count()
+    }
+
+    pub fn cleanup(&mut self, now: Instant) {
+        self.dialogs.retain(|_, dialog| match dialog.ended_at {
+            Some(ended_at) => now.duration_since(ended_at) < Self::ENDED_RETENTION,
+            None => now.duration_since(dialog.last_activity) < Self::IDLE_DIALOG_TIMEOUT,
+        });
+        let dialogs = &self.dialogs;
+        self.ports.retain(|_, call_id| dialogs.contains_key(call_id));
+
+        self.unsolicited.retain(|_, source| {
+            source.last_seen.is_some_and(|seen| now.duration_since(seen) < Self::FLOOD_WINDOW * 6)
+        });
+    }
+}
+
+/// RTP version 2 with a full fixed header.
+fn is_rtp(payload: &[u8]) -> bool {
+    payload.len() >= 12 && payload[0] >> 6 == 2
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    const CALLER: IpAddr = IpAddr::V4(Ipv4Addr::new(198, 51, 100, 20));
+    const INJECTOR: IpAddr = IpAddr::V4(Ipv4Addr::new(203, 0, 113, 66));
+    const RTP: [u8; 12] = [0x80, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 1];
+
+    fn tracker(flood_threshold: u32) -> MediaTracker {
+        MediaTracker::new(MediaConfig::default(), flood_threshold)
+    }
+
+    fn offer(port: u16) -> SessionDescription {
+        let body = format!("v=0\r\no=- 1 1 IN IP4 198.51.100.20\r\nc=IN IP4 198.51.100.20\r\nm=audio {} RTP/AVP 0 8\r\n", port);
+        parse_sdp(body.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn parses_connection_and_media_lines() {
+        let description = parse_sdp(b"v=0\r\nc=IN IP4 192.0.2.1/127\r\nm=audio 49170/2 RTP/AVP 0\r\nm=video 0 RTP/AVP 31\r\nc=IN IP6 2001:db8::1\r\n").unwrap();
+        assert_eq!(description.connection, Some("192.0.2.1".parse().unwrap()));
+        assert_eq!(description.media.len(), 2);
+        assert_eq!((description.media[0].kind.as_str(), description.media[0].port), ("audio", 49170));
+        assert_eq!(description.media[1].connection, Some("2001:db8::1".parse().unwrap()));
+        assert!(parse_sdp(b"not sdp").is_none());
+    }
+
+    #[test]
+    fn media_from_outside_the_dialog_is_reported_once() {
+        let mut tracker = tracker(500);
+        let now = Instant::now();
+        tracker.on_session_description("call-1", &offer(12000), CALLER, now);
+
+        assert_eq!(tracker.observe_packet(CALLER, 12000, &RTP, now), None);
+        // RTCP on the next port up belongs to the dialog too
+        let finding = tracker.observe_packet(INJECTOR, 12001, &RTP, now).unwrap();
+        assert_eq!(finding.event_type(), "RTP_INJECTION");
+        assert_eq!(tracker.observe_packet(INJECTOR, 12000, &RTP, now), None);
+        // Non-RTP noise into the port is not an injection
+        assert_eq!(tracker.observe_packet(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 99)), 12000, b"hello", now), None);
+    }
+
+    #[test]
+    fn media_after_the_grace_period_of_a_bye_is_reported() {
+        let mut tracker = tracker(500);
+        let now = Instant::now();
+        tracker.on_session_description("call-1", &offer(12000), CALLER, now);
+        assert_eq!(tracker.active_dialogs(), 1);
+        tracker.on_dialog_end("call-1", now);
+        assert_eq!(tracker.active_dialogs(), 0);
+
+        // Inside the grace period late packets are expected
+        assert_eq!(tracker.observe_packet(CALLER, 12000, &RTP, now + Duration::from_secs(5)), None);
+        let finding = tracker.observe_packet(CALLER, 12000, &RTP, now + Duration::from_secs(6)).unwrap();
+        assert_eq!(finding.event_type(), "RTP_AFTER_DIALOG_END");
+        assert_eq!(tracker.observe_packet(CALLER, 12000, &RTP, now + Duration::from_secs(7)), None);
+    }
+
+    #[test]
+    fn unsolicited_packets_over_the_threshold_within_the_window_are_a_flood() {
+        let mut tracker = tracker(20);
+        let now = Instant::now();
+
+        // Spread over more than the window the rate never builds up
+        for packet in 0..40u64 {
+            let at = now + Duration::from_millis(packet * 600);
+            assert_eq!(tracker.observe_packet(INJECTOR, 15000 + packet as u16, &RTP, at), None);
+        }
+
+        let burst = now + Duration::from_secs(60);
+        for packet in 0..20u16 {
+            assert_eq!(tracker.observe_packet(INJECTOR, 16000 + packet, &RTP, burst), None);
+        }
+        match tracker.observe_packet(INJECTOR, 16020, &RTP, burst) {
+            Some(MediaFinding::UnsolicitedFlood { packets, ports, .. }) => assert_eq!((packets, ports), (21, 21)),
+            other => panic!("unexpected {:?}", other),
+        }
+        // Ports outside the RTP range are not looked at
+        assert_eq!(tracker.observe_packet(INJECTOR, 5060, &RTP, burst), None);
+    }
+}
@@ -0,0 +1,760 @@
+use std::fmt;
+use thiserror::Error;
+
+// Upper bound on the header section we are willing to look at. Real SIP
+// messages stay well below this; anything larger is treated as garbage.
+const MAX_HEADER_SECTION: usize = 16 * 1024;
+
+#[derive(Debug, Error, Clone, PartialEq)]
+pub enum SipParseError {
+    #[error("empty message")]
+    Empty,
+    #[error("message is not valid UTF-8 in its header section")]
+    InvalidEncoding,
+    #[error("invalid start line: {0}")]
+    InvalidStartLine(String),
+    #[error("unsupported SIP version: {0}")]
+    UnsupportedVersion(String),
+    #[error("invalid status code: {0}")]
+    InvalidStatusCode(String),
+    #[error("malformed header line: {0}")]
+    MalformedHeader(String),
+    #[error("header section exceeds {0} bytes")]
+    HeaderTooLarge(usize),
+    #[error("invalid Content-Length: {0}")]
+    InvalidContentLength(String),
+    #[error("message exceeds {0} bytes")]
+    MessageTooLarge(usize),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum SipMethod {
+    Invite,
+    Ack,
+    Bye,
+    Cancel,
+    Register,
+    Options,
+    Prack,
+    Subscribe,
+    Notify,
+    Publish,
+    Info,
+    Refer,
+    Message,
+    Update,
+    Other(String),
+}
+
+impl SipMethod {
+    pub fn from_token(token: &str) -> Self {
+        match token {
+            "INVITE" => SipMethod::Invite,
+            "ACK" => SipMethod::Ack,
+            "BYE" => SipMethod::Bye,
+            "CANCEL" => SipMethod::Cancel,
+            "REGISTER" => SipMethod::Register,
+            "OPTIONS" => SipMethod::Options,
+            "PRACK" => SipMethod::Prack,
+            "SUBSCRIBE" => SipMethod::Subscribe,
+            "NOTIFY" => SipMethod::Notify,
+            "PUBLISH" => SipMethod::Publish,
+            "INFO" => SipMethod::Info,
+            "REFER" => SipMethod::Refer,
+            "MESSAGE" => SipMethod::Message,
+            "UPDATE" => SipMethod::Update,
+            other => SipMethod::Other(other.to_string()),
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        match self {
+            SipMethod::Invite => "INVITE",
+            SipMethod::Ack => "ACK",
+            SipMethod::Bye => "BYE",
+            SipMethod::Cancel => "CANCEL",
+            SipMethod::Register => "REGISTER",
+            SipMethod::Options => "OPTIONS",
+            SipMethod::Prack => "PRACK",
+            SipMethod::Subscribe => "SUBSCRIBE",
+            SipMethod::Notify => "NOTIFY",
+            SipMethod::Publish => "PUBLISH",
+            SipMethod::Info => "INFO",
+            SipMethod::Refer => "REFER",
+            SipMethod::Message => "MESSAGE",
+            SipMethod::Update => "UPDATE",
+            SipMethod::Other(token) => token,
+        }
+    }
+}
+
+impl fmt::Display for SipMethod {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StartLine {
+    Request { method: SipMethod, uri: String },
+    Response { status_code: u16, reason: String },
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct SipUri {
+    pub scheme: String,
+    pub user: Option<String>,
+    pub host: String,
+    pub port: Option<u16>,
+    pub params: Vec<(String, Option<String>)>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct NameAddr {
+    pub display_name: Option<String>,
+    pub uri: SipUri,
+    pub tag: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Via {
+    pub transport: String,
+    pub host: String,
+    pub port: Option<u16>,
+    pub branch: Option<String>,
+    pub received: Option<String>,
+    pub rport: Option<Option<u16>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CSeq {
+    pub sequence: u32,
+    pub method: SipMethod,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SipMessage {
+    pub start_line: StartLine,
+    pub version: String,
+    /// Headers in wire order, names expanded from compact form and values unfolded.
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl SipMessage {
+    pub fn is_request(&self) -> bool {
+        matches!(self.start_line, StartLine::Request { .. })
+    }
+
+    pub fn method(&self) -> Option<&SipMethod> {
+        match &self.start_line {
+            StartLine::Request { method, .. } => Some(method),
+            StartLine::Response { .. } => None,
+        }
+    }
+
+    pub fn request_uri(&self) -> Option<&str> {
+        match &self.start_line {
+            StartLine::Request { uri, .. } => Some(uri),
+            StartLine::Response { .. } => None,
+        }
+    }
+
+    pub fn status_code(&self) -> Option<u16> {
+        match &self.start_line {
+            StartLine::Response { status_code, .. } => Some(*status_code),
+            StartLine::Request { .. } => None,
+        }
+    }
+
+    /// First value of a header, matched case-insensitively on its full name.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter()
+            .find(|(header_name, _)| header_name.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Every value of a header, with comma-separated lists split into separate entries.
+    pub fn header_values(&self, name: &str) -> Vec<&str> {
+        self.headers.iter()
+            .filter(|(header_name, _)| header_name.eq_ignore_ascii_case(name))
+            .flat_map(|(_, value)| split_header_list(value))
+            .collect()
+    }
+
+    pub fn call_id(&self) -> Option<&str> {
+        self.header("Call-ID")
+    }
+
+    pub fn cseq(&self) -> Option<CSeq> {
+        let value = self.header("CSeq")?;
+        let mut parts = value.split_whitespace();
+        let sequence = parts.next()?.parse().ok()?;
+        let method = SipMethod::from_token(parts.next()?);
+        Some(CSeq { sequence, method })
+    }
+
+    pub fn from(&self) -> Option<NameAddr> {
+        self.header("From").and_then(parse_name_addr)
+    }
+
+    pub fn to(&self) -> Option<NameAddr> {
+        self.header("To").and_then(parse_name_addr)
+    }
+
+    pub fn vias(&self) -> Vec<Via> {
+        self.header_values("Via")
+            .into_iter()
+            .filter_map(parse_via)
+            .collect()
+    }
+
+    pub fn user_agent(&self) -> Option<&str> {
+        self.header("User-Agent")
+    }
+
+    pub fn content_length(&self) -> Option<usize> {
+        self.header("Content-Length").and_then(|value| value.trim().parse().ok())
+    }
+
+    /// Digest username from Authorization or Proxy-Authorization, if credentials were sent.
+    pub fn auth_username(&self) -> Option<String> {
+        self.auth_params()
+            .into_iter()
+            .find(|(key, _)| key == "username")
+            .map(|(_, value)| value)
+    }
+
+    /// Digest parameters from Authorization or Proxy-Authorization, keys lowercased
+    /// and values unquoted.
+    pub fn auth_params(&self) -> Vec<(String, String)> {
+        let value = match self.header("Authorization").or_else(|| self.header("Proxy-Authorization")) {
+            Some(value) => value,
+            None => return Vec::new(),
+        };
+        let params = match value.trim().strip_prefix("Digest") {
+            Some(params) => params.trim_start(),
+            None => return Vec::new(),
+        };
+        split_header_list(params).into_iter()
+            .filter_map(|param| param.split_once('='))
+            .map(|(key, value)| (key.trim().to_ascii_lowercase(), value.trim().trim_matches('"').to_string()))
+            .collect()
+    }
+}
+
+/// Expands the RFC 3261 / RFC 3515 / RFC 3892 single-letter header forms.
+pub fn canonical_header_name(name: &str) -> String {
+    let expanded = match name.to_ascii_lowercase().as_str() {
+        "i" | "call-id" => "Call-ID",
+        "m" | "contact" => "Contact",
+        "e" | "content-encoding" => "Content-Encoding",
+        "l" | "content-length" => "Content-Length",
+        "c" | "content-type" => "Content-Type",
+        "f" | "from" => "From",
+        "s" | "subject" => "Subject",
+        "k" | "supported" => "Supported",
+        "t" | "to" => "To",
+        "v" | "via" => "Via",
+        "o" | "event" => "Event",
+        "r" | "refer-to" => "Refer-To",
+        "b" | "referred-by" => "Referred-By",
+        "x" | "session-expires" => "Session-Expires",
+        "u" | "allow-events" => "Allow-Events",
+        "cseq" => "CSeq",
+        "user-agent" => "User-Agent",
+        "www-authenticate" => "WWW-Authenticate",
+        "proxy-authenticate" => "Proxy-Authenticate",
+        "authorization" => "Authorization",
+        "proxy-authorization" => "Proxy-Authorization",
+        "max-forwards" => "Max-Forwards",
+        _ => return name.to_string(),
+    };
+    expanded.to_string()
+}
+
+pub fn parse_sip_message(data: &[u8]) -> Result<SipMessage, SipParseError> {
+    // Tolerate leading CRLF keep-alives (RFC 5626) before the start line
+    let start = data.iter().position(|&b| b != b'\r' && b != b'\n').ok_or(SipParseError::Empty)?;
+    let data = &data[start..];
+
+    let (header_end, body_start) = match find_subsequence(data, b"\r\n\r\n") {
+        Some(pos) => (pos, pos + 4),
+        None => match find_subsequence(data, b"\n\n") {
+            Some(pos) => (pos, pos + 2),
+            // No blank line: a header-only datagram, which many scanners send
+            None => (data.len(), data.len()),
+        },
+    };
+
+    if header_end > MAX_HEADER_SECTION {
+        return Err(SipParseError::HeaderTooLarge(MAX_HEADER_SECTION));
+    }
+
+    let header_section = std::str::from_utf8(&data[..header_end])
+        .map_err(|_| SipParseError::InvalidEncoding)?;
+
+    let mut lines = header_section.split('\n').map(|line| line.strip_suffix('\r').unwrap_or(line));
+    let first_line = lines.next().ok_or(SipParseError::Empty)?;
+    let (start_line, version) = parse_start_line(first_line)?;
+
+    let mut headers: Vec<(String, String)> = Vec::new();
+    for line in lines {
+        if line.is_empty() {
+            continue;
+        }
+
+        // Folded header (RFC 3261 7.3.1): continuation of the previous value
+        if line.starts_with(' ') || line.starts_with('\t') {
+            match headers.last_mut() {
+                Some((_, value)) => {
+                    value.push(' ');
+                    value.push_str(line.trim());
+                }
+                None => return Err(SipParseError::MalformedHeader(line.to_string())),
+            }
+            continue;
+        }
+
+        let (name, value) = line.split_once(':')
+            .ok_or_else(|| SipParseError::MalformedHeader(line.to_string()))?;
+        let name = name.trim();
+        if name.is_empty() || name.contains(char::is_whitespace) {
+            return Err(SipParseError::MalformedHeader(line.to_string()));
+        }
+
+        headers.push((canonical_header_name(name), value.trim().to_string()));
+    }
+
+    let mut body = data[body_start..].to_vec();
+    if let Some((_, value)) = headers.iter().find(|(name, _)| name == "Content-Length") {
+        let length: usize = value.trim().parse()
+            .map_err(|_| SipParseError::InvalidContentLength(value.clone()))?;
+        // Over UDP the datagram boundary wins; just trim any trailing padding
+        body.truncate(length);
+    }
+
+    Ok(SipMessage {
+        start_line,
+        version,
+        headers,
+        body,
+    })
+}
+
+/// Length of the first complete message at the start of a stream buffer.
+///
+/// Stream transports frame messages with Content-Length (RFC 3261 18.3), so
+/// a missing header means an empty body. Returns `Ok(None)` until the whole
+/// message has arrived. Leading CRLF keep-alives are counted in the length,
+/// and a message longer than `max_message` is an error rather than a wait.
+pub fn stream_message_length(data: &[u8], max_message: usize) -> Result<Option<usize>, SipParseError> {
+    let skip = data.iter().take_while(|&&b| b == b'\r' || b == b'\n').count();
+    let data = &data[skip..];
+
+    let (header_end, body_start) = match find_subsequence(data, b"\r\n\r\n") {
+        Some(pos) => (pos, pos + 4),
+        None => {
+            if data.len() > MAX_HEADER_SECTION {
+                return Err(SipParseError::HeaderTooLarge(MAX_HEADER_SECTION));
+            }
+            return Ok(None);
+        }
+    };
+
+    if header_end > MAX_HEADER_SECTION {
+        return Err(SipParseError::HeaderTooLarge(MAX_HEADER_SECTION));
+    }
+
+    let header_section = std::str::from_utf8(&data[..header_end])
+        .map_err(|_| SipParseError::InvalidEncoding)?;
+
+    let mut content_length = 0;
+    for line in header_section.split("\r\n").skip(1) {
+        if let Some((name, value)) = line.split_once(':') {
+            if canonical_header_name(name.trim()) == "Content-Length" {
+                content_length = value.trim().parse()
+                    .map_err(|_| SipParseError::InvalidContentLength(value.trim().to_string()))?;
+            }
+        }
+    }
+
+    let total = skip.checked_add(body_start)
+        .and_then(|length| length.checked_add(content_length))
+        .filter(|&total| total <= max_message)
+        .ok_or(SipParseError::MessageTooLarge(max_message))?;
+    Ok(if skip + data.len() >= total { Some(total) } else { None })
+}
+
+/// Whether a line is a SIP request or status line, used to find the next
+/// message boundary after a stream lost data.
+pub fn is_start_line(line: &[u8]) -> bool {
+    std::str::from_utf8(line).is_ok_and(|line| parse_start_line(line).is_ok())
+}
+
+fn parse_start_line(line: &str) -> Result<(StartLine, String), SipParseError> {
+    if let Some(rest) = line.strip_prefix("SIP/") {
+        // Status-Line: SIP-Version SP Status-Code SP Reason-Phrase
+        let mut parts = line.splitn(3, ' ');
+        let version = parts.next().unwrap_or_default();
+        if !rest.starts_with("2.0") {
+            return Err(SipParseError::UnsupportedVersion(version.to_string()));
+        }
+        let code_str = parts.next().ok_or_else(|| SipParseError::InvalidStartLine(line.to_string()))?;
+        let status_code: u16 = code_str.parse()
+            .map_err(|_| SipParseError::InvalidStatusCode(code_str.to_string()))?;
+        if !(100..=699).contains(&status_code) {
+            return Err(SipParseError::InvalidStatusCode(code_str.to_string()));
+        }
+        let reason = parts.next().unwrap_or("").trim().to_string();
+
+        return Ok((StartLine::Response { status_code, reason }, version.to_string()));
+    }
+
+    // Request-Line: Method SP Request-URI SP SIP-Version
+    let parts: Vec<&str> = line.split(' ').collect();
+    if parts.len() != 3 || parts.iter().any(|part| part.is_empty()) {
+        return Err(SipParseError::InvalidStartLine(line.to_string()));
+    }
+
+    let (method, uri, version) = (parts[0], parts[1], parts[2]);
+    if !method.chars().all(|c| c.is_ascii_alphanumeric() || "-.!%*_+`'~".contains(c)) {
+        return Err(SipParseError::InvalidStartLine(line.to_string()));
+    }
+    if version != "SIP/2.0" {
+        return Err(SipParseError::UnsupportedVersion(version.to_string()));
+    }
+
+    Ok((
+        StartLine::Request {
+            method: SipMethod::from_token(method),
+            uri: uri.to_string(),
+        },
+        version.to_string(),
+    ))
+}
+
+pub fn parse_uri(input: &str) -> Option<SipUri> {
+    let input = input.trim();
+    let (scheme, rest) = input.split_once(':')?;
+    let scheme = scheme.to_ascii_lowercase();
+    if !matches!(scheme.as_str(), "sip" | "sips" | "tel") {
+        return None;
+    }
+
+    // Headers (?...) are not interesting for detection purposes
+    let rest = rest.split('?').next().unwrap_or(rest);
+    let mut segments = rest.split(';');
+    let addr = segments.next()?;
+    let params = segments
+        .filter(|param| !param.is_empty())
+        .map(|param| match param.split_once('=') {
+            Some((key, value)) => (key.to_ascii_lowercase(), Some(value.to_string())),
+            None => (param.to_ascii_lowercase(), None),
+        })
+        .collect();
+
+    if scheme == "tel" {
+        return Some(SipUri { scheme, user: Some(addr.to_string()), host: String::new(), port: None, params });
+    }
+
+    let (user, hostport) = match addr.rsplit_once('@') {
+        Some((user, hostport)) => (Some(user.split(':').next().unwrap_or(user).to_string()), hostport),
+        None => (None, addr),
+    };
+    let (host, port) = split_host_port(hostport);
+    if host.is_empty() {
+        return None;
+    }
+
+    Some(SipUri { scheme, user, host, port, params })
+}
+
+/// Parses From/To/Contact values: `"Name" <sip:user@host>;tag=x` or `sip:user@host;tag=x`.
+pub fn parse_name_addr(input: &str) -> Option<NameAddr> {
+    let input = input.trim();
+    if input == "*" {
+        return None;
+    }
+
+    let (display_name, uri_str, header_params) = match input.find('<') {
+        Some(open) => {
+            let close = input[open..].find('>')? + open;
+            let display = input[..open].trim().trim_matches('"').trim();
+            let display_name = if display.is_empty() { None } else { Some(display.to_string()) };
+            (display_name, &input[open + 1..close], &input[close + 1..])
+        }
+        None => {
+            // Without angle brackets the parameters belong to the header, not the URI
+            match input.find(';') {
+                Some(semi) => (None, &input[..semi], &input[semi..]),
+                None => (None, input, ""),
+            }
+        }
+    };
+
+    let uri = parse_uri(uri_str)?;
+    let tag = header_params.split(';')
+        .filter_map(|param| param.trim().split_once('='))
+        .find(|(key, _)| key.eq_ignore_ascii_case("tag"))
+        .map(|(_, value)| value.to_string());
+
+    Some(NameAddr { display_name, uri, tag })
+}
+
+pub fn parse_via(input: &str) -> Option<Via> {
+    // SIP / 2.0 / UDP is legal with LWS around the slashes
+    let input = input.trim();
+    let (transport, rest) = split_via_protocol(input)?;
+    let transport = transport.to_ascii_uppercase();
+
+    let mut segments = rest.split(';');
+    let (host, port) = split_host_port(segments.next()?.trim());
+    if host.is_empty() {
+        return None;
+    }
+
+    let mut via = Via { transport, host, port, branch: None, received: None, rport: None };
+    for param in segments {
+        let param = param.trim();
+        match param.split_once('=') {
+            Some((key, value)) => match key.trim().to_ascii_lowercase().as_str() {
+                "branch" => via.branch = Some(value.trim().to_string()),
+                "received" => via.received = Some(value.trim().to_string()),
+                "rport" => via.rport = Some(value.trim().parse().ok()),
+                _ => {}
+            },
+            None if param.eq_ignore_ascii_case("rport") => via.rport = Some(None),
+            None => {}
+        }
+    }
+
+    Some(via)
+}
+
+fn split_via_protocol(input: &str) -> Option<(&str, &str)> {
+    // Skip "SIP/2.0/" and return the transport token plus the sent-by remainder
+    let first = input.find('/')?;
+    let second = input[first + 1..].find('/')? + first + 1;
+    let after = input[second + 1..].trim_start();
+    let transport_end = after.find(char::is_whitespace)?;
+    Some((&after[..transport_end], after[transport_end..].trim_start()))
+}
+
+fn split_host_port(hostport: &str) -> (String, Option<u16>) {
+    let hostport = hostport.trim();
+    if let Some(rest) = hostport.strip_prefix('[') {
+        // IPv6 reference: [2001:db8::1]:5060
+        if let Some((host, tail)) = rest.split_once(']') {
+            let port = tail.strip_prefix(':').and_then(|p| p.parse().ok());
+            return (host.to_string(), port);
+        }
+    }
+    match hostport.rsplit_once(':') {
+        Some((host, port)) if !host.contains(':') => (host.to_string(), port.parse().ok()),
+        _ => (hostport.to_string(), None),
+    }
+}
+
+/// Splits a header value on commas that are not inside quotes or angle brackets.
+pub fn split_header_list(value: &str) -> Vec<&str> {
+    let mut items = Vec::new();
+    let mut in_quotes = false;
+    let mut in_angle = false;
+    let mut start = 0;
+
+    for (idx, c) in value.char_indices() {
+        match c {
+            '"' => in_quotes = !in_quotes,
+            '<' if !in_quotes => in_angle = true,
+            '>' if !in_quotes => in_angle = false,
+            ',' if !in_quotes && !in_angle => {
+                items.push(value[start..idx].trim());
+                start = idx + 1;
+            }
+            _ => {}
+        }
+    }
+    items.push(value[start..].trim());
+    items.retain(|item| !item.is_empty());
+    items
+}
+
+fn find_subsequence(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REGISTER: &str = "REGISTER sip:pbx.example.com SIP/2.0\r\n\
+        Via: SIP/2.0/UDP 203.0.113.7:5062;branch=z9hG4bK-524287-1---abc;rport\r\n\
+        Max-Forwards: 70\r\n\
+        From: \"1001\" <sip:1001@pbx.example.com>;tag=3b2c1f\r\n\
+        To: <sip:1001@pbx.example.com>\r\n\
+        Call-ID: a84b4c76e66710@203.0.113.7\r\n\
+        CSeq: 2 REGISTER\r\n\
+        Contact: <sip:1001@203.0.113.7:5062>;expires=3600\r\n\
+        User-Agent: friendly-scanner\r\n\
+        Content-Length: 0\r\n\r\n";
+
+    #[test]
+    fn parses_register_request() {
+        let msg = parse_sip_message(REGISTER.as_bytes()).unwrap();
+
+        assert_eq!(msg.method(), Some(&SipMethod::Register));
+        assert_eq!(msg.request_uri(), Some("sip:pbx.example.com"));
+        assert_eq!(msg.call_id(), Some("a84b4c76e66710@203.0.113.7"));
+        assert_eq!(msg.cseq(), Some(CSeq { sequence: 2, method: SipMethod::Register }));
+        assert_eq!(msg.user_agent(), Some("friendly-scanner"));
+
+        let from = msg.from().unwrap();
+        assert_eq!(from.display_name.as_deref(), Some("1001"));
+        assert_eq!(from.uri.user.as_deref(), Some("1001"));
+        assert_eq!(from.tag.as_deref(), Some("3b2c1f"));
+
+        let vias = msg.vias();
+        assert_eq!(vias.len(), 1);
+        assert_eq!(vias[0].transport, "UDP");
+        assert_eq!(vias[0].host, "203.0.113.7");
+        assert_eq!(vias[0].port, Some(5062));
+        assert_eq!(vias[0].branch.as_deref(), Some("z9hG4bK-524287-1---abc"));
+        assert_eq!(vias[0].rport, Some(None));
+
+        let contacts: Vec<NameAddr> = msg.header_values("Contact").into_iter().filter_map(parse_name_addr).collect();
+        assert_eq!(contacts[0].uri.port, Some(5062));
+    }
+
+    #[test]
+    fn parses_response_with_status_code() {
+        let data = "SIP/2.0 401 Unauthorized\r\n\
+            Via: SIP/2.0/UDP 203.0.113.7:5062;branch=z9hG4bK1;received=203.0.113.7;rport=5062\r\n\
+            To: <sip:1001@pbx.example.com>;tag=as5f\r\n\
+            Call-ID: abc\r\n\
+            CSeq: 1 REGISTER\r\n\r\n";
+        let msg = parse_sip_message(data.as_bytes()).unwrap();
+
+        assert!(!msg.is_request());
+        assert_eq!(msg.status_code(), Some(401));
+        assert_eq!(msg.to().unwrap().tag.as_deref(), Some("as5f"));
+        assert_eq!(msg.vias()[0].rport, Some(Some(5062)));
+        assert_eq!(msg.vias()[0].received.as_deref(), Some("203.0.113.7"));
+    }
+
+    #[test]
+    fn expands_compact_header_forms() {
+        let data = "OPTIONS sip:100@192.0.2.1 SIP/2.0\r\n\
+            v: SIP/2.0/UDP 198.51.100.4:5060;branch=z9hG4bKcompact\r\n\
+            f: sip:probe@198.51.100.4;tag=1\r\n\
+            t: sip:100@192.0.2.1\r\n\
+            i: compact-call-id\r\n\
+            m: <sip:probe@198.51.100.4>\r\n\
+            l: 0\r\n\
+            CSeq: 1 OPTIONS\r\n\r\n";
+        let msg = parse_sip_message(data.as_bytes()).unwrap();
+
+        assert_eq!(msg.call_id(), Some("compact-call-id"));
+        assert_eq!(msg.from().unwrap().tag.as_deref(), Some("1"));
+        assert_eq!(msg.to().unwrap().uri.user.as_deref(), Some("100"));
+        assert_eq!(msg.vias()[0].branch.as_deref(), Some("z9hG4bKcompact"));
+        assert_eq!(msg.header_values("Contact").len(), 1);
+        assert_eq!(msg.content_length(), Some(0));
+    }
+
+    #[test]
+    fn unfolds_continuation_lines() {
+        let data = "INVITE sip:00442071234567@pbx SIP/2.0\r\n\
+            Via: SIP/2.0/UDP 198.51.100.4;branch=z9hG4bK1\r\n\
+            Subject: I know you're there,\r\n\
+            \x20\x20\x20\x20\x20pick up the phone\r\n\
+            \tand talk to me!\r\n\
+            Call-ID: folded\r\n\
+            CSeq: 1 INVITE\r\n\r\n";
+        let msg = parse_sip_message(data.as_bytes()).unwrap();
+
+        assert_eq!(msg.header("subject"), Some("I know you're there, pick up the phone and talk to me!"));
+        assert_eq!(msg.call_id(), Some("folded"));
+    }
+
+    #[test]
+    fn splits_multi_valued_via_and_contact() {
+        let data = "SIP/2.0 200 OK\r\n\
+            Via: SIP/2.0/UDP proxy.example.com;branch=z9hG4bK2, SIP / 2.0 / TCP 10.0.0.1:5080;branch=z9hG4bK3\r\n\
+            Contact: \"A, B\" <sip:a@10.0.0.1>, <sip:b@10.0.0.2>;expires=60\r\n\
+            Call-ID: multi\r\n\
+            CSeq: 7 REGISTER\r\n\r\n";
+        let msg = parse_sip_message(data.as_bytes()).unwrap();
+
+        let vias = msg.vias();
+        assert_eq!(vias.len(), 2);
+        assert_eq!(vias[1].transport, "TCP");
+        assert_eq!(vias[1].host, "10.0.0.1");
+        assert_eq!(vias[1].port, Some(5080));
+
+        let contacts: Vec<NameAddr> = msg.header_values("Contact").into_iter().filter_map(parse_name_addr).collect();
+        assert_eq!(contacts.len(), 2);
+        assert_eq!(contacts[0].display_name.as_deref(), Some("A, B"));
+        assert_eq!(contacts[1].uri.host, "10.0.0.2");
+    }
+
+    #[test]
+    fn extracts_digest_username() {
+        let data = "INVITE sip:0090123456@pbx SIP/2.0\r\n\
+            Proxy-Authorization: Digest username=\"1001\", realm=\"pbx\", nonce=\"a,b\", uri=\"sip:0090123456@pbx\", response=\"00\"\r\n\
+            Call-ID: auth\r\n\
+            CSeq: 2 INVITE\r\n\r\n";
+        let msg = parse_sip_message(data.as_bytes()).unwrap();
+        assert_eq!(msg.auth_username().as_deref(), Some("1001"));
+    }
+
+    #[test]
+    fn extracts_body_using_content_length() {
+        let data = "INVITE sip:100@pbx SIP/2.0\r\nCall-ID: x\r\nCSeq: 1 INVITE\r\nContent-Length: 4\r\n\r\nv=0\r\njunk";
+        let msg = parse_sip_message(data.as_bytes()).unwrap();
+        assert_eq!(msg.body, b"v=0\r");
+    }
+
+    #[test]
+    fn frames_stream_messages_by_content_length() {
+        let first = "OPTIONS sip:a@b SIP/2.0\r\nCall-ID: 1\r\nl: 3\r\n\r\nabc";
+        let second = "OPTIONS sip:a@b SIP/2.0\r\nCall-ID: 2\r\n\r\n";
+        let stream = format!("\r\n\r\n{}{}", first, second);
+        let data = stream.as_bytes();
+
+        let first_len = stream_message_length(data, 1024).unwrap().unwrap();
+        assert_eq!(first_len, 4 + first.len());
+        assert_eq!(stream_message_length(&data[first_len..], 1024).unwrap(), Some(second.len()));
+        assert_eq!(stream_message_length(&data[..first_len - 1], 1024).unwrap(), None);
+        assert_eq!(stream_message_length(b"OPTIONS sip:a@b SIP/2.0\r\nCall-ID: 3\r\n", 1024).unwrap(), None);
+    }
+
+    #[test]
+    fn rejects_content_length_past_the_cap() {
+        let huge = format!("OPTIONS sip:a@b SIP/2.0\r\nContent-Length: {}\r\n\r\n", usize::MAX);
+        assert_eq!(stream_message_length(huge.as_bytes(), 1024), Err(SipParseError::MessageTooLarge(1024)));
+        let large = "OPTIONS sip:a@b SIP/2.0\r\nContent-Length: 2000\r\n\r\n";
+        assert_eq!(stream_message_length(large.as_bytes(), 1024), Err(SipParseError::MessageTooLarge(1024)));
+    }
+
+    #[test]
+    fn parses_ipv6_uri() {
+        let uri = parse_uri("sip:alice@[2001:db8::10]:5070;transport=tcp").unwrap();
+        assert_eq!(uri.user.as_deref(), Some("alice"));
+        assert_eq!(uri.host, "2001:db8::10");
+        assert_eq!(uri.port, Some(5070));
+        assert_eq!(uri.params, vec![("transport".to_string(), Some("tcp".to_string()))]);
+    }
+
+    #[test]
+    fn rejects_malformed_input() {
+        assert_eq!(parse_sip_message(b"\r\n\r\n"), Err(SipParseError::Empty));
+        assert!(matches!(parse_sip_message(b"GET / HTTP/1.1\r\n\r\n"), Err(SipParseError::UnsupportedVersion(_))));
+        assert!(matches!(parse_sip_message(b"SIP/2.0 99 Weird\r\n\r\n"), Err(SipParseError::InvalidStatusCode(_))));
+        assert!(matches!(parse_sip_message(b"OPTIONS sip:x SIP/2.0\r\nNoColonHere\r\n\r\n"), Err(SipParseError::MalformedHeader(_))));
+        assert!(matches!(
+            parse_sip_message(b"OPTIONS sip:x SIP/2.0\r\nContent-Length: abc\r\n\r\n"),
+            Err(SipParseError::InvalidContentLength(_))
+        ));
+    }
+}
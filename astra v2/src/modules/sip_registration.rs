@@ -0,0 +1,323 @@
+use std::collections::{HashMap, VecDeque};
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+use crate::core::config::RegistrationHijackConfig;
+use crate::core::netinfo::{NetInfoDatabase, NetworkInfo};
+
+/// A contact binding the registrar accepted for an address-of-record.
+#[derive(Debug, Clone)]
+pub struct ObservedBinding {
+    pub source_ip: IpAddr,
+    pub contact: String,
+    pub user_agent: Option<String>,
+    pub expires: Duration,
+    pub internal: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RegistrationFinding {
+    BindingMoved { aor: String, from_ip: IpAddr, from: NetworkInfo, to_ip: IpAddr, to: NetworkInfo, user_agent: Option<String> },
+    ConcurrentSources { aor: String, sources: Vec<IpAddr>, window: Duration },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MoveKind {
+    Country,
+    Asn,
+    Network,
+}
+
+impl RegistrationFinding {
+    pub fn event_type(&self) -> &'static str {
+        match self {
+            RegistrationFinding::BindingMoved { .. } => "SIP_REGISTRATION_HIJACK",
+            RegistrationFinding::ConcurrentSources { .. } => "SIP_REGISTRATION_CONCURRENT_SOURCES",
+        }
+    }
+
+    pub fn severity(&self) -> (u8, f32) {
+        match self {
+            RegistrationFinding::BindingMoved { from, to, .. } => match move_kind(from, to) {
+                MoveKind::Country => (8, 0.75),
+                MoveKind::Asn => (7, 0.65),
+                // Roaming between addresses of one provider is common
+                MoveKind::Network => (5, 0.5),
+            },
+            RegistrationFinding::ConcurrentSources { sources, .. } => (if sources.len() > 5 { 8 } else { 7 }, 0.75),
+        }
+    }
+
+    pub fn source_ip(&self) -> IpAddr {
+        match self {
+            RegistrationFinding::BindingMoved { to_ip, .. } => *to_ip,
+            RegistrationFinding::ConcurrentSources { sources, .. } => sources[sources.len() - 1],
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        match self {
+            RegistrationFinding::BindingMoved { aor, from_ip, from, to_ip, to, user_agent } => {
+                let change = match move_kind(from, to) {
+                    MoveKind::Country => "country",
+                    MoveKind::Asn => "ASN",
+                    MoveKind::Network => "network",
+                };
+                format!(
+                    "Registration for '{}' moved to a new {}: {} ({}) -> {} ({}), User-Agent: {}",
+                    aor, change, from_ip, from.describe(), to_ip, to.describe(),
+                    user_agent.as_deref().unwrap_or("-")
+                )
+            }
+            RegistrationFinding::ConcurrentSources { aor, sources, window } => {
+                let sources: Vec<String> = sources.iter().map(|ip| ip.to_string()).collect();
+                format!(
+                    "'{}' registered from {} addresses within {}s: {}",
+                    aor, sources.len(), window.as_secs(), sources.join(", ")
+                )
+            }
+        }
+    }
+}
+
+fn move_kind(from: &NetworkInfo, to: &NetworkInfo) -> MoveKind {
+    if from.country.is_some() && to.country.is_some() && from.country != to.country {
+        MoveKind::Country
+    } else if from.asn.is_some() && to.asn.is_some() && from.asn != to.asn {
+        MoveKind::Asn
+    } else {
+        MoveKind::Network
+    }
+}
+
+#[derive(Debug, Clone)]
+struct BindingRecord {
+    source_ip: IpAddr,
+    network: NetworkInfo,
+    contact: String,
+    internal: bool,
+    last_registered: Instant,
+    expires_at: Instant,
+}
+
+#[derive(Debug, Default)]
+struct AorHistory {
+    bindings: VecDeque<BindingRecord>,
+    last_move_alert: Option<Instant>,
+    last_concurrency_alert: Option<Instant>,
+    last_seen: Option<Instant>,
+}
+
+/// Contact/source binding history per address-of-record. Only successful
+/// registrations are recorded, so the history reflects what the registrar
+/// actually accepted, wherever that registrar runs.
+pub struct RegistrationTracker {
+    config: RegistrationHijackConfig,
+    netinfo: NetInfoDatabase,
+    aors: HashMap<String, AorHistory>,
+}
+
+impl RegistrationTracker {
+    const ALERT_COOLDOWN: Duration = Duration::from_secs(900);
+    const AOR_RETENTION: Duration = Duration::from_secs(30 * 24 * 3600);
+    const MAX_AORS: usize = 65536;
+
+    pub fn new(config: RegistrationHijackConfig, netinfo: NetInfoDatabase) -> Self {
+        RegistrationTracker {
+            config,
+            netinfo,
+            aors: HashMap::new(),
+        }
+    }
+
+    pub fn on_registered(&mut self, aor: &str, binding: ObservedBinding, now: Instant) -> Vec<RegistrationFinding> {
+        let mut findings = Vec::new();
+        if !self.config.enabled {
+            return findings;
+        }
+
+        if !self.aors.contains_key(aor) && self.aors.len() >= Self::MAX_AORS {
+            self.cleanup(now);
+            if self.aors.len() >= Self::MAX_AORS {
+                return findings;
+            }
+        }
+
+        let binding_ip = binding.source_ip;
+        let network = self.netinfo.lookup(binding_ip);
+        let concurrency_window = Duration::from_secs(self.config.concurrency_window);
+        let history_size = self.config.history_size.max(1);
+        let history = self.aors.entry(aor.to_string()).or_default();
+        history.last_seen = Some(now);
+
+        let known_network = history.bindings.iter().any(|record| record.network.network == network.network);
+        let previous = history.bindings.iter()
+            .filter(|record| !record.internal)
+            .max_by_key(|record| record.last_registered)
+            .cloned();
+
+        // Moving onto an internal address is not a hijack, while the first
+        // external binding after an internal-only history is
+        if !known_network && !binding.internal && !history.bindings.is_empty() {
+            let cooled_down = history.last_move_alert
+                .is_none_or(|last| now.duration_since(last) >= Self::ALERT_COOLDOWN);
+            if cooled_down {
+                let (from_ip, from) = match previous {
+                    Some(record) => (record.source_ip, record.network),
+                    None => {
+                        let record = &history.bindings[history.bindings.len() - 1];
+                        (record.source_ip, record.network.clone())
+                    }
+                };
+                history.last_move_alert = Some(now);
+                findings.push(RegistrationFinding::BindingMoved {
+                    aor: aor.to_string(),
+                    from_ip,
+                    from,
+                    to_ip: binding.source_ip,
+                    to: network.clone(),
+                    user_agent: binding.user_agent.clone(),
+                });
+            }
+        }
+
+        // Refreshes replace the earlier record for the same contact from the same source
+        history.bindings.retain(|record| !(record.contact == binding.contact && record.source_ip == binding.source_ip));
+        history.bindings.push_back(BindingRecord {
+            source_ip: binding.source_ip,
+            network,
+            contact: binding.contact,
+            internal: binding.internal,
+            last_registered: now,
+            expires_at: now + binding.expires,
+        });
+        while history.bindings.len() > history_size {
+            history.bindings.pop_front();
+        }
+
+        // The registering source goes last so events are attributed to it
+        let mut sources: Vec<IpAddr> = Vec::new();
+        for record in &history.bindings {
+            let active = record.expires_at > now || now.duration_since(record.last_registered) <= concurrency_window;
+            if active && record.source_ip != binding_ip && !sources.contains(&record.source_ip) {
+                sources.push(record.source_ip);
+            }
+        }
+        sources.push(binding_ip);
+
+        if sources.len() >= self.config.max_concurrent_sources.max(2) as usize {
+            let cooled_down = history.last_concurrency_alert
+                .is_none_or(|last| now.duration_since(last) >= Self::ALERT_COOLDOWN);
+            if cooled_down {
+                history.last_concurrency_alert = Some(now);
+                findings.push(RegistrationFinding::ConcurrentSources {
+                    aor: aor.to_string(),
+                    sources,
+                    window: concurrency_window,
+                });
+            }
+        }
+
+        findings
+    }
+
+    /// Expires=0 removes a binding; a `*` contact removes all of them.
+    pub fn on_unregistered(&mut self, aor: &str, contact: &str, now: Instant) {
+        if let Some(history) = self.aors.get_mut(aor) {
+            for record in history.bindings.iter_mut() {
+                if contact == "*" || record.contact == contact {
+                    record.expires_at = now;
+                }
+            }
+        }
+    }
+
+    pub fn aor_count(&self) -> usize {
+        self.aors.len()
+    }
+
+    pub fn cleanup(&mut self, now: Instant) {
+        self.aors.retain(|_, history| {
+            history.last_seen.is_some_and(|seen| now.duration_since(seen) < Self::AOR_RETENTION)
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    const AOR: &str = "1001@pbx.example.com";
+
+    fn tracker(max_concurrent_sources: u32, concurrency_window: u64) -> RegistrationTracker {
+        let config = RegistrationHijackConfig { enabled: true, history_size: 16, max_concurrent_sources, concurrency_window };
+        let (netinfo, warnings) = NetInfoDatabase::parse("198.51.100.0/24,64500,FR\n203.0.113.0/24,64501,RU\n192.0.2.0/24,64500,FR\n");
+        assert!(warnings.is_empty());
+        RegistrationTracker::new(config, netinfo)
+    }
+
+    fn binding(source: Ipv4Addr, expires: u64) -> ObservedBinding {
+        ObservedBinding {
+            source_ip: IpAddr::V4(source),
+            contact: format!("sip:1001@{}:5060", source),
+            user_agent: Some("Yealink SIP-T46S".to_string()),
+            expires: Duration::from_secs(expires),
+            internal: false,
+        }
+    }
+
+    #[test]
+    fn a_binding_moving_country_is_a_hijack_once_per_cooldown() {
+        let mut tracker = tracker(5, 300);
+        let now = Instant::now();
+        let home = Ipv4Addr::new(198, 51, 100, 20);
+        let foreign = Ipv4Addr::new(203, 0, 113, 66);
+
+        assert!(tracker.on_registered(AOR, binding(home, 3600), now).is_empty());
+        // Refreshes from the known network are routine
+        assert!(tracker.on_registered(AOR, binding(home, 3600), now + Duration::from_secs(1800)).is_empty());
+
+        let findings = tracker.on_registered(AOR, binding(foreign, 60), now + Duration::from_secs(3600));
+        assert_eq!(findings.len(), 1);
+        assert_eq!(findings[0].event_type(), "SIP_REGISTRATION_HIJACK");
+        assert_eq!(findings[0].severity(), (8, 0.75));
+        assert_eq!(findings[0].source_ip(), IpAddr::V4(foreign));
+
+        // Another unknown network inside the cooldown is not reported again
+        let moved = now + Duration::from_secs(3600);
+        let carrier_nat = Ipv4Addr::new(100, 64, 0, 1);
+        assert!(tracker.on_registered(AOR, binding(carrier_nat, 60), moved + RegistrationTracker::ALERT_COOLDOWN / 2).is_empty());
+
+        // After it the next new network is reported; with no country or ASN to compare it ranks lower
+        let findings = tracker.on_registered(AOR, binding(Ipv4Addr::new(192, 0, 2, 7), 60), moved + RegistrationTracker::ALERT_COOLDOWN);
+        assert_eq!(findings.len(), 1);
+        assert_eq!(findings[0].severity(), (5, 0.5));
+    }
+
+    #[test]
+    fn concurrent_sources_count_only_within_the_window() {
+        let mut tracker = tracker(3, 300);
+        let now = Instant::now();
+        let sources = [Ipv4Addr::new(198, 51, 100, 1), Ipv4Addr::new(198, 51, 100, 2), Ipv4Addr::new(198, 51, 100, 3)];
+
+        // Short-lived bindings spread past the window never overlap
+        for (index, source) in sources.iter().enumerate() {
+            let findings = tracker.on_registered(AOR, binding(*source, 60), now + Duration::from_secs(400 * index as u64));
+            assert!(findings.is_empty());
+        }
+
+        let start = now + Duration::from_secs(2000);
+        assert!(tracker.on_registered(AOR, binding(sources[0], 60), start).is_empty());
+        assert!(tracker.on_registered(AOR, binding(sources[1], 60), start + Duration::from_secs(100)).is_empty());
+        let findings = tracker.on_registered(AOR, binding(sources[2], 60), start + Duration::from_secs(200));
+        assert_eq!(findings.len(), 1);
+        match &findings[0] {
+            RegistrationFinding::ConcurrentSources { sources: seen, .. } => {
+                assert_eq!(seen.len(), 3);
+                assert_eq!(seen[2], IpAddr::V4(sources[2]));
+            }
+            other => panic!("unexpected finding {:?}", other),
+        }
+    }
+}
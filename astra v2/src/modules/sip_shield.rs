@@ -1,417 +1,1222 @@
-use std::collections::HashMap;
-use std::net::{IpAddr, SocketAddr};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::net::IpAddr;
 use std::sync::Arc;
 use std::time::{Duration, Instant};
-use chrono::{DateTime, Utc};
-use tokio::time::sleep;
-use pnet::datalink;
-use pnet::packet::ethernet::{EtherTypes, EthernetPacket};
-use pnet::packet::ip::IpNextHeaderProtocols;
-use pnet::packet::ipv4::Ipv4Packet;
-use pnet::packet::tcp::{TcpFlags, TcpPacket};
-use pnet::packet::Packet;
-
-use crate::core::{config::Config, logger::Logger};
+use chrono::{DateTime, Local, Timelike, Utc};
+use pnet::packet::tcp::TcpFlags;
+
+use crate::core::{event_bus::EventBus, afpacket::BpfProgram, capture::{PacketFilter, PacketSummary, TransportHeader}, config::Config, logger::Logger, netinfo::{self, NetInfoDatabase}};
+use crate::modules::sip_parser::{parse_sip_message, parse_uri, SipMessage, SipMethod};
+use crate::modules::sip_dialog::{inspect_message, DialogTable, ProtocolAnomaly};
+use crate::modules::sip_fingerprint::{FingerprintDatabase, ScannerMatch};
+use crate::modules::sip_stream::{SipStreamReassembler, TcpFlowKey, TcpSegment, TlsFailure, TlsHandshakeInfo, TlsHandshakeTracker};
+use crate::modules::sip_media::{parse_sdp, MediaFinding, MediaTracker};
+use crate::modules::sip_registration::{ObservedBinding, RegistrationFinding, RegistrationTracker};
+use crate::modules::sip_toll_fraud::{CallTracker, TollFraudFinding};
 use crate::{SecurityEvent, ThreatLevel};
 
-#[derive(Debug, Clone)]
-struct ConnectionAttempt {
-    timestamp: Instant,
-    port: u16,
-    flags: u8,
+/// Where a SIP message was seen on the wire.
+#[derive(Debug, Clone, Copy)]
+pub struct SipPacketInfo {
+    pub source_ip: IpAddr,
+    pub source_port: u16,
+    pub dest_ip: IpAddr,
+    pub dest_port: u16,
+    pub timestamp: Instant,
+    pub wall_clock: DateTime<Utc>, // For time-of-day rules
+}
+
+/// Timestamps of recent hits, pruned to a sliding window.
+#[derive(Debug, Default)]
+pub(crate) struct SlidingWindow {
+    hits: VecDeque<Instant>,
+    last_alert: Option<Instant>,
+}
+
+impl SlidingWindow {
+    pub(crate) fn record(&mut self, now: Instant, window: Duration) -> usize {
+        self.hits.push_back(now);
+        self.prune(now, window);
+        self.hits.len()
+    }
+
+    pub(crate) fn prune(&mut self, now: Instant, window: Duration) {
+        while let Some(&oldest) = self.hits.front() {
+            if now.duration_since(oldest) > window {
+                self.hits.pop_front();
+            } else {
+                break;
+            }
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.hits.len()
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.hits.is_empty()
+    }
+
+    /// Returns true at most once per cooldown period.
+    pub(crate) fn try_alert(&mut self, now: Instant, cooldown: Duration) -> bool {
+        match self.last_alert {
+            Some(last) if now.duration_since(last) < cooldown => false,
+            _ => {
+                self.last_alert = Some(now);
+                true
+            }
+        }
+    }
 }
 
+/// A REGISTER waiting for the registrar's final response.
 #[derive(Debug)]
-struct ScanProfile {
-    first_attempt: Instant,
-    attempts: Vec<ConnectionAttempt>,
-    unique_ports: std::collections::HashSet<u16>,
-    syn_flood_count: u32,
-    last_syn_time: Instant,
+struct PendingRegister {
+    source_ip: IpAddr,
+    client_ip: IpAddr,
+    extension: String,
+    aor: String,
+    contacts: Vec<String>,
+    expires: Option<u32>,
+    user_agent: Option<String>,
+    had_credentials: bool,
+    sent_at: Instant,
+}
+
+/// Failed digest authentications against one extension, from any source.
+#[derive(Debug, Default)]
+struct ExtensionFailures {
+    window: SlidingWindow,
+    sources: HashMap<IpAddr, Instant>,
+}
+
+/// Extensions a source has probed and how the PBX answered.
+#[derive(Debug, Default)]
+struct EnumerationState {
+    targets: HashMap<String, Instant>,
+    not_found: SlidingWindow,
+    answered: SlidingWindow,
+    confirmed: HashSet<String>,
+    last_alert: Option<Instant>,
+}
+
+#[derive(Debug)]
+struct SipSourceProfile {
+    last_seen: Instant,
+    recent_requests: SlidingWindow,
+    methods: HashMap<String, u32>,
+    user_agents: HashSet<String>,
+    total_requests: u32,
+    total_responses: u32,
     threat_score: f32,
+    auth_failures: SlidingWindow,
+    invites: SlidingWindow,
+    identified_tool: Option<ScannerMatch>,
+    last_fingerprint_alert: Option<Instant>,
+    enumeration: EnumerationState,
+    tls_failures: SlidingWindow,
+    protocol_anomalies: SlidingWindow,
+    anomaly_counts: HashMap<&'static str, u32>,
+}
+
+impl SipSourceProfile {
+    fn new(now: Instant) -> Self {
+        SipSourceProfile {
+            last_seen: now,
+            recent_requests: SlidingWindow::default(),
+            methods: HashMap::new(),
+            user_agents: HashSet::new(),
+            total_requests: 0,
+            total_responses: 0,
+            threat_score: 0.0,
+            auth_failures: SlidingWindow::default(),
+            invites: SlidingWindow::default(),
+            identified_tool: None,
+            last_fingerprint_alert: None,
+            enumeration: EnumerationState::default(),
+            tls_failures: SlidingWindow::default(),
+            protocol_anomalies: SlidingWindow::default(),
+            anomaly_counts: HashMap::new(),
+        }
+    }
 }
 
-pub struct TcpGuard {
+pub struct SipShield {
     config: Arc<Config>,
     logger: Arc<Logger>,
-    scan_profiles: HashMap<IpAddr, ScanProfile>,
+    source_profiles: HashMap<IpAddr, SipSourceProfile>,
+    pending_registers: HashMap<(String, u32), PendingRegister>,
+    extension_failures: HashMap<String, ExtensionFailures>,
+    call_tracker: CallTracker,
+    registrations: RegistrationTracker,
+    media: MediaTracker,
+    dialogs: DialogTable,
+    fingerprints: FingerprintDatabase,
+    reassembler: SipStreamReassembler,
+    tls_tracker: TlsHandshakeTracker,
     sensitivity_level: u8,
-    stealth_ports: Vec<u16>,
-    honeypot_responses: bool,
+    monitored_ports: Vec<u16>,
+    messages_parsed: u64,
+    parse_failures: u64,
+    events: Arc<EventBus>,
 }
 
-impl TcpGuard {
-    pub fn new(config: &Arc<Config>, logger: Arc<Logger>) -> Result<Self, Box<dyn std::error::Error>> {
-        let stealth_ports = vec![
-            22, 23, 25, 53, 80, 110, 143, 443, 993, 995,  // Common targets
-            135, 139, 445, 1433, 3389,                     // Windows services
-            21, 69, 161, 162, 514, 873,                    // Unix services
-            5060, 5061,                                     // SIP ports (monitored separately)
-        ];
+impl SipShield {
+    const RATE_WINDOW: Duration = Duration::from_secs(10);
+    const ALERT_COOLDOWN: Duration = Duration::from_secs(60);
+    const PROFILE_RETENTION: Duration = Duration::from_secs(600);
+    // RFC 3261 Timer F: a non-INVITE transaction is dead after 64*T1
+    const TRANSACTION_TIMEOUT: Duration = Duration::from_secs(32);
+    const MAX_PENDING_TRANSACTIONS: usize = 65536;
+    const MAX_TRACKED_TARGETS: usize = 4096;
+    const MAX_TCP_FLOWS: usize = 8192;
+    const MAX_FLOW_BUFFER: usize = 64 * 1024;
+    const TCP_IDLE_TIMEOUT: Duration = Duration::from_secs(120);
+    // Longer than any retransmission; by then the segment was lost in capture
+    const TCP_GAP_TIMEOUT: Duration = Duration::from_secs(5);
+    const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(30);
+    const TLS_FAILURE_WINDOW: Duration = Duration::from_secs(60);
+
+    pub fn new(config: &Arc<Config>, logger: Arc<Logger>, events: Arc<EventBus>) -> Result<Self, Box<dyn std::error::Error>> {
+        let sip_config = &config.modules.sip_shield;
+        let monitored_ports = sip_config.monitored_ports.clone();
 
-        logger.log_info("TCP Guardian initialized with advanced scan detection")?;
-        logger.log_info(&format!("Monitoring {} stealth ports", stealth_ports.len()))?;
+        logger.log_info("SIP Shield initialized with SIP message inspection")?;
+        logger.log_info(&format!("Monitoring SIP ports: {:?}", monitored_ports))?;
 
-        Ok(TcpGuard {
+        let (fingerprints, warnings) = FingerprintDatabase::load(&sip_config.fingerprint_file)?;
+        for warning in &warnings {
+            logger.log_warning(&format!("SIP fingerprint database: {}", warning))?;
+        }
+        logger.log_info(&format!("Loaded {} SIP scanner fingerprints", fingerprints.fingerprint_count()))?;
+
+        let (netinfo, warnings) = NetInfoDatabase::load(&config.network.netinfo_file)?;
+        for warning in &warnings {
+            logger.log_warning(&format!("Network database {}: {}", config.network.netinfo_file, warning))?;
+        }
+        logger.log_info(&format!("Loaded {} networks from {}", netinfo.entry_count(), config.network.netinfo_file))?;
+        let registrations = RegistrationTracker::new(sip_config.registration_hijack.clone(), netinfo);
+
+        let flood_threshold = Self::scale_for_sensitivity(sip_config.media.unsolicited_flood_threshold, sip_config.sensitivity);
+        let media = MediaTracker::new(sip_config.media.clone(), flood_threshold);
+
+        let invite_threshold = Self::scale_for_sensitivity(sip_config.invite_flood_threshold, sip_config.sensitivity);
+        let call_tracker = CallTracker::new(sip_config.toll_fraud.clone(), invite_threshold, Self::RATE_WINDOW);
+
+        Ok(SipShield {
             config: config.clone(),
             logger,
-            scan_profiles: HashMap::new(),
-            sensitivity_level: 5,
-            stealth_ports,
-            honeypot_responses: true,
+            source_profiles: HashMap::new(),
+            pending_registers: HashMap::new(),
+            extension_failures: HashMap::new(),
+            call_tracker,
+            registrations,
+            media,
+            dialogs: DialogTable::new(sip_config.protocol.max_dialogs, Instant::now()),
+            fingerprints,
+            reassembler: SipStreamReassembler::new(Self::MAX_TCP_FLOWS, Self::MAX_FLOW_BUFFER, Self::TCP_IDLE_TIMEOUT, Self::TCP_GAP_TIMEOUT),
+            tls_tracker: TlsHandshakeTracker::new(Self::MAX_TCP_FLOWS, Self::TLS_HANDSHAKE_TIMEOUT),
+            sensitivity_level: sip_config.sensitivity,
+            monitored_ports,
+            messages_parsed: 0,
+            parse_failures: 0,
+            events,
         })
     }
 
-    pub async fn scan_network(&mut self) -> Result<(), Box<dyn std::error::Error>> {
-        // Get network interfaces
-        let interfaces = datalink::interfaces();
-        
-        for interface in interfaces {
-            if interface.is_up() && !interface.is_loopback() {
-                self.monitor_interface(&interface).await?;
-            }
+    /// Handles one packet from the capture workers.
+    pub async fn process_packet(&mut self, packet: &PacketSummary) -> Result<(), Box<dyn std::error::Error>> {
+        if !self.config.modules.sip_shield.enabled {
+            return Ok(());
         }
-        
-        // Cleanup old profiles every scan cycle
-        self.cleanup_old_profiles();
-        
-        Ok(())
-    }
 
-    async fn monitor_interface(&mut self, interface: &pnet::datalink::NetworkInterface) -> Result<(), Box<dyn std::error::Error>> {
-        use pnet::datalink::Channel::Ethernet;
-        
-        let config = pnet::datalink::Config {
-            write_buffer_size: 4096,
-            read_buffer_size: 4096,
-            read_timeout: Some(Duration::from_millis(100)),
-            write_timeout: Some(Duration::from_millis(100)),
-            channel_type: pnet::datalink::ChannelType::Layer2,
-            bpf_fd_attempts: 1000,
-            linux_fanout: None,
-            promiscuous: false, // Stealth mode
+        let (source_port, dest_port, tcp_header) = match packet.transport {
+            TransportHeader::Udp { source_port, dest_port } => (source_port, dest_port, None),
+            TransportHeader::Tcp { source_port, dest_port, sequence, flags } => (source_port, dest_port, Some((sequence, flags))),
+            _ => return Ok(()),
         };
 
-        let (_, mut rx) = match pnet::datalink::channel(interface, config) {
-            Ok(Ethernet(tx, rx)) => (tx, rx),
-            Ok(_) => return Ok(()),
-            Err(e) => {
-                self.logger.log_error(&format!("Failed to create channel for {}: {}", interface.name, e))?;
-                return Ok(());
-            }
+        let info = SipPacketInfo {
+            source_ip: packet.source_ip,
+            source_port,
+            dest_ip: packet.dest_ip,
+            dest_port,
+            timestamp: packet.captured_at,
+            wall_clock: packet.timestamp,
         };
+        let payload = packet.payload();
 
-        loop {
-            match rx.next() {
-                Ok(packet) => {
-                    if let Some(ethernet) = EthernetPacket::new(packet) {
-                        self.process_ethernet_packet(&ethernet).await?;
-                    }
-                }
-                Err(_) => break, // Timeout, continue monitoring
+        if !self.is_sip_port(source_port, dest_port) {
+            if tcp_header.is_none() && self.media.is_media_port(dest_port) {
+                return self.process_media_packet(info, payload);
             }
+            return Ok(());
         }
 
-        Ok(())
+        match tcp_header {
+            Some((sequence, flags)) => self.process_tcp_segment(info, sequence, flags, payload).await,
+            None if payload.is_empty() => Ok(()),
+            None => self.process_sip_payload(info, payload).await,
+        }
     }
 
-    async fn process_ethernet_packet(&mut self, ethernet: &EthernetPacket) -> Result<(), Box<dyn std::error::Error>> {
-        match ethernet.get_ethertype() {
-            EtherTypes::Ipv4 => {
-                if let Some(ipv4) = Ipv4Packet::new(ethernet.payload()) {
-                    self.process_ipv4_packet(&ipv4).await?;
+    /// Selects the packets the capture workers queue for SIP Shield: SIP
+    /// signalling on the monitored ports and UDP into the RTP range.
+    pub fn packet_filter(config: &Config) -> PacketFilter {
+        let monitored_ports = config.modules.sip_shield.monitored_ports.clone();
+        let media = config.modules.sip_shield.media.clone();
+        Box::new(move |packet| {
+            let is_sip_port = |source_port, dest_port| {
+                monitored_ports.contains(&source_port) || monitored_ports.contains(&dest_port)
+            };
+            match packet.transport {
+                TransportHeader::Udp { source_port, dest_port } => {
+                    is_sip_port(source_port, dest_port)
+                        || (media.enabled && (media.rtp_port_min..=media.rtp_port_max).contains(&dest_port))
                 }
+                TransportHeader::Tcp { source_port, dest_port, .. } => is_sip_port(source_port, dest_port),
+                _ => false,
             }
-            _ => {} // Only monitoring IPv4 for now
+        })
+    }
+
+    pub fn kernel_filter(config: &Config) -> Result<BpfProgram, Box<dyn std::error::Error>> {
+        let sip_config = &config.modules.sip_shield;
+        let media_range = Some((sip_config.media.rtp_port_min, sip_config.media.rtp_port_max))
+            .filter(|_| sip_config.media.enabled);
+        BpfProgram::transport_ports(&sip_config.monitored_ports, media_range, config.network.ipv6_support)
+    }
+
+    pub fn perform_maintenance(&mut self, now: Instant) -> Result<(), Box<dyn std::error::Error>> {
+        for handshake in self.tls_tracker.cleanup(now) {
+            self.record_tls_outcome(handshake, Some(TlsFailure::ClosedBeforeCompletion), now)?;
         }
+        self.cleanup_old_profiles(now);
+
         Ok(())
     }
 
-    async fn process_ipv4_packet(&mut self, ipv4: &Ipv4Packet) -> Result<(), Box<dyn std::error::Error>> {
-        if ipv4.get_next_level_protocol() == IpNextHeaderProtocols::Tcp {
-            if let Some(tcp) = TcpPacket::new(ipv4.payload()) {
-                let source_ip = IpAddr::V4(ipv4.get_source());
-                let dest_port = tcp.get_destination();
-                let flags = tcp.get_flags();
-                
-                // Skip internal traffic
-                if self.is_internal_ip(source_ip) {
-                    return Ok(());
-                }
+    /// Stream transports: TLS ports only yield handshake metadata, plain TCP is
+    /// reassembled into Content-Length framed messages.
+    async fn process_tcp_segment(&mut self, info: SipPacketInfo, sequence: u32, flags: u16, payload: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
+        let key = TcpFlowKey {
+            source_ip: info.source_ip,
+            source_port: info.source_port,
+            dest_ip: info.dest_ip,
+            dest_port: info.dest_port,
+        };
+        let segment = TcpSegment {
+            sequence,
+            syn: flags & TcpFlags::SYN != 0,
+            fin: flags & TcpFlags::FIN != 0,
+            rst: flags & TcpFlags::RST != 0,
+            payload,
+        };
+
+        let tls_ports = &self.config.modules.sip_shield.tls_ports;
+        if tls_ports.contains(&info.dest_port) || tls_ports.contains(&info.source_port) {
+            let from_client = tls_ports.contains(&info.dest_port);
+            if let Some((handshake, failure)) = self.tls_tracker.observe(key, from_client, segment, info.timestamp) {
+                self.record_tls_outcome(handshake, failure, info.timestamp)?;
+            }
+            return Ok(());
+        }
 
-                self.analyze_tcp_packet(source_ip, dest_port, flags).await?;
+        let output = self.reassembler.push_segment(key, segment, info.timestamp);
+        if let Some(e) = output.framing_error {
+            self.parse_failures += 1;
+            self.logger.log_debug(&format!("Dropping SIP/TCP flow {}:{} after framing error: {}", info.source_ip, info.source_port, e))?;
+            if self.config.modules.sip_shield.protocol.count_parse_failures {
+                self.record_protocol_anomalies(info, vec![ProtocolAnomaly::from_parse_error(&e)])?;
             }
         }
+        if output.overflowed {
+            self.logger.log_debug(&format!("Dropping SIP/TCP flow {}:{}: reassembly buffer exceeded", info.source_ip, info.source_port))?;
+        }
+
+        for message in output.messages {
+            self.process_sip_payload(info, &message).await?;
+        }
+
         Ok(())
     }
 
-    async fn analyze_tcp_packet(&mut self, source_ip: IpAddr, dest_port: u16, flags: u8) -> Result<(), Box<dyn std::error::Error>> {
-        let now = Instant::now();
-        
-        // Get or create scan profile
-        let profile = self.scan_profiles.entry(source_ip).or_insert(ScanProfile {
-            first_attempt: now,
-            attempts: Vec::new(),
-            unique_ports: std::collections::HashSet::new(),
-            syn_flood_count: 0,
-            last_syn_time: now,
-            threat_score: 0.0,
-        });
-
-        // Record connection attempt
-        profile.attempts.push(ConnectionAttempt {
-            timestamp: now,
-            port: dest_port,
-            flags,
-        });
-        profile.unique_ports.insert(dest_port);
+    fn record_tls_outcome(&mut self, handshake: TlsHandshakeInfo, failure: Option<TlsFailure>, now: Instant) -> Result<(), Box<dyn std::error::Error>> {
+        let client_ip = match handshake.client_ip {
+            Some(ip) if !self.is_internal_ip(ip) => ip,
+            _ => return Ok(()),
+        };
 
-        // SYN flood detection
-        if flags & TcpFlags::SYN != 0 && flags & TcpFlags::ACK == 0 {
-            if now.duration_since(profile.last_syn_time) < Duration::from_secs(5) {
-                profile.syn_flood_count += 1;
-            } else {
-                profile.syn_flood_count = 1;
-                profile.last_syn_time = now;
+        let failure = match failure {
+            Some(failure) => failure,
+            None => {
+                self.logger.log_debug(&format!(
+                    "SIP/TLS session from {} established (SNI: {}, certificate: {})",
+                    client_ip,
+                    handshake.sni.as_deref().unwrap_or("-"),
+                    handshake.certificate_cn.as_deref().unwrap_or("-")
+                ))?;
+                return Ok(());
             }
+        };
 
-            // SYN flood threshold
-            if profile.syn_flood_count > 10 {
-                self.trigger_syn_flood_alert(source_ip, profile.syn_flood_count).await?;
-                profile.threat_score += 0.8;
+        let threshold = self.scaled_threshold(self.config.modules.sip_shield.tls_failure_threshold) as usize;
+        let profile = self.source_profiles
+            .entry(client_ip)
+            .or_insert_with(|| SipSourceProfile::new(now));
+        profile.last_seen = now;
+
+        let count = profile.tls_failures.record(now, Self::TLS_FAILURE_WINDOW);
+        if count < threshold || !profile.tls_failures.try_alert(now, Self::ALERT_COOLDOWN) {
+            return Ok(());
+        }
+
+        profile.threat_score = (profile.threat_score + 0.3).min(1.0);
+        let threat_score = profile.threat_score;
+
+        let reason = match failure {
+            TlsFailure::FatalAlert { description } => format!("fatal alert {}", description),
+            TlsFailure::ClosedBeforeCompletion => "closed before completion".to_string(),
+            TlsFailure::NotTls => "non-TLS data on TLS port".to_string(),
+        };
+
+        let event = SecurityEvent {
+            timestamp: Utc::now(),
+            source_ip: client_ip,
+            event_type: "SIP_TLS_HANDSHAKE_FAILURES".to_string(),
+            threat_level: ThreatLevel {
+                level: if count >= threshold * 3 { 7 } else { 5 },
+                confidence: 0.7,
+                category: "PROTOCOL_ABUSE".to_string(),
+            },
+            details: format!(
+                "{} failed TLS handshakes in {}s (last: {}, SNI: {}), threat_score: {:.2}",
+                count, Self::TLS_FAILURE_WINDOW.as_secs(), reason,
+                handshake.sni.as_deref().unwrap_or("-"), threat_score
+            ),
+            action_taken: "MONITORING_ENHANCED".to_string(),
+            evidence_id: None,
+            related_ips: Vec::new(),
+        };
+
+        self.events.publish_or_log(&self.logger, event)
+    }
+
+    pub async fn process_sip_payload(&mut self, info: SipPacketInfo, payload: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
+        match parse_sip_message(payload) {
+            Ok(message) => {
+                self.messages_parsed += 1;
+                self.process_sip_message(info, &message).await
+            }
+            Err(e) => {
+                self.parse_failures += 1;
+                self.logger.log_debug(&format!("Unparseable SIP payload from {}:{}: {}", info.source_ip, info.source_port, e))?;
+                if self.config.modules.sip_shield.protocol.count_parse_failures {
+                    self.record_protocol_anomalies(info, vec![ProtocolAnomaly::from_parse_error(&e)])?;
+                }
+                Ok(())
             }
         }
+    }
+
+    /// Dispatches a parsed message to the per-source detectors.
+    pub async fn process_sip_message(&mut self, info: SipPacketInfo, message: &SipMessage) -> Result<(), Box<dyn std::error::Error>> {
+        // Media expectations and dialog state come from every call, internal ones included
+        self.track_media(info, message);
+        let mut anomalies = Vec::new();
+        if message.is_request() {
+            anomalies.extend(self.dialogs.on_request(info.source_ip, message, info.timestamp));
+        } else {
+            self.dialogs.on_response(message, info.timestamp);
+        }
+        if self.config.modules.sip_shield.protocol.enabled {
+            anomalies.extend(inspect_message(message, &self.config.modules.sip_shield.protocol));
+        }
+        self.record_protocol_anomalies(info, anomalies)?;
 
-        // Port scan detection - Advanced heuristics
-        self.detect_port_scanning(source_ip, profile).await?;
+        if message.is_request() {
+            // Internal registrations still establish each AOR's binding baseline
+            if message.method() == Some(&SipMethod::Register) {
+                self.track_register(info, message);
+            }
+            // Either side may hang up, so calls the PBX ends leave the tracker too
+            if matches!(message.method(), Some(SipMethod::Bye) | Some(SipMethod::Cancel)) {
+                if let Some(call_id) = message.call_id() {
+                    self.call_tracker.on_dialog_end(call_id);
+                }
+            }
+
+            // Requests are attributed to their sender
+            if self.is_internal_ip(info.source_ip) {
+                return Ok(());
+            }
+            self.track_request(info, message);
+            self.fingerprint_request(info, message).await?;
+            match message.method() {
+                Some(SipMethod::Register) => self.track_probe_target(info, message),
+                Some(SipMethod::Options) => self.track_probe_target(info, message),
+                Some(SipMethod::Invite) => {
+                    self.track_probe_target(info, message);
+                    self.track_invite(info, message).await?;
+                }
+                _ => {}
+            }
+            self.detect_request_flood(info.source_ip, info.timestamp).await?;
+        } else {
+            // Responses travel back towards the peer that sent the request
+            if let Some(profile) = self.source_profiles.get_mut(&info.dest_ip) {
+                profile.total_responses += 1;
+                profile.last_seen = info.timestamp;
+            }
+            self.handle_register_response(message, info.timestamp).await?;
+            self.handle_invite_response(info, message).await?;
+            self.detect_extension_enumeration(info, message).await?;
+        }
 
         Ok(())
     }
 
-    async fn detect_port_scanning(&mut self, source_ip: IpAddr, profile: &mut ScanProfile) -> Result<(), Box<dyn std::error::Error>> {
-        let now = Instant::now();
-        let time_window = Duration::from_secs(10);
-        
-        // Count recent attempts
-        let recent_attempts: Vec<_> = profile.attempts.iter()
-            .filter(|attempt| now.duration_since(attempt.timestamp) < time_window)
-            .collect();
+    /// Learns negotiated media from SDP offers/answers and retires it when the
+    /// dialog is torn down or the INVITE fails.
+    fn track_media(&mut self, info: SipPacketInfo, message: &SipMessage) {
+        let call_id = match message.call_id() {
+            Some(call_id) => call_id,
+            None => return,
+        };
 
-        let recent_unique_ports: std::collections::HashSet<u16> = recent_attempts.iter()
-            .map(|attempt| attempt.port)
-            .collect();
+        match (message.method(), message.status_code()) {
+            (Some(SipMethod::Bye), _) | (Some(SipMethod::Cancel), _) => {
+                self.media.on_dialog_end(call_id, info.timestamp);
+                return;
+            }
+            (None, Some(status_code)) if status_code >= 300 => {
+                if message.cseq().is_some_and(|cseq| cseq.method == SipMethod::Invite) {
+                    self.media.on_dialog_end(call_id, info.timestamp);
+                }
+                return;
+            }
+            _ => {}
+        }
 
-        // Multiple scanning patterns detection
-        let mut scan_detected = false;
-        let mut scan_type = String::new();
-        let mut threat_increase = 0.0;
+        let is_sdp = message.header("Content-Type")
+            .is_some_and(|content_type| content_type.trim().to_ascii_lowercase().starts_with("application/sdp"));
+        if !is_sdp || message.body.is_empty() {
+            return;
+        }
 
-        // 1. Rapid port scanning (>2 ports in 10 seconds)
-        if recent_unique_ports.len() > 2 {
-            scan_detected = true;
-            scan_type = format!("RAPID_PORT_SCAN ({} ports)", recent_unique_ports.len());
-            threat_increase = 0.6;
+        if let Some(description) = parse_sdp(&message.body) {
+            self.media.on_session_description(call_id, &description, info.source_ip, info.timestamp);
         }
+    }
 
-        // 2. Sequential port scanning detection
-        if self.detect_sequential_scan(&recent_attempts) {
-            scan_detected = true;
-            scan_type = "SEQUENTIAL_SCAN".to_string();
-            threat_increase = 0.7;
+    fn process_media_packet(&mut self, info: SipPacketInfo, payload: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
+        if self.is_internal_ip(info.source_ip) {
+            return Ok(());
         }
 
-        // 3. Stealth scan detection (specific flag combinations)
-        if self.detect_stealth_scan(&recent_attempts) {
-            scan_detected = true;
-            scan_type = "STEALTH_SCAN".to_string();
-            threat_increase = 0.9;
+        match self.media.observe_packet(info.source_ip, info.dest_port, payload, info.timestamp) {
+            Some(finding) => self.report_media_finding(finding),
+            None => Ok(()),
         }
+    }
 
-        // 4. Service enumeration detection
-        if self.detect_service_enumeration(&recent_unique_ports) {
-            scan_detected = true;
-            scan_type = "SERVICE_ENUMERATION".to_string();
-            threat_increase = 0.5;
+    fn report_media_finding(&mut self, finding: MediaFinding) -> Result<(), Box<dyn std::error::Error>> {
+        let (level, confidence) = finding.severity();
+        let source_ip = finding.source_ip();
+        if let Some(profile) = self.source_profiles.get_mut(&source_ip) {
+            profile.threat_score = (profile.threat_score + confidence * 0.5).min(1.0);
         }
 
-        if scan_detected {
-            profile.threat_score += threat_increase;
-            self.trigger_scan_alert(source_ip, &scan_type, recent_unique_ports.len(), profile.threat_score).await?;
+        let event = SecurityEvent {
+            timestamp: Utc::now(),
+            source_ip,
+            event_type: finding.event_type().to_string(),
+            threat_level: ThreatLevel {
+                level,
+                confidence,
+                category: finding.category().to_string(),
+            },
+            details: finding.describe(),
+            action_taken: "MONITORING_ENHANCED".to_string(),
+            evidence_id: None,
+            related_ips: Vec::new(),
+        };
+        self.events.publish_or_log(&self.logger, event)
+    }
+
+    /// Counts malformed and out-of-state messages per sender; a single odd
+    /// message is common, a steady stream of them is a fuzzer.
+    fn record_protocol_anomalies(&mut self, info: SipPacketInfo, anomalies: Vec<ProtocolAnomaly>) -> Result<(), Box<dyn std::error::Error>> {
+        let protocol = &self.config.modules.sip_shield.protocol;
+        if anomalies.is_empty() || !protocol.enabled || self.is_internal_ip(info.source_ip) {
+            return Ok(());
         }
 
-        Ok(())
+        let now = info.timestamp;
+        let window = Duration::from_secs(protocol.anomaly_window);
+        let threshold = self.scaled_threshold(protocol.anomaly_threshold) as usize;
+        let profile = self.source_profiles
+            .entry(info.source_ip)
+            .or_insert_with(|| SipSourceProfile::new(now));
+        profile.last_seen = now;
+
+        let mut count = 0;
+        for anomaly in &anomalies {
+            *profile.anomaly_counts.entry(anomaly.label()).or_insert(0) += 1;
+            count = profile.protocol_anomalies.record(now, window);
+        }
+
+        if count < threshold || !profile.protocol_anomalies.try_alert(now, Self::ALERT_COOLDOWN) {
+            return Ok(());
+        }
+
+        profile.threat_score = (profile.threat_score + 0.3).min(1.0);
+        let threat_score = profile.threat_score;
+        let mut breakdown: Vec<String> = profile.anomaly_counts.iter()
+            .map(|(label, total)| format!("{}={}", label, total))
+            .collect();
+        breakdown.sort();
+
+        // Report under the most severe anomaly in the triggering message
+        let worst = anomalies.iter().max_by_key(|anomaly| anomaly.level()).unwrap_or(&anomalies[0]);
+        let event = SecurityEvent {
+            timestamp: Utc::now(),
+            source_ip: info.source_ip,
+            event_type: worst.event_type().to_string(),
+            threat_level: ThreatLevel {
+                level: if count >= threshold * 4 { worst.level() + 1 } else { worst.level() },
+                confidence: 0.7,
+                category: "PROTOCOL_ABUSE".to_string(),
+            },
+            details: format!(
+                "{} protocol anomalies in {}s, latest: {} ({}), threat_score: {:.2}",
+                count, window.as_secs(), worst, breakdown.join(", "), threat_score
+            ),
+            action_taken: "MONITORING_ENHANCED".to_string(),
+            evidence_id: None,
+            related_ips: Vec::new(),
+        };
+        self.events.publish_or_log(&self.logger, event)
     }
 
-    fn detect_sequential_scan(&self, attempts: &[&ConnectionAttempt]) -> bool {
-        if attempts.len() < 3 {
-            return false;
+    fn track_request(&mut self, info: SipPacketInfo, message: &SipMessage) {
+        let now = info.timestamp;
+        let profile = self.source_profiles
+            .entry(info.source_ip)
+            .or_insert_with(|| SipSourceProfile::new(now));
+
+        profile.last_seen = now;
+        profile.total_requests += 1;
+        profile.recent_requests.record(now, Self::RATE_WINDOW);
+
+        if let Some(method) = message.method() {
+            *profile.methods.entry(method.to_string()).or_insert(0) += 1;
+        }
+        if let Some(user_agent) = message.user_agent() {
+            // Bound the set so a randomising scanner cannot grow it forever
+            if profile.user_agents.len() < 32 {
+                profile.user_agents.insert(user_agent.to_string());
+            }
         }
+    }
+
+    async fn detect_request_flood(&mut self, source_ip: IpAddr, now: Instant) -> Result<(), Box<dyn std::error::Error>> {
+        let threshold = self.scaled_threshold(self.config.modules.sip_shield.attack_threshold);
 
-        let mut ports: Vec<u16> = attempts.iter().map(|a| a.port).collect();
-        ports.sort();
-        
-        // Check for sequential ports (like 80, 81, 82 or 443, 444, 445)
-        let mut sequential_count = 1;
-        for i in 1..ports.len() {
-            if ports[i] == ports[i-1] + 1 {
-                sequential_count += 1;
-                if sequential_count >= 3 {
-                    return true;
+        let (request_count, threat_score) = match self.source_profiles.get_mut(&source_ip) {
+            Some(profile) => {
+                let request_count = profile.recent_requests.len() as u32;
+                if request_count <= threshold || !profile.recent_requests.try_alert(now, Self::ALERT_COOLDOWN) {
+                    return Ok(());
                 }
-            } else {
-                sequential_count = 1;
+                profile.threat_score = (profile.threat_score + 0.3).min(1.0);
+                (request_count, profile.threat_score)
             }
-        }
-        
-        false
+            None => return Ok(()),
+        };
+
+        let event = SecurityEvent {
+            timestamp: Utc::now(),
+            source_ip,
+            event_type: "SIP_REQUEST_FLOOD".to_string(),
+            threat_level: ThreatLevel {
+                level: if threat_score > 0.8 { 8 } else if threat_score > 0.5 { 6 } else { 4 },
+                confidence: 0.7,
+                category: "DOS_ATTACK".to_string(),
+            },
+            details: format!(
+                "{} SIP requests in {}s (threshold {}), threat_score: {:.2}",
+                request_count, Self::RATE_WINDOW.as_secs(), threshold, threat_score
+            ),
+            action_taken: "MONITORING_ENHANCED".to_string(),
+            evidence_id: None,
+            related_ips: Vec::new(),
+        };
+
+        self.events.publish_or_log(&self.logger, event)?;
+        Ok(())
     }
 
-    fn detect_stealth_scan(&self, attempts: &[&ConnectionAttempt]) -> bool {
-        for attempt in attempts {
-            // SYN scan detection
-            if attempt.flags == TcpFlags::SYN {
-                return true;
+    /// Remembers which user part a REGISTER/OPTIONS/INVITE was aimed at.
+    fn track_probe_target(&mut self, info: SipPacketInfo, message: &SipMessage) {
+        let target = message.request_uri()
+            .and_then(parse_uri)
+            .and_then(|uri| uri.user)
+            .or_else(|| message.to().and_then(|to| to.uri.user));
+
+        let target = match target {
+            Some(target) if !target.is_empty() => target,
+            _ => return,
+        };
+
+        let window = Duration::from_secs(self.config.modules.sip_shield.enumeration_window);
+        if let Some(profile) = self.source_profiles.get_mut(&info.source_ip) {
+            let targets = &mut profile.enumeration.targets;
+            if targets.len() >= Self::MAX_TRACKED_TARGETS {
+                targets.retain(|_, seen| info.timestamp.duration_since(*seen) <= window);
             }
-            // FIN scan detection
-            if attempt.flags == TcpFlags::FIN {
-                return true;
+            if targets.len() < Self::MAX_TRACKED_TARGETS {
+                targets.insert(target, info.timestamp);
             }
-            // NULL scan detection  
-            if attempt.flags == 0 {
-                return true;
+        }
+    }
+
+    /// Classifies the PBX's answer to a probe: 404/604 mean the extension does
+    /// not exist, while a challenge or any other user-level answer confirms it.
+    async fn detect_extension_enumeration(&mut self, info: SipPacketInfo, message: &SipMessage) -> Result<(), Box<dyn std::error::Error>> {
+        let status_code = match message.status_code() {
+            Some(code) if code >= 200 => code,
+            _ => return Ok(()),
+        };
+        let probed = matches!(
+            message.cseq().map(|cseq| cseq.method),
+            Some(SipMethod::Register) | Some(SipMethod::Options) | Some(SipMethod::Invite)
+        );
+        if !probed {
+            return Ok(());
+        }
+
+        let sip_config = &self.config.modules.sip_shield;
+        let window = Duration::from_secs(sip_config.enumeration_window);
+        let threshold = self.scaled_threshold(sip_config.enumeration_threshold) as usize;
+        let now = info.timestamp;
+
+        // The response is addressed to the prober
+        let profile = match self.source_profiles.get_mut(&info.dest_ip) {
+            Some(profile) => profile,
+            None => return Ok(()),
+        };
+        let state = &mut profile.enumeration;
+
+        match status_code {
+            404 | 604 => {
+                state.not_found.record(now, window);
             }
-            // XMAS scan detection
-            if attempt.flags & (TcpFlags::FIN | TcpFlags::PSH | TcpFlags::URG) != 0 {
-                return true;
+            200 | 401 | 403 | 407 | 480 | 486 => {
+                state.answered.record(now, window);
+                if let Some(user) = message.to().and_then(|to| to.uri.user) {
+                    if state.confirmed.len() < 256 {
+                        state.confirmed.insert(user);
+                    }
+                }
             }
+            _ => return Ok(()),
         }
-        false
-    }
 
-    fn detect_service_enumeration(&self, ports: &std::collections::HashSet<u16>) -> bool {
-        // Common service ports that indicate enumeration
-        let service_ports: std::collections::HashSet<u16> = [
-            21, 22, 23, 25, 53, 80, 110, 135, 139, 143, 443, 445, 993, 995, 1433, 3389, 5060, 5061
-        ].iter().cloned().collect();
+        state.targets.retain(|_, seen| now.duration_since(*seen) <= window);
+        state.not_found.prune(now, window);
+        state.answered.prune(now, window);
 
-        let enumerated_services = ports.intersection(&service_ports).count();
-        enumerated_services >= 3
-    }
+        let distinct_targets = state.targets.len();
+        let not_found = state.not_found.len();
+        let total = not_found + state.answered.len();
+        if distinct_targets < threshold || total == 0 {
+            return Ok(());
+        }
+
+        // A sweep hits mostly non-existent users; a handful of real phones
+        // re-registering the same few extensions never looks like this.
+        let not_found_ratio = not_found as f32 / total as f32;
+        if not_found_ratio < 0.3 && distinct_targets < threshold * 3 {
+            return Ok(());
+        }
+
+        let cooled_down = state.last_alert
+            .is_none_or(|last| now.duration_since(last) >= Self::ALERT_COOLDOWN);
+        if !cooled_down {
+            return Ok(());
+        }
+        state.last_alert = Some(now);
+
+        let mut confirmed: Vec<&String> = state.confirmed.iter().collect();
+        confirmed.sort();
+        let confirmed_list = confirmed.iter().take(20).map(|s| s.as_str()).collect::<Vec<_>>().join(",");
+        let confirmed_count = confirmed.len();
+
+        let confidence = (0.5 + not_found_ratio * 0.3 + (distinct_targets as f32 / (threshold as f32 * 5.0)).min(1.0) * 0.2).min(0.95);
+        profile.threat_score = (profile.threat_score + 0.5).min(1.0);
+        let threat_score = profile.threat_score;
 
-    async fn trigger_scan_alert(&self, source_ip: IpAddr, scan_type: &str, port_count: usize, threat_score: f32) -> Result<(), Box<dyn std::error::Error>> {
         let event = SecurityEvent {
             timestamp: Utc::now(),
-            source_ip,
-            event_type: "TCP_SCAN_DETECTED".to_string(),
+            source_ip: info.dest_ip,
+            event_type: "EXTENSION_ENUMERATION".to_string(),
             threat_level: ThreatLevel {
-                level: if threat_score > 0.8 { 9 } else if threat_score > 0.5 { 7 } else { 5 },
-                confidence: 0.95,
+                level: if confirmed_count > 0 { 8 } else { 6 },
+                confidence,
                 category: "RECONNAISSANCE".to_string(),
             },
-            details: format!("{} - {} ports scanned, threat_score: {:.2}", scan_type, port_count, threat_score),
+            details: format!(
+                "{} distinct extensions probed in {}s, {:.0}% not found, {} confirmed valid [{}], threat_score: {:.2}",
+                distinct_targets, window.as_secs(), not_found_ratio * 100.0, confirmed_count, confirmed_list, threat_score
+            ),
             action_taken: "MONITORING_ENHANCED".to_string(),
+            evidence_id: None,
+            related_ips: Vec::new(),
         };
 
-        self.logger.log_security_event(&event)?;
-        
-        // If threat score is high enough, recommend immediate blocking
-        if threat_score > 0.7 {
-            self.logger.log_critical(&format!("🚨 CRITICAL: {} engaging in {} - IMMEDIATE CONTAINMENT RECOMMENDED", source_ip, scan_type))?;
-        }
-
+        self.events.publish_or_log(&self.logger, event)?;
         Ok(())
     }
 
-    async fn trigger_syn_flood_alert(&self, source_ip: IpAddr, syn_count: u32) -> Result<(), Box<dyn std::error::Error>> {
+    async fn fingerprint_request(&mut self, info: SipPacketInfo, message: &SipMessage) -> Result<(), Box<dyn std::error::Error>> {
+        let scanner = match self.fingerprints.identify(message) {
+            Some(scanner) => scanner,
+            None => return Ok(()),
+        };
+
+        let now = info.timestamp;
+        let profile = match self.source_profiles.get_mut(&info.source_ip) {
+            Some(profile) => profile,
+            None => return Ok(()),
+        };
+
+        // Re-alert only when the identification changes or firms up, or after the cooldown
+        let is_new = profile.identified_tool.as_ref().is_none_or(|known| {
+            known.tool != scanner.tool || scanner.confidence > known.confidence + 0.05
+        });
+        let cooled_down = profile.last_fingerprint_alert
+            .is_none_or(|last| now.duration_since(last) >= Self::ALERT_COOLDOWN);
+        if !is_new && !cooled_down {
+            return Ok(());
+        }
+
+        profile.last_fingerprint_alert = Some(now);
+        profile.threat_score = (profile.threat_score + 0.6 * scanner.confidence).min(1.0);
+        let threat_score = profile.threat_score;
+
+        let matched: Vec<String> = scanner.matched_traits.iter().map(|field| format!("{:?}", field)).collect();
         let event = SecurityEvent {
             timestamp: Utc::now(),
-            source_ip,
-            event_type: "SYN_FLOOD_DETECTED".to_string(),
+            source_ip: info.source_ip,
+            event_type: "SIP_SCANNER_DETECTED".to_string(),
             threat_level: ThreatLevel {
-                level: if syn_count > 50 { 10 } else if syn_count > 25 { 8 } else { 6 },
-                confidence: 0.9,
-                category: "DOS_ATTACK".to_string(),
+                level: scanner.threat_level,
+                confidence: scanner.confidence,
+                category: "RECONNAISSANCE".to_string(),
             },
-            details: format!("SYN flood attack detected - {} SYN packets in 5 seconds", syn_count),
-            action_taken: "RATE_LIMITING_APPLIED".to_string(),
+            details: format!(
+                "Identified SIP scanner '{}' ({:.0}% match on {}), threat_score: {:.2}",
+                scanner.tool, scanner.confidence * 100.0, matched.join(", "), threat_score
+            ),
+            action_taken: "MONITORING_ENHANCED".to_string(),
+            evidence_id: None,
+            related_ips: Vec::new(),
         };
 
-        self.logger.log_security_event(&event)?;
-        self.logger.log_critical(&format!("🚨 SYN FLOOD ATTACK: {} sent {} SYN packets - DEFENSIVE MEASURES ACTIVATED", source_ip, syn_count))?;
-
+        profile.identified_tool = Some(scanner);
+        self.events.publish_or_log(&self.logger, event)?;
         Ok(())
     }
 
-    fn cleanup_old_profiles(&mut self) {
-        let now = Instant::now();
-        let retention_time = Duration::from_secs(300); // 5 minutes
+    async fn track_invite(&mut self, info: SipPacketInfo, message: &SipMessage) -> Result<(), Box<dyn std::error::Error>> {
+        // Re-INVITEs inside an established dialog carry a To tag and are not new calls
+        if message.to().is_some_and(|to| to.tag.is_some()) {
+            return Ok(());
+        }
 
-        self.scan_profiles.retain(|_, profile| {
-            now.duration_since(profile.first_attempt) < retention_time
-        });
+        let now = info.timestamp;
+        let threshold = self.scaled_threshold(self.config.modules.sip_shield.invite_flood_threshold) as usize;
+        let flood = match self.source_profiles.get_mut(&info.source_ip) {
+            Some(profile) => {
+                let count = profile.invites.record(now, Self::RATE_WINDOW);
+                if count > threshold && profile.invites.try_alert(now, Self::ALERT_COOLDOWN) {
+                    profile.threat_score = (profile.threat_score + 0.4).min(1.0);
+                    Some((count, profile.threat_score))
+                } else {
+                    None
+                }
+            }
+            None => None,
+        };
+
+        if let Some((count, threat_score)) = flood {
+            let event = SecurityEvent {
+                timestamp: Utc::now(),
+                source_ip: info.source_ip,
+                event_type: "SIP_INVITE_FLOOD".to_string(),
+                threat_level: ThreatLevel {
+                    level: if count > threshold * 4 { 9 } else if count > threshold * 2 { 8 } else { 6 },
+                    confidence: 0.85,
+                    category: "DOS_ATTACK".to_string(),
+                },
+                details: format!(
+                    "{} INVITEs in {}s (threshold {}), threat_score: {:.2}",
+                    count, Self::RATE_WINDOW.as_secs(), threshold, threat_score
+                ),
+                action_taken: "MONITORING_ENHANCED".to_string(),
+                evidence_id: None,
+                related_ips: Vec::new(),
+            };
+            self.events.publish_or_log(&self.logger, event)?;
+        }
+
+        // Toll-fraud accounting only makes sense for calls tied to an account
+        let account = match message.auth_username() {
+            Some(account) => account,
+            None => return Ok(()),
+        };
+        let call_id = match message.call_id() {
+            Some(call_id) => call_id,
+            None => return Ok(()),
+        };
+        let dialed_number = message.request_uri()
+            .and_then(parse_uri)
+            .and_then(|uri| uri.user)
+            .unwrap_or_default();
+
+        let findings = self.call_tracker.on_invite(&account, call_id, &dialed_number, info.wall_clock.with_timezone(&Local).hour(), now);
+        self.report_toll_fraud(info.source_ip, findings)
     }
 
-    fn is_internal_ip(&self, ip: IpAddr) -> bool {
-        match ip {
-            IpAddr::V4(ipv4) => {
-                let octets = ipv4.octets();
-                // RFC 1918 private networks
-                matches!(octets[0], 10) ||
-                (octets[0] == 172 && (16..=31).contains(&octets[1])) ||
-                (octets[0] == 192 && octets[1] == 168) ||
-                // Loopback
-                octets[0] == 127 ||
-                // Link-local
-                (octets[0] == 169 && octets[1] == 254)
+    async fn handle_invite_response(&mut self, info: SipPacketInfo, message: &SipMessage) -> Result<(), Box<dyn std::error::Error>> {
+        let (call_id, status_code) = match (message.call_id(), message.cseq(), message.status_code()) {
+            (Some(call_id), Some(cseq), Some(code)) if cseq.method == SipMethod::Invite => (call_id, code),
+            _ => return Ok(()),
+        };
+
+        let findings = self.call_tracker.on_invite_response(call_id, status_code, info.timestamp);
+        // The 200 OK flows back to the caller, so the caller is the destination
+        self.report_toll_fraud(info.dest_ip, findings)
+    }
+
+    fn report_toll_fraud(&mut self, source_ip: IpAddr, findings: Vec<TollFraudFinding>) -> Result<(), Box<dyn std::error::Error>> {
+        for finding in findings {
+            let (level, confidence) = finding.severity();
+            if let Some(profile) = self.source_profiles.get_mut(&source_ip) {
+                profile.threat_score = (profile.threat_score + confidence * 0.5).min(1.0);
             }
-            IpAddr::V6(_) => false, // Skip IPv6 for now
+
+            let category = match finding {
+                TollFraudFinding::AccountInviteFlood { .. } => "DOS_ATTACK",
+                _ => "TOLL_FRAUD",
+            };
+
+            let event = SecurityEvent {
+                timestamp: Utc::now(),
+                source_ip,
+                event_type: finding.event_type().to_string(),
+                threat_level: ThreatLevel {
+                    level,
+                    confidence,
+                    category: category.to_string(),
+                },
+                details: finding.describe(),
+                action_taken: "MONITORING_ENHANCED".to_string(),
+                evidence_id: None,
+                related_ips: Vec::new(),
+            };
+            self.events.publish_or_log(&self.logger, event)?;
         }
+
+        Ok(())
     }
 
-    pub fn set_sensitivity_level(&mut self, level: u8) -> Result<(), Box<dyn std::error::Error>> {
-        if level > 10 {
-            return Err("Sensitivity level must be between 1-10".into());
+    fn track_register(&mut self, info: SipPacketInfo, message: &SipMessage) {
+        if !self.config.modules.sip_shield.registration_monitoring {
+            return;
         }
-        
-        self.sensitivity_level = level;
-        self.logger.log_info(&format!("TCP Guardian sensitivity set to level {}", level))?;
-        
-        // Adjust detection thresholds based on sensitivity
-        match level {
-            1..=3 => { /* Low sensitivity - higher thresholds */ }
-            4..=6 => { /* Normal sensitivity */ }
-            7..=10 => { /* High sensitivity - lower thresholds */ }
-            _ => {}
+
+        let (call_id, cseq) = match (message.call_id(), message.cseq()) {
+            (Some(call_id), Some(cseq)) => (call_id.to_string(), cseq.sequence),
+            _ => return,
+        };
+
+        // The AOR being registered lives in To; fall back to From for broken clients
+        let aor_uri = message.to().or_else(|| message.from()).map(|addr| addr.uri);
+        let extension = aor_uri.as_ref()
+            .and_then(|uri| uri.user.clone())
+            .unwrap_or_default();
+        let aor = match &aor_uri {
+            Some(uri) => format!("{}@{}", extension, uri.host.to_ascii_lowercase()),
+            None => String::new(),
+        };
+
+        // Behind an outbound proxy the UAC's own Via is the last one, stamped
+        // with the address the proxy received it from
+        let vias = message.vias();
+        let client_ip = match vias.last() {
+            Some(via) if vias.len() > 1 => via.received.as_deref()
+                .and_then(|received| received.parse().ok())
+                .or_else(|| via.host.trim_matches(|c| c == '[' || c == ']').parse().ok())
+                .unwrap_or(info.source_ip),
+            _ => info.source_ip,
+        };
+
+        if self.pending_registers.len() >= Self::MAX_PENDING_TRANSACTIONS {
+            self.expire_pending_registers(info.timestamp);
+            if self.pending_registers.len() >= Self::MAX_PENDING_TRANSACTIONS {
+                return;
+            }
+        }
+
+        self.pending_registers.insert((call_id, cseq), PendingRegister {
+            source_ip: info.source_ip,
+            client_ip,
+            extension,
+            aor,
+            contacts: message.header_values("Contact").into_iter().map(str::to_string).collect(),
+            expires: message.header("Expires").and_then(|value| value.trim().parse().ok()),
+            user_agent: message.user_agent().map(str::to_string),
+            had_credentials: message.header("Authorization").is_some()
+                || message.header("Proxy-Authorization").is_some(),
+            sent_at: info.timestamp,
+        });
+    }
+
+    /// Matches a registrar response to its REGISTER by Call-ID and CSeq.
+    async fn handle_register_response(&mut self, message: &SipMessage, now: Instant) -> Result<(), Box<dyn std::error::Error>> {
+        let status_code = match message.status_code() {
+            Some(code) if code >= 200 => code,
+            _ => return Ok(()), // Provisional responses don't complete the transaction
+        };
+
+        let (call_id, cseq) = match (message.call_id(), message.cseq()) {
+            (Some(call_id), Some(cseq)) if cseq.method == SipMethod::Register => (call_id, cseq.sequence),
+            _ => return Ok(()),
+        };
+
+        let pending = match self.pending_registers.remove(&(call_id.to_string(), cseq)) {
+            Some(pending) => pending,
+            None => return Ok(()),
+        };
+
+        // A 401/407 to a REGISTER without credentials is the normal first
+        // challenge. Only a challenge to a REGISTER that carried credentials,
+        // or an outright 403, means the digest response was wrong.
+        let failed = match status_code {
+            401 | 407 => pending.had_credentials,
+            403 => true,
+            _ => false,
+        };
+
+        if (200..300).contains(&status_code) {
+            self.record_registration(pending, now)?;
+        } else if failed && !self.is_internal_ip(pending.source_ip) {
+            self.record_auth_failure(pending, status_code, now).await?;
         }
-        
+
         Ok(())
     }
 
-    pub async fn deploy_honeypot_response(&mut self, source_ip: IpAddr, target_port: u16) -> Result<(), Box<dyn std::error::Error>> {
-        if !self.honeypot_responses {
+    /// Feeds the bindings a registrar accepted into the per-AOR history.
+    fn record_registration(&mut self, pending: PendingRegister, now: Instant) -> Result<(), Box<dyn std::error::Error>> {
+        if pending.aor.is_empty() {
             return Ok(());
         }
 
-        // Deploy different honeypot responses based on targeted port
-        match target_port {
-            22 => {
-                // SSH honeypot response
-                self.logger.log_info(&format!("Deploying SSH honeypot for {}", source_ip))?;
-                // Could implement fake SSH banner response
+        let internal = self.is_internal_ip(pending.client_ip);
+        let mut findings = Vec::new();
+        for contact in &pending.contacts {
+            let (binding, expires) = contact_binding(contact);
+            let expires = expires.or(pending.expires).unwrap_or(3600);
+            if expires == 0 || binding == "*" {
+                self.registrations.on_unregistered(&pending.aor, &binding, now);
+                continue;
             }
-            80 | 443 => {
-                // HTTP/HTTPS honeypot
-                self.logger.log_info(&format!("Deploying HTTP honeypot for {}", source_ip))?;
-                // Could implement fake web server response
+
+            findings.extend(self.registrations.on_registered(&pending.aor, ObservedBinding {
+                source_ip: pending.client_ip,
+                contact: binding,
+                user_agent: pending.user_agent.clone(),
+                expires: Duration::from_secs(expires as u64),
+                internal,
+            }, now));
+        }
+
+        self.report_registration_findings(findings)
+    }
+
+    fn report_registration_findings(&mut self, findings: Vec<RegistrationFinding>) -> Result<(), Box<dyn std::error::Error>> {
+        for finding in findings {
+            let (level, confidence) = finding.severity();
+            let source_ip = finding.source_ip();
+            if let Some(profile) = self.source_profiles.get_mut(&source_ip) {
+                profile.threat_score = (profile.threat_score + confidence * 0.5).min(1.0);
             }
-            5060 | 5061 => {
-                // SIP honeypot (handled by SIP Shield)
-                self.logger.log_info(&format!("Coordinating SIP honeypot response for {}", source_ip))?;
+
+            let event = SecurityEvent {
+                timestamp: Utc::now(),
+                source_ip,
+                event_type: finding.event_type().to_string(),
+                threat_level: ThreatLevel {
+                    level,
+                    confidence,
+                    category: "ACCOUNT_HIJACK".to_string(),
+                },
+                details: finding.describe(),
+                action_taken: "MONITORING_ENHANCED".to_string(),
+                evidence_id: None,
+                related_ips: Vec::new(),
+            };
+            self.events.publish_or_log(&self.logger, event)?;
+        }
+
+        Ok(())
+    }
+
+    async fn record_auth_failure(&mut self, pending: PendingRegister, status_code: u16, now: Instant) -> Result<(), Box<dyn std::error::Error>> {
+        let sip_config = &self.config.modules.sip_shield;
+        let window = Duration::from_secs(sip_config.brute_force_window);
+        let threshold = self.scaled_threshold(sip_config.brute_force_threshold) as usize;
+        let source_ip = pending.source_ip;
+
+        let source_failures = {
+            let profile = self.source_profiles
+                .entry(source_ip)
+                .or_insert_with(|| SipSourceProfile::new(now));
+            let count = profile.auth_failures.record(now, window);
+            if count >= threshold && profile.auth_failures.try_alert(now, Self::ALERT_COOLDOWN) {
+                profile.threat_score = (profile.threat_score + 0.5).min(1.0);
+                Some((count, profile.threat_score))
+            } else {
+                None
             }
-            _ => {
-                // Generic honeypot
-                self.logger.log_info(&format!("Deploying generic honeypot for {} on port {}", source_ip, target_port))?;
+        };
+
+        let extension_failures = if pending.extension.is_empty() {
+            None
+        } else {
+            let entry = self.extension_failures.entry(pending.extension.clone()).or_default();
+            let count = entry.window.record(now, window);
+            entry.sources.insert(source_ip, now);
+            entry.sources.retain(|_, seen| now.duration_since(*seen) <= window);
+            if count >= threshold && entry.window.try_alert(now, Self::ALERT_COOLDOWN) {
+                let mut related: Vec<IpAddr> = entry.sources.keys().filter(|source| **source != source_ip).cloned().collect();
+                related.sort();
+                Some((count, related))
+            } else {
+                None
             }
+        };
+
+        if let Some((count, threat_score)) = source_failures {
+            let event = SecurityEvent {
+                timestamp: Utc::now(),
+                source_ip,
+                event_type: "SIP_REGISTER_BRUTE_FORCE".to_string(),
+                threat_level: ThreatLevel {
+                    level: if count >= threshold * 4 { 9 } else if count >= threshold * 2 { 8 } else { 7 },
+                    confidence: 0.9,
+                    category: "CREDENTIAL_ATTACK".to_string(),
+                },
+                details: format!(
+                    "{} failed REGISTER authentications in {}s (last: {} for extension '{}'), threat_score: {:.2}",
+                    count, window.as_secs(), status_code, pending.extension, threat_score
+                ),
+                action_taken: "MONITORING_ENHANCED".to_string(),
+                evidence_id: None,
+                related_ips: Vec::new(),
+            };
+            self.events.publish_or_log(&self.logger, event)?;
+        }
+
+        if let Some((count, related_ips)) = extension_failures {
+            let distinct_sources = related_ips.len() + 1;
+            let event = SecurityEvent {
+                timestamp: Utc::now(),
+                source_ip,
+                event_type: "SIP_EXTENSION_PASSWORD_GUESSING".to_string(),
+                threat_level: ThreatLevel {
+                    level: if distinct_sources > 1 { 8 } else { 7 },
+                    confidence: 0.85,
+                    category: "CREDENTIAL_ATTACK".to_string(),
+                },
+                details: format!(
+                    "Extension '{}' failed authentication {} times in {}s from {} source(s)",
+                    pending.extension, count, window.as_secs(), distinct_sources
+                ),
+                action_taken: "MONITORING_ENHANCED".to_string(),
+                evidence_id: None,
+                related_ips,
+            };
+            self.events.publish_or_log(&self.logger, event)?;
+        }
+
+        Ok(())
+    }
+
+    fn expire_pending_registers(&mut self, now: Instant) {
+        self.pending_registers.retain(|_, pending| {
+            now.duration_since(pending.sent_at) < Self::TRANSACTION_TIMEOUT
+        });
+    }
+
+    /// Applies the sensitivity level to a configured threshold: low sensitivity
+    /// doubles it, high sensitivity halves it.
+    fn scaled_threshold(&self, base: u32) -> u32 {
+        Self::scale_for_sensitivity(base, self.sensitivity_level)
+    }
+
+    fn scale_for_sensitivity(base: u32, sensitivity_level: u8) -> u32 {
+        let scaled = match sensitivity_level {
+            1..=3 => base.saturating_mul(2),
+            4..=6 => base,
+            _ => base / 2,
+        };
+        scaled.max(1)
+    }
+
+    fn is_sip_port(&self, source_port: u16, dest_port: u16) -> bool {
+        self.monitored_ports.contains(&dest_port) || self.monitored_ports.contains(&source_port)
+    }
+
+    fn cleanup_old_profiles(&mut self, now: Instant) {
+        self.source_profiles.retain(|_, profile| {
+            now.duration_since(profile.last_seen) < Self::PROFILE_RETENTION
+        });
+
+        self.expire_pending_registers(now);
+        self.call_tracker.cleanup(now);
+        self.registrations.cleanup(now);
+        self.media.cleanup(now);
+        self.dialogs.cleanup(now);
+        self.reassembler.cleanup(now);
+
+        let window = Duration::from_secs(self.config.modules.sip_shield.brute_force_window);
+        self.extension_failures.retain(|_, failures| {
+            failures.window.prune(now, window);
+            !failures.window.is_empty()
+        });
+    }
+
+    fn is_internal_ip(&self, ip: IpAddr) -> bool {
+        netinfo::is_internal_address(ip)
+    }
+
+    pub fn set_sensitivity_level(&mut self, level: u8) -> Result<(), Box<dyn std::error::Error>> {
+        if !(1..=10).contains(&level) {
+            return Err("Sensitivity level must be between 1-10".into());
+        }
+
+        if self.sensitivity_level != level {
+            self.sensitivity_level = level;
+            let invite_threshold = self.scaled_threshold(self.config.modules.sip_shield.invite_flood_threshold);
+            self.call_tracker.set_invite_threshold(invite_threshold);
+            let flood_threshold = self.scaled_threshold(self.config.modules.sip_shield.media.unsolicited_flood_threshold);
+            self.media.set_flood_threshold(flood_threshold);
+            self.logger.log_info(&format!("SIP Shield sensitivity set to level {}", level))?;
         }
 
         Ok(())
@@ -419,69 +1224,278 @@ impl TcpGuard {
 
     pub fn get_threat_statistics(&self) -> HashMap<String, u32> {
         let mut stats = HashMap::new();
-        
-        let total_profiles = self.scan_profiles.len() as u32;
-        let high_threat_count = self.scan_profiles.values()
-            .filter(|p| p.threat_score > 0.7)
+
+        let register_sources = self.source_profiles.values()
+            .filter(|p| p.methods.contains_key(SipMethod::Register.as_str()))
             .count() as u32;
-        let active_scanners = self.scan_profiles.values()
-            .filter(|p| p.unique_ports.len() > 2)
+        let high_threat_count = self.source_profiles.values()
+            .filter(|p| p.threat_score > 0.7)
             .count() as u32;
 
-        stats.insert("total_monitored_ips".to_string(), total_profiles);
+        stats.insert("total_monitored_ips".to_string(), self.source_profiles.len() as u32);
         stats.insert("high_threat_ips".to_string(), high_threat_count);
-        stats.insert("active_scanners".to_string(), active_scanners);
-        stats.insert("stealth_ports_monitored".to_string(), self.stealth_ports.len() as u32);
+        stats.insert("register_sources".to_string(), register_sources);
+        stats.insert("auth_failing_sources".to_string(), self.source_profiles.values()
+            .filter(|p| !p.auth_failures.is_empty())
+            .count() as u32);
+        stats.insert("pending_registers".to_string(), self.pending_registers.len() as u32);
+        stats.insert("tracked_dialogs".to_string(), self.dialogs.dialog_count() as u32);
+        stats.insert("protocol_abusers".to_string(), self.source_profiles.values()
+            .filter(|p| !p.protocol_anomalies.is_empty())
+            .count() as u32);
+        stats.insert("media_dialogs".to_string(), self.media.active_dialogs() as u32);
+        stats.insert("tracked_aors".to_string(), self.registrations.aor_count() as u32);
+        stats.insert("active_calls".to_string(), self.call_tracker.active_calls() as u32);
+        stats.insert("tcp_flows".to_string(), self.reassembler.flow_count() as u32);
+        stats.insert("tcp_buffer_overflows".to_string(), self.reassembler.overflow_count.min(u32::MAX as u64) as u32);
+        stats.insert("tcp_dropped_segments".to_string(), self.reassembler.dropped_segments.min(u32::MAX as u64) as u32);
+        stats.insert("tcp_resyncs".to_string(), self.reassembler.resync_count.min(u32::MAX as u64) as u32);
+        stats.insert("identified_scanners".to_string(), self.source_profiles.values()
+            .filter(|p| p.identified_tool.is_some())
+            .count() as u32);
+        stats.insert("messages_parsed".to_string(), self.messages_parsed.min(u32::MAX as u64) as u32);
+        stats.insert("parse_failures".to_string(), self.parse_failures.min(u32::MAX as u64) as u32);
 
         stats
     }
+}
+
+/// Contact URI and its expires parameter, from one Contact header value.
+fn contact_binding(contact: &str) -> (String, Option<u32>) {
+    let contact = contact.trim();
+    if contact == "*" {
+        return ("*".to_string(), None);
+    }
 
-    pub async fn perform_counter_reconnaissance(&mut self, source_ip: IpAddr) -> Result<(), Box<dyn std::error::Error>> {
-        // Passive counter-reconnaissance - gather intel on the attacker
-        self.logger.log_info(&format!("Initiating passive counter-reconnaissance on {}", source_ip))?;
-        
-        // Log detailed information about the attacker's behavior
-        if let Some(profile) = self.scan_profiles.get(&source_ip) {
-            let recon_data = format!(
-                "ATTACKER_PROFILE: {} | First_seen: {:?} | Unique_ports: {} | Total_attempts: {} | Threat_score: {:.2} | Techniques: {:?}",
-                source_ip,
-                profile.first_attempt,
-                profile.unique_ports.len(),
-                profile.attempts.len(),
-                profile.threat_score,
-                self.analyze_attack_techniques(profile)
-            );
-            
-            self.logger.log_info(&recon_data)?;
+    // Header parameters follow the closing '>' of a name-addr, or the URI itself
+    let (uri, params) = match contact.find('>') {
+        Some(end) => (contact[..end].rsplit('<').next().unwrap_or(""), &contact[end + 1..]),
+        None => match contact.split_once(';') {
+            Some((uri, params)) => (uri, params),
+            None => (contact, ""),
+        },
+    };
+
+    let expires = params.split(';')
+        .filter_map(|param| param.split_once('='))
+        .find(|(key, _)| key.trim().eq_ignore_ascii_case("expires"))
+        .and_then(|(_, value)| value.trim().parse().ok());
+
+    (uri.trim().to_string(), expires)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+    use tokio::sync::mpsc;
+
+    const PBX: Ipv4Addr = Ipv4Addr::new(192, 0, 2, 10);
+    const ATTACKER: Ipv4Addr = Ipv4Addr::new(203, 0, 113, 5);
+
+    fn shield(name: &str, configure: impl FnOnce(&mut Config)) -> (SipShield, mpsc::Receiver<Arc<SecurityEvent>>) {
+        let directory = std::env::temp_dir().join(format!("astra_sip_{}_{}", name, uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let mut config = Config::default();
+        config.logging.log_file = directory.join("astra.log").to_string_lossy().to_string();
+        config.logging.audit_trail = false;
+        config.network.netinfo_file = directory.join("netinfo.csv").to_string_lossy().to_string();
+        config.modules.sip_shield.fingerprint_file = directory.join("fingerprints.json").to_string_lossy().to_string();
+        config.modules.sip_shield.sensitivity = 5;
+        // Keep the request flood detector out of the way
+        config.modules.sip_shield.attack_threshold = 1000;
+        configure(&mut config);
+        let config = Arc::new(config);
+
+        let events = Arc::new(EventBus::new(&config));
+        let receiver = events.subscribe("test");
+        let logger = Arc::new(Logger::new(&config).unwrap());
+        (SipShield::new(&config, logger, events).unwrap(), receiver)
+    }
+
+    fn info(source: Ipv4Addr, dest: Ipv4Addr, timestamp: Instant) -> SipPacketInfo {
+        SipPacketInfo {
+            source_ip: IpAddr::V4(source),
+            source_port: 5060,
+            dest_ip: IpAddr::V4(dest),
+            dest_port: 5060,
+            timestamp,
+            wall_clock: Utc::now(),
         }
+    }
 
-        Ok(())
+    fn register(extension: &str, cseq: u32, credentials: bool) -> String {
+        let authorization = if credentials {
+            format!("Authorization: Digest username=\"{}\", realm=\"pbx\", nonce=\"abc\", uri=\"sip:pbx.example.com\", response=\"0000\"\r\n", extension)
+        } else {
+            String::new()
+        };
+        format!(
+            "REGISTER sip:pbx.example.com SIP/2.0\r\n\
+             Via: SIP/2.0/UDP {attacker}:5060;branch=z9hG4bK{extension}{cseq}\r\n\
+             Max-Forwards: 70\r\n\
+             From: <sip:{extension}@pbx.example.com>;tag=f{cseq}\r\n\
+             To: <sip:{extension}@pbx.example.com>\r\n\
+             Call-ID: reg-{extension}@{attacker}\r\n\
+             CSeq: {cseq} REGISTER\r\n\
+             Contact: <sip:{extension}@{attacker}:5060>\r\n\
+             {authorization}Content-Length: 0\r\n\r\n",
+            attacker = ATTACKER, extension = extension, cseq = cseq, authorization = authorization
+        )
+    }
+
+    fn register_response(status: &str, extension: &str, cseq: u32) -> String {
+        format!(
+            "SIP/2.0 {status}\r\n\
+             Via: SIP/2.0/UDP {attacker}:5060;branch=z9hG4bK{extension}{cseq}\r\n\
+             From: <sip:{extension}@pbx.example.com>;tag=f{cseq}\r\n\
+             To: <sip:{extension}@pbx.example.com>;tag=t{cseq}\r\n\
+             Call-ID: reg-{extension}@{attacker}\r\n\
+             CSeq: {cseq} REGISTER\r\n\
+             Content-Length: 0\r\n\r\n",
+            status = status, attacker = ATTACKER, extension = extension, cseq = cseq
+        )
+    }
+
+    /// One REGISTER transaction from the attacker and the PBX's final answer
+    async fn exchange(shield: &mut SipShield, extension: &str, cseq: u32, credentials: bool, status: &str, now: Instant) {
+        shield.process_sip_payload(info(ATTACKER, PBX, now), register(extension, cseq, credentials).as_bytes()).await.unwrap();
+        shield.process_sip_payload(info(PBX, ATTACKER, now), register_response(status, extension, cseq).as_bytes()).await.unwrap();
+    }
+
+    fn event_types(receiver: &mut mpsc::Receiver<Arc<SecurityEvent>>) -> Vec<String> {
+        let mut types = Vec::new();
+        while let Ok(event) = receiver.try_recv() {
+            types.push(event.event_type.clone());
+        }
+        types
+    }
+
+    #[tokio::test]
+    async fn failed_digest_responses_within_the_window_are_brute_force() {
+        let (mut shield, mut receiver) = shield("brute", |config| {
+            config.modules.sip_shield.brute_force_threshold = 3;
+            config.modules.sip_shield.brute_force_window = 60;
+        });
+        let start = Instant::now();
+
+        // The first challenge to a REGISTER without credentials is normal
+        exchange(&mut shield, "1001", 1, false, "401 Unauthorized", start).await;
+        exchange(&mut shield, "1001", 2, true, "401 Unauthorized", start + Duration::from_secs(1)).await;
+        exchange(&mut shield, "1002", 3, true, "403 Forbidden", start + Duration::from_secs(2)).await;
+        assert!(!event_types(&mut receiver).contains(&"SIP_REGISTER_BRUTE_FORCE".to_string()));
+
+        exchange(&mut shield, "1003", 4, true, "403 Forbidden", start + Duration::from_secs(3)).await;
+        assert!(event_types(&mut receiver).contains(&"SIP_REGISTER_BRUTE_FORCE".to_string()));
+    }
+
+    #[tokio::test]
+    async fn failures_spread_beyond_the_window_are_not_brute_force() {
+        let (mut shield, mut receiver) = shield("slow_brute", |config| {
+            config.modules.sip_shield.brute_force_threshold = 3;
+            config.modules.sip_shield.brute_force_window = 60;
+        });
+        let start = Instant::now();
+
+        for attempt in 0..6u32 {
+            let extension = format!("20{:02}", attempt);
+            exchange(&mut shield, &extension, attempt + 1, true, "403 Forbidden", start + Duration::from_secs(31 * attempt as u64)).await;
+        }
+        assert!(event_types(&mut receiver).iter().all(|event_type| event_type != "SIP_REGISTER_BRUTE_FORCE"));
     }
 
-    fn analyze_attack_techniques(&self, profile: &ScanProfile) -> Vec<String> {
-        let mut techniques = Vec::new();
-        
-        // Analyze scan patterns
-        if profile.unique_ports.len() > 10 {
-            techniques.push("COMPREHENSIVE_SCAN".to_string());
-        }
-        
-        if profile.syn_flood_count > 5 {
-            techniques.push("SYN_FLOOD".to_string());
-        }
-        
-        // Check for common attack patterns
-        let common_exploit_ports: std::collections::HashSet<u16> = [
-            135, 139, 445, // SMB
-            1433, 1521,    // Database
-            3389,          // RDP
-            5985, 5986,    // WinRM
-        ].iter().cloned().collect();
-        
-        if profile.unique_ports.intersection(&common_exploit_ports).count() > 0 {
-            techniques.push("EXPLOIT_TARGETING".to_string());
-        }
-        
-        techniques
-    }
-} 
\ No newline at end of file
+    #[tokio::test]
+    async fn one_extension_failing_repeatedly_is_password_guessing() {
+        let (mut shield, mut receiver) = shield("guessing", |config| {
+            config.modules.sip_shield.brute_force_threshold = 3;
+        });
+        let start = Instant::now();
+
+        for cseq in 1..=3u32 {
+            exchange(&mut shield, "1001", cseq, true, "401 Unauthorized", start + Duration::from_secs(cseq as u64)).await;
+        }
+        let types = event_types(&mut receiver);
+        assert!(types.contains(&"SIP_EXTENSION_PASSWORD_GUESSING".to_string()));
+        assert!(types.contains(&"SIP_REGISTER_BRUTE_FORCE".to_string()));
+    }
+
+    #[tokio::test]
+    async fn distributed_guessing_names_every_source() {
+        let (mut shield, mut receiver) = shield("distributed_guessing", |config| {
+            config.modules.sip_shield.brute_force_threshold = 3;
+        });
+        let start = Instant::now();
+        let sources = [Ipv4Addr::new(198, 51, 100, 1), Ipv4Addr::new(198, 51, 100, 2), Ipv4Addr::new(198, 51, 100, 3)];
+
+        // One failure each, so no single source is brute forcing
+        for (index, source) in sources.iter().enumerate() {
+            let cseq = index as u32 + 1;
+            let now = start + Duration::from_secs(cseq as u64);
+            shield.process_sip_payload(info(*source, PBX, now), register("1001", cseq, true).as_bytes()).await.unwrap();
+            shield.process_sip_payload(info(PBX, *source, now), register_response("401 Unauthorized", "1001", cseq).as_bytes()).await.unwrap();
+        }
+
+        let mut guessing = Vec::new();
+        while let Ok(event) = receiver.try_recv() {
+            assert_ne!(event.event_type, "SIP_REGISTER_BRUTE_FORCE");
+            if event.event_type == "SIP_EXTENSION_PASSWORD_GUESSING" {
+                guessing.push(event);
+            }
+        }
+        assert_eq!(guessing.len(), 1);
+        assert_eq!(guessing[0].source_ip, IpAddr::V4(sources[2]));
+        assert_eq!(guessing[0].related_ips, vec![IpAddr::V4(sources[0]), IpAddr::V4(sources[1])]);
+    }
+
+    fn enumeration_shield(name: &str) -> (SipShield, mpsc::Receiver<Arc<SecurityEvent>>) {
+        shield(name, |config| {
+            config.modules.sip_shield.enumeration_threshold = 5;
+            config.modules.sip_shield.enumeration_window = 120;
+        })
+    }
+
+    #[tokio::test]
+    async fn probes_mostly_answered_404_are_enumeration() {
+        let (mut shield, mut receiver) = enumeration_shield("enumeration");
+        let start = Instant::now();
+
+        for (index, extension) in ["100", "101", "102", "103"].iter().enumerate() {
+            exchange(&mut shield, extension, index as u32 + 1, false, "404 Not Found", start + Duration::from_secs(index as u64)).await;
+        }
+        assert!(!event_types(&mut receiver).contains(&"EXTENSION_ENUMERATION".to_string()));
+
+        // The fifth distinct extension reaches the threshold; its challenge confirms it exists
+        exchange(&mut shield, "104", 5, false, "401 Unauthorized", start + Duration::from_secs(4)).await;
+        let event = std::iter::from_fn(|| receiver.try_recv().ok())
+            .find(|event| event.event_type == "EXTENSION_ENUMERATION")
+            .expect("enumeration reported");
+        assert!(event.details.contains("5 distinct extensions"));
+        assert!(event.details.contains("1 confirmed valid [104]"));
+        assert_eq!(event.threat_level.level, 8);
+    }
+
+    #[tokio::test]
+    async fn challenges_to_a_few_real_extensions_are_not_enumeration() {
+        let (mut shield, mut receiver) = enumeration_shield("phones");
+        let start = Instant::now();
+
+        // Every extension exists and is challenged: no 404s, and too few to be a sweep
+        for cseq in 1..=10u32 {
+            let extension = format!("20{}", cseq % 5);
+            exchange(&mut shield, &extension, cseq, false, "401 Unauthorized", start + Duration::from_secs(cseq as u64)).await;
+        }
+        assert!(!event_types(&mut receiver).contains(&"EXTENSION_ENUMERATION".to_string()));
+    }
+
+    #[tokio::test]
+    async fn probes_outside_the_window_do_not_add_up() {
+        let (mut shield, mut receiver) = enumeration_shield("slow_enumeration");
+        let start = Instant::now();
+
+        for index in 0..8u32 {
+            let extension = format!("30{}", index);
+            exchange(&mut shield, &extension, index + 1, false, "404 Not Found", start + Duration::from_secs(40 * index as u64)).await;
+        }
+        assert!(!event_types(&mut receiver).contains(&"EXTENSION_ENUMERATION".to_string()));
+    }
+}
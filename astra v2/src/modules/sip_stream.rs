@@ -0,0 +1,538 @@
+use std::collections::{BTreeMap, HashMap};
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+use crate::modules::sip_parser::{is_start_line, stream_message_length, SipParseError};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TcpFlowKey {
+    pub source_ip: IpAddr,
+    pub source_port: u16,
+    pub dest_ip: IpAddr,
+    pub dest_port: u16,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct TcpSegment<'a> {
+    pub sequence: u32,
+    pub syn: bool,
+    pub fin: bool,
+    pub rst: bool,
+    pub payload: &'a [u8],
+}
+
+/// What came out of feeding one segment into a flow.
+#[derive(Debug, Default)]
+pub struct StreamOutput {
+    pub messages: Vec<Vec<u8>>,
+    pub framing_error: Option<SipParseError>,
+    pub overflowed: bool,
+}
+
+#[derive(Debug)]
+struct FlowState {
+    next_sequence: Option<u32>,
+    buffer: Vec<u8>,
+    out_of_order: BTreeMap<u32, Vec<u8>>,
+    // When data first went missing ahead of the parked segments
+    gap_since: Option<Instant>,
+    // After skipping a gap, bytes are discarded up to the next start line
+    resyncing: bool,
+    last_activity: Instant,
+}
+
+/// Per-direction TCP reassembly that hands out whole SIP messages framed by
+/// Content-Length, including several pipelined in one segment. A segment
+/// that never arrives (lost in capture, not retransmitted) is given up on
+/// after `gap_timeout`, and the flow picks up again at the next start line.
+pub struct SipStreamReassembler {
+    flows: HashMap<TcpFlowKey, FlowState>,
+    max_flows: usize,
+    max_buffer: usize,
+    idle_timeout: Duration,
+    gap_timeout: Duration,
+    pub overflow_count: u64,
+    pub dropped_segments: u64,
+    pub resync_count: u64,
+}
+
+impl SipStreamReassembler {
+    const MAX_OUT_OF_ORDER_SEGMENTS: usize = 64;
+
+    pub fn new(max_flows: usize, max_buffer: usize, idle_timeout: Duration, gap_timeout: Duration) -> Self {
+        SipStreamReassembler {
+            flows: HashMap::new(),
+            max_flows,
+            max_buffer,
+            idle_timeout,
+            gap_timeout,
+            overflow_count: 0,
+            dropped_segments: 0,
+            resync_count: 0,
+        }
+    }
+
+    pub fn push_segment(&mut self, key: TcpFlowKey, segment: TcpSegment<'_>, now: Instant) -> StreamOutput {
+        let mut output = StreamOutput::default();
+
+        if segment.rst {
+            self.flows.remove(&key);
+            return output;
+        }
+
+        if !self.flows.contains_key(&key) {
+            if segment.payload.is_empty() && !segment.syn {
+                return output;
+            }
+            if self.flows.len() >= self.max_flows {
+                self.evict_oldest();
+            }
+        }
+
+        let flow = self.flows.entry(key).or_insert_with(|| FlowState {
+            next_sequence: None,
+            buffer: Vec::new(),
+            out_of_order: BTreeMap::new(),
+            gap_since: None,
+            resyncing: false,
+            last_activity: now,
+        });
+        flow.last_activity = now;
+
+        if segment.syn {
+            // SYN consumes one sequence number
+            flow.next_sequence = Some(segment.sequence.wrapping_add(1));
+            flow.buffer.clear();
+            flow.out_of_order.clear();
+            flow.gap_since = None;
+            flow.resyncing = false;
+        }
+
+        if !segment.payload.is_empty() {
+            // Activity alone does not end a gap; only the missing data would
+            if flow.gap_since.is_some_and(|since| now.duration_since(since) >= self.gap_timeout) {
+                Self::skip_gap(flow);
+                self.resync_count += 1;
+            }
+            // Mid-stream pickup: trust the first segment we see
+            let expected = *flow.next_sequence.get_or_insert(segment.sequence);
+            if !Self::accept_segment(flow, expected, segment.sequence, segment.payload) {
+                self.dropped_segments += 1;
+            }
+            flow.gap_since = if flow.out_of_order.is_empty() { None } else { flow.gap_since.or(Some(now)) };
+        }
+
+        if flow.buffer.len() + flow.out_of_order.values().map(Vec::len).sum::<usize>() > self.max_buffer {
+            self.overflow_count += 1;
+            self.flows.remove(&key);
+            output.overflowed = true;
+            return output;
+        }
+
+        // Until a start line shows up after a skipped gap there is nothing to frame
+        if !flow.resyncing || Self::find_resync_point(flow) {
+            loop {
+                match stream_message_length(&flow.buffer, self.max_buffer) {
+                    Ok(Some(length)) => {
+                        let message: Vec<u8> = flow.buffer.drain(..length).collect();
+                        // Pure CRLF keep-alives frame to nothing useful
+                        if message.iter().any(|&b| b != b'\r' && b != b'\n') {
+                            output.messages.push(message);
+                        }
+                    }
+                    Ok(None) => break,
+                    Err(e) => {
+                        // No way to find the next message boundary; give up on the flow
+                        output.framing_error = Some(e);
+                        self.flows.remove(&key);
+                        return output;
+                    }
+                }
+            }
+        }
+
+        if segment.fin {
+            self.flows.remove(&key);
+        }
+
+        output
+    }
+
+    /// Returns false when the segment had to be dropped because too many
+    /// are already parked behind a gap.
+    fn accept_segment(flow: &mut FlowState, expected: u32, sequence: u32, payload: &[u8]) -> bool {
+        let offset = sequence.wrapping_sub(expected) as i32;
+
+        if offset > 0 {
+            // Future data: park it until the gap is filled
+            if flow.out_of_order.contains_key(&sequence) {
+                return true;
+            }
+            if flow.out_of_order.len() >= Self::MAX_OUT_OF_ORDER_SEGMENTS {
+                return false;
+            }
+            flow.out_of_order.insert(sequence, payload.to_vec());
+            return true;
+        }
+
+        // Drop the part we already have (retransmission or overlap)
+        let already_seen = offset.unsigned_abs() as usize;
+        if already_seen >= payload.len() {
+            return true;
+        }
+        flow.buffer.extend_from_slice(&payload[already_seen..]);
+        let mut next = sequence.wrapping_add(payload.len() as u32);
+
+        // Drain any parked segments that are now contiguous
+        while let Some((&parked_sequence, _)) = flow.out_of_order.iter().next() {
+            let gap = parked_sequence.wrapping_sub(next) as i32;
+            if gap > 0 {
+                break;
+            }
+            let parked = flow.out_of_order.remove(&parked_sequence).unwrap_or_default();
+            let overlap = gap.unsigned_abs() as usize;
+            if overlap < parked.len() {
+                flow.buffer.extend_from_slice(&parked[overlap..]);
+                next = parked_sequence.wrapping_add(parked.len() as u32);
+            }
+        }
+
+        flow.next_sequence = Some(next);
+        true
+    }
+
+    /// Gives up on the missing bytes: whatever message they belonged to is
+    /// lost, so the flow continues from the earliest parked segment.
+    fn skip_gap(flow: &mut FlowState) {
+        flow.buffer.clear();
+        flow.gap_since = None;
+        flow.resyncing = true;
+        if let Some((sequence, payload)) = flow.out_of_order.pop_first() {
+            Self::accept_segment(flow, sequence, sequence, &payload);
+        }
+    }
+
+    /// Drops buffered bytes up to the next request or status line. Returns
+    /// false while none has arrived yet, keeping only a trailing partial line.
+    fn find_resync_point(flow: &mut FlowState) -> bool {
+        let mut line_start = 0;
+        while let Some(length) = flow.buffer[line_start..].windows(2).position(|pair| pair == b"\r\n") {
+            if is_start_line(&flow.buffer[line_start..line_start + length]) {
+                flow.buffer.drain(..line_start);
+                flow.resyncing = false;
+                return true;
+            }
+            line_start += length + 2;
+        }
+        flow.buffer.drain(..line_start);
+        false
+    }
+
+    fn evict_oldest(&mut self) {
+        if let Some(oldest) = self.flows.iter()
+            .min_by_key(|(_, flow)| flow.last_activity)
+            .map(|(key, _)| *key)
+        {
+            self.flows.remove(&oldest);
+        }
+    }
+
+    pub fn cleanup(&mut self, now: Instant) {
+        let idle_timeout = self.idle_timeout;
+        self.flows.retain(|_, flow| now.duration_since(flow.last_activity) < idle_timeout);
+    }
+
+    pub fn flow_count(&self) -> usize {
+        self.flows.len()
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct TlsHandshakeInfo {
+    pub client_ip: Option<IpAddr>,
+    pub sni: Option<String>,
+    pub client_version: Option<u16>,
+    pub certificate_cn: Option<String>,
+    pub server_hello_seen: bool,
+    pub completed: bool,
+    pub alert: Option<(u8, u8)>,
+}
+
+/// Why a TLS session on the SIP port is considered failed.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TlsFailure {
+    FatalAlert { description: u8 },
+    ClosedBeforeCompletion,
+    NotTls,
+}
+
+#[derive(Debug)]
+struct TlsFlow {
+    info: TlsHandshakeInfo,
+    last_activity: Instant,
+}
+
+/// Handshake-metadata view of SIP over TLS: SNI and certificate names are
+/// visible before encryption starts, and failed handshakes are countable.
+pub struct TlsHandshakeTracker {
+    flows: HashMap<(IpAddr, u16, IpAddr, u16), TlsFlow>,
+    max_flows: usize,
+    idle_timeout: Duration,
+}
+
+const TLS_CHANGE_CIPHER_SPEC: u8 = 20;
+const TLS_ALERT: u8 = 21;
+const TLS_HANDSHAKE: u8 = 22;
+const TLS_APPLICATION_DATA: u8 = 23;
+
+impl TlsHandshakeTracker {
+    pub fn new(max_flows: usize, idle_timeout: Duration) -> Self {
+        TlsHandshakeTracker {
+            flows: HashMap::new(),
+            max_flows,
+            idle_timeout,
+        }
+    }
+
+    /// Feeds one segment. `from_client` is true for traffic towards the TLS port.
+    /// Returns the finished handshake and a failure reason once the outcome is known.
+    pub fn observe(&mut self, key: TcpFlowKey, from_client: bool, segment: TcpSegment<'_>, now: Instant) -> Option<(TlsHandshakeInfo, Option<TlsFailure>)> {
+        // Key both directions on (client, server)
+        let flow_key = if from_client {
+            (key.source_ip, key.source_port, key.dest_ip, key.dest_port)
+        } else {
+            (key.dest_ip, key.dest_port, key.source_ip, key.source_port)
+        };
+
+        if !self.flows.contains_key(&flow_key) {
+            if segment.payload.is_empty() {
+                return None;
+            }
+            if self.flows.len() >= self.max_flows {
+                return None;
+            }
+        }
+
+        let flow = self.flows.entry(flow_key).or_insert_with(|| TlsFlow {
+            info: TlsHandshakeInfo { client_ip: Some(flow_key.0), ..Default::default() },
+            last_activity: now,
+        });
+        flow.last_activity = now;
+
+        let mut failure = None;
+        if !segment.payload.is_empty() && !flow.info.completed {
+            failure = Self::inspect_records(&mut flow.info, from_client, segment.payload);
+        }
+
+        if failure.is_none() && (segment.fin || segment.rst) && !flow.info.completed {
+            failure = Some(TlsFailure::ClosedBeforeCompletion);
+        }
+
+        if failure.is_some() {
+            let flow = self.flows.remove(&flow_key)?;
+            return Some((flow.info, failure));
+        }
+
+        if flow.info.completed {
+            // Nothing more to learn once the session is encrypted
+            let flow = self.flows.remove(&flow_key)?;
+            return Some((flow.info, None));
+        }
+
+        None
+    }
+
+    fn inspect_records(info: &mut TlsHandshakeInfo, from_client: bool, mut data: &[u8]) -> Option<TlsFailure> {
+        // The client's first bytes must be a TLS record carrying a ClientHello
+        let expect_hello = from_client && info.client_version.is_none();
+
+        while data.len() >= 5 {
+            let content_type = data[0];
+            let record_length = u16::from_be_bytes([data[3], data[4]]) as usize;
+            if !(TLS_CHANGE_CIPHER_SPEC..=TLS_APPLICATION_DATA).contains(&content_type) || data[1] != 3 {
+                return if expect_hello { Some(TlsFailure::NotTls) } else { None };
+            }
+
+            let body = &data[5..(5 + record_length).min(data.len())];
+            match content_type {
+                TLS_HANDSHAKE => Self::inspect_handshake(info, body),
+                TLS_ALERT if body.len() >= 2 => {
+                    info.alert = Some((body[0], body[1]));
+                    // Level 2 is fatal; close_notify (0) is a normal shutdown
+                    if body[0] == 2 {
+                        return Some(TlsFailure::FatalAlert { description: body[1] });
+                    }
+                }
+                TLS_APPLICATION_DATA => {
+                    info.completed = true;
+                    return None;
+                }
+                _ => {}
+            }
+
+            if data.len() < 5 + record_length {
+                break;
+            }
+            data = &data[5 + record_length..];
+        }
+
+        None
+    }
+
+    fn inspect_handshake(info: &mut TlsHandshakeInfo, body: &[u8]) {
+        if body.len() < 4 {
+            return;
+        }
+        let message = &body[4..];
+
+        match body[0] {
+            1 => Self::parse_client_hello(info, message),
+            2 => info.server_hello_seen = true,
+            // Certificate (TLS 1.2 and earlier; 1.3 encrypts it)
+            11 => info.certificate_cn = extract_common_name(message),
+            _ => {}
+        }
+    }
+
+    fn parse_client_hello(info: &mut TlsHandshakeInfo, hello: &[u8]) {
+        if hello.len() < 2 {
+            return;
+        }
+        info.client_version = Some(u16::from_be_bytes([hello[0], hello[1]]));
+
+        // version(2) + random(32), then session id, cipher suites, compression
+        let mut pos = 34;
+        let session_id_len = *hello.get(pos).unwrap_or(&0) as usize;
+        pos += 1 + session_id_len;
+        let cipher_len = match hello.get(pos..pos + 2) {
+            Some(bytes) => u16::from_be_bytes([bytes[0], bytes[1]]) as usize,
+            None => return,
+        };
+        pos += 2 + cipher_len;
+        let compression_len = *hello.get(pos).unwrap_or(&0) as usize;
+        pos += 1 + compression_len;
+        let extensions_len = match hello.get(pos..pos + 2) {
+            Some(bytes) => u16::from_be_bytes([bytes[0], bytes[1]]) as usize,
+            None => return,
+        };
+        pos += 2;
+        let end = (pos + extensions_len).min(hello.len());
+
+        while pos + 4 <= end {
+            let extension_type = u16::from_be_bytes([hello[pos], hello[pos + 1]]);
+            let extension_len = u16::from_be_bytes([hello[pos + 2], hello[pos + 3]]) as usize;
+            let extension = match hello.get(pos + 4..pos + 4 + extension_len) {
+                Some(extension) => extension,
+                None => return,
+            };
+
+            // server_name: list length(2), name type(1), name length(2), name
+            if extension_type == 0 && extension.len() > 5 && extension[2] == 0 {
+                let name_len = u16::from_be_bytes([extension[3], extension[4]]) as usize;
+                if let Some(name) = extension.get(5..5 + name_len) {
+                    info.sni = Some(String::from_utf8_lossy(name).to_string());
+                }
+            }
+
+            pos += 4 + extension_len;
+        }
+    }
+
+    pub fn cleanup(&mut self, now: Instant) -> Vec<TlsHandshakeInfo> {
+        let idle_timeout = self.idle_timeout;
+        let mut stalled = Vec::new();
+        self.flows.retain(|_, flow| {
+            let alive = now.duration_since(flow.last_activity) < idle_timeout;
+            if !alive && !flow.info.completed {
+                stalled.push(flow.info.clone());
+            }
+            alive
+        });
+        stalled
+    }
+}
+
+/// Pulls the first commonName (OID 2.5.4.3) out of a DER certificate chain.
+fn extract_common_name(data: &[u8]) -> Option<String> {
+    const CN_OID: [u8; 5] = [0x06, 0x03, 0x55, 0x04, 0x03];
+    let pos = data.windows(CN_OID.len()).position(|window| window == CN_OID)?;
+    let string_header = pos + CN_OID.len();
+    let length = *data.get(string_header + 1)? as usize;
+    let value = data.get(string_header + 2..string_header + 2 + length)?;
+    Some(String::from_utf8_lossy(value).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    const KEY: TcpFlowKey = TcpFlowKey {
+        source_ip: IpAddr::V4(Ipv4Addr::new(203, 0, 113, 5)),
+        source_port: 40000,
+        dest_ip: IpAddr::V4(Ipv4Addr::new(192, 0, 2, 10)),
+        dest_port: 5060,
+    };
+    const GAP_TIMEOUT: Duration = Duration::from_secs(5);
+
+    fn segment(sequence: u32, payload: &[u8]) -> TcpSegment<'_> {
+        TcpSegment { sequence, syn: false, fin: false, rst: false, payload }
+    }
+
+    fn options(call_id: u32) -> Vec<u8> {
+        format!("OPTIONS sip:100@pbx SIP/2.0\r\nCall-ID: {}\r\nCSeq: 1 OPTIONS\r\n\r\n", call_id).into_bytes()
+    }
+
+    #[test]
+    fn reorders_segments_and_frames_pipelined_messages() {
+        let mut reassembler = SipStreamReassembler::new(16, 4096, Duration::from_secs(60), GAP_TIMEOUT);
+        let now = Instant::now();
+        let stream = [options(1), options(2)].concat();
+        let (head, tail) = stream.split_at(30);
+
+        let syn = TcpSegment { syn: true, ..segment(999, b"") };
+        reassembler.push_segment(KEY, syn, now);
+        assert!(reassembler.push_segment(KEY, segment(1030, tail), now).messages.is_empty());
+        let output = reassembler.push_segment(KEY, segment(1000, head), now);
+        assert_eq!(output.messages, vec![options(1), options(2)]);
+    }
+
+    #[test]
+    fn lost_segment_resyncs_on_the_next_start_line() {
+        let mut reassembler = SipStreamReassembler::new(16, 4096, Duration::from_secs(60), GAP_TIMEOUT);
+        let start = Instant::now();
+        let first = options(1);
+        let lost_len = 20u32;
+        let mut sequence = 1000;
+
+        // The first segment is complete; the next 20 bytes are never seen
+        assert_eq!(reassembler.push_segment(KEY, segment(sequence, &first), start).messages.len(), 1);
+        sequence += first.len() as u32 + lost_len;
+        let rest = options(2);
+        let after_gap = [&rest[lost_len as usize..], &options(3)[..]].concat();
+        assert!(reassembler.push_segment(KEY, segment(sequence, &after_gap), start).messages.is_empty());
+        sequence += after_gap.len() as u32;
+
+        // Still inside the timeout: later data keeps waiting behind the gap
+        let fourth = options(4);
+        let waiting = start + GAP_TIMEOUT / 2;
+        assert!(reassembler.push_segment(KEY, segment(sequence, &fourth), waiting).messages.is_empty());
+        sequence += fourth.len() as u32;
+
+        let fifth = options(5);
+        let output = reassembler.push_segment(KEY, segment(sequence, &fifth), start + GAP_TIMEOUT);
+        assert_eq!(output.messages, vec![options(3), options(4), options(5)]);
+        assert_eq!(reassembler.resync_count, 1);
+    }
+
+    #[test]
+    fn counts_segments_dropped_behind_a_gap() {
+        let mut reassembler = SipStreamReassembler::new(16, 1 << 20, Duration::from_secs(60), GAP_TIMEOUT);
+        let now = Instant::now();
+        reassembler.push_segment(KEY, segment(1000, b"OPTIONS sip:100@pbx SIP/2.0\r\n"), now);
+        for index in 0..=SipStreamReassembler::MAX_OUT_OF_ORDER_SEGMENTS as u32 {
+            reassembler.push_segment(KEY, segment(2000 + index * 10, b"0123456789"), now);
+        }
+        assert_eq!(reassembler.dropped_segments, 1);
+    }
+}
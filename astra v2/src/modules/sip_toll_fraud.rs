@@ -0,0 +1,292 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::core::config::TollFraudConfig;
+use crate::modules::sip_shield::SlidingWindow;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DestinationClass {
+    Domestic,
+    International,
+    Premium,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TollFraudFinding {
+    AccountInviteFlood { account: String, count: usize, window: Duration },
+    InternationalBurst { account: String, count: usize, window: Duration, last_number: String },
+    PremiumBurst { account: String, count: usize, window: Duration, last_number: String },
+    ParallelCalls { account: String, active: usize, limit: u32 },
+    UnusualHour { account: String, hour: u32, share: f32, learned_calls: u32 },
+}
+
+impl TollFraudFinding {
+    pub fn event_type(&self) -> &'static str {
+        match self {
+            TollFraudFinding::AccountInviteFlood { .. } => "SIP_ACCOUNT_INVITE_FLOOD",
+            TollFraudFinding::InternationalBurst { .. } => "SIP_TOLL_FRAUD_INTERNATIONAL_BURST",
+            TollFraudFinding::PremiumBurst { .. } => "SIP_TOLL_FRAUD_PREMIUM_BURST",
+            TollFraudFinding::ParallelCalls { .. } => "SIP_TOLL_FRAUD_PARALLEL_CALLS",
+            TollFraudFinding::UnusualHour { .. } => "SIP_TOLL_FRAUD_UNUSUAL_HOURS",
+        }
+    }
+
+    pub fn severity(&self) -> (u8, f32) {
+        match self {
+            TollFraudFinding::AccountInviteFlood { .. } => (6, 0.75),
+            TollFraudFinding::InternationalBurst { .. } => (7, 0.8),
+            TollFraudFinding::PremiumBurst { .. } => (9, 0.9),
+            TollFraudFinding::ParallelCalls { .. } => (8, 0.85),
+            // Behavioural deviation on its own is a weak signal
+            TollFraudFinding::UnusualHour { .. } => (5, 0.5),
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        match self {
+            TollFraudFinding::AccountInviteFlood { account, count, window } => {
+                format!("Account '{}' sent {} INVITEs in {}s", account, count, window.as_secs())
+            }
+            TollFraudFinding::InternationalBurst { account, count, window, last_number } => {
+                format!("Account '{}' placed {} international calls in {}s (last: {})", account, count, window.as_secs(), last_number)
+            }
+            TollFraudFinding::PremiumBurst { account, count, window, last_number } => {
+                format!("Account '{}' placed {} premium-rate calls in {}s (last: {})", account, count, window.as_secs(), last_number)
+            }
+            TollFraudFinding::ParallelCalls { account, active, limit } => {
+                format!("Account '{}' has {} parallel calls (limit {})", account, active, limit)
+            }
+            TollFraudFinding::UnusualHour { account, hour, share, learned_calls } => {
+                format!(
+                    "Account '{}' calling at {:02}:00, an hour that accounts for {:.1}% of its {} learned calls",
+                    account, hour, share * 100.0, learned_calls
+                )
+            }
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct AccountCallState {
+    invites: SlidingWindow,
+    international: SlidingWindow,
+    premium: SlidingWindow,
+    active_dialogs: HashMap<String, Instant>,
+    hourly_calls: [u32; 24],
+    last_parallel_alert: Option<Instant>,
+    last_hour_alert: Option<Instant>,
+    last_seen: Option<Instant>,
+}
+
+#[derive(Debug)]
+struct PendingInvite {
+    account: String,
+    sent_at: Instant,
+}
+
+/// Per-account call accounting behind the toll-fraud detectors.
+pub struct CallTracker {
+    config: TollFraudConfig,
+    invite_threshold: u32,
+    rate_window: Duration,
+    accounts: HashMap<String, AccountCallState>,
+    pending_invites: HashMap<String, PendingInvite>,
+}
+
+impl CallTracker {
+    const ALERT_COOLDOWN: Duration = Duration::from_secs(300);
+    const INVITE_TIMEOUT: Duration = Duration::from_secs(180);
+    // Dialogs whose BYE we never saw are reaped after this long
+    const MAX_DIALOG_DURATION: Duration = Duration::from_secs(4 * 3600);
+    const ACCOUNT_RETENTION: Duration = Duration::from_secs(7 * 24 * 3600);
+    const MAX_PENDING_INVITES: usize = 65536;
+
+    pub fn new(config: TollFraudConfig, invite_threshold: u32, rate_window: Duration) -> Self {
+        CallTracker {
+            config,
+            invite_threshold,
+            rate_window,
+            accounts: HashMap::new(),
+            pending_invites: HashMap::new(),
+        }
+    }
+
+    pub fn set_invite_threshold(&mut self, threshold: u32) {
+        self.invite_threshold = threshold;
+    }
+
+    /// Records an initial INVITE from an authenticated account.
+    pub fn on_invite(&mut self, account: &str, call_id: &str, dialed_number: &str, hour: u32, now: Instant) -> Vec<TollFraudFinding> {
+        let mut findings = Vec::new();
+        let rate_window = self.rate_window;
+        let burst_window = Duration::from_secs(self.config.burst_window);
+        let class = self.classify_destination(dialed_number);
+        let state = self.accounts.entry(account.to_string()).or_default();
+        state.last_seen = Some(now);
+
+        let invite_count = state.invites.record(now, rate_window);
+        if invite_count > self.invite_threshold as usize && state.invites.try_alert(now, Self::ALERT_COOLDOWN) {
+            findings.push(TollFraudFinding::AccountInviteFlood {
+                account: account.to_string(),
+                count: invite_count,
+                window: rate_window,
+            });
+        }
+
+        if !self.config.enabled {
+            return findings;
+        }
+
+        let burst_threshold = self.config.burst_threshold.max(1) as usize;
+        match class {
+            DestinationClass::Premium => {
+                let count = state.premium.record(now, burst_window);
+                if count >= burst_threshold && state.premium.try_alert(now, Self::ALERT_COOLDOWN) {
+                    findings.push(TollFraudFinding::PremiumBurst {
+                        account: account.to_string(),
+                        count,
+                        window: burst_window,
+                        last_number: dialed_number.to_string(),
+                    });
+                }
+            }
+            DestinationClass::International => {
+                let count = state.international.record(now, burst_window);
+                if count >= burst_threshold && state.international.try_alert(now, Self::ALERT_COOLDOWN) {
+                    findings.push(TollFraudFinding::InternationalBurst {
+                        account: account.to_string(),
+                        count,
+                        window: burst_window,
+                        last_number: dialed_number.to_string(),
+                    });
+                }
+            }
+            DestinationClass::Domestic => {}
+        }
+
+        // Compare against the learned hour-of-day profile before folding this call in
+        let learned_calls: u32 = state.hourly_calls.iter().sum();
+        let hour = (hour % 24) as usize;
+        if learned_calls >= self.config.baseline_min_calls {
+            let share = state.hourly_calls[hour] as f32 / learned_calls as f32;
+            let cooled_down = state.last_hour_alert
+                .is_none_or(|last| now.duration_since(last) >= Self::ALERT_COOLDOWN);
+            if share < self.config.unusual_hour_ratio && cooled_down {
+                state.last_hour_alert = Some(now);
+                findings.push(TollFraudFinding::UnusualHour {
+                    account: account.to_string(),
+                    hour: hour as u32,
+                    share,
+                    learned_calls,
+                });
+            }
+        }
+        state.hourly_calls[hour] = state.hourly_calls[hour].saturating_add(1);
+
+        if self.pending_invites.len() < Self::MAX_PENDING_INVITES {
+            self.pending_invites.insert(call_id.to_string(), PendingInvite {
+                account: account.to_string(),
+                sent_at: now,
+            });
+        }
+
+        findings
+    }
+
+    /// Final response to an INVITE: a 2xx establishes the dialog.
+    pub fn on_invite_response(&mut self, call_id: &str, status_code: u16, now: Instant) -> Vec<TollFraudFinding> {
+        if status_code < 200 {
+            return Vec::new();
+        }
+
+        let pending = match self.pending_invites.remove(call_id) {
+            Some(pending) => pending,
+            None => return Vec::new(),
+        };
+
+        if status_code >= 300 || !self.config.enabled {
+            return Vec::new();
+        }
+
+        let limit = self.call_limit(&pending.account);
+        let state = self.accounts.entry(pending.account.clone()).or_default();
+        state.active_dialogs.insert(call_id.to_string(), now);
+
+        let active = state.active_dialogs.len();
+        let cooled_down = state.last_parallel_alert
+            .is_none_or(|last| now.duration_since(last) >= Self::ALERT_COOLDOWN);
+        if active > limit as usize && cooled_down {
+            state.last_parallel_alert = Some(now);
+            return vec![TollFraudFinding::ParallelCalls {
+                account: pending.account,
+                active,
+                limit,
+            }];
+        }
+
+        Vec::new()
+    }
+
+    /// BYE or CANCEL for a Call-ID tears the dialog down.
+    pub fn on_dialog_end(&mut self, call_id: &str) {
+        if let Some(pending) = self.pending_invites.remove(call_id) {
+            if let Some(state) = self.accounts.get_mut(&pending.account) {
+                state.active_dialogs.remove(call_id);
+            }
+            return;
+        }
+
+        for state in self.accounts.values_mut() {
+            if state.active_dialogs.remove(call_id).is_some() {
+                break;
+            }
+        }
+    }
+
+    pub fn active_calls(&self) -> usize {
+        self.accounts.values().map(|state| state.active_dialogs.len()).sum()
+    }
+
+    pub fn classify_destination(&self, dialed_number: &str) -> DestinationClass {
+        let number = normalize_number(dialed_number);
+        if number.is_empty() {
+            return DestinationClass::Domestic;
+        }
+
+        if self.config.premium_prefixes.iter().any(|prefix| number.starts_with(prefix.as_str())) {
+            DestinationClass::Premium
+        } else if self.config.international_prefixes.iter().any(|prefix| number.starts_with(prefix.as_str())) {
+            DestinationClass::International
+        } else {
+            DestinationClass::Domestic
+        }
+    }
+
+    fn call_limit(&self, account: &str) -> u32 {
+        self.config.account_call_limits
+            .get(account)
+            .copied()
+            .unwrap_or(self.config.max_parallel_calls)
+    }
+
+    pub fn cleanup(&mut self, now: Instant) {
+        self.pending_invites.retain(|_, pending| now.duration_since(pending.sent_at) < Self::INVITE_TIMEOUT);
+
+        for state in self.accounts.values_mut() {
+            state.active_dialogs.retain(|_, started| now.duration_since(*started) < Self::MAX_DIALOG_DURATION);
+        }
+
+        // Keep idle accounts around long enough to preserve their hour-of-day baseline
+        self.accounts.retain(|_, state| {
+            !state.active_dialogs.is_empty()
+                || state.last_seen.is_some_and(|seen| now.duration_since(seen) < Self::ACCOUNT_RETENTION)
+        });
+    }
+}
+
+/// Strips visual separators so "+44 (20) 7946-0000" and "+442079460000" compare equal.
+pub fn normalize_number(number: &str) -> String {
+    number.chars()
+        .filter(|c| c.is_ascii_digit() || *c == '+' || *c == '*' || *c == '#')
+        .collect()
+}
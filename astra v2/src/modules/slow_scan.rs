@@ -0,0 +1,285 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+use chrono::Utc;
+
+use crate::core::config::{Config, SlowScanConfig};
+use crate::core::netinfo;
+use crate::{SecurityEvent, ThreatLevel};
+
+// 512 hashed port bits per epoch; linear counting stays accurate to a few hundred ports
+const BITMAP_WORDS: usize = 8;
+const BITMAP_BITS: usize = BITMAP_WORDS * 64;
+// Each horizon slides in quarters, so it always covers at least 3/4 of its length
+const EPOCHS: usize = 4;
+// Share of the tracked sources dropped at once when the table is full
+const EVICTION_FRACTION: usize = 10;
+
+#[derive(Debug, Clone, Copy, Default)]
+struct PortBitmap([u64; BITMAP_WORDS]);
+
+impl PortBitmap {
+    fn insert(&mut self, port: u16) {
+        // Integer mixer so sequential and strided sweeps spread over the whole bitmap
+        let mut hash = port as u32;
+        hash ^= hash >> 16;
+        hash = hash.wrapping_mul(0x7feb_352d);
+        hash ^= hash >> 15;
+        hash = hash.wrapping_mul(0x846c_a68b);
+        hash ^= hash >> 16;
+        let bit = (hash >> (32 - BITMAP_BITS.trailing_zeros())) as usize;
+        self.0[bit / 64] |= 1 << (bit % 64);
+    }
+
+    fn union(&mut self, other: &PortBitmap) {
+        for (word, other) in self.0.iter_mut().zip(other.0.iter()) {
+            *word |= other;
+        }
+    }
+
+    /// Linear counting estimate of the distinct ports inserted.
+    fn estimate(&self) -> usize {
+        let zeros = BITMAP_BITS - self.0.iter().map(|word| word.count_ones() as usize).sum::<usize>();
+        let m = BITMAP_BITS as f64;
+        (m * (m / zeros.max(1) as f64).ln()).round() as usize
+    }
+}
+
+struct HorizonWindow {
+    epochs: [PortBitmap; EPOCHS],
+    current: usize,
+    epoch_start: Instant,
+    reported_at: Option<Instant>,
+}
+
+impl HorizonWindow {
+    fn new(now: Instant) -> Self {
+        HorizonWindow {
+            epochs: [PortBitmap::default(); EPOCHS],
+            current: 0,
+            epoch_start: now,
+            reported_at: None,
+        }
+    }
+
+    fn advance(&mut self, epoch_length: Duration, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.epoch_start);
+        let steps = (elapsed.as_secs() / epoch_length.as_secs().max(1)) as usize;
+        if steps == 0 {
+            return;
+        }
+        for _ in 0..steps.min(EPOCHS) {
+            self.current = (self.current + 1) % EPOCHS;
+            self.epochs[self.current] = PortBitmap::default();
+        }
+        self.epoch_start += epoch_length * steps as u32;
+    }
+
+    fn distinct_ports(&self) -> usize {
+        let mut seen = PortBitmap::default();
+        for epoch in &self.epochs {
+            seen.union(epoch);
+        }
+        seen.estimate()
+    }
+}
+
+struct SourceHistory {
+    horizons: Vec<HorizonWindow>,
+    last_seen: Instant,
+}
+
+/// A source that touched too many ports within one horizon.
+#[derive(Debug, Clone)]
+pub struct SlowScan {
+    pub source: IpAddr,
+    pub horizon: Duration,
+    pub ports: usize,
+    pub confidence: f32,
+}
+
+impl SlowScan {
+    pub fn to_event(&self, protocol: &str) -> SecurityEvent {
+        SecurityEvent {
+            timestamp: Utc::now(),
+            source_ip: self.source,
+            event_type: "SLOW_SCAN_DETECTED".to_string(),
+            threat_level: ThreatLevel {
+                level: 6,
+                confidence: self.confidence,
+                category: "RECONNAISSANCE".to_string(),
+            },
+            details: format!(
+                "{} LOW_AND_SLOW_SCAN - ~{} distinct ports probed within {}",
+                protocol, self.ports, describe_horizon(self.horizon)
+            ),
+            action_taken: "MONITORED".to_string(),
+            evidence_id: None,
+            related_ips: Vec::new(),
+        }
+    }
+}
+
+/// Long-horizon companion to the per-source detectors, whose profiles only
+/// live for seconds to minutes. Each source keeps a few hashed port bitmaps
+/// per horizon instead of its probes, so a scan trickling one port every few
+/// minutes is still visible after days at a fixed cost of well under a
+/// kilobyte per source.
+pub struct SlowScanTracker {
+    config: SlowScanConfig,
+    ipv6_prefix_len: u8,
+    longest_horizon: Duration,
+    sources: HashMap<IpAddr, SourceHistory>,
+}
+
+impl SlowScanTracker {
+    pub fn new(config: &Config) -> Self {
+        let slow_config = config.modules.slow_scan.clone();
+        let longest_horizon = slow_config.horizons.iter()
+            .map(|horizon| Duration::from_secs(horizon.duration))
+            .max()
+            .unwrap_or_default();
+
+        SlowScanTracker {
+            config: slow_config,
+            ipv6_prefix_len: config.firewall.ipv6_block_prefix,
+            longest_horizon,
+            sources: HashMap::new(),
+        }
+    }
+
+    /// Records a probe and returns any horizon the source has just crossed.
+    /// A horizon reports a source at most once per its own length.
+    pub fn record(&mut self, source: IpAddr, port: u16, now: Instant) -> Vec<SlowScan> {
+        if !self.config.enabled || self.config.horizons.is_empty() {
+            return Vec::new();
+        }
+
+        // IPv6 scanners rotate through their prefix; track it like the firewall blocks it
+        let key = netinfo::aggregate_address(source, self.ipv6_prefix_len);
+        if !self.sources.contains_key(&key) && self.sources.len() >= self.config.max_sources.max(1) {
+            self.evict();
+        }
+        let horizon_count = self.config.horizons.len();
+        let history = self.sources.entry(key).or_insert_with(|| SourceHistory {
+            horizons: (0..horizon_count).map(|_| HorizonWindow::new(now)).collect(),
+            last_seen: now,
+        });
+        history.last_seen = now;
+
+        let mut scans = Vec::new();
+        for (horizon, window) in self.config.horizons.iter().zip(history.horizons.iter_mut()) {
+            let length = Duration::from_secs(horizon.duration.max(EPOCHS as u64));
+            window.advance(length / EPOCHS as u32, now);
+            window.epochs[window.current].insert(port);
+
+            if window.reported_at.is_some_and(|reported_at| now.duration_since(reported_at) < length) {
+                continue;
+            }
+            let ports = window.distinct_ports();
+            if ports >= horizon.port_threshold.max(2) {
+                window.reported_at = Some(now);
+                scans.push(SlowScan {
+                    source,
+                    horizon: length,
+                    ports,
+                    confidence: horizon.confidence.clamp(0.0, 1.0),
+                });
+            }
+        }
+        scans
+    }
+
+    /// Forgets sources that have been quiet for longer than every horizon.
+    pub fn expire(&mut self, now: Instant) {
+        let longest = self.longest_horizon;
+        self.sources.retain(|_, history| now.duration_since(history.last_seen) < longest);
+    }
+
+    // Drops the least recently seen tenth of the table in one pass, so a
+    // flood of new sources does not rescan it for every packet.
+    fn evict(&mut self) {
+        let mut last_seen: Vec<(Instant, IpAddr)> = self.sources.iter()
+            .map(|(key, history)| (history.last_seen, *key))
+            .collect();
+        last_seen.sort_unstable();
+        let count = (last_seen.len() / EVICTION_FRACTION).max(1);
+        for (_, key) in last_seen.into_iter().take(count) {
+            self.sources.remove(&key);
+        }
+    }
+}
+
+fn describe_horizon(horizon: Duration) -> String {
+    let secs = horizon.as_secs();
+    if secs.is_multiple_of(86400) && secs > 86400 {
+        format!("{}d", secs / 86400)
+    } else if secs.is_multiple_of(3600) {
+        format!("{}h", secs / 3600)
+    } else if secs.is_multiple_of(60) {
+        format!("{}m", secs / 60)
+    } else {
+        format!("{}s", secs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::config::SlowScanHorizon;
+
+    const SOURCE: IpAddr = IpAddr::V4(std::net::Ipv4Addr::new(203, 0, 113, 5));
+
+    fn tracker(duration: u64, port_threshold: usize) -> SlowScanTracker {
+        let mut config = Config::default();
+        config.modules.slow_scan.horizons = vec![SlowScanHorizon { duration, port_threshold, confidence: 0.8 }];
+        SlowScanTracker::new(&config)
+    }
+
+    #[test]
+    fn bitmap_estimates_distinct_ports() {
+        for count in [10u16, 100, 300] {
+            let mut bitmap = PortBitmap::default();
+            for port in 0..count {
+                // Repeats must not count twice
+                bitmap.insert(1000 + port);
+                bitmap.insert(1000 + port);
+            }
+            let estimate = bitmap.estimate() as f64;
+            assert!((estimate - count as f64).abs() <= count as f64 * 0.1, "{} ports estimated as {}", count, estimate);
+        }
+    }
+
+    #[test]
+    fn reports_a_trickle_once_it_crosses_the_horizon_threshold() {
+        let mut tracker = tracker(3600, 15);
+        let start = Instant::now();
+        let minutes = |n: u64| start + Duration::from_secs(n * 60);
+
+        // One port every three minutes stays under the threshold until the 15th
+        for port in 0..14u16 {
+            assert!(tracker.record(SOURCE, 2000 + port, minutes(port as u64 * 3)).is_empty());
+        }
+        let scans = tracker.record(SOURCE, 2014, minutes(42));
+        assert_eq!(scans.len(), 1);
+        assert!(scans[0].ports >= 15);
+        assert_eq!(scans[0].horizon, Duration::from_secs(3600));
+
+        // Reported once per horizon, however much more it probes
+        assert!(tracker.record(SOURCE, 2015, minutes(45)).is_empty());
+    }
+
+    #[test]
+    fn probes_older_than_the_horizon_stop_counting() {
+        let mut tracker = tracker(3600, 15);
+        let start = Instant::now();
+        for port in 0..10u16 {
+            tracker.record(SOURCE, 3000 + port, start);
+        }
+        // Two hours on, the first ten ports have rotated out of every epoch
+        let later = start + Duration::from_secs(7200);
+        for port in 10..24u16 {
+            assert!(tracker.record(SOURCE, 3000 + port, later).is_empty());
+        }
+    }
+}
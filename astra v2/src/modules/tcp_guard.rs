@@ -1,164 +1,181 @@
-use std::collections::HashMap;
-use std::net::{IpAddr, SocketAddr};
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
 use std::sync::Arc;
 use std::time::{Duration, Instant};
-use chrono::{DateTime, Utc};
-use tokio::time::sleep;
-use pnet::datalink;
-use pnet::packet::ethernet::{EtherTypes, EthernetPacket};
-use pnet::packet::ip::IpNextHeaderProtocols;
-use pnet::packet::ipv4::Ipv4Packet;
-use pnet::packet::tcp::{TcpFlags, TcpPacket};
-use pnet::packet::Packet;
-
-use crate::core::{config::Config, logger::Logger};
+use chrono::Utc;
+use pnet::packet::tcp::TcpFlags;
+
+use crate::core::{event_bus::EventBus, afpacket::BpfProgram, capture::{PacketFilter, PacketSummary, TransportHeader}, config::{Config, TcpGuardConfig}, logger::Logger, netinfo};
+use crate::modules::{scan_correlator::ScanCorrelator, slow_scan::SlowScanTracker};
 use crate::{SecurityEvent, ThreatLevel};
 
 #[derive(Debug, Clone)]
 struct ConnectionAttempt {
     timestamp: Instant,
     port: u16,
-    flags: u8,
+    flags: u16,
 }
 
 #[derive(Debug)]
 struct ScanProfile {
     first_attempt: Instant,
     attempts: Vec<ConnectionAttempt>,
-    unique_ports: std::collections::HashSet<u16>,
+    unique_ports: HashSet<u16>,
     syn_flood_count: u32,
     last_syn_time: Instant,
     threat_score: f32,
 }
 
+/// Thresholds in effect after applying the sensitivity level to TcpGuardConfig.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct DetectionThresholds {
+    scan_ports: usize,
+    time_window: Duration,
+    syn_flood: u32,
+}
+
+impl DetectionThresholds {
+    /// Low sensitivity doubles the counts, high sensitivity halves them and
+    /// doubles the window so slower scans still fall inside it.
+    fn derive(config: &TcpGuardConfig, sensitivity_level: u8) -> Self {
+        let scan_ports = config.scan_threshold.max(1);
+        let syn_flood = config.syn_flood_threshold.max(1);
+        let time_window = Duration::from_secs(config.time_window.max(1));
+
+        match sensitivity_level {
+            1..=3 => DetectionThresholds {
+                scan_ports: scan_ports.saturating_mul(2),
+                time_window,
+                syn_flood: syn_flood.saturating_mul(2),
+            },
+            4..=6 => DetectionThresholds {
+                scan_ports,
+                time_window,
+                syn_flood,
+            },
+            _ => DetectionThresholds {
+                scan_ports: (scan_ports / 2).max(2),
+                time_window: time_window * 2,
+                syn_flood: (syn_flood / 2).max(1),
+            },
+        }
+    }
+}
+
 pub struct TcpGuard {
     config: Arc<Config>,
     logger: Arc<Logger>,
     scan_profiles: HashMap<IpAddr, ScanProfile>,
     sensitivity_level: u8,
-    stealth_ports: Vec<u16>,
+    thresholds: DetectionThresholds,
+    stealth_ports: HashSet<u16>,
+    #[allow(dead_code)]
     honeypot_responses: bool,
+    correlator: ScanCorrelator,
+    slow_scans: SlowScanTracker,
+    events: Arc<EventBus>,
 }
 
 impl TcpGuard {
-    pub fn new(config: &Arc<Config>, logger: Arc<Logger>) -> Result<Self, Box<dyn std::error::Error>> {
-        let stealth_ports = vec![
-            22, 23, 25, 53, 80, 110, 143, 443, 993, 995,  // Common targets
-            135, 139, 445, 1433, 3389,                     // Windows services
-            21, 69, 161, 162, 514, 873,                    // Unix services
-            5060, 5061,                                     // SIP ports (monitored separately)
-        ];
+    const SYN_FLOOD_WINDOW: Duration = Duration::from_secs(5);
+
+    pub fn new(config: &Arc<Config>, logger: Arc<Logger>, events: Arc<EventBus>) -> Result<Self, Box<dyn std::error::Error>> {
+        let guard_config = &config.modules.tcp_guard;
+        let stealth_ports: HashSet<u16> = guard_config.stealth_ports.iter().cloned().collect();
+        let sensitivity_level = guard_config.sensitivity.clamp(1, 10);
+        let thresholds = DetectionThresholds::derive(guard_config, sensitivity_level);
 
         logger.log_info("TCP Guardian initialized with advanced scan detection")?;
         logger.log_info(&format!("Monitoring {} stealth ports", stealth_ports.len()))?;
+        logger.log_info(&format!(
+            "TCP Guardian thresholds: {} ports / {}s scan window, {} SYN / {}s",
+            thresholds.scan_ports, thresholds.time_window.as_secs(), thresholds.syn_flood, Self::SYN_FLOOD_WINDOW.as_secs()
+        ))?;
 
         Ok(TcpGuard {
             config: config.clone(),
             logger,
             scan_profiles: HashMap::new(),
-            sensitivity_level: 5,
+            sensitivity_level,
+            thresholds,
             stealth_ports,
-            honeypot_responses: true,
+            honeypot_responses: guard_config.honeypot_responses,
+            correlator: ScanCorrelator::new(&config.modules.scan_correlation, &config.network.netinfo_file)?,
+            slow_scans: SlowScanTracker::new(config),
+            events,
         })
     }
 
-    pub async fn scan_network(&mut self) -> Result<(), Box<dyn std::error::Error>> {
-        // Get network interfaces
-        let interfaces = datalink::interfaces();
-        
-        for interface in interfaces {
-            if interface.is_up() && !interface.is_loopback() {
-                self.monitor_interface(&interface).await?;
-            }
-        }
-        
-        // Cleanup old profiles every scan cycle
-        self.cleanup_old_profiles();
-        
-        Ok(())
-    }
-
-    async fn monitor_interface(&mut self, interface: &pnet::datalink::NetworkInterface) -> Result<(), Box<dyn std::error::Error>> {
-        use pnet::datalink::Channel::Ethernet;
-        
-        let config = pnet::datalink::Config {
-            write_buffer_size: 4096,
-            read_buffer_size: 4096,
-            read_timeout: Some(Duration::from_millis(100)),
-            write_timeout: Some(Duration::from_millis(100)),
-            channel_type: pnet::datalink::ChannelType::Layer2,
-            bpf_fd_attempts: 1000,
-            linux_fanout: None,
-            promiscuous: false, // Stealth mode
-        };
-
-        let (_, mut rx) = match pnet::datalink::channel(interface, config) {
-            Ok(Ethernet(tx, rx)) => (tx, rx),
-            Ok(_) => return Ok(()),
-            Err(e) => {
-                self.logger.log_error(&format!("Failed to create channel for {}: {}", interface.name, e))?;
+    /// Handles one packet from the capture workers; anything but TCP is ignored.
+    pub async fn process_packet(&mut self, packet: &PacketSummary) -> Result<(), Box<dyn std::error::Error>> {
+        if let TransportHeader::Tcp { dest_port, flags, .. } = packet.transport {
+            // Skip internal traffic
+            if self.is_internal_ip(packet.source_ip) {
                 return Ok(());
             }
-        };
 
-        loop {
-            match rx.next() {
-                Ok(packet) => {
-                    if let Some(ethernet) = EthernetPacket::new(packet) {
-                        self.process_ethernet_packet(&ethernet).await?;
-                    }
+            // Connection openers and flag probes; replies and teardown say nothing about a sweep
+            let probe = flags & (TcpFlags::SYN | TcpFlags::ACK) == TcpFlags::SYN
+                || flags & (TcpFlags::SYN | TcpFlags::ACK | TcpFlags::RST) == 0;
+            if probe {
+                self.correlator.record(packet.dest_ip, packet.source_ip, dest_port, packet.captured_at);
+                for scan in self.slow_scans.record(packet.source_ip, dest_port, packet.captured_at) {
+                    self.logger.log_warning(&format!(
+                        "🐢 SLOW TCP SCAN: {} probed ~{} ports over {}s", scan.source, scan.ports, scan.horizon.as_secs()
+                    ))?;
+                    self.events.publish_or_log(&self.logger, scan.to_event("TCP"))?;
                 }
-                Err(_) => break, // Timeout, continue monitoring
             }
-        }
 
+            self.analyze_tcp_packet(packet.source_ip, dest_port, flags, packet.captured_at).await?;
+        }
         Ok(())
     }
 
-    async fn process_ethernet_packet(&mut self, ethernet: &EthernetPacket) -> Result<(), Box<dyn std::error::Error>> {
-        match ethernet.get_ethertype() {
-            EtherTypes::Ipv4 => {
-                if let Some(ipv4) = Ipv4Packet::new(ethernet.payload()) {
-                    self.process_ipv4_packet(&ipv4).await?;
-                }
+    /// Same selection as the kernel filter: SYN, FIN, RST and NULL probes.
+    pub fn packet_filter() -> PacketFilter {
+        Box::new(|packet| match packet.transport {
+            TransportHeader::Tcp { flags, .. } => {
+                flags & (TcpFlags::FIN | TcpFlags::SYN | TcpFlags::RST) != 0 || flags & 0x3f == 0
             }
-            _ => {} // Only monitoring IPv4 for now
-        }
-        Ok(())
+            _ => false,
+        })
     }
 
-    async fn process_ipv4_packet(&mut self, ipv4: &Ipv4Packet) -> Result<(), Box<dyn std::error::Error>> {
-        if ipv4.get_next_level_protocol() == IpNextHeaderProtocols::Tcp {
-            if let Some(tcp) = TcpPacket::new(ipv4.payload()) {
-                let source_ip = IpAddr::V4(ipv4.get_source());
-                let dest_port = tcp.get_destination();
-                let flags = tcp.get_flags();
-                
-                // Skip internal traffic
-                if self.is_internal_ip(source_ip) {
-                    return Ok(());
-                }
+    /// Only connection control segments reach userspace on AF_PACKET rings.
+    pub fn kernel_filter(config: &Config) -> BpfProgram {
+        BpfProgram::tcp_control_segments(config.network.ipv6_support)
+    }
 
-                self.analyze_tcp_packet(source_ip, dest_port, flags).await?;
-            }
+    pub fn perform_maintenance(&mut self, now: Instant) -> Result<(), Box<dyn std::error::Error>> {
+        self.cleanup_old_profiles(now);
+        self.slow_scans.expire(now);
+
+        for campaign in self.correlator.evaluate(now) {
+            self.logger.log_critical(&format!(
+                "🚨 DISTRIBUTED TCP SCAN: {} sources ({}) sweeping {}", campaign.sources.len(), campaign.scope, campaign.target
+            ))?;
+            self.events.publish_or_log(&self.logger, campaign.to_event("TCP", self.correlator.window()))?;
         }
+
         Ok(())
     }
 
-    async fn analyze_tcp_packet(&mut self, source_ip: IpAddr, dest_port: u16, flags: u8) -> Result<(), Box<dyn std::error::Error>> {
-        let now = Instant::now();
-        
+    async fn analyze_tcp_packet(&mut self, source_ip: IpAddr, dest_port: u16, flags: u16, now: Instant) -> Result<(), Box<dyn std::error::Error>> {
+        let thresholds = self.thresholds;
+
         // Get or create scan profile
         let profile = self.scan_profiles.entry(source_ip).or_insert(ScanProfile {
             first_attempt: now,
             attempts: Vec::new(),
-            unique_ports: std::collections::HashSet::new(),
+            unique_ports: HashSet::new(),
             syn_flood_count: 0,
             last_syn_time: now,
             threat_score: 0.0,
         });
 
-        // Record connection attempt
+        // Record connection attempt, forgetting anything outside the scan window
+        profile.attempts.retain(|attempt| now.duration_since(attempt.timestamp) < thresholds.time_window);
         profile.attempts.push(ConnectionAttempt {
             timestamp: now,
             port: dest_port,
@@ -167,37 +184,41 @@ impl TcpGuard {
         profile.unique_ports.insert(dest_port);
 
         // SYN flood detection
+        let mut syn_flood = None;
         if flags & TcpFlags::SYN != 0 && flags & TcpFlags::ACK == 0 {
-            if now.duration_since(profile.last_syn_time) < Duration::from_secs(5) {
+            if now.duration_since(profile.last_syn_time) < Self::SYN_FLOOD_WINDOW {
                 profile.syn_flood_count += 1;
             } else {
                 profile.syn_flood_count = 1;
                 profile.last_syn_time = now;
             }
 
-            // SYN flood threshold
-            if profile.syn_flood_count > 10 {
-                self.trigger_syn_flood_alert(source_ip, profile.syn_flood_count).await?;
+            if profile.syn_flood_count > thresholds.syn_flood {
                 profile.threat_score += 0.8;
+                syn_flood = Some(profile.syn_flood_count);
             }
         }
 
         // Port scan detection - Advanced heuristics
-        self.detect_port_scanning(source_ip, profile).await?;
+        let scan = Self::detect_port_scanning(profile, &self.stealth_ports, thresholds, now);
+
+        if let Some(syn_count) = syn_flood {
+            self.trigger_syn_flood_alert(source_ip, syn_count).await?;
+        }
+        if let Some((scan_type, port_count, threat_score)) = scan {
+            self.trigger_scan_alert(source_ip, &scan_type, port_count, threat_score).await?;
+        }
 
         Ok(())
     }
 
-    async fn detect_port_scanning(&mut self, source_ip: IpAddr, profile: &mut ScanProfile) -> Result<(), Box<dyn std::error::Error>> {
-        let now = Instant::now();
-        let time_window = Duration::from_secs(10);
-        
-        // Count recent attempts
+    /// Returns the scan type, ports seen in the window and the updated threat score.
+    fn detect_port_scanning(profile: &mut ScanProfile, stealth_ports: &HashSet<u16>, thresholds: DetectionThresholds, now: Instant) -> Option<(String, usize, f32)> {
         let recent_attempts: Vec<_> = profile.attempts.iter()
-            .filter(|attempt| now.duration_since(attempt.timestamp) < time_window)
+            .filter(|attempt| now.duration_since(attempt.timestamp) < thresholds.time_window)
             .collect();
 
-        let recent_unique_ports: std::collections::HashSet<u16> = recent_attempts.iter()
+        let recent_unique_ports: HashSet<u16> = recent_attempts.iter()
             .map(|attempt| attempt.port)
             .collect();
 
@@ -206,29 +227,29 @@ impl TcpGuard {
         let mut scan_type = String::new();
         let mut threat_increase = 0.0;
 
-        // 1. Rapid port scanning (>2 ports in 10 seconds)
-        if recent_unique_ports.len() > 2 {
+        // 1. Rapid port scanning (scan_threshold ports within the window)
+        if recent_unique_ports.len() >= thresholds.scan_ports {
             scan_detected = true;
             scan_type = format!("RAPID_PORT_SCAN ({} ports)", recent_unique_ports.len());
             threat_increase = 0.6;
         }
 
         // 2. Sequential port scanning detection
-        if self.detect_sequential_scan(&recent_attempts) {
+        if Self::detect_sequential_scan(&recent_attempts, thresholds.scan_ports) {
             scan_detected = true;
             scan_type = "SEQUENTIAL_SCAN".to_string();
             threat_increase = 0.7;
         }
 
         // 3. Stealth scan detection (specific flag combinations)
-        if self.detect_stealth_scan(&recent_attempts) {
+        if Self::detect_stealth_scan(&recent_attempts, recent_unique_ports.len(), thresholds.scan_ports) {
             scan_detected = true;
             scan_type = "STEALTH_SCAN".to_string();
             threat_increase = 0.9;
         }
 
         // 4. Service enumeration detection
-        if self.detect_service_enumeration(&recent_unique_ports) {
+        if Self::detect_service_enumeration(&recent_unique_ports, stealth_ports, thresholds.scan_ports) {
             scan_detected = true;
             scan_type = "SERVICE_ENUMERATION".to_string();
             threat_increase = 0.5;
@@ -236,66 +257,62 @@ impl TcpGuard {
 
         if scan_detected {
             profile.threat_score += threat_increase;
-            self.trigger_scan_alert(source_ip, &scan_type, recent_unique_ports.len(), profile.threat_score).await?;
+            return Some((scan_type, recent_unique_ports.len(), profile.threat_score));
         }
 
-        Ok(())
+        None
     }
 
-    fn detect_sequential_scan(&self, attempts: &[&ConnectionAttempt]) -> bool {
-        if attempts.len() < 3 {
+    fn detect_sequential_scan(attempts: &[&ConnectionAttempt], run_length: usize) -> bool {
+        let run_length = run_length.max(2);
+        if attempts.len() < run_length {
             return false;
         }
 
         let mut ports: Vec<u16> = attempts.iter().map(|a| a.port).collect();
         ports.sort();
-        
+        ports.dedup();
+
         // Check for sequential ports (like 80, 81, 82 or 443, 444, 445)
         let mut sequential_count = 1;
         for i in 1..ports.len() {
             if ports[i] == ports[i-1] + 1 {
                 sequential_count += 1;
-                if sequential_count >= 3 {
+                if sequential_count >= run_length {
                     return true;
                 }
             } else {
                 sequential_count = 1;
             }
         }
-        
+
         false
     }
 
-    fn detect_stealth_scan(&self, attempts: &[&ConnectionAttempt]) -> bool {
-        for attempt in attempts {
+    /// Unusual flag combinations only count as a scan once they hit enough
+    /// ports; a lone SYN is just a connection attempt.
+    fn detect_stealth_scan(attempts: &[&ConnectionAttempt], unique_ports: usize, scan_ports: usize) -> bool {
+        if unique_ports < scan_ports {
+            return false;
+        }
+
+        let xmas = TcpFlags::FIN | TcpFlags::PSH | TcpFlags::URG;
+        attempts.iter().any(|attempt| {
             // SYN scan detection
-            if attempt.flags == TcpFlags::SYN {
-                return true;
-            }
+            attempt.flags == TcpFlags::SYN ||
             // FIN scan detection
-            if attempt.flags == TcpFlags::FIN {
-                return true;
-            }
-            // NULL scan detection  
-            if attempt.flags == 0 {
-                return true;
-            }
+            attempt.flags == TcpFlags::FIN ||
+            // NULL scan detection
+            attempt.flags == 0 ||
             // XMAS scan detection
-            if attempt.flags & (TcpFlags::FIN | TcpFlags::PSH | TcpFlags::URG) != 0 {
-                return true;
-            }
-        }
-        false
+            attempt.flags & xmas == xmas
+        })
     }
 
-    fn detect_service_enumeration(&self, ports: &std::collections::HashSet<u16>) -> bool {
-        // Common service ports that indicate enumeration
-        let service_ports: std::collections::HashSet<u16> = [
-            21, 22, 23, 25, 53, 80, 110, 135, 139, 143, 443, 445, 993, 995, 1433, 3389, 5060, 5061
-        ].iter().cloned().collect();
-
-        let enumerated_services = ports.intersection(&service_ports).count();
-        enumerated_services >= 3
+    fn detect_service_enumeration(ports: &HashSet<u16>, stealth_ports: &HashSet<u16>, scan_ports: usize) -> bool {
+        // Probes against the configured stealth ports indicate enumeration
+        let enumerated_services = ports.intersection(stealth_ports).count();
+        enumerated_services >= scan_ports
     }
 
     async fn trigger_scan_alert(&self, source_ip: IpAddr, scan_type: &str, port_count: usize, threat_score: f32) -> Result<(), Box<dyn std::error::Error>> {
@@ -310,9 +327,11 @@ impl TcpGuard {
             },
             details: format!("{} - {} ports scanned, threat_score: {:.2}", scan_type, port_count, threat_score),
             action_taken: "MONITORING_ENHANCED".to_string(),
+            evidence_id: None,
+            related_ips: Vec::new(),
         };
 
-        self.logger.log_security_event(&event)?;
+        self.events.publish_or_log(&self.logger, event)?;
         
         // If threat score is high enough, recommend immediate blocking
         if threat_score > 0.7 {
@@ -334,17 +353,18 @@ impl TcpGuard {
             },
             details: format!("SYN flood attack detected - {} SYN packets in 5 seconds", syn_count),
             action_taken: "RATE_LIMITING_APPLIED".to_string(),
+            evidence_id: None,
+            related_ips: Vec::new(),
         };
 
-        self.logger.log_security_event(&event)?;
+        self.events.publish_or_log(&self.logger, event)?;
         self.logger.log_critical(&format!("🚨 SYN FLOOD ATTACK: {} sent {} SYN packets - DEFENSIVE MEASURES ACTIVATED", source_ip, syn_count))?;
 
         Ok(())
     }
 
-    fn cleanup_old_profiles(&mut self) {
-        let now = Instant::now();
-        let retention_time = Duration::from_secs(300); // 5 minutes
+    fn cleanup_old_profiles(&mut self, now: Instant) {
+        let retention_time = Duration::from_secs(300).max(self.thresholds.time_window); // 5 minutes
 
         self.scan_profiles.retain(|_, profile| {
             now.duration_since(profile.first_attempt) < retention_time
@@ -352,41 +372,39 @@ impl TcpGuard {
     }
 
     fn is_internal_ip(&self, ip: IpAddr) -> bool {
-        match ip {
-            IpAddr::V4(ipv4) => {
-                let octets = ipv4.octets();
-                // RFC 1918 private networks
-                matches!(octets[0], 10) ||
-                (octets[0] == 172 && (16..=31).contains(&octets[1])) ||
-                (octets[0] == 192 && octets[1] == 168) ||
-                // Loopback
-                octets[0] == 127 ||
-                // Link-local
-                (octets[0] == 169 && octets[1] == 254)
-            }
-            IpAddr::V6(_) => false, // Skip IPv6 for now
-        }
+        netinfo::is_internal_address(ip)
     }
 
     pub fn set_sensitivity_level(&mut self, level: u8) -> Result<(), Box<dyn std::error::Error>> {
-        if level > 10 {
+        if !(1..=10).contains(&level) {
             return Err("Sensitivity level must be between 1-10".into());
         }
-        
+
+        // Called on every calibration tick, so only log actual changes
+        if self.sensitivity_level == level {
+            return Ok(());
+        }
+
         self.sensitivity_level = level;
-        self.logger.log_info(&format!("TCP Guardian sensitivity set to level {}", level))?;
-        
+
         // Adjust detection thresholds based on sensitivity
-        match level {
-            1..=3 => { /* Low sensitivity - higher thresholds */ }
-            4..=6 => { /* Normal sensitivity */ }
-            7..=10 => { /* High sensitivity - lower thresholds */ }
-            _ => {}
-        }
-        
+        self.thresholds = DetectionThresholds::derive(&self.config.modules.tcp_guard, level);
+        let profile = match level {
+            1..=3 => "low sensitivity - higher thresholds",
+            4..=6 => "normal sensitivity",
+            _ => "high sensitivity - lower thresholds",
+        };
+
+        self.logger.log_info(&format!(
+            "TCP Guardian sensitivity set to level {} ({}): {} ports / {}s, {} SYN / {}s",
+            level, profile, self.thresholds.scan_ports, self.thresholds.time_window.as_secs(),
+            self.thresholds.syn_flood, Self::SYN_FLOOD_WINDOW.as_secs()
+        ))?;
+
         Ok(())
     }
 
+    #[allow(dead_code)]
     pub async fn deploy_honeypot_response(&mut self, source_ip: IpAddr, target_port: u16) -> Result<(), Box<dyn std::error::Error>> {
         if !self.honeypot_responses {
             return Ok(());
@@ -405,8 +423,16 @@ impl TcpGuard {
                 // Could implement fake web server response
             }
             5060 | 5061 => {
-                // SIP honeypot (handled by SIP Shield)
-                self.logger.log_info(&format!("Coordinating SIP honeypot response for {}", source_ip))?;
+                // SIP honeypot runs as its own listener (modules::sip_honeypot)
+                let honeypot = &self.config.modules.sip_honeypot;
+                if honeypot.enabled {
+                    self.logger.log_info(&format!(
+                        "SIP probe from {} - SIP honeypot answering on {}:{}",
+                        source_ip, honeypot.bind_address, honeypot.port
+                    ))?;
+                } else {
+                    self.logger.log_info(&format!("SIP probe from {} - SIP honeypot disabled, left to SIP Shield", source_ip))?;
+                }
             }
             _ => {
                 // Generic honeypot
@@ -425,7 +451,7 @@ impl TcpGuard {
             .filter(|p| p.threat_score > 0.7)
             .count() as u32;
         let active_scanners = self.scan_profiles.values()
-            .filter(|p| p.unique_ports.len() > 2)
+            .filter(|p| p.unique_ports.len() >= self.thresholds.scan_ports)
             .count() as u32;
 
         stats.insert("total_monitored_ips".to_string(), total_profiles);
@@ -436,6 +462,7 @@ impl TcpGuard {
         stats
     }
 
+    #[allow(dead_code)]
     pub async fn perform_counter_reconnaissance(&mut self, source_ip: IpAddr) -> Result<(), Box<dyn std::error::Error>> {
         // Passive counter-reconnaissance - gather intel on the attacker
         self.logger.log_info(&format!("Initiating passive counter-reconnaissance on {}", source_ip))?;
@@ -484,4 +511,74 @@ impl TcpGuard {
         
         techniques
     }
-} 
\ No newline at end of file
+} 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn guard_config(scan_threshold: usize, time_window: u64, syn_flood_threshold: u32) -> TcpGuardConfig {
+        let mut config = Config::default().modules.tcp_guard;
+        config.scan_threshold = scan_threshold;
+        config.time_window = time_window;
+        config.syn_flood_threshold = syn_flood_threshold;
+        config
+    }
+
+    fn profile_at(now: Instant) -> ScanProfile {
+        ScanProfile {
+            first_attempt: now,
+            attempts: Vec::new(),
+            unique_ports: HashSet::new(),
+            syn_flood_count: 0,
+            last_syn_time: now,
+            threat_score: 0.0,
+        }
+    }
+
+    #[test]
+    fn sensitivity_scales_the_configured_thresholds() {
+        let config = guard_config(6, 10, 40);
+        let normal = DetectionThresholds::derive(&config, 5);
+        assert_eq!(normal, DetectionThresholds { scan_ports: 6, time_window: Duration::from_secs(10), syn_flood: 40 });
+
+        let low = DetectionThresholds::derive(&config, 2);
+        assert_eq!((low.scan_ports, low.syn_flood, low.time_window), (12, 80, Duration::from_secs(10)));
+
+        let high = DetectionThresholds::derive(&config, 9);
+        assert_eq!((high.scan_ports, high.syn_flood, high.time_window), (3, 20, Duration::from_secs(20)));
+
+        // Halving never drops below a meaningful count
+        let tight = DetectionThresholds::derive(&guard_config(1, 1, 1), 10);
+        assert_eq!((tight.scan_ports, tight.syn_flood), (2, 1));
+    }
+
+    #[test]
+    fn port_scans_need_the_threshold_within_the_window() {
+        let thresholds = DetectionThresholds::derive(&guard_config(4, 10, 40), 5);
+        let stealth_ports = HashSet::new();
+        let start = Instant::now();
+        let mut profile = profile_at(start);
+        let attempt = |port, timestamp| ConnectionAttempt { timestamp, port, flags: TcpFlags::SYN | TcpFlags::ACK };
+
+        // Spaced ports, so only the rapid scan rule can fire
+        for port in [1000, 2000, 3000] {
+            profile.attempts.push(attempt(port, start));
+        }
+        assert!(TcpGuard::detect_port_scanning(&mut profile, &stealth_ports, thresholds, start).is_none());
+
+        let now = start + Duration::from_secs(1);
+        profile.attempts.push(attempt(4000, now));
+        let (scan_type, ports, _) = TcpGuard::detect_port_scanning(&mut profile, &stealth_ports, thresholds, now).unwrap();
+        assert_eq!(ports, 4);
+        assert!(scan_type.starts_with("RAPID_PORT_SCAN"));
+
+        // The same ports spread past the window are not a scan
+        let mut profile = profile_at(start);
+        for port in [1000, 2000, 3000] {
+            profile.attempts.push(attempt(port, start));
+        }
+        let late = start + thresholds.time_window;
+        profile.attempts.push(attempt(4000, late));
+        assert!(TcpGuard::detect_port_scanning(&mut profile, &stealth_ports, thresholds, late).is_none());
+    }
+}
@@ -0,0 +1,233 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use chrono::{DateTime, TimeZone, Utc};
+
+use crate::core::{capture::PacketSummary, config::Config, event_bus::EventBus, logger::Logger};
+use crate::modules::{probe_guard::ProbeGuard, sip_shield::SipShield, tcp_guard::TcpGuard};
+use crate::SecurityEvent;
+
+// pcap link-layer header types
+const LINKTYPE_ETHERNET: i32 = 1;
+const LINKTYPE_RAW: i32 = 101;
+const LINKTYPE_LINUX_SLL: i32 = 113;
+const LINKTYPE_IPV4: i32 = 228;
+const LINKTYPE_IPV6: i32 = 229;
+
+const MAINTENANCE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Feeds a capture file through TcpGuard, SipShield and ProbeGuard offline. Packet
+/// timestamps drive every detector clock, events are printed instead of
+/// acted on, and the firewall is never touched. Events go to `output` when
+/// given, otherwise to stdout.
+pub async fn run_replay(path: &Path, json_output: bool, output: Option<&Path>) -> Result<(), Box<dyn std::error::Error>> {
+    let mut writer: Box<dyn Write> = match output {
+        Some(output) => Box::new(BufWriter::new(File::create(output)?)),
+        None => Box::new(io::stdout()),
+    };
+
+    let mut config = Config::load()?;
+    // Keep replay noise out of the production logs
+    let log_file = std::env::temp_dir().join("astra_replay.log");
+    config.logging.log_file = log_file.to_string_lossy().to_string();
+    config.logging.audit_trail = false;
+    let config = Arc::new(config);
+
+    let mut logger = Logger::new(&config)?;
+    logger.set_console_output(false);
+    let logger = Arc::new(logger);
+
+    let events = Arc::new(EventBus::new(&config));
+    let mut event_receiver = events.subscribe("replay");
+    let mut tcp_guard = TcpGuard::new(&config, logger.clone(), events.clone())?;
+    let mut sip_shield = SipShield::new(&config, logger.clone(), events.clone())?;
+    let mut probe_guard = ProbeGuard::new(&config, logger.clone(), events)?;
+    let tcp_filter = TcpGuard::packet_filter();
+    let sip_filter = SipShield::packet_filter(&config);
+    let probe_filter = ProbeGuard::packet_filter();
+    let probe_enabled = config.modules.probe_guard.enabled;
+
+    let mut capture = pcap::Capture::from_file(path)?;
+    let linktype = capture.get_datalink().0;
+    let interface: Arc<str> = Arc::from("replay");
+
+    let mut clock = ReplayClock::new(Instant::now());
+    let mut last_maintenance = clock.base;
+    let (mut packets, mut decoded, mut corrupt) = (0u64, 0u64, 0u64);
+    let mut event_counts: BTreeMap<String, u64> = BTreeMap::new();
+
+    loop {
+        let packet = match capture.next_packet() {
+            Ok(packet) => packet,
+            Err(pcap::Error::NoMorePackets) => break,
+            Err(e) => return Err(e.into()),
+        };
+        packets += 1;
+
+        let Some((captured_at, timestamp)) = clock.advance(&packet.header.ts) else {
+            corrupt += 1;
+            continue;
+        };
+
+        let summary = match linktype {
+            LINKTYPE_ETHERNET => PacketSummary::from_ethernet(&interface, packet.data, captured_at, timestamp),
+            LINKTYPE_RAW | LINKTYPE_IPV4 | LINKTYPE_IPV6 => PacketSummary::from_ip(&interface, packet.data, captured_at, timestamp),
+            // Linux cooked capture: 16 byte header ending in the ethertype
+            LINKTYPE_LINUX_SLL if packet.data.len() > 16 && matches!(packet.data[14..16], [0x08, 0x00] | [0x86, 0xdd]) => {
+                PacketSummary::from_ip(&interface, &packet.data[16..], captured_at, timestamp)
+            }
+            LINKTYPE_LINUX_SLL => None,
+            other => return Err(format!("unsupported pcap link type {}", other).into()),
+        };
+
+        // Same as live capture: IPv6 only when enabled
+        if let Some(summary) = summary.filter(|summary| config.network.ipv6_support || summary.source_ip.is_ipv4()) {
+            decoded += 1;
+            // Live capture applies the same filters before the analyzers see a packet
+            if tcp_filter(&summary) {
+                tcp_guard.process_packet(&summary).await?;
+            }
+            if sip_filter(&summary) {
+                sip_shield.process_packet(&summary).await?;
+            }
+            if probe_enabled && probe_filter(&summary) {
+                probe_guard.process_packet(&summary).await?;
+            }
+        }
+
+        if captured_at.duration_since(last_maintenance) >= MAINTENANCE_INTERVAL {
+            tcp_guard.perform_maintenance(captured_at)?;
+            sip_shield.perform_maintenance(captured_at)?;
+            probe_guard.perform_maintenance(captured_at)?;
+            last_maintenance = captured_at;
+        }
+
+        while let Ok(event) = event_receiver.try_recv() {
+            // Modules stamp events with the wall clock; replay reports capture time
+            let mut event = (*event).clone();
+            event.timestamp = timestamp;
+            *event_counts.entry(event.event_type.clone()).or_insert(0) += 1;
+            write_event(&mut writer, &event, json_output)?;
+        }
+    }
+    writer.flush()?;
+
+    eprintln!(
+        "Replayed {} packets ({} decoded) from {}: {} events",
+        packets, decoded, path.display(), event_counts.values().sum::<u64>()
+    );
+    if corrupt > 0 {
+        eprintln!("  Skipped {} records with a corrupt timestamp", corrupt);
+    }
+    for (event_type, count) in &event_counts {
+        eprintln!("  {:<40} {}", event_type, count);
+    }
+    eprintln!("Module log: {}", log_file.display());
+
+    Ok(())
+}
+
+/// Maps capture timestamps onto the monotonic clock the detectors run on,
+/// starting from `base` at the first packet.
+struct ReplayClock {
+    base: Instant,
+    first: Option<DateTime<Utc>>,
+}
+
+impl ReplayClock {
+    fn new(base: Instant) -> Self {
+        ReplayClock { base, first: None }
+    }
+
+    /// The detector instant and capture time of a record, or None for a
+    /// corrupt timestamp. Those records are left out rather than given a
+    /// made-up time, which would move every detector window with it.
+    fn advance(&mut self, ts: &libc::timeval) -> Option<(Instant, DateTime<Utc>)> {
+        let timestamp = packet_timestamp(ts)?;
+        let first = *self.first.get_or_insert(timestamp);
+        // Out-of-order timestamps are held at the start of the capture
+        Some((self.base + (timestamp - first).to_std().unwrap_or_default(), timestamp))
+    }
+}
+
+/// A corrupt record's microseconds can be negative or a whole second or more.
+// time_t is only i64 on 64-bit targets
+#[allow(clippy::useless_conversion)]
+fn packet_timestamp(ts: &libc::timeval) -> Option<DateTime<Utc>> {
+    let micros = u32::try_from(ts.tv_usec).ok().filter(|micros| *micros < 1_000_000)?;
+    Utc.timestamp_opt(i64::try_from(ts.tv_sec).ok()?, micros * 1000).single()
+}
+
+fn write_event(writer: &mut dyn Write, event: &SecurityEvent, json_output: bool) -> io::Result<()> {
+    if json_output {
+        let line = serde_json::json!({
+            "timestamp": event.timestamp.to_rfc3339(),
+            "source_ip": event.source_ip.to_string(),
+            "event_type": event.event_type,
+            "threat_level": {
+                "level": event.threat_level.level,
+                "confidence": event.threat_level.confidence,
+                "category": event.threat_level.category
+            },
+            "details": event.details,
+            "action_taken": event.action_taken,
+            "related_ips": event.related_ips.iter().map(|ip| ip.to_string()).collect::<Vec<_>>()
+        });
+        writeln!(writer, "{}", line)
+    } else {
+        writeln!(
+            writer,
+            "{} {} from {} - Threat Level: {}/10 ({}%) [{}] - {}",
+            event.timestamp.format("%Y-%m-%d %H:%M:%S%.3f"),
+            event.event_type,
+            event.source_ip,
+            event.threat_level.level,
+            (event.threat_level.confidence * 100.0) as u8,
+            event.threat_level.category,
+            event.details
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn timeval(tv_sec: i64, tv_usec: i64) -> libc::timeval {
+        libc::timeval { tv_sec: tv_sec as libc::time_t, tv_usec: tv_usec as libc::suseconds_t }
+    }
+
+    #[test]
+    fn packet_timestamps_keep_microseconds() {
+        let timestamp = packet_timestamp(&timeval(1_700_000_000, 123_456)).unwrap();
+        assert_eq!(timestamp.timestamp(), 1_700_000_000);
+        assert_eq!(timestamp.timestamp_subsec_micros(), 123_456);
+    }
+
+    #[test]
+    fn corrupt_records_leave_the_replay_clock_alone() {
+        let base = Instant::now();
+        let mut clock = ReplayClock::new(base);
+
+        // A corrupt first record does not become the start of the capture
+        assert!(clock.advance(&timeval(1_700_000_000, -5)).is_none());
+        let (start, _) = clock.advance(&timeval(1_700_000_000, 0)).unwrap();
+        assert_eq!(start, base);
+
+        // Nor does one in the middle move the clock
+        assert!(clock.advance(&timeval(1_700_000_001, 2_000_000)).is_none());
+        let (captured_at, timestamp) = clock.advance(&timeval(1_700_000_002, 500_000)).unwrap();
+        assert_eq!(captured_at.duration_since(base), Duration::from_millis(2500));
+        assert_eq!(timestamp.timestamp(), 1_700_000_002);
+    }
+
+    #[test]
+    fn corrupt_microseconds_are_rejected() {
+        assert!(packet_timestamp(&timeval(1_700_000_000, -1)).is_none());
+        assert!(packet_timestamp(&timeval(1_700_000_000, 1_000_000)).is_none());
+        assert!(packet_timestamp(&timeval(1_700_000_000, 999_999)).is_some());
+    }
+}